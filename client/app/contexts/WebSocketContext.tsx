@@ -18,13 +18,38 @@ export const WebSocketProvider: React.FC<{ children: React.ReactNode }> = ({ chi
     const wsRef = useRef<WebSocket | null>(null);
 
     useEffect(() => {
-        const wsUrl = "ws://127.0.0.1:8080/ws/";
-        const socket = new WebSocket(wsUrl);
+        let socket: WebSocket | null = null;
+        let cancelled = false;
 
-        wsRef.current = socket;
-        setWs(socket);
+        // Registered players log in through /api/login, everyone else plays
+        // as a guest. Either way the token is kept so reconnecting keeps the seat.
+        const getToken = async (fresh: boolean) => {
+            const saved = localStorage.getItem("chess_token");
+            if (saved && !fresh) {
+                return saved;
+            }
+            const res = await fetch("http://127.0.0.1:8080/api/guest", { method: "POST" });
+            const { token } = await res.json();
+            localStorage.setItem("chess_token", token);
+            return token as string;
+        };
 
-        socket.onopen = () => {
+        // The server turns down the upgrade when the token is stale (expired
+        // or signed with another secret), which shows up as a close before the
+        // socket ever opened. Replace that token with a new guest's and try once more.
+        const connect = (fresh: boolean) => getToken(fresh).then((token) => {
+          if (cancelled) {
+            return;
+          }
+          const wsUrl = `ws://127.0.0.1:8080/ws/?token=${encodeURIComponent(token)}`;
+          socket = new WebSocket(wsUrl);
+          let opened = false;
+
+          wsRef.current = socket;
+          setWs(socket);
+
+          socket.onopen = () => {
+            opened = true;
             console.log("WebSocket connection established");
           };
       
@@ -35,14 +60,21 @@ export const WebSocketProvider: React.FC<{ children: React.ReactNode }> = ({ chi
       
           socket.onclose = () => {
             console.log("WebSocket connection closed");
+            if (!opened && !fresh && !cancelled) {
+              connect(true);
+            }
           };
       
           socket.onerror = (error) => {
             console.error("WebSocket error:", error);
           };
+        });
+
+        connect(false);
       
           return () => {
-            socket.close();
+            cancelled = true;
+            socket?.close();
           };
     }, [])
 
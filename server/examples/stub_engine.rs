@@ -0,0 +1,85 @@
+// examples/stub_engine.rs
+
+// A stand-in UCI engine for the tests of the external engine adapter. It
+// answers the handshake and plays the first legal move at once, reporting
+// a fixed info line. The first argument makes it misbehave:
+//
+//     normal        plays as above
+//     hang          never answers `go`, not even after `stop`
+//     crash         exits as soon as it's told to search
+//     crash-once    exits on its first search, marked by creating the file
+//                   named by the second argument, and plays after that
+//
+// In the other modes a file as the second argument gets every command
+// received, one per line.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use chess_server::position::Position;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mode = args.first().map_or("normal", String::as_str);
+    let file = args.get(1);
+    let mut log = file
+        .filter(|_| mode != "crash-once")
+        .and_then(|path| OpenOptions::new().create(true).append(true).open(path).ok());
+    let mut position = Position::startpos();
+
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if let Some(log) = log.as_mut() {
+            let _ = writeln!(log, "{}", line);
+        }
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("uci") => {
+                println!("id name Stub");
+                println!("uciok");
+            }
+            Some("isready") => println!("readyok"),
+            Some("position") => {
+                let rest: Vec<&str> = words.collect();
+                let moves_at = rest.iter().position(|word| *word == "moves");
+                let setup = &rest[..moves_at.unwrap_or(rest.len())];
+                position = match setup {
+                    ["fen", fen @ ..] => {
+                        Position::from_fen(&fen.join(" ")).unwrap_or_else(|_| Position::startpos())
+                    }
+                    _ => Position::startpos(),
+                };
+                for uci in moves_at.map_or(&[][..], |i| &rest[i + 1..]) {
+                    if let Some(mv) = position.find_uci_move(uci) {
+                        position = position.make_move(&mv);
+                    }
+                }
+            }
+            Some("go") => match mode {
+                "hang" => {}
+                "crash" => std::process::exit(1),
+                "crash-once" if file.is_some_and(|path| !Path::new(path).exists()) => {
+                    if let Some(path) = file {
+                        let _ = File::create(path);
+                    }
+                    std::process::exit(1);
+                }
+                _ => {
+                    let best = position.legal_moves().first().map(|mv| mv.to_uci());
+                    match best {
+                        Some(best) => {
+                            println!("info depth 1 score cp 12 nodes 20 time 1 pv {}", best);
+                            println!("bestmove {}", best);
+                        }
+                        None => println!("bestmove 0000"),
+                    }
+                }
+            },
+            Some("quit") => return,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,248 @@
+// src/accounts.rs
+
+use actix_web::{web, HttpResponse};
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::api::{bad_request, error_response, internal_error, not_found};
+use crate::auth::{hash_password, verify_password, TokenSigner};
+use crate::bot::BOT_USERNAME;
+use crate::rating::Speed;
+use crate::storage::{GameStore, StorageError, UserRecord};
+
+const MIN_PASSWORD_LEN: usize = 8;
+const GUEST_PREFIX: &str = "Guest-";
+
+#[derive(Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+    // Registering with a guest's token keeps the guest's id and games
+    #[serde(default)]
+    pub guest_token: Option<String>,
+}
+
+enum Registration {
+    Created(UserRecord),
+    UsernameTaken,
+    NotAGuest,
+}
+
+fn validate_username(username: &str) -> Result<(), &'static str> {
+    if !(3..=20).contains(&username.len()) {
+        return Err("Username must be between 3 and 20 characters");
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err("Username may only contain letters, digits, '_' and '-'");
+    }
+    if username
+        .to_ascii_lowercase()
+        .starts_with(&GUEST_PREFIX.to_ascii_lowercase())
+    {
+        return Err("Usernames starting with 'Guest-' are reserved");
+    }
+    if username.eq_ignore_ascii_case(BOT_USERNAME) {
+        return Err("This username is reserved");
+    }
+    Ok(())
+}
+
+fn session_response(user: &UserRecord, signer: &TokenSigner) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "user_id": user.id,
+        "username": user.username,
+        "guest": user.is_guest,
+        "token": signer.issue(user.id),
+    }))
+}
+
+// Guests get a random name like "Guest-48213" and no password, their token
+// is the only way back into the account
+pub async fn create_guest(
+    store: web::Data<Arc<dyn GameStore>>,
+    signer: web::Data<Arc<TokenSigner>>,
+) -> HttpResponse {
+    let store = store.get_ref().clone();
+    let created = web::block(move || {
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let username = format!("{}{:05}", GUEST_PREFIX, rng.gen_range(0..100_000));
+            if store.find_user(&username)?.is_none() {
+                return store.create_user(&username, "", true);
+            }
+        }
+        Err(StorageError("ran out of guest names".to_string()))
+    })
+    .await;
+
+    match created {
+        Ok(Ok(user)) => {
+            println!("Created guest {} ({})", user.username, user.id);
+            session_response(&user, &signer)
+        }
+        Ok(Err(err)) => internal_error(&err.to_string()),
+        Err(err) => internal_error(&err.to_string()),
+    }
+}
+
+pub async fn register(
+    store: web::Data<Arc<dyn GameStore>>,
+    signer: web::Data<Arc<TokenSigner>>,
+    body: web::Json<Credentials>,
+) -> HttpResponse {
+    let Credentials {
+        username,
+        password,
+        guest_token,
+    } = body.into_inner();
+    if let Err(err) = validate_username(&username) {
+        return bad_request(err);
+    }
+    if password.len() < MIN_PASSWORD_LEN {
+        return bad_request("Password must be at least 8 characters");
+    }
+    let guest_id = match guest_token {
+        Some(token) => match signer.verify(&token) {
+            Some(user_id) => Some(user_id),
+            None => return bad_request("Invalid guest token"),
+        },
+        None => None,
+    };
+
+    let store = store.get_ref().clone();
+    let created = web::block(move || {
+        if store.find_user(&username)?.is_some() {
+            return Ok(Registration::UsernameTaken);
+        }
+        let hash = hash_password(&password).map_err(StorageError)?;
+        let user = match guest_id {
+            Some(user_id) => match store.claim_guest(user_id, &username, &hash)? {
+                Some(user) => user,
+                None => return Ok(Registration::NotAGuest),
+            },
+            None => store.create_user(&username, &hash, false)?,
+        };
+        Ok::<_, StorageError>(Registration::Created(user))
+    })
+    .await;
+
+    match created {
+        Ok(Ok(Registration::Created(user))) => {
+            println!("Registered user {} ({})", user.username, user.id);
+            session_response(&user, &signer)
+        }
+        Ok(Ok(Registration::UsernameTaken)) => error_response(
+            actix_web::http::StatusCode::CONFLICT,
+            "Username is already taken",
+        ),
+        Ok(Ok(Registration::NotAGuest)) => bad_request("Guest token doesn't belong to a guest"),
+        Ok(Err(err)) => internal_error(&err.to_string()),
+        Err(err) => internal_error(&err.to_string()),
+    }
+}
+
+pub async fn login(
+    store: web::Data<Arc<dyn GameStore>>,
+    signer: web::Data<Arc<TokenSigner>>,
+    body: web::Json<Credentials>,
+) -> HttpResponse {
+    let Credentials {
+        username, password, ..
+    } = body.into_inner();
+    let store = store.get_ref().clone();
+    // Hashing is slow on purpose, keep it off the async workers
+    let user = web::block(move || {
+        store
+            .find_user(&username)
+            .map(|user| user.filter(|user| verify_password(&password, &user.password_hash)))
+    })
+    .await;
+
+    match user {
+        Ok(Ok(Some(user))) => session_response(&user, &signer),
+        Ok(Ok(None)) => error_response(
+            actix_web::http::StatusCode::UNAUTHORIZED,
+            "Invalid username or password",
+        ),
+        Ok(Err(err)) => internal_error(&err.to_string()),
+        Err(err) => internal_error(&err.to_string()),
+    }
+}
+
+pub async fn get_user(
+    store: web::Data<Arc<dyn GameStore>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let store = store.get_ref().clone();
+    let found = web::block(move || match store.find_user(&path)? {
+        Some(user) => {
+            let ratings = store.load_ratings(user.id)?;
+            Ok(Some((user, ratings)))
+        }
+        None => Ok::<_, StorageError>(None),
+    })
+    .await;
+
+    match found {
+        Ok(Ok(Some((user, ratings)))) => HttpResponse::Ok().json(json!({
+            "user_id": user.id,
+            "username": user.username,
+            "guest": user.is_guest,
+            "created_at": user.created_at,
+            "ratings": ratings
+                .iter()
+                .map(|(speed, rating)| {
+                    let rating = json!({
+                        "rating": rating.rating.round(),
+                        "deviation": rating.deviation.round(),
+                        "volatility": rating.volatility,
+                    });
+                    (speed.as_str().to_string(), rating)
+                })
+                .collect::<serde_json::Map<_, _>>(),
+        })),
+        Ok(Ok(None)) => not_found("User not found"),
+        Ok(Err(err)) => internal_error(&err.to_string()),
+        Err(err) => internal_error(&err.to_string()),
+    }
+}
+
+pub async fn get_rating_history(
+    store: web::Data<Arc<dyn GameStore>>,
+    path: web::Path<(String, String)>,
+) -> HttpResponse {
+    let (username, speed) = path.into_inner();
+    let speed: Speed = match speed.parse() {
+        Ok(speed) => speed,
+        Err(err) => return bad_request(&err),
+    };
+    let store = store.get_ref().clone();
+    let history = web::block(move || match store.find_user(&username)? {
+        Some(user) => store.rating_history(user.id, speed).map(Some),
+        None => Ok(None),
+    })
+    .await;
+
+    match history {
+        Ok(Ok(Some(history))) => HttpResponse::Ok().json(json!({
+            "speed": speed,
+            "history": history
+                .iter()
+                .map(|entry| json!({
+                    "game_id": entry.room_id.to_string(),
+                    "rating": entry.rating.rating.round(),
+                    "deviation": entry.rating.deviation.round(),
+                    "recorded_at": entry.recorded_at,
+                }))
+                .collect::<Vec<_>>(),
+        })),
+        Ok(Ok(None)) => not_found("User not found"),
+        Ok(Err(err)) => internal_error(&err.to_string()),
+        Err(err) => internal_error(&err.to_string()),
+    }
+}
@@ -0,0 +1,303 @@
+// src/adjudication.rs
+
+// Ends games that are decided or dead drawn without playing them out. The
+// rules are chosen when a room is created, for games against the bot and
+// for the games of a tournament that asks for them, e.g.
+//
+//     draw=40:8:10,win=5:1000,tablebase
+//
+// draws a game once the evaluation has stayed within 10 centipawns for 8
+// moves after move 40, gives it to the side that has been at least 1000
+// centipawns ahead for 5 moves and lets the tablebases at
+// CHESS_SYZYGY_PATH decide positions in them. Every rule is optional.
+// Untimed games between players can ask for them too, they are our
+// correspondence games. The bot doesn't play itself on the server, engine
+// matches are adjudicated by `engine_match` instead.
+//
+// The position after every move is searched on a thread of its own and
+// the result is sent back to the server as a `PositionEvaluated`. A room
+// only gets a few positions queued at a time, and what's still queued when
+// it finishes is dropped.
+
+use actix::Addr;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, OnceLock};
+use uuid::Uuid;
+
+use crate::bot;
+use crate::engine::{self, Engine, Limits, Wdl};
+use crate::game_room::GameResult;
+use crate::messages::PositionEvaluated;
+use crate::position::{Color, Move, Position};
+use crate::server::Server;
+
+const DEPTH: i32 = 10;
+const NODES: u64 = 100_000; // per position, in case the depth takes long
+const HASH_MB: usize = 16;
+// Positions of one room waiting to be searched, beyond which more are
+// dropped until the search catches up
+const QUEUED_PER_ROOM: usize = 4;
+// Bounds of the rule arguments
+const MAX_MOVES: usize = 500;
+const MAX_MARGIN: usize = 10_000; // centipawns
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DrawRule {
+    pub after_move: usize,
+    pub moves: usize,
+    pub margin: i32, // centipawns either way
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WinRule {
+    pub moves: usize,
+    pub margin: i32, // centipawns ahead
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rules {
+    pub draw: Option<DrawRule>,
+    pub win: Option<WinRule>,
+    pub tablebase: bool,
+}
+
+impl FromStr for Rules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = Rules::default();
+        for rule in s.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let (name, args) = rule.split_once('=').unwrap_or((rule, ""));
+            let numbers: Vec<&str> = args.split(':').collect();
+            let number = |i: usize, max: usize| -> Result<usize, String> {
+                let n: usize = numbers
+                    .get(i)
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| format!("Expected a number in '{}'", rule))?;
+                if n > max {
+                    return Err(format!("Numbers in '{}' go up to {}", rule, max));
+                }
+                Ok(n)
+            };
+            match (name, numbers.len()) {
+                ("draw", 3) => {
+                    rules.draw = Some(DrawRule {
+                        after_move: number(0, MAX_MOVES)?,
+                        moves: number(1, MAX_MOVES)?.max(1),
+                        margin: number(2, MAX_MARGIN)? as i32,
+                    })
+                }
+                ("win", 2) => {
+                    rules.win = Some(WinRule {
+                        moves: number(0, MAX_MOVES)?.max(1),
+                        margin: number(1, MAX_MARGIN)? as i32,
+                    })
+                }
+                ("tablebase", _) if args.is_empty() => rules.tablebase = true,
+                _ => return Err(format!("Unknown adjudication rule '{}'", rule)),
+            }
+        }
+        Ok(rules)
+    }
+}
+
+// The same form `from_str` reads, which is how rooms store their rules
+impl fmt::Display for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rules = Vec::new();
+        if let Some(draw) = self.draw {
+            rules.push(format!(
+                "draw={}:{}:{}",
+                draw.after_move, draw.moves, draw.margin
+            ));
+        }
+        if let Some(win) = self.win {
+            rules.push(format!("win={}:{}", win.moves, win.margin));
+        }
+        if self.tablebase {
+            rules.push("tablebase".to_string());
+        }
+        f.write_str(&rules.join(","))
+    }
+}
+
+// The rules a room is created with, None if `spec` sets none
+pub fn parse(spec: &str) -> Result<Option<Rules>, String> {
+    let rules: Rules = spec.parse()?;
+    if rules.tablebase && bot::tablebases().is_none() {
+        return Err("There are no tablebases to adjudicate with".to_string());
+    }
+    Ok(Some(rules).filter(|rules| *rules != Rules::default()))
+}
+
+struct Job {
+    server: Addr<Server>,
+    room_id: Uuid,
+    moves: Vec<Move>, // from the initial position
+    tablebase: bool,
+}
+
+// Jobs waiting by room, rooms without an entry have finished
+fn queued() -> &'static Mutex<HashMap<Uuid, usize>> {
+    static QUEUED: OnceLock<Mutex<HashMap<Uuid, usize>>> = OnceLock::new();
+    QUEUED.get_or_init(Default::default)
+}
+
+fn queue() -> &'static Sender<Job> {
+    static QUEUE: OnceLock<Sender<Job>> = OnceLock::new();
+    QUEUE.get_or_init(|| {
+        let (sender, jobs) = mpsc::channel::<Job>();
+        std::thread::spawn(move || {
+            let mut engine = Engine::new(HASH_MB);
+            engine.set_tablebases(bot::tablebases());
+            for job in jobs {
+                if take(job.room_id) {
+                    run(&mut engine, job);
+                }
+            }
+        });
+        sender
+    })
+}
+
+// Counts a job of the room in, false if it has enough queued already
+fn reserve(room_id: Uuid) -> bool {
+    let mut queued = queued().lock().unwrap();
+    let count = queued.entry(room_id).or_default();
+    if *count >= QUEUED_PER_ROOM {
+        return false;
+    }
+    *count += 1;
+    true
+}
+
+// Counts a job of the room out, false if the room has finished since
+fn take(room_id: Uuid) -> bool {
+    match queued().lock().unwrap().get_mut(&room_id) {
+        Some(count) => {
+            *count = count.saturating_sub(1);
+            true
+        }
+        None => false,
+    }
+}
+
+// Queues a search of the position after `moves`, unless the room has
+// enough queued already
+pub fn request(server: Addr<Server>, room_id: Uuid, moves: Vec<Move>, rules: &Rules) {
+    if !reserve(room_id) {
+        return;
+    }
+    let _ = queue().send(Job {
+        server,
+        room_id,
+        moves,
+        tablebase: rules.tablebase,
+    });
+}
+
+// Drops whatever of the room is still queued
+pub fn forget(room_id: Uuid) {
+    queued().lock().unwrap().remove(&room_id);
+}
+
+fn run(engine: &mut Engine, job: Job) {
+    let mut position = Position::startpos();
+    let mut history = Vec::with_capacity(job.moves.len());
+    for mv in &job.moves {
+        history.push(engine::hash(&position));
+        position = position.make_move(mv);
+    }
+    let result = engine.search(
+        &position,
+        &history,
+        Limits {
+            depth: Some(DEPTH),
+            movetime: None,
+            nodes: Some(NODES),
+        },
+    );
+    let sign = match position.side_to_move {
+        Color::White => 1,
+        Color::Black => -1,
+    };
+    // Only right after a capture or pawn move, so the fifty-move rule is
+    // accounted for
+    let tablebase = bot::tablebases()
+        .filter(|_| job.tablebase && position.halfmove_clock == 0)
+        .and_then(|tablebases| tablebases.probe_wdl(&position))
+        .map(|wdl| {
+            let white = if sign == 1 { wdl } else { wdl.flip() };
+            match white {
+                Wdl::Win => GameResult::WhiteWins,
+                Wdl::Loss => GameResult::BlackWins,
+                // A cursed win is a draw under the fifty-move rule
+                _ => GameResult::Draw,
+            }
+        });
+    job.server.do_send(PositionEvaluated {
+        room_id: job.room_id,
+        ply: job.moves.len(),
+        cp: sign * result.score,
+        tablebase,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_rule() {
+        let rules: Rules = "draw=40:8:10, win=5:1000".parse().unwrap();
+        assert_eq!(
+            rules,
+            Rules {
+                draw: Some(DrawRule {
+                    after_move: 40,
+                    moves: 8,
+                    margin: 10,
+                }),
+                win: Some(WinRule {
+                    moves: 5,
+                    margin: 1000,
+                }),
+                tablebase: false,
+            }
+        );
+        assert_eq!(rules.to_string().parse(), Ok(rules));
+        assert_eq!(parse("").unwrap(), None);
+        assert!(parse("draw=40:8").is_err());
+        assert!(parse("resign=5").is_err());
+        assert!(parse("win=5:4294967296").is_err());
+        assert!(parse("draw=18446744073709551615:8:10").is_err());
+    }
+
+    #[test]
+    fn queues_a_few_positions_per_room() {
+        let (room, other) = (Uuid::new_v4(), Uuid::new_v4());
+        for _ in 0..QUEUED_PER_ROOM {
+            assert!(reserve(room));
+        }
+        assert!(!reserve(room));
+        assert!(reserve(other));
+        assert!(take(room));
+        assert!(reserve(room));
+        forget(room);
+        forget(other);
+    }
+
+    #[test]
+    fn drops_the_positions_of_finished_rooms() {
+        let room = Uuid::new_v4();
+        assert!(reserve(room));
+        assert!(reserve(room));
+        forget(room);
+        assert!(!take(room));
+        assert!(!take(room));
+    }
+}
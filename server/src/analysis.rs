@@ -0,0 +1,146 @@
+// src/analysis.rs
+
+// Live analysis for the `analyze` message. Searches run on a fixed pool of
+// threads so the server actor never waits on one, and send an `analysis`
+// message straight to the session for every line of every completed depth.
+// An analysis runs until its depth, a forced mate or MAX_TIME, or until the
+// session stops it, starts another one or goes away. Positions in the
+// tablebases get their result and the best move from them as well.
+
+use actix::Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::bot;
+use crate::engine::{self, Engine, EngineProfile, ExternalEngine, Limits, SearchInfo};
+use crate::messages::{AnalysisDone, AnalysisLine, ErrorMessage, TablebaseInfo};
+use crate::notation::{move_to_san, moves_to_san};
+use crate::position::{Move, Position};
+use crate::websocket::MyWebSocket;
+
+pub const MAX_DEPTH: i32 = 40;
+pub const MAX_MULTIPV: usize = 5;
+const MAX_TIME: Duration = Duration::from_secs(5 * 60);
+const HASH_MB: usize = 32;
+
+pub struct Job {
+    pub start: Position,
+    pub moves: Vec<Move>, // from `start` to the position to analyze
+    pub depth: Option<i32>,
+    pub multipv: usize,
+    pub engine: Option<&'static EngineProfile>, // the built-in engine if None
+    pub session: Addr<MyWebSocket>,
+    pub stop: Arc<AtomicBool>,
+}
+
+pub struct AnalysisPool {
+    jobs: Sender<Job>,
+}
+
+impl AnalysisPool {
+    pub fn new(workers: usize) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..workers.max(1) {
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                // Kept from job to job, so is what it learned
+                let mut engine = Engine::new(HASH_MB);
+                engine.set_tablebases(bot::tablebases());
+                loop {
+                    let job = queue.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => run(&mut engine, job),
+                        Err(_) => return,
+                    }
+                }
+            });
+        }
+        AnalysisPool { jobs }
+    }
+
+    // Waits in line while every worker is busy
+    pub fn submit(&self, job: Job) {
+        let _ = self.jobs.send(job);
+    }
+}
+
+fn run(engine: &mut Engine, job: Job) {
+    // Stopped before a worker got to it
+    if job.stop.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut position = job.start.clone();
+    let mut history = Vec::with_capacity(job.moves.len());
+    for mv in &job.moves {
+        history.push(engine::hash(&position));
+        position = position.make_move(mv);
+    }
+    let fen = position.to_fen();
+    let tablebase = bot::tablebases()
+        .and_then(|tablebases| tablebases.probe_root(&position))
+        .map(|probe| TablebaseInfo {
+            wdl: probe.wdl.as_str(),
+            dtz: probe.dtz,
+            best_move: probe
+                .moves
+                .first()
+                .map(|(mv, _)| move_to_san(&position, mv)),
+        });
+    let limits = Limits {
+        depth: job.depth,
+        movetime: Some(MAX_TIME),
+        nodes: None,
+    };
+
+    let report = |info: &SearchInfo| {
+        if job.stop.load(Ordering::Relaxed) {
+            return;
+        }
+        let ms = info.elapsed.as_millis() as u64;
+        let mate = engine::mate_in(info.score);
+        job.session.do_send(AnalysisLine {
+            fen: fen.clone(),
+            depth: info.depth,
+            multipv: info.multipv,
+            cp: info.score,
+            mate,
+            pv: moves_to_san(&position, &info.pv),
+            nodes: info.nodes,
+            nps: info.nodes * 1000 / ms.max(1),
+            tablebase: tablebase.clone(),
+        });
+    };
+    let best_move = match job.engine {
+        Some(profile) => {
+            let mut external = ExternalEngine::new(profile.clone());
+            external.set_stop_flag(job.stop.clone());
+            external.set_multipv(job.multipv);
+            match external.search(&job.start, &job.moves, limits, report) {
+                Ok(result) => result.best_move,
+                Err(err) => {
+                    return job.session.do_send(ErrorMessage {
+                        error: format!("Analysis failed: {}", err),
+                    });
+                }
+            }
+        }
+        None => {
+            engine.set_stop_flag(job.stop.clone());
+            engine.set_multipv(job.multipv);
+            engine
+                .search_with_info(&position, &history, limits, report)
+                .best_move
+        }
+    };
+
+    if !job.stop.load(Ordering::Relaxed) {
+        job.session.do_send(AnalysisDone {
+            fen,
+            best_move: best_move.map(|mv| move_to_san(&position, &mv)),
+            tablebase,
+        });
+    }
+}
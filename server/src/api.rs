@@ -0,0 +1,551 @@
+// src/api.rs
+
+use actix::Addr;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::accounts;
+use crate::auth::TokenSigner;
+use crate::bot::{self, DEFAULT_LEVEL};
+use crate::clock::TimeControl;
+use crate::engine::LEVELS;
+use crate::explorer;
+use crate::messages::ListLiveRooms;
+use crate::notation::{moves_to_san, to_pgn};
+use crate::review::{self, Review};
+use crate::server::Server;
+use crate::storage::{
+    civil_from_days, days_from_civil, player_for, replay, unix_now, GameStore, RoomFilter,
+    RoomRecord,
+};
+use crate::tournament;
+
+const DEFAULT_PER_PAGE: usize = 20;
+const MAX_PER_PAGE: usize = 100;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api")
+            .route("/register", web::post().to(accounts::register))
+            .route("/login", web::post().to(accounts::login))
+            .route("/guest", web::post().to(accounts::create_guest))
+            .route("/users/{username}", web::get().to(accounts::get_user))
+            .route(
+                "/users/{username}/ratings/{speed}",
+                web::get().to(accounts::get_rating_history),
+            )
+            .route("/games", web::get().to(list_games))
+            .route("/games/{id}", web::get().to(get_game))
+            .route("/games/{id}/pgn", web::get().to(get_game_pgn))
+            .route("/games/{id}/fen", web::get().to(get_game_fen))
+            .route("/games/{id}/review", web::get().to(get_game_review))
+            .route("/games/{id}/review", web::post().to(request_game_review))
+            .route("/explorer", web::get().to(explorer::get_explorer))
+            .route("/rooms/live", web::get().to(list_live_rooms))
+            .route("/bot/levels", web::get().to(list_bot_levels))
+            .route(
+                "/tournaments",
+                web::get().to(tournament::http::list_tournaments),
+            )
+            .route(
+                "/tournaments",
+                web::post().to(tournament::http::create_tournament),
+            )
+            .route(
+                "/tournaments/{id}",
+                web::get().to(tournament::http::get_tournament),
+            )
+            .route(
+                "/tournaments/{id}/join",
+                web::post().to(tournament::http::join_tournament),
+            )
+            .route(
+                "/tournaments/{id}/withdraw",
+                web::post().to(tournament::http::withdraw_tournament),
+            )
+            .route(
+                "/tournaments/{id}/start",
+                web::post().to(tournament::http::start_tournament),
+            )
+            .route(
+                "/tournaments/{id}/standings",
+                web::get().to(tournament::http::get_standings),
+            ),
+    );
+}
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+impl PageQuery {
+    // (page, per_page, offset) with page counted from 1. The offset has to
+    // fit SQLite's signed OFFSET.
+    fn resolve(&self) -> Result<(usize, usize, usize), &'static str> {
+        let page = self.page.unwrap_or(1).max(1);
+        let per_page = self
+            .per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE);
+        match (page - 1).checked_mul(per_page) {
+            Some(offset) if offset <= i64::MAX as usize => Ok((page, per_page, offset)),
+            _ => Err("Page out of range"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct GameQuery {
+    pub player: Option<String>, // username
+    pub result: Option<String>,
+    pub date: Option<String>, // YYYY-MM-DD
+    pub since: Option<u64>,   // unix seconds
+    pub until: Option<u64>,
+    pub time_control: Option<String>, // "300+3"
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct FenQuery {
+    pub ply: Option<usize>,
+}
+
+pub(crate) fn error_response(status: actix_web::http::StatusCode, error: &str) -> HttpResponse {
+    HttpResponse::build(status).json(json!({ "error": error }))
+}
+
+pub(crate) fn bad_request(error: &str) -> HttpResponse {
+    error_response(actix_web::http::StatusCode::BAD_REQUEST, error)
+}
+
+pub(crate) fn not_found(error: &str) -> HttpResponse {
+    error_response(actix_web::http::StatusCode::NOT_FOUND, error)
+}
+
+pub(crate) fn internal_error(error: &str) -> HttpResponse {
+    println!("API error: {}", error);
+    error_response(
+        actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal server error",
+    )
+}
+
+// User id from the session token of /api/login, which is sent as
+// `Authorization: Bearer <token>`
+pub(crate) fn authenticate(req: &HttpRequest, signer: &TokenSigner) -> Option<usize> {
+    req.headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| signer.verify(token.trim()))
+}
+
+pub(crate) fn unauthorized() -> HttpResponse {
+    error_response(
+        actix_web::http::StatusCode::UNAUTHORIZED,
+        "Missing or invalid session token",
+    )
+}
+
+// YYYY-MM-DD, as unix seconds at midnight UTC
+fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    u64::try_from(days_from_civil(year, month, day) * 86400).ok()
+}
+
+fn game_summary(record: &RoomRecord) -> serde_json::Value {
+    json!({
+        "id": record.id.to_string(),
+        "white": player_for(record, "w"),
+        "black": player_for(record, "b"),
+        "time_control": record.time_control.map(|tc| tc.to_string()),
+        "result": record.result,
+        "termination": record.termination,
+        "ply_count": record.moves.len(),
+        "rematch_of": record.rematch_of.map(|id| id.to_string()),
+        "rated": record.rated,
+        "eco": record.eco,
+        "opening": record.opening,
+        "created_at": record.created_at,
+        "finished_at": record.finished_at,
+    })
+}
+
+async fn load_game(store: &Arc<dyn GameStore>, id: &str) -> Result<RoomRecord, HttpResponse> {
+    let room_id = Uuid::parse_str(id).map_err(|_| bad_request("Invalid game ID format"))?;
+    let store = store.clone();
+    match web::block(move || store.load_room(room_id)).await {
+        Ok(Ok(Some(record))) => Ok(record),
+        Ok(Ok(None)) => Err(not_found("Game not found")),
+        Ok(Err(err)) => Err(internal_error(&err.to_string())),
+        Err(err) => Err(internal_error(&err.to_string())),
+    }
+}
+
+async fn list_games(
+    store: web::Data<Arc<dyn GameStore>>,
+    query: web::Query<GameQuery>,
+) -> HttpResponse {
+    let mut filter = RoomFilter {
+        player_id: None,
+        result: query.result.clone(),
+        since: query.since,
+        until: query.until,
+        time_control: None,
+    };
+
+    if let Some(date) = &query.date {
+        match parse_date(date) {
+            Some(start) => {
+                filter.since = Some(filter.since.map_or(start, |s| s.max(start)));
+                filter.until = Some(filter.until.map_or(start + 86400, |u| u.min(start + 86400)));
+            }
+            None => return bad_request("Invalid date, expected YYYY-MM-DD"),
+        }
+    }
+
+    if let Some(tc) = &query.time_control {
+        // A literal '+' in a query string arrives as a space
+        match tc.replace(' ', "+").parse::<TimeControl>() {
+            Ok(tc) => filter.time_control = Some(tc),
+            Err(err) => return bad_request(&err),
+        }
+    }
+
+    let page = PageQuery {
+        page: query.page,
+        per_page: query.per_page,
+    };
+    let (page, per_page, offset) = match page.resolve() {
+        Ok(page) => page,
+        Err(err) => return bad_request(err),
+    };
+    let player = query.player.clone();
+    let store = store.get_ref().clone();
+    let listed = web::block(move || {
+        if let Some(username) = player {
+            match store.find_user(&username)? {
+                Some(user) => filter.player_id = Some(user.id),
+                None => return Ok(None),
+            }
+        }
+        store.list_rooms(&filter, offset, per_page).map(Some)
+    })
+    .await;
+    match listed {
+        Ok(Ok(None)) => not_found("User not found"),
+        Ok(Ok(Some((records, total)))) => HttpResponse::Ok().json(json!({
+            "page": page,
+            "per_page": per_page,
+            "total": total,
+            "games": records.iter().map(game_summary).collect::<Vec<_>>(),
+        })),
+        Ok(Err(err)) => internal_error(&err.to_string()),
+        Err(err) => internal_error(&err.to_string()),
+    }
+}
+
+async fn get_game(store: web::Data<Arc<dyn GameStore>>, path: web::Path<String>) -> HttpResponse {
+    let record = match load_game(store.get_ref(), &path).await {
+        Ok(record) => record,
+        Err(response) => return response,
+    };
+    let (moves, positions) = match replay(&record) {
+        Ok(replayed) => replayed,
+        Err(err) => return internal_error(&err),
+    };
+
+    let sans = moves_to_san(&positions[0], &moves);
+    let mut game = game_summary(&record);
+    game["white_time_ms"] = json!(record.white_time_ms);
+    game["black_time_ms"] = json!(record.black_time_ms);
+    game["moves"] = json!(moves
+        .iter()
+        .zip(sans)
+        .map(|(mv, san)| json!({ "uci": mv.to_uci(), "san": san }))
+        .collect::<Vec<_>>());
+    game["fen"] = json!(positions.last().map(|p| p.to_fen()));
+
+    HttpResponse::Ok().json(game)
+}
+
+async fn get_game_pgn(
+    store: web::Data<Arc<dyn GameStore>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let record = match load_game(store.get_ref(), &path).await {
+        Ok(record) => record,
+        Err(response) => return response,
+    };
+    let (moves, positions) = match replay(&record) {
+        Ok(replayed) => replayed,
+        Err(err) => return internal_error(&err),
+    };
+
+    let (year, month, day) = civil_from_days((record.created_at / 86400) as i64);
+    let result = record.result.clone().unwrap_or_else(|| "*".to_string());
+    let ids = (player_for(&record, "w"), player_for(&record, "b"));
+    let users = store.get_ref().clone();
+    let names = web::block(move || {
+        let name = |id: Option<usize>| match id {
+            Some(id) => users
+                .load_user(id)
+                .map(|user| user.map_or_else(|| format!("Player {}", id), |u| u.username)),
+            None => Ok("?".to_string()),
+        };
+        Ok::<_, crate::storage::StorageError>((name(ids.0)?, name(ids.1)?))
+    })
+    .await;
+    let (white, black) = match names {
+        Ok(Ok(names)) => names,
+        Ok(Err(err)) => return internal_error(&err.to_string()),
+        Err(err) => return internal_error(&err.to_string()),
+    };
+    // A review of the game, if there is one, is written in as annotations
+    let annotations = match load_review(store.get_ref(), record.id).await {
+        Ok(Some(review)) => match serde_json::from_str::<Review>(&review) {
+            Ok(review) if review.moves.len() == moves.len() => {
+                review.moves.iter().map(|mv| mv.annotation()).collect()
+            }
+            _ => Vec::new(),
+        },
+        Ok(None) => Vec::new(),
+        Err(response) => return response,
+    };
+
+    let mut headers = vec![
+        ("Event".to_string(), "Casual game".to_string()),
+        ("Site".to_string(), "chess_server".to_string()),
+        (
+            "Date".to_string(),
+            format!("{:04}.{:02}.{:02}", year, month, day),
+        ),
+        ("Round".to_string(), "-".to_string()),
+        ("White".to_string(), white),
+        ("Black".to_string(), black),
+        ("Result".to_string(), result.clone()),
+        (
+            "TimeControl".to_string(),
+            record
+                .time_control
+                .map(|tc| tc.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+        ),
+    ];
+    if let Some(termination) = &record.termination {
+        headers.push(("Termination".to_string(), termination.clone()));
+    }
+    if let (Some(eco), Some(opening)) = (&record.eco, &record.opening) {
+        headers.push(("ECO".to_string(), eco.clone()));
+        headers.push(("Opening".to_string(), opening.clone()));
+    }
+    headers.push(("GameId".to_string(), record.id.to_string()));
+
+    HttpResponse::Ok()
+        .content_type("application/x-chess-pgn")
+        .body(to_pgn(
+            &headers,
+            &positions[0],
+            &moves,
+            &annotations,
+            &result,
+        ))
+}
+
+async fn load_review(
+    store: &Arc<dyn GameStore>,
+    room_id: Uuid,
+) -> Result<Option<String>, HttpResponse> {
+    let store = store.clone();
+    match web::block(move || store.load_review(room_id)).await {
+        Ok(Ok(review)) => Ok(review),
+        Ok(Err(err)) => Err(internal_error(&err.to_string())),
+        Err(err) => Err(internal_error(&err.to_string())),
+    }
+}
+
+fn review_response(room_id: Uuid, review: Option<String>) -> HttpResponse {
+    match review.map(|review| serde_json::from_str::<serde_json::Value>(&review)) {
+        Some(Ok(review)) => HttpResponse::Ok().json(json!({
+            "id": room_id.to_string(),
+            "status": "done",
+            "review": review,
+        })),
+        Some(Err(err)) => internal_error(&err.to_string()),
+        None if review::is_pending(room_id) => HttpResponse::Accepted().json(json!({
+            "id": room_id.to_string(),
+            "status": "pending",
+        })),
+        None => not_found("This game hasn't been reviewed"),
+    }
+}
+
+async fn get_game_review(
+    store: web::Data<Arc<dyn GameStore>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let record = match load_game(store.get_ref(), &path).await {
+        Ok(record) => record,
+        Err(response) => return response,
+    };
+    match load_review(store.get_ref(), record.id).await {
+        Ok(review) => review_response(record.id, review),
+        Err(response) => response,
+    }
+}
+
+// Starts a review of a finished game, or returns the one already done
+async fn request_game_review(
+    req: HttpRequest,
+    store: web::Data<Arc<dyn GameStore>>,
+    signer: web::Data<Arc<TokenSigner>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let Some(user_id) = authenticate(&req, &signer) else {
+        return unauthorized();
+    };
+    let record = match load_game(store.get_ref(), &path).await {
+        Ok(record) => record,
+        Err(response) => return response,
+    };
+    if record.result.is_none() {
+        return bad_request("The game isn't finished");
+    }
+    let review = match load_review(store.get_ref(), record.id).await {
+        Ok(review) => review,
+        Err(response) => return response,
+    };
+    if review.is_some() || review::is_pending(record.id) {
+        return review_response(record.id, review);
+    }
+
+    // Reviews cost a lot of engine time. Guests can only have their own
+    // games reviewed, and nobody more than a few an hour.
+    let played = record.players.iter().any(|p| p.player_id == user_id);
+    if !played {
+        let store = store.get_ref().clone();
+        match web::block(move || store.load_user(user_id)).await {
+            Ok(Ok(Some(user))) if !user.is_guest => {}
+            Ok(Ok(_)) => {
+                return error_response(
+                    actix_web::http::StatusCode::FORBIDDEN,
+                    "Guests can only have their own games reviewed",
+                )
+            }
+            Ok(Err(err)) => return internal_error(&err.to_string()),
+            Err(err) => return internal_error(&err.to_string()),
+        }
+    }
+    if !review::allow_request(user_id, unix_now()) {
+        return error_response(
+            actix_web::http::StatusCode::TOO_MANY_REQUESTS,
+            "Too many reviews requested, try again later",
+        );
+    }
+    review::request(store.get_ref().clone(), record.id);
+    review_response(record.id, review)
+}
+
+async fn get_game_fen(
+    store: web::Data<Arc<dyn GameStore>>,
+    path: web::Path<String>,
+    query: web::Query<FenQuery>,
+) -> HttpResponse {
+    let record = match load_game(store.get_ref(), &path).await {
+        Ok(record) => record,
+        Err(response) => return response,
+    };
+    let (_, positions) = match replay(&record) {
+        Ok(replayed) => replayed,
+        Err(err) => return internal_error(&err),
+    };
+
+    let ply = query.ply.unwrap_or(positions.len() - 1);
+    match positions.get(ply) {
+        Some(position) => HttpResponse::Ok().json(json!({
+            "id": record.id.to_string(),
+            "ply": ply,
+            "fen": position.to_fen(),
+        })),
+        None => bad_request(&format!(
+            "Ply out of range, the game has {} plies",
+            positions.len() - 1
+        )),
+    }
+}
+
+async fn list_live_rooms(
+    srv: web::Data<Arc<Addr<Server>>>,
+    query: web::Query<PageQuery>,
+) -> HttpResponse {
+    let rooms = match srv.send(ListLiveRooms).await {
+        Ok(rooms) => rooms,
+        Err(err) => return internal_error(&err.to_string()),
+    };
+
+    let (page, per_page, offset) = match query.resolve() {
+        Ok(page) => page,
+        Err(err) => return bad_request(err),
+    };
+    HttpResponse::Ok().json(json!({
+        "page": page,
+        "per_page": per_page,
+        "total": rooms.len(),
+        "rooms": rooms.iter().skip(offset).take(per_page).collect::<Vec<_>>(),
+    }))
+}
+
+// The levels `create_room` accepts for bot games and the rating each one is
+// tuned to play at, and the external engines the bot can play as
+async fn list_bot_levels() -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "default": DEFAULT_LEVEL,
+        "engines": bot::engine_profiles()
+            .iter()
+            .map(|profile| profile.name.as_str())
+            .collect::<Vec<_>>(),
+        "levels": LEVELS
+            .iter()
+            .map(|level| json!({ "level": level.level, "rating": level.rating }))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(page: Option<usize>, per_page: Option<usize>) -> Option<(usize, usize, usize)> {
+        PageQuery { page, per_page }.resolve().ok()
+    }
+
+    #[test]
+    fn pages_are_clamped_and_bounded() {
+        assert_eq!(resolve(None, None), Some((1, DEFAULT_PER_PAGE, 0)));
+        assert_eq!(resolve(Some(0), Some(0)), Some((1, 1, 0)));
+        assert_eq!(resolve(Some(3), Some(1000)), Some((3, MAX_PER_PAGE, 200)));
+        assert_eq!(resolve(Some(usize::MAX), Some(50)), None);
+        assert_eq!(resolve(Some(i64::MAX as usize), Some(2)), None);
+    }
+
+    #[test]
+    fn dates_stay_in_range() {
+        assert_eq!(parse_date("1970-01-02"), Some(86400));
+        assert_eq!(parse_date("2024-02-29"), Some(1_709_164_800));
+        assert_eq!(parse_date("1969-12-31"), None);
+        assert_eq!(parse_date("9223372036854775807-01-01"), None);
+        assert_eq!(parse_date("2024-13-01"), None);
+    }
+}
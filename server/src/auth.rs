@@ -0,0 +1,95 @@
+// src/auth.rs
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+use crate::storage::{unix_now, GameStore, StorageResult};
+
+const TOKEN_LIFETIME: u64 = 30 * 24 * 60 * 60; // seconds
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Argon2 PHC string, the random salt is stored inside it
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+// Issues and checks session tokens of the form `payload.signature`, where the
+// payload is "user_id:expires_at" and the signature is an HMAC-SHA256 of it.
+pub struct TokenSigner {
+    secret: Vec<u8>,
+}
+
+impl TokenSigner {
+    pub fn new(secret: Vec<u8>) -> Self {
+        TokenSigner { secret }
+    }
+
+    // Uses CHESS_TOKEN_SECRET when set. Otherwise a random secret is made up
+    // the first time the server runs and kept in the store, so tokens stay
+    // valid across restarts.
+    pub fn from_env(store: &dyn GameStore) -> StorageResult<Self> {
+        match std::env::var("CHESS_TOKEN_SECRET") {
+            Ok(secret) if !secret.is_empty() => Ok(TokenSigner::new(secret.into_bytes())),
+            _ => {
+                let mut generated = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut generated);
+                Ok(TokenSigner::new(store.token_secret(&generated)?))
+            }
+        }
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+
+    pub fn issue(&self, user_id: usize) -> String {
+        let payload = format!("{}:{}", user_id, unix_now() + TOKEN_LIFETIME);
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        let signature = mac.finalize().into_bytes();
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(payload),
+            URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    // Returns the user id if the token is authentic and hasn't expired
+    pub fn verify(&self, token: &str) -> Option<usize> {
+        let (payload, signature) = token.split_once('.')?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+        let mut mac = self.mac();
+        mac.update(&payload);
+        mac.verify_slice(&signature).ok()?;
+
+        let payload = String::from_utf8(payload).ok()?;
+        let (user_id, expires_at) = payload.split_once(':')?;
+        let expires_at: u64 = expires_at.parse().ok()?;
+        if expires_at < unix_now() {
+            return None;
+        }
+        user_id.parse().ok()
+    }
+}
@@ -0,0 +1,317 @@
+// src/bin/match.rs
+
+// Plays two engines against each other to tell whether one is stronger,
+// for checking every engine change. An engine is a bot level or the name
+// of a UCI engine in the CHESS_ENGINES file. Each opening of the suite (an
+// EPD file, or a PGN file whose games are cut to --plies) is played twice
+// with colors swapped, on the server's clocks. With Syzygy tablebases at
+// CHESS_SYZYGY_PATH the built-in engine uses them, and a game that reaches
+// them is decided by what they say.
+//
+// The games go to the PGN file, the result is the Elo difference with its
+// 95% interval. With --sprt the match is a sequential probability ratio
+// test of elo0 against elo1 and stops once it's decided.
+//
+//     cargo run --release --bin match -- <engine a> <engine b> [--games 100]
+//         [--tc 10+0] [--openings suite.epd] [--plies 8] [--concurrency 1]
+//         [--pgn match.pgn] [--sprt 0,5]
+
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chess_server::clock::TimeControl;
+use chess_server::engine::{self, load_profiles, EngineProfile, Tablebases};
+use chess_server::engine_match::{
+    play_game, random_opening, result_str, sprt_bounds, sprt_verdict, Opening, Player, Side, Tally,
+    TimeLimit,
+};
+use chess_server::notation::{parse_pgn, to_pgn};
+use chess_server::position::Position;
+
+// One position per line, the four FEN fields followed by any operations
+fn read_epd(text: &str) -> Result<Vec<Opening>, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().take(4).collect();
+            let fen = format!("{} 0 1", fields.join(" "));
+            Position::from_fen(&fen)
+                .map(|start| Opening {
+                    start,
+                    moves: Vec::new(),
+                })
+                .map_err(|err| format!("Invalid EPD line '{}': {}", line, err))
+        })
+        .collect()
+}
+
+fn read_pgn(text: &str, plies: Option<usize>) -> Vec<Opening> {
+    parse_pgn(text)
+        .into_iter()
+        .filter_map(|game| match game {
+            Ok(game) => Some(game),
+            Err(err) => {
+                eprintln!("Skipping opening: {}", err);
+                None
+            }
+        })
+        .map(|mut game| {
+            game.moves.truncate(plies.unwrap_or(usize::MAX));
+            Opening {
+                start: game.start,
+                moves: game.moves,
+            }
+        })
+        .collect()
+}
+
+fn load_openings(path: &str, plies: Option<usize>) -> Result<Vec<Opening>, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    let openings = if path.to_ascii_lowercase().ends_with(".pgn") {
+        read_pgn(&text, plies)
+    } else {
+        read_epd(&text)?
+    };
+    // Games that are already over make no opening
+    let openings: Vec<Opening> = openings
+        .into_iter()
+        .filter(|opening| !opening.position().legal_moves().is_empty())
+        .collect();
+    if openings.is_empty() {
+        return Err(format!("No openings in {}", path));
+    }
+    Ok(openings)
+}
+
+struct Options {
+    a: Player,
+    b: Player,
+    games: usize,
+    time_control: TimeControl,
+    openings: Option<String>,
+    plies: Option<usize>,
+    concurrency: usize,
+    pgn: String,
+    sprt: Option<(f64, f64)>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: match <engine a> <engine b> [--games n] [--tc initial+increment] \
+         [--openings file.epd|file.pgn] [--plies n] [--concurrency n] [--pgn file] \
+         [--sprt elo0,elo1]\n\
+         An engine is a level from 1 to {} or the name of an engine in CHESS_ENGINES.",
+        engine::LEVELS.len()
+    );
+    std::process::exit(2);
+}
+
+fn player(arg: &str, profiles: &[EngineProfile]) -> Player {
+    if let Some(level) = arg.parse().ok().and_then(engine::level) {
+        return Player::Level(level);
+    }
+    match profiles.iter().find(|profile| profile.name == arg) {
+        Some(profile) => Player::External(profile.clone()),
+        None => {
+            eprintln!("Unknown engine '{}'", arg);
+            usage();
+        }
+    }
+}
+
+fn parse_options() -> Options {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        usage();
+    }
+    let profiles = match std::env::var("CHESS_ENGINES") {
+        Ok(path) => load_profiles(&path).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path, err);
+            std::process::exit(1);
+        }),
+        Err(_) => Vec::new(),
+    };
+    let mut options = Options {
+        a: player(&args[0], &profiles),
+        b: player(&args[1], &profiles),
+        games: 100,
+        time_control: TimeControl {
+            initial: 10,
+            increment: 0,
+        },
+        openings: None,
+        plies: None,
+        concurrency: 1,
+        pgn: "match.pgn".to_string(),
+        sprt: None,
+    };
+    let mut rest = args[2..].iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next().unwrap_or_else(|| usage());
+        let number = || -> usize { value.parse().unwrap_or_else(|_| usage()) };
+        match flag.as_str() {
+            "--games" => options.games = number(),
+            "--tc" => options.time_control = value.parse().unwrap_or_else(|_| usage()),
+            "--openings" => options.openings = Some(value.clone()),
+            "--plies" => options.plies = Some(number()),
+            "--concurrency" => options.concurrency = number().max(1),
+            "--pgn" => options.pgn = value.clone(),
+            "--sprt" => {
+                let bounds = value
+                    .split_once(',')
+                    .and_then(|(elo0, elo1)| Some((elo0.parse().ok()?, elo1.parse().ok()?)));
+                options.sprt = Some(bounds.unwrap_or_else(|| usage()));
+            }
+            _ => usage(),
+        }
+    }
+    options
+}
+
+fn main() {
+    let options = parse_options();
+    let openings = match &options.openings {
+        Some(path) => load_openings(path, options.plies).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }),
+        None => (0..options.games.div_ceil(2))
+            .map(|_| random_opening())
+            .collect(),
+    };
+    let tablebases = std::env::var("CHESS_SYZYGY_PATH").ok().map(|path| {
+        let tablebases = Tablebases::open(&path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        });
+        println!("Using {} tablebases", tablebases.len());
+        Arc::new(tablebases)
+    });
+    let tablebases = tablebases.as_ref();
+    let pgn = File::create(&options.pgn).unwrap_or_else(|err| {
+        eprintln!("{}: {}", options.pgn, err);
+        std::process::exit(1);
+    });
+
+    let (a_name, b_name) = (options.a.name(), options.b.name());
+    println!(
+        "{} against {}, {} games at {} from {} openings",
+        a_name,
+        b_name,
+        options.games,
+        options.time_control,
+        openings.len()
+    );
+    let next = AtomicUsize::new(0);
+    let decided = AtomicBool::new(false);
+    let results = Mutex::new((Tally::default(), pgn));
+    std::thread::scope(|scope| {
+        for _ in 0..options.concurrency {
+            scope.spawn(|| {
+                let mut a = Side::new(&options.a, tablebases);
+                let mut b = Side::new(&options.b, tablebases);
+                loop {
+                    let round = next.fetch_add(1, Ordering::Relaxed);
+                    if round >= options.games || decided.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    // Each opening twice, engine a white the first time
+                    let opening = &openings[(round / 2) % openings.len()];
+                    let a_white = round.is_multiple_of(2);
+                    let sides = if a_white {
+                        [&mut a, &mut b]
+                    } else {
+                        [&mut b, &mut a]
+                    };
+                    let time_limit = TimeLimit::Clock(options.time_control);
+                    let game = play_game(sides, opening, time_limit, tablebases);
+                    let (white, black) = if a_white {
+                        (&a_name, &b_name)
+                    } else {
+                        (&b_name, &a_name)
+                    };
+
+                    let mut headers = vec![
+                        ("Event".to_string(), "Engine match".to_string()),
+                        ("Site".to_string(), "?".to_string()),
+                        ("Date".to_string(), "????.??.??".to_string()),
+                        ("Round".to_string(), (round + 1).to_string()),
+                        ("White".to_string(), white.clone()),
+                        ("Black".to_string(), black.clone()),
+                        ("Result".to_string(), result_str(game.score).to_string()),
+                        ("TimeControl".to_string(), options.time_control.to_string()),
+                        ("Termination".to_string(), game.termination.to_string()),
+                    ];
+                    if opening.start.to_fen() != Position::startpos().to_fen() {
+                        headers.push(("SetUp".to_string(), "1".to_string()));
+                        headers.push(("FEN".to_string(), opening.start.to_fen()));
+                    }
+                    let text = to_pgn(
+                        &headers,
+                        &opening.start,
+                        &game.moves,
+                        &[],
+                        result_str(game.score),
+                    );
+
+                    let mut results = results.lock().unwrap();
+                    let (tally, pgn) = &mut *results;
+                    let score = if a_white {
+                        game.score
+                    } else {
+                        1.0 - game.score
+                    };
+                    tally.add(score);
+                    if let Err(err) = writeln!(pgn, "{}", text) {
+                        eprintln!("{}: {}", options.pgn, err);
+                    }
+                    let (elo, margin) = tally.elo();
+                    let mut line = format!(
+                        "{:>5} games: +{} ={} -{}  {:+.0} ± {:.0} Elo",
+                        tally.games(),
+                        tally.wins,
+                        tally.draws,
+                        tally.losses,
+                        elo,
+                        margin
+                    );
+                    if let Some((elo0, elo1)) = options.sprt {
+                        let llr = tally.llr(elo0, elo1);
+                        let (lower, upper) = sprt_bounds();
+                        line.push_str(&format!("  LLR {:.2} ({:.2}, {:.2})", llr, lower, upper));
+                        if sprt_verdict(llr).is_some() {
+                            decided.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    println!("{}", line);
+                }
+            });
+        }
+    });
+
+    let (tally, _) = results.into_inner().unwrap();
+    let (elo, margin) = tally.elo();
+    println!(
+        "{} scored {:.1}% against {} in {} games: {:+.0} ± {:.0} Elo",
+        a_name,
+        tally.score() * 100.0,
+        b_name,
+        tally.games(),
+        elo,
+        margin
+    );
+    if let Some((elo0, elo1)) = options.sprt {
+        let llr = tally.llr(elo0, elo1);
+        println!(
+            "SPRT elo0 {} elo1 {}: LLR {:.2}, {}",
+            elo0,
+            elo1,
+            llr,
+            sprt_verdict(llr).unwrap_or("inconclusive")
+        );
+    }
+    println!("Games written to {}", options.pgn);
+}
@@ -0,0 +1,103 @@
+// src/bin/selfplay.rs
+
+// Plays two bot levels against each other and estimates the Elo gap between
+// them. Every opening (a few random moves) is played twice with colors
+// swapped, and games run in parallel on all cores. With Syzygy tablebases at
+// CHESS_SYZYGY_PATH both sides use them, and a game that reaches them is
+// decided by what they say.
+//
+//     cargo run --release --bin selfplay -- <level a> <level b> [pairs] [movetime ms]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chess_server::engine::{self, Level, Tablebases};
+use chess_server::engine_match::{play_game, random_opening, Player, Side, Tally, TimeLimit};
+
+fn usage() -> ! {
+    eprintln!("usage: selfplay <level a> <level b> [pairs] [movetime ms]");
+    std::process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        usage();
+    }
+    let level = |arg: &String| -> &'static Level {
+        arg.parse()
+            .ok()
+            .and_then(engine::level)
+            .unwrap_or_else(|| usage())
+    };
+    let (a, b) = (level(&args[0]), level(&args[1]));
+    let pairs: usize = args
+        .get(2)
+        .map_or(Some(50), |arg| arg.parse().ok())
+        .unwrap_or_else(|| usage());
+    let movetime = args
+        .get(3)
+        .map_or(Some(100), |arg| arg.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or_else(|| usage());
+
+    println!(
+        "Level {} ({}) against level {} ({}), {} games",
+        a.level,
+        a.rating,
+        b.level,
+        b.rating,
+        pairs * 2
+    );
+    let tablebases = std::env::var("CHESS_SYZYGY_PATH").ok().map(|path| {
+        let tablebases = Tablebases::open(&path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        });
+        println!("Adjudicating with {} tablebases", tablebases.len());
+        Arc::new(tablebases)
+    });
+    let tablebases = tablebases.as_ref();
+    let next = AtomicUsize::new(0);
+    let tally = Mutex::new(Tally::default());
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let time_limit = TimeLimit::PerMove(movetime);
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                let mut a_side = Side::new(&Player::Level(a), tablebases);
+                let mut b_side = Side::new(&Player::Level(b), tablebases);
+                while next.fetch_add(1, Ordering::Relaxed) < pairs {
+                    let opening = random_opening();
+                    let sides = [&mut a_side, &mut b_side];
+                    let first = play_game(sides, &opening, time_limit, tablebases).score;
+                    let sides = [&mut b_side, &mut a_side];
+                    let second = 1.0 - play_game(sides, &opening, time_limit, tablebases).score;
+                    let mut tally = tally.lock().unwrap();
+                    tally.add(first);
+                    tally.add(second);
+                    println!(
+                        "{:>4} games: +{} ={} -{}",
+                        tally.games(),
+                        tally.wins,
+                        tally.draws,
+                        tally.losses
+                    );
+                }
+            });
+        }
+    });
+
+    let tally = tally.into_inner().unwrap();
+    let (elo, margin) = tally.elo();
+    println!(
+        "Level {} scored {:.1}% against level {}: {:+.0} ± {:.0} Elo (expected {:+})",
+        a.level,
+        tally.score() * 100.0,
+        b.level,
+        elo,
+        margin,
+        a.rating as i64 - b.rating as i64
+    );
+}
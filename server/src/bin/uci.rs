@@ -0,0 +1,253 @@
+// src/bin/uci.rs
+
+// The built-in engine behind the Universal Chess Interface, for chess GUIs
+// and engine testing tools. Searches run on a thread of their own so `stop`
+// and `isready` are answered while the engine thinks.
+//
+//     cargo run --release --bin uci
+
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use chess_server::engine::{self, Engine, Limits, SearchInfo, Tablebases, MAX_THREADS};
+use chess_server::position::{Color, Position};
+
+const NAME: &str = "chess_server";
+const DEFAULT_HASH_MB: usize = 16;
+const MAX_HASH_MB: usize = 4096;
+const MAX_MULTIPV: usize = 64;
+// Moves left to plan for when the GUI doesn't say
+const MOVES_TO_GO: u32 = 30;
+// Kept back from the clock for the GUI to get the move
+const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+
+struct Uci {
+    engine: Option<Engine>, // None while it's searching
+    search: Option<JoinHandle<Engine>>,
+    stop: Arc<AtomicBool>,
+    position: Position,
+    history: Vec<u64>, // hashes of the positions before `position`
+}
+
+impl Uci {
+    fn new() -> Self {
+        let engine = Engine::new(DEFAULT_HASH_MB);
+        Uci {
+            stop: engine.stop_flag(),
+            engine: Some(engine),
+            search: None,
+            position: Position::startpos(),
+            history: Vec::new(),
+        }
+    }
+
+    // Waits for the search in progress, if any, to hand the engine back
+    fn engine(&mut self) -> &mut Engine {
+        if let Some(search) = self.search.take() {
+            self.engine = Some(search.join().expect("search thread panicked"));
+        }
+        self.engine
+            .get_or_insert_with(|| Engine::new(DEFAULT_HASH_MB))
+    }
+
+    fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.engine();
+    }
+
+    // Returns false on `quit`
+    fn handle(&mut self, line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first().copied() {
+            Some("uci") => {
+                println!("id name {}", NAME);
+                println!("id author The {} developers", NAME);
+                println!(
+                    "option name Hash type spin default {} min 1 max {}",
+                    DEFAULT_HASH_MB, MAX_HASH_MB
+                );
+                println!(
+                    "option name Threads type spin default 1 min 1 max {}",
+                    MAX_THREADS
+                );
+                println!(
+                    "option name MultiPV type spin default 1 min 1 max {}",
+                    MAX_MULTIPV
+                );
+                println!("option name SyzygyPath type string default <empty>");
+                println!("uciok");
+            }
+            Some("isready") => println!("readyok"),
+            Some("ucinewgame") => self.engine().clear(),
+            Some("setoption") => self.set_option(&words[1..]),
+            Some("position") => {
+                if let Err(err) = self.set_position(&words[1..]) {
+                    println!("info string {}", err);
+                }
+            }
+            Some("go") => self.go(&words[1..]),
+            Some("stop") => self.stop(),
+            Some("quit") => {
+                self.stop();
+                return false;
+            }
+            Some(other) => println!("info string Unknown command {}", other),
+            None => {}
+        }
+        true
+    }
+
+    // setoption name <name> value <value>
+    fn set_option(&mut self, words: &[&str]) {
+        let name: Vec<&str> = words
+            .iter()
+            .skip_while(|w| **w != "name")
+            .skip(1)
+            .take_while(|w| **w != "value")
+            .copied()
+            .collect();
+        let name = name.join(" ");
+        let text: Vec<&str> = words
+            .iter()
+            .skip_while(|w| **w != "value")
+            .skip(1)
+            .copied()
+            .collect();
+        let text = text.join(" ");
+        let value = text.parse::<usize>().ok();
+        match (name.to_ascii_lowercase().as_str(), value) {
+            ("syzygypath", _) => self.set_tablebases(&text),
+            ("hash", Some(mb)) => self.engine().set_hash(mb.clamp(1, MAX_HASH_MB)),
+            ("threads", Some(threads)) => self.engine().set_threads(threads),
+            ("multipv", Some(lines)) => self.engine().set_multipv(lines.min(MAX_MULTIPV)),
+            _ => println!("info string Unknown option or value: {}", words.join(" ")),
+        }
+    }
+
+    fn set_tablebases(&mut self, path: &str) {
+        if path.is_empty() || path == "<empty>" {
+            return self.engine().set_tablebases(None);
+        }
+        match Tablebases::open(path) {
+            Ok(tablebases) => {
+                println!(
+                    "info string Found {} tablebases of up to {} pieces",
+                    tablebases.len(),
+                    tablebases.max_pieces()
+                );
+                self.engine().set_tablebases(Some(Arc::new(tablebases)));
+            }
+            Err(err) => println!("info string {}", err),
+        }
+    }
+
+    // position [startpos | fen <fen>] [moves <move>...]
+    fn set_position(&mut self, words: &[&str]) -> Result<(), String> {
+        let moves_at = words
+            .iter()
+            .position(|w| *w == "moves")
+            .unwrap_or(words.len());
+        let mut position = match words.first().copied() {
+            Some("startpos") => Position::startpos(),
+            Some("fen") => Position::from_fen(&words[1..moves_at].join(" "))?,
+            _ => return Err("Expected startpos or fen".to_string()),
+        };
+        let mut history = Vec::new();
+        for uci in words.iter().skip(moves_at + 1) {
+            let mv = position
+                .find_uci_move(uci)
+                .ok_or_else(|| format!("Illegal move {}", uci))?;
+            history.push(engine::hash(&position));
+            position = position.make_move(&mv);
+        }
+        self.position = position;
+        self.history = history;
+        Ok(())
+    }
+
+    fn go(&mut self, words: &[&str]) {
+        if self.search.is_some() {
+            self.stop();
+        }
+        let mut engine = self
+            .engine
+            .take()
+            .unwrap_or_else(|| Engine::new(DEFAULT_HASH_MB));
+
+        let number = |name: &str| -> Option<u64> {
+            let i = words.iter().position(|w| *w == name)?;
+            words.get(i + 1)?.parse().ok()
+        };
+        let infinite = words.contains(&"infinite");
+        let (time, increment) = match self.position.side_to_move {
+            Color::White => (number("wtime"), number("winc")),
+            Color::Black => (number("btime"), number("binc")),
+        };
+        let movetime = number("movetime")
+            .map(|ms| Duration::from_millis(ms).saturating_sub(MOVE_OVERHEAD))
+            .or(time.map(|ms| {
+                engine::allot_time(
+                    Duration::from_millis(ms).saturating_sub(MOVE_OVERHEAD),
+                    Duration::from_millis(increment.unwrap_or(0)),
+                    number("movestogo").map_or(MOVES_TO_GO, |n| n as u32),
+                )
+            }));
+        let limits = Limits {
+            depth: number("depth").map(|depth| depth as i32),
+            movetime: movetime.filter(|_| !infinite),
+            nodes: number("nodes"),
+        };
+
+        self.stop.store(false, Ordering::Relaxed);
+        let stop = self.stop.clone();
+        let position = self.position.clone();
+        let history = self.history.clone();
+        self.search = Some(std::thread::spawn(move || {
+            let result = engine.search_with_info(&position, &history, limits, print_info);
+            // An infinite search only answers once it's told to stop
+            while infinite && !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            match result.best_move {
+                Some(mv) => println!("bestmove {}", mv.to_uci()),
+                None => println!("bestmove 0000"),
+            }
+            engine
+        }));
+    }
+}
+
+fn print_info(info: &SearchInfo) {
+    let score = match engine::mate_in(info.score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", info.score),
+    };
+    let ms = info.elapsed.as_millis() as u64;
+    let pv: Vec<String> = info.pv.iter().map(|mv| mv.to_uci()).collect();
+    println!(
+        "info depth {} multipv {} score {} nodes {} nps {} time {} pv {}",
+        info.depth,
+        info.multipv,
+        score,
+        info.nodes,
+        info.nodes * 1000 / ms.max(1),
+        ms,
+        pv.join(" ")
+    );
+}
+
+fn main() {
+    let mut uci = Uci::new();
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if !uci.handle(&line) {
+            return;
+        }
+    }
+    uci.stop();
+}
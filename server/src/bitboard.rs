@@ -26,6 +26,8 @@ pub struct PieceInfo {
 pub struct MoveData {
     pub from: usize,
     pub to: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub promotion: Option<String>, // "q", "r", "b" or "n"
 }
 
 pub fn init_bitboard() -> Bitboard {
@@ -61,88 +63,6 @@ pub fn init_bitboard() -> Bitboard {
     bitboard
 }
 
-pub fn apply_move(bitboard: &mut Bitboard, move_data: &MoveData) -> bool {
-    let from_bb = 1u64 << move_data.from;
-    let to_bb = 1u64 << move_data.to;
-
-    let piece_masks = [
-        ("pawns", bitboard.pawns),
-        ("knights", bitboard.knights),
-        ("bishops", bitboard.bishops),
-        ("rooks", bitboard.rooks),
-        ("queens", bitboard.queens),
-        ("kings", bitboard.kings),
-    ];
-
-    let mut piece_type_moving = None;
-    for (piece_type, mask) in &piece_masks {
-        if mask & from_bb != 0 {
-            piece_type_moving = Some(*piece_type);
-            break;
-        }
-    }
-
-    if let Some(piece_type) = piece_type_moving {
-        match piece_type {
-            "pawns" => bitboard.pawns &= !from_bb,
-            "knights" => bitboard.knights &= !from_bb,
-            "bishops" => bitboard.bishops &= !from_bb,
-            "rooks" => bitboard.rooks &= !from_bb,
-            "queens" => bitboard.queens &= !from_bb,
-            "kings" => bitboard.kings &= !from_bb,
-            _ => (),
-        }
-
-        let all_pieces_masks = [
-            &mut bitboard.pawns,
-            &mut bitboard.knights,
-            &mut bitboard.bishops,
-            &mut bitboard.rooks,
-            &mut bitboard.queens,
-            &mut bitboard.kings,
-        ];
-
-        for mask in all_pieces_masks {
-            if *mask & to_bb != 0 {
-                *mask &= !to_bb;
-            }
-        }
-
-        match piece_type {
-            "pawns" => bitboard.pawns |= to_bb,
-            "knights" => bitboard.knights |= to_bb,
-            "bishops" => bitboard.bishops |= to_bb,
-            "rooks" => bitboard.rooks |= to_bb,
-            "queens" => bitboard.queens |= to_bb,
-            "kings" => bitboard.kings |= to_bb,
-            _ => (),
-        }
-
-        update_occupancy(bitboard);
-
-        true
-    } else {
-        false
-    }
-}
-
-fn update_occupancy(bitboard: &mut Bitboard) {
-    bitboard.white_pieces = (bitboard.pawns
-        | bitboard.knights
-        | bitboard.bishops
-        | bitboard.rooks
-        | bitboard.queens
-        | bitboard.kings)
-        & 0x000000000000FFFF;
-    bitboard.black_pieces = (bitboard.pawns
-        | bitboard.knights
-        | bitboard.bishops
-        | bitboard.rooks
-        | bitboard.queens
-        | bitboard.kings)
-        & 0xFFFF000000000000;
-}
-
 pub fn bitboard_to_pieces(bitboard: &Bitboard) -> Vec<serde_json::Value> {
     let mut pieces = Vec::new();
 
@@ -173,11 +93,12 @@ pub fn bitboard_to_pieces(bitboard: &Bitboard) -> Vec<serde_json::Value> {
                 continue;
             };
 
-            pieces.push(json!({
-                "piece_type": pt,
-                "color": color,
-                "position": position,
-            }));
+            let info = PieceInfo {
+                piece_type: pt.to_string(),
+                color: color.to_string(),
+                position,
+            };
+            pieces.push(json!(info));
         }
     }
 
@@ -0,0 +1,340 @@
+// src/bot.rs
+
+// The built-in computer opponent. A room against the bot has the bot's user
+// in one seat. Whenever it's the bot's turn the engine searches on a thread
+// of a small pool and the move comes back as a `BotMoved`, which the server
+// plays as a `ClientMove` from the bot's session so it goes through the
+// same checks as any player's move.
+//
+// Besides its own levels the bot can be any UCI engine listed in the JSON
+// file named by CHESS_ENGINES, e.g.
+//
+//     [{ "name": "stockfish", "path": "/usr/bin/stockfish", "options": { "Hash": "64" } }]
+//
+// With a Polyglot book at CHESS_BOOK the levels play their first moves from
+// it.
+//
+// Syzygy tablebases in the directories at CHESS_SYZYGY_PATH, separated like
+// PATH, make the levels play endings in them perfectly.
+
+use actix::Addr;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::bitboard::MoveData;
+use crate::engine::{
+    self, Book, Engine, EngineProfile, ExternalEngine, Level, Limits, Tablebases, LEVELS,
+};
+use crate::game_room::GameRoom;
+use crate::messages::BotMoved;
+use crate::position::{Move, Position};
+use crate::server::Server;
+
+pub const BOT_USERNAME: &str = "Bot";
+pub const MIN_LEVEL: u8 = 1;
+pub const MAX_LEVEL: u8 = LEVELS.len() as u8;
+pub const DEFAULT_LEVEL: u8 = 4;
+
+const HASH_MB: usize = 16;
+// Untimed games, and the most the bot ever spends on a move
+const MAX_THINK_TIME: Duration = Duration::from_secs(3);
+// Moves left to plan for when splitting the remaining time
+const MOVES_TO_GO: u32 = 30;
+
+pub struct BotGame {
+    pub level: &'static Level,
+    engine: Option<String>, // name of the engine profile
+    // Plays instead of the built-in engine when set, which takes over at
+    // `level` should the external one fail
+    external: Option<Arc<Mutex<ExternalEngine>>>,
+    thinking_at: Option<usize>, // ply of the search in progress
+}
+
+// Loaded once, the first time they're asked for
+pub fn engine_profiles() -> &'static [EngineProfile] {
+    static PROFILES: OnceLock<Vec<EngineProfile>> = OnceLock::new();
+    PROFILES.get_or_init(|| {
+        let Ok(path) = std::env::var("CHESS_ENGINES") else {
+            return Vec::new();
+        };
+        match engine::load_profiles(&path) {
+            Ok(profiles) => {
+                println!("Loaded {} engine profiles from {}", profiles.len(), path);
+                profiles
+            }
+            Err(err) => {
+                println!("Failed to load engine profiles from {}: {}", path, err);
+                Vec::new()
+            }
+        }
+    })
+}
+
+pub fn engine_profile(name: &str) -> Option<&'static EngineProfile> {
+    engine_profiles()
+        .iter()
+        .find(|profile| profile.name == name)
+}
+
+// Loaded once, the first time it's asked for
+pub fn opening_book() -> Option<&'static Book> {
+    static BOOK: OnceLock<Option<Book>> = OnceLock::new();
+    BOOK.get_or_init(|| {
+        let path = std::env::var("CHESS_BOOK").ok()?;
+        match Book::open(&path) {
+            Ok(book) => {
+                println!("Loaded opening book {} with {} entries", path, book.len());
+                Some(book)
+            }
+            Err(err) => {
+                println!("Failed to load the opening book {}: {}", path, err);
+                None
+            }
+        }
+    })
+    .as_ref()
+}
+
+// Shared by every engine the server runs
+pub fn tablebases() -> Option<Arc<Tablebases>> {
+    static TABLEBASES: OnceLock<Option<Arc<Tablebases>>> = OnceLock::new();
+    TABLEBASES
+        .get_or_init(|| {
+            let path = std::env::var("CHESS_SYZYGY_PATH").ok()?;
+            match Tablebases::open(&path) {
+                Ok(tablebases) => {
+                    println!(
+                        "Loaded {} tablebases of up to {} pieces from {}",
+                        tablebases.len(),
+                        tablebases.max_pieces(),
+                        path
+                    );
+                    Some(Arc::new(tablebases))
+                }
+                Err(err) => {
+                    println!("Failed to load the tablebases: {}", err);
+                    None
+                }
+            }
+        })
+        .clone()
+}
+
+pub struct Bot {
+    pub user_id: usize,
+    pub session_id: usize, // never connected, only used to send moves
+    pub games: HashMap<Uuid, BotGame>,
+    // Level and engine of games that are over, until their room is evicted,
+    // so a rematch is played the same way
+    finished: HashMap<Uuid, (u8, Option<String>)>,
+    jobs: Sender<Job>,
+}
+
+impl Bot {
+    // Searches on up to `workers` threads at once
+    pub fn new(user_id: usize, session_id: usize, workers: usize) -> Self {
+        Bot {
+            user_id,
+            session_id,
+            games: HashMap::new(),
+            finished: HashMap::new(),
+            jobs: start_workers(workers),
+        }
+    }
+
+    // `engine` names one of the engine profiles
+    pub fn add_game(&mut self, room_id: Uuid, level: u8, engine: Option<&str>) {
+        let Some(level) = engine::level(level).or(engine::level(DEFAULT_LEVEL)) else {
+            return;
+        };
+        let external = engine
+            .and_then(engine_profile)
+            .map(|profile| Arc::new(Mutex::new(ExternalEngine::new(profile.clone()))));
+        self.games.insert(
+            room_id,
+            BotGame {
+                level,
+                engine: engine.map(str::to_string),
+                external,
+                thinking_at: None,
+            },
+        );
+    }
+
+    pub fn finish_game(&mut self, room_id: Uuid) {
+        if let Some(game) = self.games.remove(&room_id) {
+            self.finished
+                .insert(room_id, (game.level.level, game.engine));
+        }
+    }
+
+    // Plays the rematch of a finished game at the same level. Returns false
+    // if the bot doesn't know the game.
+    pub fn add_rematch(&mut self, room_id: Uuid, new_room_id: Uuid) -> bool {
+        let Some((level, engine)) = self.finished.remove(&room_id) else {
+            return false;
+        };
+        self.add_game(new_room_id, level, engine.as_deref());
+        true
+    }
+
+    pub fn forget_evicted(&mut self, rooms: &HashMap<Uuid, GameRoom>) {
+        self.finished
+            .retain(|room_id, _| rooms.contains_key(room_id));
+    }
+
+    // Starts a search in every game where the bot is to move and isn't
+    // already thinking
+    pub fn play(&mut self, rooms: &HashMap<Uuid, GameRoom>, server: &Addr<Server>) {
+        for (room_id, game) in self.games.iter_mut() {
+            let Some(room) = rooms.get(room_id) else {
+                continue;
+            };
+            if room.is_finished()
+                || !room.is_full()
+                || room.seat(room.turn()) != Some(self.user_id)
+                || game.thinking_at == Some(room.moves.len())
+            {
+                continue;
+            }
+            game.thinking_at = Some(room.moves.len());
+            let _ = self.jobs.send(Job {
+                room_id: *room_id,
+                level: game.level,
+                external: game.external.clone(),
+                moves: room.moves.clone(),
+                movetime: movetime(room),
+                server: server.clone(),
+            });
+        }
+    }
+
+    // The search from `ply` on is over, whether or not it found a move
+    pub fn search_done(&mut self, room_id: Uuid, ply: usize) {
+        if let Some(game) = self.games.get_mut(&room_id) {
+            if game.thinking_at == Some(ply) {
+                game.thinking_at = None;
+            }
+        }
+    }
+}
+
+// A share of what's left on the clock, the lower levels mostly run out of
+// nodes well before that
+fn movetime(room: &GameRoom) -> Duration {
+    match room.clock.as_ref() {
+        Some(clock) => {
+            let remaining = clock.remaining(room.turn());
+            let increment = Duration::from_secs(clock.time_control.increment);
+            engine::allot_time(remaining, increment, MOVES_TO_GO).min(MAX_THINK_TIME)
+        }
+        None => MAX_THINK_TIME,
+    }
+}
+
+struct Job {
+    room_id: Uuid,
+    level: &'static Level,
+    external: Option<Arc<Mutex<ExternalEngine>>>,
+    moves: Vec<MoveData>,
+    movetime: Duration,
+    server: Addr<Server>,
+}
+
+fn new_engine() -> Engine {
+    let mut engine = Engine::new(HASH_MB);
+    engine.set_tablebases(tablebases());
+    engine
+}
+
+// Runs the searches of every bot game on a fixed number of threads, each
+// keeping its engine from move to move. The server hears back after every
+// search, even one that failed, so no game is left waiting on the bot.
+fn start_workers(workers: usize) -> Sender<Job> {
+    let (jobs, queue) = mpsc::channel::<Job>();
+    let queue = Arc::new(Mutex::new(queue));
+    for _ in 0..workers.max(1) {
+        let queue = queue.clone();
+        std::thread::spawn(move || {
+            let mut engine = new_engine();
+            loop {
+                let job = queue.lock().unwrap().recv();
+                let Ok(job) = job else {
+                    return;
+                };
+                let (room_id, ply, server) = (job.room_id, job.moves.len(), job.server.clone());
+                let mv = panic::catch_unwind(AssertUnwindSafe(|| search(&mut engine, job)))
+                    .unwrap_or_else(|_| {
+                        println!("Bot search in room {} failed", room_id);
+                        engine = new_engine();
+                        None
+                    });
+                server.do_send(BotMoved {
+                    room_id,
+                    ply,
+                    move_data: mv.map(Into::into),
+                });
+            }
+        });
+    }
+    jobs
+}
+
+fn search(engine: &mut Engine, job: Job) -> Option<Move> {
+    let Job {
+        room_id,
+        level,
+        external,
+        moves: move_data,
+        movetime,
+        ..
+    } = job;
+    // Rooms always start from the initial position
+    let mut position = Position::startpos();
+    let mut history = Vec::with_capacity(move_data.len());
+    let mut moves = Vec::with_capacity(move_data.len());
+    for move_data in &move_data {
+        history.push(engine::hash(&position));
+        let mv = position.find_move(move_data)?;
+        position = position.make_move(&mv);
+        moves.push(mv);
+    }
+
+    let limits = Limits {
+        movetime: Some(movetime),
+        ..Limits::default()
+    };
+    let played = external.and_then(|external| {
+        let mut external = external.lock().unwrap();
+        match external.search(&Position::startpos(), &moves, limits, |_| {}) {
+            Ok(result) => Some((result.best_move, external.name().to_string())),
+            Err(err) => {
+                println!(
+                    "Engine {} failed in room {}: {}, level {} takes over",
+                    external.name(),
+                    room_id,
+                    err,
+                    level.level
+                );
+                None
+            }
+        }
+    });
+    let (mv, player) = played.unwrap_or_else(|| {
+        let book_move = opening_book()
+            .filter(|_| moves.len() < level.book_plies)
+            .and_then(|book| book.choose(&position, &mut rand::thread_rng()));
+        if book_move.is_some() {
+            return (book_move, format!("level {} (book)", level.level));
+        }
+        let mv = level.choose_move(engine, &position, &history, Some(movetime));
+        (mv, format!("level {}", level.level))
+    });
+    let mv = mv?;
+    println!("Bot {} plays {} in room {}", player, mv.to_uci(), room_id);
+    Some(mv)
+}
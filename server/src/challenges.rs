@@ -0,0 +1,31 @@
+// src/challenges.rs
+
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::clock::TimeControl;
+
+// Unanswered challenges are withdrawn after this long
+pub const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct Challenge {
+    pub id: Uuid,
+    pub challenger: usize, // user ids
+    pub challenger_session: usize,
+    pub target: usize,
+    pub time_control: Option<TimeControl>,
+    pub color: String, // the challenger's choice: "white", "black" or "random"
+    pub variant: String,
+    pub rated: bool,
+    pub created_at: Instant,
+}
+
+impl Challenge {
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now.duration_since(self.created_at) >= CHALLENGE_TIMEOUT
+    }
+
+    pub fn expires_in(&self) -> Duration {
+        CHALLENGE_TIMEOUT.saturating_sub(self.created_at.elapsed())
+    }
+}
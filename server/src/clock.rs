@@ -0,0 +1,167 @@
+// src/clock.rs
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TimeControl {
+    pub initial: u64,   // seconds
+    pub increment: u64, // seconds
+}
+
+// Longer games than this aren't played live
+pub const MAX_INITIAL: u64 = 3 * 60 * 60; // seconds
+pub const MAX_INCREMENT: u64 = 3 * 60; // seconds
+
+impl TimeControl {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.initial == 0 && self.increment == 0 {
+            return Err("A time control needs some time on the clock".to_string());
+        }
+        if self.initial > MAX_INITIAL || self.increment > MAX_INCREMENT {
+            return Err(format!(
+                "Time controls go up to {}+{}",
+                MAX_INITIAL, MAX_INCREMENT
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Written as "initial+increment" in seconds, the PGN TimeControl format
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{}", self.initial, self.increment)
+    }
+}
+
+impl FromStr for TimeControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (initial, increment) = s.split_once('+').unwrap_or((s, "0"));
+        let time_control = TimeControl {
+            initial: initial
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid time control '{}'", s))?,
+            increment: increment
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid time control '{}'", s))?,
+        };
+        time_control.validate()?;
+        Ok(time_control)
+    }
+}
+
+pub struct Clock {
+    pub time_control: TimeControl,
+    pub white_remaining: Duration,
+    pub black_remaining: Duration,
+    pub running: Option<(String, Instant)>, // (color, started at)
+}
+
+impl Clock {
+    pub fn new(time_control: TimeControl) -> Self {
+        let initial = Duration::from_secs(time_control.initial);
+        Clock {
+            time_control,
+            white_remaining: initial,
+            black_remaining: initial,
+            running: None,
+        }
+    }
+
+    pub fn start(&mut self, color: &str) {
+        self.running = Some((color.to_string(), Instant::now()));
+    }
+
+    pub fn stop(&mut self) {
+        if let Some((color, started)) = self.running.take() {
+            let elapsed = started.elapsed();
+            let remaining = self.remaining_mut(&color);
+            *remaining = remaining.saturating_sub(elapsed);
+        }
+    }
+
+    pub fn remaining(&self, color: &str) -> Duration {
+        let stored = if color == "w" {
+            self.white_remaining
+        } else {
+            self.black_remaining
+        };
+
+        match &self.running {
+            Some((running_color, started)) if running_color == color => {
+                stored.saturating_sub(started.elapsed())
+            }
+            _ => stored,
+        }
+    }
+
+    pub fn is_flagged(&self, color: &str) -> bool {
+        self.remaining(color).is_zero()
+    }
+
+    // Stops the mover's clock, adds the increment and starts the opponent's.
+    // Returns false if the mover had already run out of time.
+    pub fn press(&mut self, color: &str) -> bool {
+        self.stop();
+
+        if self.remaining(color).is_zero() {
+            return false;
+        }
+
+        let increment = Duration::from_secs(self.time_control.increment);
+        let remaining = self.remaining_mut(color);
+        *remaining = remaining.saturating_add(increment);
+
+        let opponent = if color == "w" { "b" } else { "w" };
+        self.start(opponent);
+        true
+    }
+
+    fn remaining_mut(&mut self, color: &str) -> &mut Duration {
+        if color == "w" {
+            &mut self.white_remaining
+        } else {
+            &mut self.black_remaining
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_only_sane_time_controls() {
+        assert_eq!(
+            "300+5".parse(),
+            Ok(TimeControl {
+                initial: 300,
+                increment: 5
+            })
+        );
+        assert!("0+0".parse::<TimeControl>().is_err());
+        assert!("10801+0".parse::<TimeControl>().is_err());
+        assert!("60+181".parse::<TimeControl>().is_err());
+        assert!("18446744073709551615+18446744073709551615"
+            .parse::<TimeControl>()
+            .is_err());
+    }
+
+    #[test]
+    fn increment_saturates_a_full_clock() {
+        let mut clock = Clock::new(TimeControl {
+            initial: 60,
+            increment: 2,
+        });
+        clock.white_remaining = Duration::MAX;
+        assert!(clock.press("w"));
+        assert_eq!(clock.white_remaining, Duration::MAX);
+    }
+}
@@ -0,0 +1,112 @@
+// src/eco.rs
+
+// Opening classification. The ECO table in eco.tsv, a line or more for every
+// code from A00 to E99, is replayed once into a map from position hash to
+// opening. A game is named after the last position it reached that the
+// table knows, however it got there, and the name is stored with its result.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::engine;
+use crate::notation::parse_san;
+use crate::position::Position;
+
+const TABLE: &str = include_str!("eco.tsv");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opening {
+    pub eco: &'static str, // "B90"
+    pub name: &'static str,
+}
+
+// The opening a line of the table names and the position its moves reach
+fn parse_line(line: &'static str) -> Result<(Opening, Position), String> {
+    let mut fields = line.split('\t');
+    let (Some(eco), Some(name), Some(moves)) = (fields.next(), fields.next(), fields.next()) else {
+        return Err("missing fields".to_string());
+    };
+    let mut position = Position::startpos();
+    for san in moves.split_whitespace() {
+        match parse_san(&position, san) {
+            Some(mv) => position = position.make_move(&mv),
+            None => return Err(format!("illegal move {}", san)),
+        }
+    }
+    Ok((Opening { eco, name }, position))
+}
+
+fn lines() -> impl Iterator<Item = &'static str> {
+    TABLE
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+fn openings() -> &'static HashMap<u64, Opening> {
+    static OPENINGS: OnceLock<HashMap<u64, Opening>> = OnceLock::new();
+    OPENINGS.get_or_init(|| {
+        let mut openings = HashMap::new();
+        for line in lines() {
+            match parse_line(line) {
+                Ok((opening, position)) => {
+                    openings.insert(engine::hash(&position), opening);
+                }
+                Err(err) => println!("Skipping ECO line '{}': {}", line, err),
+            }
+        }
+        openings
+    })
+}
+
+pub fn lookup(position: &Position) -> Option<Opening> {
+    openings().get(&engine::hash(position)).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    // What a room playing the moves ends up named
+    fn opening_after(moves: &str) -> Option<Opening> {
+        let mut position = Position::startpos();
+        let mut opening = lookup(&position);
+        for san in moves.split_whitespace() {
+            position = position.make_move(&parse_san(&position, san).unwrap());
+            opening = lookup(&position).or(opening);
+        }
+        opening
+    }
+
+    #[test]
+    fn every_line_of_the_table_is_legal() {
+        for line in lines() {
+            if let Err(err) = parse_line(line) {
+                panic!("{}: {}", line, err);
+            }
+        }
+    }
+
+    #[test]
+    fn covers_every_eco_code() {
+        let codes: HashSet<&str> = lines()
+            .filter_map(|line| parse_line(line).ok())
+            .map(|(opening, _)| opening.eco)
+            .collect();
+        for volume in ['A', 'B', 'C', 'D', 'E'] {
+            for number in 0..100 {
+                let code = format!("{}{:02}", volume, number);
+                assert!(codes.contains(code.as_str()), "no line for {}", code);
+            }
+        }
+    }
+
+    #[test]
+    fn names_a_game_after_the_last_known_position() {
+        let najdorf = "e4 c5 Nf3 d6 d4 cxd4 Nxd4 Nf6 Nc3 a6 Bg5 e6 f4 Be7 Qf3 Qc7 O-O-O Nbd7 g4 b5";
+        assert_eq!(opening_after(najdorf).unwrap().eco, "B99");
+        // Reached by transposition
+        assert_eq!(opening_after("c4 e6 d4 Nf6 g3 d5").unwrap().eco, "E01");
+        assert_eq!(opening_after(""), None);
+    }
+}
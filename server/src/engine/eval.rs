@@ -0,0 +1,154 @@
+// src/engine/eval.rs
+
+// Static evaluation: material plus piece-square tables, with separate king
+// tables for the middlegame and the endgame blended by the material left on
+// the board. Scores are in centipawns from the side to move's point of view.
+
+use crate::position::{Color, PieceKind, Position, Squares};
+
+// Pawn, knight, bishop, rook, queen, king
+pub const PIECE_VALUES: [i32; 6] = [100, 320, 330, 500, 900, 0];
+
+const BISHOP_PAIR: i32 = 30;
+
+// Game phase weights of the pieces, 24 with all of them on the board
+const PHASE_WEIGHTS: [i32; 6] = [0, 1, 1, 2, 4, 0];
+const MAX_PHASE: i32 = 24;
+
+// The tables are written from white's side, rank 8 first, so a white piece
+// on `sq` is looked up at `sq ^ 56` and a black one at `sq`
+#[rustfmt::skip]
+const PAWN: [i32; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+    50,  50,  50,  50,  50,  50,  50,  50,
+    10,  10,  20,  30,  30,  20,  10,  10,
+     5,   5,  10,  25,  25,  10,   5,   5,
+     0,   0,   0,  20,  20,   0,   0,   0,
+     5,  -5, -10,   0,   0, -10,  -5,   5,
+     5,  10,  10, -20, -20,  10,  10,   5,
+     0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+#[rustfmt::skip]
+const KNIGHT: [i32; 64] = [
+    -50, -40, -30, -30, -30, -30, -40, -50,
+    -40, -20,   0,   0,   0,   0, -20, -40,
+    -30,   0,  10,  15,  15,  10,   0, -30,
+    -30,   5,  15,  20,  20,  15,   5, -30,
+    -30,   0,  15,  20,  20,  15,   0, -30,
+    -30,   5,  10,  15,  15,  10,   5, -30,
+    -40, -20,   0,   5,   5,   0, -20, -40,
+    -50, -40, -30, -30, -30, -30, -40, -50,
+];
+
+#[rustfmt::skip]
+const BISHOP: [i32; 64] = [
+    -20, -10, -10, -10, -10, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,  10,  10,   5,   0, -10,
+    -10,   5,   5,  10,  10,   5,   5, -10,
+    -10,   0,  10,  10,  10,  10,   0, -10,
+    -10,  10,  10,  10,  10,  10,  10, -10,
+    -10,   5,   0,   0,   0,   0,   5, -10,
+    -20, -10, -10, -10, -10, -10, -10, -20,
+];
+
+#[rustfmt::skip]
+const ROOK: [i32; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+     5,  10,  10,  10,  10,  10,  10,   5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+     0,   0,   0,   5,   5,   0,   0,   0,
+];
+
+#[rustfmt::skip]
+const QUEEN: [i32; 64] = [
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,   5,   5,   5,   0, -10,
+     -5,   0,   5,   5,   5,   5,   0,  -5,
+      0,   0,   5,   5,   5,   5,   0,  -5,
+    -10,   5,   5,   5,   5,   5,   0, -10,
+    -10,   0,   5,   0,   0,   0,   0, -10,
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+];
+
+#[rustfmt::skip]
+const KING_MIDDLEGAME: [i32; 64] = [
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -20, -30, -30, -40, -40, -30, -30, -20,
+    -10, -20, -20, -20, -20, -20, -20, -10,
+     20,  20,   0,   0,   0,   0,  20,  20,
+     20,  30,  10,   0,   0,  10,  30,  20,
+];
+
+#[rustfmt::skip]
+const KING_ENDGAME: [i32; 64] = [
+    -50, -40, -30, -20, -20, -30, -40, -50,
+    -30, -20, -10,   0,   0, -10, -20, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -30,   0,   0,   0,   0, -30, -30,
+    -50, -30, -30, -30, -30, -30, -30, -50,
+];
+
+pub fn piece_value(kind: PieceKind) -> i32 {
+    PIECE_VALUES[kind.index()]
+}
+
+fn table(kind: PieceKind) -> &'static [i32; 64] {
+    match kind {
+        PieceKind::Pawn => &PAWN,
+        PieceKind::Knight => &KNIGHT,
+        PieceKind::Bishop => &BISHOP,
+        PieceKind::Rook => &ROOK,
+        PieceKind::Queen => &QUEEN,
+        PieceKind::King => &KING_MIDDLEGAME,
+    }
+}
+
+pub fn evaluate(position: &Position) -> i32 {
+    let mut middlegame = 0;
+    let mut endgame = 0;
+    let mut phase = 0;
+
+    for color in [Color::White, Color::Black] {
+        let sign = if color == Color::White { 1 } else { -1 };
+        let flip = if color == Color::White { 56 } else { 0 };
+        let own = position.occupancy(color);
+        for kind in PieceKind::ALL {
+            for sq in Squares(position.pieces(kind) & own) {
+                let i = sq ^ flip;
+                let material = piece_value(kind);
+                middlegame += sign * (material + table(kind)[i]);
+                endgame += sign
+                    * (material
+                        + match kind {
+                            PieceKind::King => KING_ENDGAME[i],
+                            _ => table(kind)[i],
+                        });
+                phase += PHASE_WEIGHTS[kind.index()];
+            }
+        }
+        if (position.pieces(PieceKind::Bishop) & own).count_ones() >= 2 {
+            middlegame += sign * BISHOP_PAIR;
+            endgame += sign * BISHOP_PAIR;
+        }
+    }
+
+    let phase = phase.min(MAX_PHASE);
+    let score = (middlegame * phase + endgame * (MAX_PHASE - phase)) / MAX_PHASE;
+    match position.side_to_move {
+        Color::White => score,
+        Color::Black => -score,
+    }
+}
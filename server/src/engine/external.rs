@@ -0,0 +1,348 @@
+// src/engine/external.rs
+
+// Drives any UCI engine running as a child process, for when the built-in
+// engine isn't what's wanted. The process is started on first use and
+// started again after it crashed or stopped answering, so a broken engine
+// costs a search and not the rest of the game.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::search::{mate_score, Limits, SearchInfo, SearchResult};
+use crate::position::{Move, Position};
+
+// How long the engine gets to answer `uci` and `isready`
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// How far past its time the engine may go before it's told to stop, and
+// how long after that before it's given up on
+const SEARCH_GRACE: Duration = Duration::from_secs(2);
+// For searches limited by neither time, depth nor nodes
+const MAX_SEARCH_TIME: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EngineProfile {
+    pub name: String,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    // Sent with setoption after the handshake, e.g. "Hash": "64"
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+pub enum EngineError {
+    Start(String),
+    Crashed,
+    Timeout,
+    Protocol(String),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Start(reason) => write!(f, "engine failed to start: {}", reason),
+            EngineError::Crashed => write!(f, "engine crashed"),
+            EngineError::Timeout => write!(f, "engine stopped answering"),
+            EngineError::Protocol(reason) => write!(f, "engine protocol error: {}", reason),
+        }
+    }
+}
+
+// A JSON array of profiles
+pub fn load_profiles(path: &str) -> Result<Vec<EngineProfile>, String> {
+    let json = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    serde_json::from_str(&json).map_err(|err| err.to_string())
+}
+
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>, // stdout, read on a thread of its own
+    multipv: usize,          // as last set in the engine
+}
+
+impl Process {
+    fn spawn(profile: &EngineProfile) -> Result<Self, EngineError> {
+        let mut child = Command::new(&profile.path)
+            .args(&profile.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| EngineError::Start(err.to_string()))?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            let _ = child.kill();
+            return Err(EngineError::Start("no pipes to the engine".to_string()));
+        };
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Process {
+            child,
+            stdin,
+            lines,
+            multipv: 1,
+        })
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), EngineError> {
+        writeln!(self.stdin, "{}", command)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|_| EngineError::Crashed)
+    }
+
+    fn read_line(&self, timeout: Duration) -> Result<String, EngineError> {
+        self.lines.recv_timeout(timeout).map_err(|err| match err {
+            RecvTimeoutError::Timeout => EngineError::Timeout,
+            RecvTimeoutError::Disconnected => EngineError::Crashed,
+        })
+    }
+
+    // Skips everything up to the line `expected`
+    fn wait_for(&self, expected: &str, timeout: Duration) -> Result<(), EngineError> {
+        let deadline = Instant::now() + timeout;
+        while self
+            .read_line(deadline.saturating_duration_since(Instant::now()))?
+            .trim()
+            != expected
+        {}
+        Ok(())
+    }
+
+    fn handshake(&mut self, profile: &EngineProfile) -> Result<(), EngineError> {
+        self.send("uci")?;
+        self.wait_for("uciok", HANDSHAKE_TIMEOUT)?;
+        for (name, value) in &profile.options {
+            self.send(&format!("setoption name {} value {}", name, value))?;
+        }
+        self.send("isready")?;
+        self.wait_for("readyok", HANDSHAKE_TIMEOUT)
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub struct ExternalEngine {
+    profile: EngineProfile,
+    process: Option<Process>, // None until needed, and after a failure
+    multipv: usize,
+    stop: Arc<AtomicBool>,
+}
+
+impl ExternalEngine {
+    pub fn new(profile: EngineProfile) -> Self {
+        ExternalEngine {
+            profile,
+            process: None,
+            multipv: 1,
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.profile.name
+    }
+
+    // As for `Engine::stop_flag`
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    pub fn set_stop_flag(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = stop;
+    }
+
+    // Through the engine's MultiPV option, which most engines have
+    pub fn set_multipv(&mut self, lines: usize) {
+        self.multipv = lines.max(1);
+    }
+
+    fn process(&mut self) -> Result<&mut Process, EngineError> {
+        if self.process.is_none() {
+            let mut process = Process::spawn(&self.profile)?;
+            process
+                .handshake(&self.profile)
+                .map_err(|err| EngineError::Start(err.to_string()))?;
+            self.process = Some(process);
+        }
+        Ok(self.process.as_mut().expect("process was just started"))
+    }
+
+    // Searches the position after `moves` from `start`. An engine that
+    // crashed, here or since the last search, is started again and gets
+    // one more try.
+    pub fn search(
+        &mut self,
+        start: &Position,
+        moves: &[Move],
+        limits: Limits,
+        mut report: impl FnMut(&SearchInfo),
+    ) -> Result<SearchResult, EngineError> {
+        let mut result = self.try_search(start, moves, &limits, &mut report);
+        if matches!(result, Err(EngineError::Crashed)) {
+            println!("Engine {} crashed, restarting it", self.profile.name);
+            self.process = None;
+            result = self.try_search(start, moves, &limits, &mut report);
+        }
+        // Whatever state a failed engine is in, it starts afresh next time
+        if result.is_err() {
+            self.process = None;
+        }
+        result
+    }
+
+    fn try_search(
+        &mut self,
+        start: &Position,
+        moves: &[Move],
+        limits: &Limits,
+        report: &mut impl FnMut(&SearchInfo),
+    ) -> Result<SearchResult, EngineError> {
+        let mut position = start.clone();
+        for mv in moves {
+            position = position.make_move(mv);
+        }
+        let stop = self.stop.clone();
+        let multipv = self.multipv;
+        let process = self.process()?;
+        if process.multipv != multipv {
+            process.send(&format!("setoption name MultiPV value {}", multipv))?;
+            process.multipv = multipv;
+        }
+
+        let mut command = format!("position fen {}", start.to_fen());
+        if !moves.is_empty() {
+            command.push_str(" moves");
+            for mv in moves {
+                command.push(' ');
+                command.push_str(&mv.to_uci());
+            }
+        }
+        process.send(&command)?;
+
+        let movetime = limits.movetime.or_else(|| {
+            (limits.depth.is_none() && limits.nodes.is_none()).then_some(MAX_SEARCH_TIME)
+        });
+        let mut command = "go".to_string();
+        if let Some(depth) = limits.depth {
+            command.push_str(&format!(" depth {}", depth));
+        }
+        if let Some(nodes) = limits.nodes {
+            command.push_str(&format!(" nodes {}", nodes));
+        }
+        if let Some(movetime) = movetime {
+            command.push_str(&format!(" movetime {}", movetime.as_millis()));
+        }
+        process.send(&command)?;
+
+        let mut deadline = Instant::now() + movetime.unwrap_or(MAX_SEARCH_TIME) + SEARCH_GRACE;
+        let mut stopping = false;
+        let mut last: Option<SearchInfo> = None;
+        loop {
+            if !stopping && (stop.load(Ordering::Relaxed) || Instant::now() >= deadline) {
+                process.send("stop")?;
+                stopping = true;
+                deadline = Instant::now() + SEARCH_GRACE;
+            }
+            let line = match process.read_line(POLL_INTERVAL) {
+                Ok(line) => line,
+                // Past the first deadline the engine is told to stop first
+                Err(EngineError::Timeout) if !stopping || Instant::now() < deadline => continue,
+                Err(err) => return Err(err),
+            };
+            if let Some(info) = parse_info(&line, &position) {
+                report(&info);
+                if info.multipv == 1 {
+                    last = Some(info);
+                }
+            } else if let Some(rest) = line.strip_prefix("bestmove") {
+                let best_move = match rest.split_whitespace().next() {
+                    None | Some("0000") | Some("(none)") => None,
+                    Some(uci) => Some(position.find_uci_move(uci).ok_or_else(|| {
+                        EngineError::Protocol(format!("illegal best move {}", uci))
+                    })?),
+                };
+                return Ok(SearchResult {
+                    best_move,
+                    score: last.as_ref().map_or(0, |info| info.score),
+                    depth: last.as_ref().map_or(0, |info| info.depth),
+                    nodes: last.as_ref().map_or(0, |info| info.nodes),
+                });
+            }
+        }
+    }
+}
+
+// An `info` line with a depth and an exact score, the pv is kept up to its
+// first move that isn't legal
+fn parse_info(line: &str, position: &Position) -> Option<SearchInfo> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some("info") {
+        return None;
+    }
+    let mut depth = None;
+    let mut multipv = 1;
+    let mut score = None;
+    let mut nodes = 0;
+    let mut elapsed = Duration::ZERO;
+    let mut pv = Vec::new();
+    while let Some(word) = words.next() {
+        match word {
+            "depth" => depth = words.next()?.parse().ok(),
+            "nodes" => nodes = words.next()?.parse().ok()?,
+            "time" => elapsed = Duration::from_millis(words.next()?.parse().ok()?),
+            "multipv" => multipv = words.next()?.parse().ok()?,
+            "lowerbound" | "upperbound" => return None,
+            "score" => {
+                score = match (words.next()?, words.next()?.parse::<i32>().ok()?) {
+                    ("cp", cp) => Some(cp),
+                    ("mate", moves) => Some(mate_score(moves)),
+                    _ => None,
+                }
+            }
+            "pv" => {
+                let mut position = position.clone();
+                for uci in words.by_ref() {
+                    let Some(mv) = position.find_uci_move(uci) else {
+                        break;
+                    };
+                    pv.push(mv);
+                    position = position.make_move(&mv);
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(SearchInfo {
+        depth: depth?,
+        multipv,
+        score: score?,
+        nodes,
+        elapsed,
+        pv,
+    })
+}
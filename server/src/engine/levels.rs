@@ -0,0 +1,174 @@
+// src/engine/levels.rs
+
+// Difficulty levels for the bot. Lower levels search less, see the board
+// through noisy evaluations, now and then play a move that only looks good
+// at a glance and leave the opening book sooner, each of these a little
+// more than the level above. The ratings are what each level is tuned to
+// play like, the self-play harness in src/bin/selfplay.rs checks the gaps
+// between them.
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::time::Duration;
+
+use super::search::{Engine, Limits};
+use crate::position::{Move, Position};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Level {
+    pub level: u8,
+    pub rating: u32, // target rating
+    depth: Option<i32>,
+    nodes: Option<u64>,
+    noise: i32,            // centipawns
+    mistake_rate: f64,     // chance of passing over the best move
+    mistake_margin: i32,   // how much worse, at a glance, the mistake may look
+    pub book_plies: usize, // how far into the game the opening book is used
+}
+
+pub const LEVELS: [Level; 8] = [
+    Level {
+        level: 1,
+        rating: 800,
+        depth: Some(1),
+        nodes: Some(500),
+        noise: 120,
+        mistake_rate: 0.25,
+        mistake_margin: 250,
+        book_plies: 2,
+    },
+    Level {
+        level: 2,
+        rating: 1000,
+        depth: Some(2),
+        nodes: Some(2_000),
+        noise: 90,
+        mistake_rate: 0.20,
+        mistake_margin: 200,
+        book_plies: 4,
+    },
+    Level {
+        level: 3,
+        rating: 1200,
+        depth: Some(3),
+        nodes: Some(5_000),
+        noise: 70,
+        mistake_rate: 0.15,
+        mistake_margin: 160,
+        book_plies: 6,
+    },
+    Level {
+        level: 4,
+        rating: 1400,
+        depth: Some(4),
+        nodes: Some(12_000),
+        noise: 50,
+        mistake_rate: 0.11,
+        mistake_margin: 120,
+        book_plies: 8,
+    },
+    Level {
+        level: 5,
+        rating: 1600,
+        depth: Some(5),
+        nodes: Some(30_000),
+        noise: 35,
+        mistake_rate: 0.07,
+        mistake_margin: 90,
+        book_plies: 10,
+    },
+    Level {
+        level: 6,
+        rating: 1800,
+        depth: Some(6),
+        nodes: Some(70_000),
+        noise: 20,
+        mistake_rate: 0.04,
+        mistake_margin: 60,
+        book_plies: 14,
+    },
+    Level {
+        level: 7,
+        rating: 2000,
+        depth: Some(7),
+        nodes: Some(150_000),
+        noise: 10,
+        mistake_rate: 0.02,
+        mistake_margin: 30,
+        book_plies: 20,
+    },
+    // Full strength, limited only by time
+    Level {
+        level: 8,
+        rating: 2200,
+        depth: None,
+        nodes: None,
+        noise: 0,
+        mistake_rate: 0.0,
+        mistake_margin: 0,
+        book_plies: 40,
+    },
+];
+
+pub fn level(level: u8) -> Option<&'static Level> {
+    LEVELS.iter().find(|l| l.level == level)
+}
+
+impl Level {
+    // `history` as for `Engine::search`. None only if there's no legal move.
+    pub fn choose_move(
+        &self,
+        engine: &mut Engine,
+        position: &Position,
+        history: &[u64],
+        movetime: Option<Duration>,
+    ) -> Option<Move> {
+        engine.set_noise(self.noise);
+        let limits = Limits {
+            depth: self.depth,
+            movetime,
+            nodes: self.nodes,
+        };
+        let best = engine.search(position, history, limits).best_move?;
+
+        let mut rng = rand::thread_rng();
+        if !rng.gen_bool(self.mistake_rate) {
+            return Some(best);
+        }
+        let scored = engine.score_root_moves(position, history);
+        let top = scored.iter().map(|(_, score)| *score).max()?;
+        let candidates: Vec<Move> = scored
+            .into_iter()
+            .filter(|(mv, score)| *mv != best && top - score <= self.mistake_margin)
+            .map(|(mv, _)| mv)
+            .collect();
+        Some(candidates.choose(&mut rng).copied().unwrap_or(best))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_level_is_stronger_than_the_one_below() {
+        // No limit is the strongest of all
+        let limit = |limit: Option<u64>| limit.unwrap_or(u64::MAX);
+        for pair in LEVELS.windows(2) {
+            let (lower, higher) = (&pair[0], &pair[1]);
+            let name = format!("level {} to {}", lower.level, higher.level);
+            assert_eq!(higher.level, lower.level + 1, "{}", name);
+            assert!(higher.rating > lower.rating, "{}", name);
+            assert!(
+                limit(higher.depth.map(|d| d as u64)) > limit(lower.depth.map(|d| d as u64)),
+                "{}",
+                name
+            );
+            assert!(limit(higher.nodes) > limit(lower.nodes), "{}", name);
+            assert!(higher.noise < lower.noise, "{}", name);
+            assert!(higher.mistake_rate < lower.mistake_rate, "{}", name);
+            assert!(higher.mistake_margin < lower.mistake_margin, "{}", name);
+            assert!(higher.book_plies > lower.book_plies, "{}", name);
+        }
+    }
+}
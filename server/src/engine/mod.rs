@@ -0,0 +1,24 @@
+// src/engine/mod.rs
+
+// The built-in chess engine, external.rs for driving other engines over UCI
+// and book.rs for Polyglot opening books. It works on `Position` directly
+// and knows nothing about rooms or players, see bot.rs for how the server
+// uses it.
+
+mod book;
+mod eval;
+mod external;
+mod levels;
+mod search;
+mod syzygy;
+mod tt;
+mod zobrist;
+
+pub use book::{polyglot_hash, Book, BookMove};
+pub use external::{load_profiles, EngineError, EngineProfile, ExternalEngine};
+pub use levels::{level, Level, LEVELS};
+pub use search::{
+    allot_time, mate_in, mate_score, Engine, Limits, SearchInfo, SearchResult, MAX_THREADS,
+};
+pub use syzygy::{RootProbe, Tablebases, Wdl};
+pub use zobrist::hash;
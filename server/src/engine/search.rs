@@ -0,0 +1,658 @@
+// src/engine/search.rs
+
+// Iterative deepening alpha-beta (principal variation search) with a
+// quiescence search over captures at the leaves. Moves are tried in the
+// order transposition table move, captures by MVV-LVA, killer moves, then
+// quiet moves by history score. Extra threads search the same position
+// alongside the main one and only help by filling the shared table.
+// With tablebases the root only considers the moves that keep the best
+// result, and positions in them after a capture or pawn move are scored
+// straight from the tables.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::eval::{evaluate, piece_value};
+use super::syzygy::{Tablebases, Wdl};
+use super::tt::{Bound, Table};
+use super::zobrist;
+use crate::position::{Move, PieceKind, Position};
+
+pub const MATE: i32 = 30_000;
+pub const MATE_BOUND: i32 = MATE - 1_000; // anything beyond is a forced mate
+const INFINITY: i32 = MATE + 1;
+const MAX_DEPTH: i32 = 64;
+const MAX_PLY: usize = 128;
+// A tablebase win, below any mate the search can find
+const TB_WIN: i32 = MATE_BOUND - MAX_PLY as i32 - 1;
+pub const MAX_THREADS: usize = 64;
+
+const TT_MOVE_SCORE: i32 = 1_000_000;
+const CAPTURE_SCORE: i32 = 100_000;
+const PROMOTION_SCORE: i32 = 90_000;
+const KILLER_SCORE: i32 = 80_000;
+const HISTORY_LIMIT: i32 = 50_000;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub depth: Option<i32>,
+    pub movetime: Option<Duration>,
+    pub nodes: Option<u64>, // over all threads
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    pub score: i32, // centipawns for the side to move
+    pub depth: i32, // last completed iteration
+    pub nodes: u64,
+}
+
+// Reported after every completed iteration, once for each line
+#[derive(Debug, Clone)]
+pub struct SearchInfo {
+    pub depth: i32,
+    pub multipv: usize, // 1 for the best line, 2 for the second best and so on
+    pub score: i32,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    pub pv: Vec<Move>,
+}
+
+// Full moves until mate, negative when the side to move gets mated
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score >= MATE_BOUND {
+        Some((MATE - score + 1) / 2)
+    } else if score <= -MATE_BOUND {
+        Some(-(MATE + score) / 2)
+    } else {
+        None
+    }
+}
+
+// The score for mate in `moves`, as reported by UCI engines
+pub fn mate_score(moves: i32) -> i32 {
+    if moves > 0 {
+        MATE - (2 * moves - 1)
+    } else {
+        -MATE - 2 * moves
+    }
+}
+
+// How long to think with `remaining` on the clock
+pub fn allot_time(remaining: Duration, increment: Duration, moves_to_go: u32) -> Duration {
+    (remaining / moves_to_go.max(1) + increment * 3 / 4).min(remaining / 2)
+}
+
+pub struct Engine {
+    tt: Table,
+    threads: usize,
+    multipv: usize, // lines searched and reported
+    stop: Arc<AtomicBool>,
+    noise: i32, // largest random change to an evaluation, in centipawns
+    tablebases: Option<Arc<Tablebases>>,
+}
+
+// What the threads of one search share
+struct Shared<'a> {
+    tt: &'a Table,
+    stop: &'a AtomicBool, // set from outside
+    done: AtomicBool,     // set once the main thread is finished
+    nodes: AtomicU64,
+    node_limit: Option<u64>,
+    deadline: Option<Instant>,
+    noise: i32,
+    noise_seed: u64,
+    multipv: usize,
+    tablebases: Option<&'a Tablebases>,
+    root_moves: Option<Vec<Move>>, // the only root moves searched
+}
+
+struct Worker<'a> {
+    shared: &'a Shared<'a>,
+    killers: Vec<[Option<Move>; 2]>, // by ply
+    history: Box<[[i32; 64]; 64]>,   // by from and to square
+    path: Vec<u64>,                  // hashes of the game so far and the line being searched
+    nodes: u64,
+    stopped: bool,
+    root_best: Option<(Move, i32)>,
+    excluded: Vec<Move>, // root moves already reported as better lines
+}
+
+impl Engine {
+    pub fn new(hash_mb: usize) -> Self {
+        Engine {
+            tt: Table::new(hash_mb),
+            threads: 1,
+            multipv: 1,
+            stop: Arc::new(AtomicBool::new(false)),
+            noise: 0,
+            tablebases: None,
+        }
+    }
+
+    pub fn set_hash(&mut self, hash_mb: usize) {
+        self.tt = Table::new(hash_mb);
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.clamp(1, MAX_THREADS);
+    }
+
+    // Reports the best `lines` moves instead of only the best one, at the
+    // cost of searching each of them with a full window
+    pub fn set_multipv(&mut self, lines: usize) {
+        self.multipv = lines.max(1);
+    }
+
+    // Forgets everything learned, for a new game
+    pub fn clear(&mut self) {
+        self.tt.clear();
+    }
+
+    // Setting the flag ends the search in progress as if its time was up.
+    // It isn't cleared by the engine, whoever sets it clears it again
+    // before starting the next search.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    pub fn set_stop_flag(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = stop;
+    }
+
+    // Weaker play for the lower bot levels. The noise depends only on the
+    // position and a seed drawn for every search, so a position evaluates
+    // the same way wherever it turns up in the tree.
+    pub fn set_noise(&mut self, noise: i32) {
+        self.noise = noise;
+    }
+
+    pub fn set_tablebases(&mut self, tablebases: Option<Arc<Tablebases>>) {
+        self.tablebases = tablebases;
+    }
+
+    fn shared(&self, position: &Position, limits: &Limits, started: Instant) -> Shared<'_> {
+        let tablebases = self.tablebases.as_deref();
+        // Every line is wanted with several, so they're all searched then
+        let root_moves = tablebases
+            .filter(|_| self.multipv == 1)
+            .and_then(|tablebases| tablebases.probe_root(position))
+            .map(|probe| probe.best_moves());
+        Shared {
+            tt: &self.tt,
+            stop: &self.stop,
+            done: AtomicBool::new(false),
+            nodes: AtomicU64::new(0),
+            node_limit: limits.nodes,
+            deadline: limits.movetime.map(|movetime| started + movetime),
+            noise: self.noise,
+            noise_seed: rand::random(),
+            multipv: self.multipv,
+            tablebases,
+            root_moves,
+        }
+    }
+
+    // `history` holds the hashes of the positions that came before this one
+    // in the game, oldest first, so repetitions are scored as draws
+    pub fn search(&mut self, position: &Position, history: &[u64], limits: Limits) -> SearchResult {
+        self.search_with_info(position, history, limits, |_| {})
+    }
+
+    pub fn search_with_info(
+        &mut self,
+        position: &Position,
+        history: &[u64],
+        limits: Limits,
+        mut report: impl FnMut(&SearchInfo),
+    ) -> SearchResult {
+        let started = Instant::now();
+        let shared = self.shared(position, &limits, started);
+        let max_depth = limits.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
+        std::thread::scope(|scope| {
+            for i in 1..self.threads {
+                let shared = &shared;
+                scope.spawn(move || {
+                    // Half the helpers start a ply deeper so the threads
+                    // don't all walk the tree in step
+                    let mut worker = Worker::new(shared, history);
+                    for depth in (1 + i as i32 % 2)..=MAX_DEPTH {
+                        worker.negamax(position, depth, 0, -INFINITY, INFINITY);
+                        if worker.stopped {
+                            break;
+                        }
+                    }
+                });
+            }
+            let mut worker = Worker::new(&shared, history);
+            let result = worker.deepen(position, max_depth, &limits, started, &mut report);
+            shared.done.store(true, Ordering::Relaxed);
+            result
+        })
+    }
+
+    // Every legal move with a quick score from a quiescence search, for
+    // picking a plausible second-best move
+    pub fn score_root_moves(&mut self, position: &Position, history: &[u64]) -> Vec<(Move, i32)> {
+        let shared = self.shared(position, &Limits::default(), Instant::now());
+        let mut worker = Worker::new(&shared, history);
+        position
+            .legal_moves()
+            .into_iter()
+            .map(|mv| {
+                let child = position.make_move(&mv);
+                (mv, -worker.quiesce(&child, 1, -INFINITY, INFINITY))
+            })
+            .collect()
+    }
+}
+
+impl<'a> Worker<'a> {
+    fn new(shared: &'a Shared<'a>, history: &[u64]) -> Self {
+        Worker {
+            shared,
+            killers: vec![[None; 2]; MAX_PLY],
+            history: Box::new([[0; 64]; 64]),
+            path: history.to_vec(),
+            nodes: 0,
+            stopped: false,
+            root_best: None,
+            excluded: Vec::new(),
+        }
+    }
+
+    fn deepen(
+        &mut self,
+        position: &Position,
+        max_depth: i32,
+        limits: &Limits,
+        started: Instant,
+        report: &mut impl FnMut(&SearchInfo),
+    ) -> SearchResult {
+        let mut result = SearchResult {
+            // Something legal in case not even depth 1 completes
+            best_move: match &self.shared.root_moves {
+                Some(moves) => moves.first().copied(),
+                None => position.legal_moves().first().copied(),
+            },
+            score: 0,
+            depth: 0,
+            nodes: 0,
+        };
+        'deepen: for depth in 1..=max_depth {
+            // Each line is the best move once the lines before it are left out
+            self.excluded.clear();
+            for line in 1..=self.shared.multipv {
+                self.root_best = None;
+                let score = self.negamax(position, depth, 0, -INFINITY, INFINITY);
+                if self.stopped {
+                    // The previous best move is searched first, so any move
+                    // that got to replace it before the stop is at least as
+                    // good
+                    if let Some((best, score)) = self.root_best.filter(|_| line == 1) {
+                        result.best_move = Some(best);
+                        result.score = score;
+                    }
+                    break 'deepen;
+                }
+                // No moves left to try
+                let Some((best, _)) = self.root_best else {
+                    break;
+                };
+                if line == 1 {
+                    result.best_move = Some(best);
+                    result.score = score;
+                    result.depth = depth;
+                }
+                report(&SearchInfo {
+                    depth,
+                    multipv: line,
+                    score,
+                    nodes: self.total_nodes(),
+                    elapsed: started.elapsed(),
+                    pv: self.principal_variation(position, best, depth),
+                });
+                self.excluded.push(best);
+            }
+            if result.score.abs() >= MATE_BOUND {
+                break;
+            }
+            // The next iteration would most likely not finish in time
+            if limits
+                .movetime
+                .is_some_and(|movetime| started.elapsed() * 2 > movetime)
+            {
+                break;
+            }
+        }
+        result.nodes = self.total_nodes();
+        result
+    }
+
+    // The best move followed by the best replies stored in the table, as
+    // long as they're legal and don't go round in circles
+    fn principal_variation(&self, position: &Position, best: Move, depth: i32) -> Vec<Move> {
+        let mut pv = vec![best];
+        let mut seen = vec![zobrist::hash(position)];
+        let mut position = position.make_move(&best);
+        while pv.len() < depth.max(1) as usize {
+            let key = zobrist::hash(&position);
+            if seen.contains(&key) {
+                break;
+            }
+            seen.push(key);
+            let Some(mv) = self
+                .shared
+                .tt
+                .probe(key, 0)
+                .and_then(|entry| entry.best)
+                .filter(|mv| position.legal_moves().contains(mv))
+            else {
+                break;
+            };
+            pv.push(mv);
+            position = position.make_move(&mv);
+        }
+        pv
+    }
+
+    // Counted locally and handed to the shared counter in batches
+    fn count_node(&mut self) {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(1024) {
+            self.shared.nodes.fetch_add(1024, Ordering::Relaxed);
+        }
+    }
+
+    fn total_nodes(&self) -> u64 {
+        self.shared.nodes.load(Ordering::Relaxed) + self.nodes % 1024
+    }
+
+    fn evaluate(&self, position: &Position) -> i32 {
+        let score = evaluate(position);
+        let noise = self.shared.noise;
+        if noise == 0 {
+            return score;
+        }
+        let mixed =
+            (zobrist::hash(position) ^ self.shared.noise_seed).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        let span = 2 * noise as u64 + 1;
+        score + ((mixed >> 32) % span) as i32 - noise
+    }
+
+    fn out_of_time(&mut self) -> bool {
+        let shared = self.shared;
+        if !self.stopped
+            && shared
+                .node_limit
+                .is_some_and(|limit| self.total_nodes() >= limit)
+        {
+            self.stopped = true;
+        }
+        if !self.stopped && self.nodes.is_multiple_of(1024) {
+            self.stopped = shared.stop.load(Ordering::Relaxed)
+                || shared.done.load(Ordering::Relaxed)
+                || shared
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline);
+        }
+        self.stopped
+    }
+
+    fn is_draw(&self, position: &Position, key: u64) -> bool {
+        position.halfmove_clock >= 100
+            || position.is_insufficient_material()
+            // A single repetition is enough, the side that could avoid it
+            // would have done so
+            || self
+                .path
+                .iter()
+                .rev()
+                .take(position.halfmove_clock as usize)
+                .any(|previous| *previous == key)
+    }
+
+    fn is_root_move(&self, mv: &Move) -> bool {
+        !self.excluded.contains(mv)
+            && self
+                .shared
+                .root_moves
+                .as_ref()
+                .is_none_or(|moves| moves.contains(mv))
+    }
+
+    // Only right after a capture or pawn move, where the tables can be
+    // trusted without knowing how close the fifty-move rule is
+    fn probe_wdl(&self, position: &Position) -> Option<Wdl> {
+        let tablebases = self.shared.tablebases?;
+        if position.halfmove_clock != 0 || !tablebases.covers(position) {
+            return None;
+        }
+        tablebases.probe_wdl(position)
+    }
+
+    fn negamax(
+        &mut self,
+        position: &Position,
+        depth: i32,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        if self.out_of_time() {
+            return 0;
+        }
+        let key = zobrist::hash(position);
+        if ply > 0 && self.is_draw(position, key) {
+            return 0;
+        }
+        let in_check = position.in_check();
+        // Checks are searched one ply deeper
+        let depth = if in_check { depth + 1 } else { depth };
+        if depth <= 0 {
+            return self.quiesce(position, ply, alpha, beta);
+        }
+        if ply >= MAX_PLY - 1 {
+            return self.evaluate(position);
+        }
+        self.count_node();
+
+        let entry = self.shared.tt.probe(key, ply as i32);
+        if let Some(entry) = entry.filter(|entry| ply > 0 && entry.depth >= depth) {
+            match entry.bound {
+                Bound::Exact => return entry.score,
+                Bound::Lower if entry.score >= beta => return entry.score,
+                Bound::Upper if entry.score <= alpha => return entry.score,
+                _ => {}
+            }
+        }
+
+        if ply > 0 {
+            if let Some(wdl) = self.probe_wdl(position) {
+                return match wdl {
+                    Wdl::Win => TB_WIN - ply as i32,
+                    Wdl::Loss => -TB_WIN + ply as i32,
+                    // Drawn by the fifty-move rule, but only just
+                    Wdl::CursedWin => 1,
+                    Wdl::BlessedLoss => -1,
+                    Wdl::Draw => 0,
+                };
+            }
+        }
+
+        let mut moves = Vec::with_capacity(64);
+        position.pseudo_legal_moves(&mut moves);
+        let mut moves = self.score_moves(position, moves, entry.and_then(|e| e.best), ply);
+
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+        let mut legal = 0;
+        self.path.push(key);
+        for i in 0..moves.len() {
+            let mv = pick_next(&mut moves, i);
+            if ply == 0 && !self.is_root_move(&mv) {
+                continue;
+            }
+            let Some(child) = play(position, &mv) else {
+                continue;
+            };
+            legal += 1;
+
+            let score = if legal == 1 {
+                -self.negamax(&child, depth - 1, ply + 1, -beta, -alpha)
+            } else {
+                // Every later move is expected to fail low, which a null
+                // window shows cheaply
+                let score = -self.negamax(&child, depth - 1, ply + 1, -alpha - 1, -alpha);
+                if score > alpha && score < beta {
+                    -self.negamax(&child, depth - 1, ply + 1, -beta, -alpha)
+                } else {
+                    score
+                }
+            };
+            if self.stopped {
+                self.path.pop();
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(mv);
+            }
+            if score > alpha {
+                alpha = score;
+                if ply == 0 {
+                    self.root_best = Some((mv, score));
+                }
+                if alpha >= beta {
+                    if !position.is_capture(&mv) && mv.promotion.is_none() {
+                        self.remember_quiet(mv, depth, ply);
+                    }
+                    break;
+                }
+            }
+        }
+        self.path.pop();
+
+        if legal == 0 {
+            return if in_check { -MATE + ply as i32 } else { 0 };
+        }
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        // Without its best moves the root isn't worth remembering
+        if ply > 0 || self.excluded.is_empty() {
+            self.shared
+                .tt
+                .store(key, depth, best_score, bound, best_move, ply as i32);
+        }
+        best_score
+    }
+
+    // Captures and queen promotions only, until the position is quiet.
+    // The side to move can always stand pat instead.
+    fn quiesce(&mut self, position: &Position, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        if self.out_of_time() {
+            return 0;
+        }
+        self.count_node();
+        let stand_pat = self.evaluate(position);
+        if stand_pat >= beta || ply >= MAX_PLY - 1 {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let mut moves = Vec::with_capacity(32);
+        position.pseudo_legal_moves(&mut moves);
+        moves.retain(|mv| position.is_capture(mv) || mv.promotion == Some(PieceKind::Queen));
+        let mut moves = self.score_moves(position, moves, None, ply);
+        for i in 0..moves.len() {
+            let mv = pick_next(&mut moves, i);
+            let Some(child) = play(position, &mv) else {
+                continue;
+            };
+            let score = -self.quiesce(&child, ply + 1, -beta, -alpha);
+            if self.stopped {
+                return 0;
+            }
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+
+    fn score_moves(
+        &self,
+        position: &Position,
+        moves: Vec<Move>,
+        tt_move: Option<Move>,
+        ply: usize,
+    ) -> Vec<(Move, i32)> {
+        moves
+            .into_iter()
+            .map(|mv| {
+                let score = if Some(mv) == tt_move {
+                    TT_MOVE_SCORE
+                } else if position.is_capture(&mv) {
+                    // Most valuable victim first, least valuable attacker
+                    // among equal victims
+                    let victim = position
+                        .piece_at(mv.to as usize)
+                        .map_or(PieceKind::Pawn, |(_, kind)| kind);
+                    let attacker = position
+                        .piece_at(mv.from as usize)
+                        .map_or(PieceKind::Pawn, |(_, kind)| kind);
+                    CAPTURE_SCORE + 10 * piece_value(victim) - piece_value(attacker)
+                } else if let Some(kind) = mv.promotion {
+                    PROMOTION_SCORE + piece_value(kind)
+                } else if let Some(i) = self.killers[ply].iter().position(|k| *k == Some(mv)) {
+                    KILLER_SCORE - i as i32
+                } else {
+                    self.history[mv.from as usize][mv.to as usize]
+                };
+                (mv, score)
+            })
+            .collect()
+    }
+
+    // A quiet move that caused a cutoff is tried early in sibling nodes
+    fn remember_quiet(&mut self, mv: Move, depth: i32, ply: usize) {
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(mv) {
+            killers[1] = killers[0];
+            killers[0] = Some(mv);
+        }
+        let history = &mut self.history[mv.from as usize][mv.to as usize];
+        *history += depth * depth;
+        if *history > HISTORY_LIMIT {
+            self.history
+                .iter_mut()
+                .flatten()
+                .for_each(|score| *score /= 2);
+        }
+    }
+}
+
+// Moves the best scored of the remaining moves to `i`, a selection sort that
+// only does the work for moves that actually get searched
+fn pick_next(moves: &mut [(Move, i32)], i: usize) -> Move {
+    let best = (i..moves.len()).max_by_key(|j| moves[*j].1).unwrap_or(i);
+    moves.swap(i, best);
+    moves[i].0
+}
+
+// The position after a pseudo-legal move, None if it leaves the king in check
+fn play(position: &Position, mv: &Move) -> Option<Position> {
+    let us = position.side_to_move;
+    let child = position.make_move(mv);
+    (!child.is_square_attacked(child.king_square(us), us.opposite())).then_some(child)
+}
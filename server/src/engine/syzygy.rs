@@ -0,0 +1,1346 @@
+// src/engine/syzygy.rs
+
+// Syzygy endgame tablebases. A WDL table (.rtbw) tells whether a position
+// is won, drawn or lost, counting wins that the fifty-move rule spoils as
+// cursed and losses it saves as blessed. A DTZ table (.rtbz) gives the
+// number of plies to the next capture or pawn move on the way there, which
+// is what playing the ending out needs.
+//
+// The decoding follows the reference prober, down to the layout of the
+// files: the pieces of a position are mapped to an index, which is looked
+// up in blocks of Huffman coded values built by recursive pairing. Files
+// are mapped into memory the first time they're probed, so only the blocks
+// actually read take any, which is what makes 6 and 7 piece tables usable.
+
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use crate::position::{Color, Move, PieceKind, Position, Squares};
+
+pub const MAX_PIECES: usize = 7;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+// Flags of a table, all but SINGLE_VALUE only used in DTZ tables
+const STM: u8 = 1; // stored for black to move
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+// Flags of a file
+const SPLIT: u8 = 1; // a WDL table for each side to move
+const HAS_PAWNS: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Option<Wdl> {
+        match value {
+            -2 => Some(Wdl::Loss),
+            -1 => Some(Wdl::BlessedLoss),
+            0 => Some(Wdl::Draw),
+            1 => Some(Wdl::CursedWin),
+            2 => Some(Wdl::Win),
+            _ => None,
+        }
+    }
+
+    // The same position seen by the other side
+    pub fn flip(self) -> Wdl {
+        Wdl::from_value(-(self as i32)).unwrap_or(Wdl::Draw)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Wdl::Loss => "loss",
+            Wdl::BlessedLoss => "blessed_loss",
+            Wdl::Draw => "draw",
+            Wdl::CursedWin => "cursed_win",
+            Wdl::Win => "win",
+        }
+    }
+
+    fn sign(self) -> i32 {
+        (self as i32).signum()
+    }
+}
+
+// The DTZ of the move before a capture or pawn move that leads to `wdl`
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+        Wdl::Draw => 0,
+    }
+}
+
+// The result of probing the root of a search
+#[derive(Debug, Clone)]
+pub struct RootProbe {
+    pub wdl: Wdl,
+    pub dtz: i32,
+    // Every legal move with the DTZ it leaves, from the root's side and
+    // counting the move itself, best first
+    pub moves: Vec<(Move, i32)>,
+}
+
+impl RootProbe {
+    // The moves as good as the best one
+    pub fn best_moves(&self) -> Vec<Move> {
+        let best = self.moves.first().map(|(_, dtz)| rank(*dtz));
+        self.moves
+            .iter()
+            .filter(|(_, dtz)| Some(rank(*dtz)) == best)
+            .map(|(mv, _)| *mv)
+            .collect()
+    }
+}
+
+// Higher is better: the quickest win, then a draw, then the longest loss
+fn rank(dtz: i32) -> i32 {
+    match dtz {
+        0 => 0,
+        dtz if dtz > 0 => 10_000 - dtz,
+        dtz => -10_000 - dtz,
+    }
+}
+
+// Index tables shared by every table, as built by the reference prober
+struct Maps {
+    map_pawns: [u64; 64],
+    map_b1h1h7: [u64; 64],
+    map_a1d1d4: [usize; 64],
+    map_kk: [[u64; 64]; 10],
+    binomial: [[u64; 64]; 6],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+fn off_diagonal(sq: usize) -> i32 {
+    (sq / 8) as i32 - (sq % 8) as i32
+}
+
+fn maps() -> &'static Maps {
+    static MAPS: OnceLock<Maps> = OnceLock::new();
+    MAPS.get_or_init(|| {
+        let mut maps = Maps {
+            map_pawns: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; 6],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        // Squares below the a1-h8 diagonal
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_diagonal(sq) < 0 {
+                maps.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        // The a1-d1-d4 triangle, the diagonal last
+        let mut code = 0;
+        let mut diagonal = Vec::new();
+        for sq in 0..=27 {
+            if sq % 8 > 3 {
+                continue;
+            }
+            if off_diagonal(sq) < 0 {
+                maps.map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_diagonal(sq) == 0 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            maps.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // The 462 ways to place two kings with the first in the triangle,
+        // and not above the diagonal if the first is on it
+        let mut code = 0;
+        let mut both_on_diagonal = Vec::new();
+        for idx in 0..10 {
+            for s1 in 0..=27 {
+                if s1 % 8 > 3 || maps.map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    let touching = (s1 % 8).abs_diff(s2 % 8) <= 1 && (s1 / 8).abs_diff(s2 / 8) <= 1;
+                    if touching || (off_diagonal(s1) == 0 && off_diagonal(s2) > 0) {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        maps.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            maps.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        maps.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..6.min(n + 1) {
+                maps.binomial[k][n] = if k > 0 {
+                    maps.binomial[k - 1][n - 1]
+                } else {
+                    0
+                } + if k < n { maps.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // Pawns on a2-h7 map to 0..47, the leading pawn being the one with
+        // the highest value: nearest the edge, then lowest
+        let mut available: i32 = 47;
+        for lead_pawns in 1..=5 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        maps.map_pawns[sq] = available as u64;
+                        maps.map_pawns[sq ^ 7] = (available - 1) as u64;
+                        available -= 2;
+                    }
+                    maps.lead_pawn_idx[lead_pawns][sq] = idx;
+                    idx += maps.binomial[lead_pawns - 1][maps.map_pawns[sq] as usize];
+                }
+                maps.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+        maps
+    })
+}
+
+fn read<const N: usize>(bytes: &[u8], at: usize) -> Option<[u8; N]> {
+    bytes.get(at..at.checked_add(N)?)?.try_into().ok()
+}
+
+fn u16_le(bytes: &[u8], at: usize) -> Option<u16> {
+    read(bytes, at).map(u16::from_le_bytes)
+}
+
+fn u32_le(bytes: &[u8], at: usize) -> Option<u32> {
+    read(bytes, at).map(u32::from_le_bytes)
+}
+
+// The decoder reads a little past the end of the last block, as zeros
+fn read_padded<const N: usize>(bytes: &[u8], at: usize) -> Option<[u8; N]> {
+    let available = bytes.get(at..)?;
+    let mut buf = [0; N];
+    let len = available.len().min(N);
+    buf[..len].copy_from_slice(&available[..len]);
+    Some(buf)
+}
+
+fn u32_be(bytes: &[u8], at: usize) -> Option<u32> {
+    read_padded(bytes, at).map(u32::from_be_bytes)
+}
+
+fn u64_be(bytes: &[u8], at: usize) -> Option<u64> {
+    read_padded(bytes, at).map(u64::from_be_bytes)
+}
+
+fn byte(bytes: &[u8], at: usize) -> Result<u8, String> {
+    bytes
+        .get(at)
+        .copied()
+        .ok_or_else(|| "truncated".to_string())
+}
+
+// How one table of a file is compressed. Offsets point into the file.
+#[derive(Debug, Clone, Default)]
+struct PairsData {
+    flags: u8,
+    min_sym_len: u8, // the value itself for SINGLE_VALUE tables
+    block_size: usize,
+    span: usize, // a sparse index entry about every `span` values
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    num_blocks: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>, // values represented by each symbol, less one
+    pieces: [u8; MAX_PIECES],
+    group_idx: [u64; MAX_PIECES + 1],
+    group_len: [usize; MAX_PIECES + 1],
+    map_idx: [usize; 4], // WDL win, loss, cursed win and blessed loss in DTZ tables
+}
+
+// A table file, mapped read-only for as long as the tablebases are open
+struct Mapping {
+    ptr: *const u8,
+    len: usize,
+}
+
+// The mapping is never written to
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    #[cfg(unix)]
+    fn open(path: &Path) -> Result<Mapping, String> {
+        use std::os::fd::AsRawFd;
+
+        let file = std::fs::File::open(path).map_err(|err| err.to_string())?;
+        let len = file.metadata().map_err(|err| err.to_string())?.len() as usize;
+        if len == 0 {
+            return Ok(Mapping {
+                ptr: std::ptr::NonNull::dangling().as_ptr(),
+                len,
+            });
+        }
+        // The file can be closed once it's mapped
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        Ok(Mapping {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    // Elsewhere the file is read whole
+    #[cfg(not(unix))]
+    fn open(path: &Path) -> Result<Mapping, String> {
+        let bytes = std::fs::read(path).map_err(|err| err.to_string())?;
+        let bytes = Box::leak(bytes.into_boxed_slice());
+        Ok(Mapping {
+            ptr: bytes.as_ptr(),
+            len: bytes.len(),
+        })
+    }
+}
+
+impl Deref for Mapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.len > 0 {
+            unsafe {
+                libc::munmap(self.ptr as *mut libc::c_void, self.len);
+            }
+        }
+        #[cfg(not(unix))]
+        unsafe {
+            drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                self.ptr as *mut u8,
+                self.len,
+            )));
+        }
+    }
+}
+
+// The tables of one file: [side to move][file of the leading pawn]
+struct Table {
+    bytes: Mapping,
+    items: [[PairsData; 4]; 2],
+    map: usize, // start of the DTZ value maps
+}
+
+// One set of material, such as KRvK, which covers KvKR too
+struct Material {
+    name: String,
+    dir: PathBuf,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    pawn_count: [usize; 2], // leading color first
+    symmetric: bool,
+    wdl: OnceLock<Option<Table>>,
+    dtz: OnceLock<Option<Table>>,
+}
+
+impl Material {
+    fn new(name: &str, dir: &Path) -> Option<Material> {
+        let (white, black) = name.split_once('v')?;
+        let kinds = |side: &str| -> Option<Vec<PieceKind>> {
+            side.chars()
+                .map(|c| PieceKind::from_char(c).filter(|_| c.is_ascii_uppercase()))
+                .collect()
+        };
+        let (white, black) = (kinds(white)?, kinds(black)?);
+        let count = |pieces: &[PieceKind], kind| pieces.iter().filter(|k| **k == kind).count();
+        if count(&white, PieceKind::King) != 1 || count(&black, PieceKind::King) != 1 {
+            return None;
+        }
+        let piece_count = white.len() + black.len();
+        if piece_count > MAX_PIECES {
+            return None;
+        }
+        let has_unique_pieces = [&white, &black].iter().any(|side| {
+            PieceKind::ALL[..5]
+                .iter()
+                .any(|kind| count(side, *kind) == 1)
+        });
+        let (white_pawns, black_pawns) = (
+            count(&white, PieceKind::Pawn),
+            count(&black, PieceKind::Pawn),
+        );
+        // The side with fewer pawns leads, it compresses better
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        Some(Material {
+            name: name.to_string(),
+            dir: dir.to_path_buf(),
+            piece_count,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawn_count: if white_leads {
+                [white_pawns, black_pawns]
+            } else {
+                [black_pawns, white_pawns]
+            },
+            symmetric: white == black,
+            wdl: OnceLock::new(),
+            dtz: OnceLock::new(),
+        })
+    }
+
+    fn table(&self, dtz: bool) -> Option<&Table> {
+        let (cell, extension) = if dtz {
+            (&self.dtz, "rtbz")
+        } else {
+            (&self.wdl, "rtbw")
+        };
+        cell.get_or_init(|| {
+            let path = self.dir.join(format!("{}.{}", self.name, extension));
+            match self.load(&path, dtz) {
+                Ok(table) => Some(table),
+                Err(err) => {
+                    // A missing DTZ table only means there's no DTZ
+                    if path.exists() || !dtz {
+                        println!("Failed to read tablebase {}: {}", path.display(), err);
+                    }
+                    None
+                }
+            }
+        })
+        .as_ref()
+    }
+
+    fn sides(&self, dtz: bool) -> usize {
+        if dtz || self.symmetric {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn files(&self) -> usize {
+        if self.has_pawns {
+            4
+        } else {
+            1
+        }
+    }
+
+    fn load(&self, path: &Path, dtz: bool) -> Result<Table, String> {
+        let bytes = Mapping::open(path)?;
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if bytes.get(..4) != Some(&magic[..]) {
+            return Err("not a Syzygy table".to_string());
+        }
+        let end = bytes.len();
+        let mut table = Table {
+            bytes,
+            items: Default::default(),
+            map: 0,
+        };
+        let flags = byte(&table.bytes, 4)?;
+        if (flags & HAS_PAWNS != 0) != self.has_pawns
+            || (!dtz && (flags & SPLIT != 0) == self.symmetric)
+        {
+            return Err("the table doesn't match its name".to_string());
+        }
+        self.read_layout(&mut table, dtz)?;
+        if table
+            .items
+            .iter()
+            .flatten()
+            .any(|d| d.flags & SINGLE_VALUE == 0 && d.data + d.num_blocks * d.block_size > end)
+        {
+            return Err("truncated".to_string());
+        }
+        Ok(table)
+    }
+
+    fn read_layout(&self, table: &mut Table, dtz: bool) -> Result<(), String> {
+        let bytes = &*table.bytes;
+        let sides = self.sides(dtz);
+        let pawns_both_sides = self.has_pawns && self.pawn_count[1] > 0;
+        let mut at = 5;
+
+        for file in 0..self.files() {
+            let order_byte = byte(bytes, at)?;
+            let second = if pawns_both_sides {
+                byte(bytes, at + 1)?
+            } else {
+                0xFF
+            };
+            let order = [
+                [(order_byte & 0xF) as usize, (second & 0xF) as usize],
+                [(order_byte >> 4) as usize, (second >> 4) as usize],
+            ];
+            at += 1 + pawns_both_sides as usize;
+            for k in 0..self.piece_count {
+                let pieces = byte(bytes, at)?;
+                for side in 0..sides {
+                    table.items[side][file].pieces[k] =
+                        if side == 1 { pieces >> 4 } else { pieces & 0xF };
+                }
+                at += 1;
+            }
+            for (side, order) in order.into_iter().enumerate().take(sides) {
+                self.set_groups(&mut table.items[side][file], order, file);
+            }
+        }
+        at += at & 1;
+
+        for file in 0..self.files() {
+            for side in 0..sides {
+                at = set_sizes(&mut table.items[side][file], bytes, at)?;
+            }
+        }
+
+        if dtz {
+            table.map = at;
+            for file in 0..self.files() {
+                let d = &mut table.items[0][file];
+                if d.flags & MAPPED == 0 {
+                    continue;
+                }
+                if d.flags & WIDE != 0 {
+                    at += at & 1;
+                    for i in 0..4 {
+                        d.map_idx[i] = (at - table.map) / 2 + 1;
+                        let len = u16_le(bytes, at).ok_or("truncated")? as usize;
+                        at += 2 * len + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        d.map_idx[i] = at - table.map + 1;
+                        at += byte(bytes, at)? as usize + 1;
+                    }
+                }
+            }
+            at += at & 1;
+        }
+
+        for file in 0..self.files() {
+            for side in 0..sides {
+                let d = &mut table.items[side][file];
+                d.sparse_index = at;
+                at += d.sparse_index_size * 6;
+            }
+        }
+        for file in 0..self.files() {
+            for side in 0..sides {
+                let d = &mut table.items[side][file];
+                d.block_length = at;
+                at += d.block_length_size * 2;
+            }
+        }
+        for file in 0..self.files() {
+            for side in 0..sides {
+                let d = &mut table.items[side][file];
+                at = (at + 0x3F) & !0x3F;
+                d.data = at;
+                at += d.num_blocks * d.block_size;
+            }
+        }
+        Ok(())
+    }
+
+    // Pieces of the same kind and color are placed together. The leading
+    // group is the pawns of one side, or without pawns the first three
+    // pieces, or only the kings when there's no lone piece besides them.
+    fn set_groups(&self, d: &mut PairsData, order: [usize; 2], file: usize) {
+        let maps = maps();
+        let mut n = 0;
+        let mut first_len: i32 = if self.has_pawns {
+            0
+        } else if self.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        d.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        // The groups are encoded in the order the file gives, the leading
+        // group at order[0] and the other side's pawns at order[1]
+        let pawns_both_sides = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = if pawns_both_sides { 2 } else { 1 };
+        let mut free_squares =
+            64 - d.group_len[0] - if pawns_both_sides { d.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                d.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    maps.lead_pawns_size[d.group_len[0]][file]
+                } else if self.has_unique_pieces {
+                    31_332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                d.group_idx[1] = idx;
+                idx *= maps.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= maps.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+    }
+}
+
+fn set_sizes(d: &mut PairsData, bytes: &[u8], mut at: usize) -> Result<usize, String> {
+    d.flags = byte(bytes, at)?;
+    at += 1;
+    if d.flags & SINGLE_VALUE != 0 {
+        d.min_sym_len = byte(bytes, at)?;
+        return Ok(at + 1);
+    }
+
+    let groups = d.group_len.iter().position(|len| *len == 0).unwrap_or(0);
+    let size = d.group_idx[groups] as usize;
+    d.block_size = 1 << byte(bytes, at)?;
+    d.span = 1 << byte(bytes, at + 1)?;
+    d.sparse_index_size = size.div_ceil(d.span);
+    let padding = byte(bytes, at + 2)? as usize;
+    d.num_blocks = u32_le(bytes, at + 3).ok_or("truncated")? as usize;
+    d.block_length_size = d.num_blocks + padding;
+    let max_sym_len = byte(bytes, at + 7)? as usize;
+    d.min_sym_len = byte(bytes, at + 8)?;
+    let min_sym_len = d.min_sym_len as usize;
+    at += 9;
+    if min_sym_len == 0 || max_sym_len < min_sym_len || max_sym_len > 32 {
+        return Err("bad symbol lengths".to_string());
+    }
+    d.lowest_sym = at;
+
+    // Longer codes have lower values in the canonical Huffman code, so
+    // base64[l] padded to 64 bits is the lowest code of length l
+    let lengths = max_sym_len - min_sym_len + 1;
+    let lowest = |i: usize| u16_le(bytes, d.lowest_sym + 2 * i).map(u64::from);
+    d.base64 = vec![0; lengths];
+    for i in (0..lengths - 1).rev() {
+        let (this, next) = (
+            lowest(i).ok_or("truncated")?,
+            lowest(i + 1).ok_or("truncated")?,
+        );
+        d.base64[i] = (d.base64[i + 1] + this).wrapping_sub(next) / 2;
+    }
+    for (i, base) in d.base64.iter_mut().enumerate() {
+        *base <<= 64 - i - min_sym_len;
+    }
+    at += lengths * 2;
+
+    let symbols = u16_le(bytes, at).ok_or("truncated")? as usize;
+    at += 2;
+    d.btree = at;
+    if bytes.len() < at + symbols * 3 {
+        return Err("truncated".to_string());
+    }
+    d.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for sym in 0..symbols {
+        if !visited[sym] {
+            d.symlen[sym] = set_symlen(d, bytes, sym, &mut visited)?;
+        }
+    }
+    Ok(at + symbols * 3 + (symbols & 1))
+}
+
+// Each symbol stands for a pair of symbols, a leaf for a single value.
+// Left child in the low 12 bits, right child in the high 12.
+fn children(bytes: &[u8], btree: usize, sym: usize) -> Option<(usize, usize)> {
+    let lr: [u8; 3] = read(bytes, btree + 3 * sym)?;
+    let left = ((lr[1] as usize & 0xF) << 8) | lr[0] as usize;
+    let right = ((lr[2] as usize) << 4) | (lr[1] as usize >> 4);
+    Some((left, right))
+}
+
+fn set_symlen(
+    d: &mut PairsData,
+    bytes: &[u8],
+    sym: usize,
+    visited: &mut [bool],
+) -> Result<u8, String> {
+    visited[sym] = true;
+    let (left, right) = children(bytes, d.btree, sym).ok_or("truncated")?;
+    if right == 0xFFF {
+        return Ok(0);
+    }
+    if left >= d.symlen.len() || right >= d.symlen.len() {
+        return Err("bad symbol tree".to_string());
+    }
+    for child in [left, right] {
+        if !visited[child] {
+            d.symlen[child] = set_symlen(d, bytes, child, visited)?;
+        }
+    }
+    Ok(d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1))
+}
+
+// The value stored at `idx`
+fn decompress(d: &PairsData, bytes: &[u8], idx: u64) -> Option<usize> {
+    if d.flags & SINGLE_VALUE != 0 {
+        return Some(d.min_sym_len as usize);
+    }
+
+    // The sparse index points at the block and offset of every span-th
+    // value, from there it's block lengths to the one holding `idx`
+    let k = (idx / d.span as u64) as usize;
+    let entry = d.sparse_index + 6 * k;
+    let mut block = u32_le(bytes, entry)? as usize;
+    let mut offset = u16_le(bytes, entry + 4)? as i64;
+    offset += (idx % d.span as u64) as i64 - (d.span / 2) as i64;
+    let block_length =
+        |block: usize| -> Option<i64> { Some(u16_le(bytes, d.block_length + 2 * block)? as i64) };
+    while offset < 0 {
+        block = block.checked_sub(1)?;
+        offset += block_length(block)? + 1;
+    }
+    while offset > block_length(block)? {
+        offset -= block_length(block)? + 1;
+        block += 1;
+    }
+
+    // Walk the symbols of the block until the one that covers `offset`
+    let min_sym_len = d.min_sym_len as usize;
+    let mut at = d.data + block * d.block_size;
+    let mut buf = u64_be(bytes, at)?;
+    at += 8;
+    let mut buf_size = 64;
+    let mut sym;
+    loop {
+        let mut len = 0;
+        while buf < *d.base64.get(len)? {
+            len += 1;
+        }
+        sym = ((buf - d.base64[len]) >> (64 - len - min_sym_len)) as usize;
+        sym += u16_le(bytes, d.lowest_sym + 2 * len)? as usize;
+        let count = *d.symlen.get(sym)? as i64 + 1;
+        if offset < count {
+            break;
+        }
+        offset -= count;
+        len += min_sym_len;
+        buf <<= len;
+        buf_size -= len;
+        if buf_size <= 32 {
+            buf_size += 32;
+            buf |= (u32_be(bytes, at)? as u64) << (64 - buf_size);
+            at += 4;
+        }
+    }
+
+    // Then down the pairs to the single value
+    while d.symlen[sym] != 0 {
+        let (left, right) = children(bytes, d.btree, sym)?;
+        let count = *d.symlen.get(left)? as i64 + 1;
+        if offset < count {
+            sym = left;
+        } else {
+            offset -= count;
+            sym = right;
+        }
+    }
+    children(bytes, d.btree, sym).map(|(left, _)| left)
+}
+
+// Piece codes of the files: 1 to 6 for pawn to king, plus 8 for black
+fn piece_code(color: Color, kind: PieceKind) -> u8 {
+    kind.index() as u8 + 1 + if color == Color::Black { 8 } else { 0 }
+}
+
+// "KRPvKR" with the pieces of `first` before the 'v'
+fn material_name(position: &Position, first: Color) -> String {
+    let mut name = String::new();
+    for color in [first, first.opposite()] {
+        if color != first {
+            name.push('v');
+        }
+        let own = position.occupancy(color);
+        for kind in PieceKind::ALL.iter().rev() {
+            let count = (position.pieces(*kind) & own).count_ones();
+            for _ in 0..count {
+                name.push(kind.to_char().to_ascii_uppercase());
+            }
+        }
+    }
+    name
+}
+
+enum Lookup {
+    Value(i32),
+    // One-sided DTZ table stored for the other side to move
+    ChangeStm,
+}
+
+pub struct Tablebases {
+    materials: HashMap<String, Arc<Material>>, // under both names
+    max_pieces: usize,
+    count: usize,
+}
+
+impl Tablebases {
+    // Every WDL table in the directories of `paths`, separated like PATH.
+    // DTZ tables are expected next to them.
+    pub fn open(paths: &str) -> Result<Self, String> {
+        let mut tablebases = Tablebases {
+            materials: HashMap::new(),
+            max_pieces: 0,
+            count: 0,
+        };
+        for dir in std::env::split_paths(paths) {
+            let entries =
+                std::fs::read_dir(&dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("rtbw") {
+                    continue;
+                }
+                let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                    continue;
+                };
+                if tablebases.materials.contains_key(name) {
+                    continue;
+                }
+                let Some(material) = Material::new(name, &dir) else {
+                    continue;
+                };
+                let (white, black) = name.split_once('v').unwrap_or_default();
+                let mirrored = format!("{}v{}", black, white);
+                tablebases.max_pieces = tablebases.max_pieces.max(material.piece_count);
+                tablebases.count += 1;
+                let material = Arc::new(material);
+                tablebases.materials.insert(mirrored, material.clone());
+                tablebases.materials.insert(name.to_string(), material);
+            }
+        }
+        Ok(tablebases)
+    }
+
+    // Sets of material found
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    // Whether the position could be in the tables at all
+    pub fn covers(&self, position: &Position) -> bool {
+        position.castling == 0 && position.occupied().count_ones() as usize <= self.max_pieces
+    }
+
+    // From the side to move's point of view, None if the position isn't in
+    // the tables
+    pub fn probe_wdl(&self, position: &Position) -> Option<Wdl> {
+        if !self.covers(position) {
+            return None;
+        }
+        self.search(position, false).map(|(wdl, _)| wdl)
+    }
+
+    // Plies to the next capture or pawn move of the best play, positive
+    // when the side to move wins and beyond 100 for cursed wins and blessed
+    // losses. 0 for draws.
+    pub fn probe_dtz(&self, position: &Position) -> Option<i32> {
+        if !self.covers(position) {
+            return None;
+        }
+        self.dtz(position)
+    }
+
+    // The position and every move from it, for playing it out perfectly
+    pub fn probe_root(&self, position: &Position) -> Option<RootProbe> {
+        let wdl = self.probe_wdl(position)?;
+        let dtz = self.probe_dtz(position)?;
+        let mut moves = Vec::new();
+        for mv in position.legal_moves() {
+            let child = position.make_move(&mv);
+            let mut dtz = if child.halfmove_clock == 0 {
+                dtz_before_zeroing(self.probe_wdl(&child)?.flip())
+            } else {
+                let dtz = -self.probe_dtz(&child)?;
+                dtz + dtz.signum()
+            };
+            if dtz == 2 && child.in_check() && child.legal_moves().is_empty() {
+                dtz = 1;
+            }
+            moves.push((mv, dtz));
+        }
+        moves.sort_by_key(|(_, dtz)| std::cmp::Reverse(rank(*dtz)));
+        Some(RootProbe { wdl, dtz, moves })
+    }
+
+    fn material(&self, position: &Position) -> Option<&Material> {
+        self.materials
+            .get(&material_name(position, Color::White))
+            .map(|material| material.as_ref())
+    }
+
+    // Captures, and with `zeroing` pawn moves too, aren't stored reliably:
+    // the tables hold whatever compresses best where one of them is the
+    // best move. So they're searched and weighed against the stored value.
+    // The flag is set when the best move is such a move.
+    fn search(&self, position: &Position, zeroing: bool) -> Option<(Wdl, bool)> {
+        let moves = position.legal_moves();
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for mv in &moves {
+            let pawn_move = matches!(
+                position.piece_at(mv.from as usize),
+                Some((_, PieceKind::Pawn))
+            );
+            if !(position.is_capture(mv) || (zeroing && pawn_move)) {
+                continue;
+            }
+            searched += 1;
+            let (value, _) = self.search(&position.make_move(mv), false)?;
+            let value = value.flip();
+            if value > best {
+                best = value;
+                if value >= Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        let all_searched = searched > 0 && searched == moves.len();
+        let value = if all_searched {
+            best
+        } else {
+            match self.probe_table(position, false, Wdl::Draw)? {
+                Lookup::Value(value) => Wdl::from_value(value)?,
+                Lookup::ChangeStm => return None,
+            }
+        };
+        if best >= value {
+            return Some((best, best > Wdl::Draw || all_searched));
+        }
+        Some((value, false))
+    }
+
+    fn dtz(&self, position: &Position) -> Option<i32> {
+        let (wdl, zeroing_best) = self.search(position, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing_best {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        match self.probe_table(position, true, wdl)? {
+            Lookup::Value(dtz) => {
+                let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+                Some((dtz + if cursed { 100 } else { 0 }) * wdl.sign())
+            }
+            // One ply deeper then, keeping the quickest way to the result
+            Lookup::ChangeStm => {
+                let mut best = i32::MAX;
+                for mv in position.legal_moves() {
+                    let zeroing = position.is_capture(&mv)
+                        || matches!(
+                            position.piece_at(mv.from as usize),
+                            Some((_, PieceKind::Pawn))
+                        );
+                    let child = position.make_move(&mv);
+                    let mut dtz = if zeroing {
+                        -dtz_before_zeroing(self.search(&child, false)?.0)
+                    } else {
+                        -self.dtz(&child)?
+                    };
+                    if dtz == 1 && child.in_check() && child.legal_moves().is_empty() {
+                        best = 1;
+                    }
+                    if !zeroing {
+                        dtz += dtz.signum();
+                    }
+                    if dtz < best && dtz.signum() == wdl.sign() {
+                        best = dtz;
+                    }
+                }
+                Some(if best == i32::MAX { -1 } else { best })
+            }
+        }
+    }
+
+    fn probe_table(&self, position: &Position, dtz: bool, wdl: Wdl) -> Option<Lookup> {
+        let occupied = position.occupied();
+        if occupied.count_ones() == 2 {
+            return Some(Lookup::Value(0)); // the kings alone
+        }
+        let material = self.material(position)?;
+        let table = material.table(dtz)?;
+        let maps = maps();
+
+        // Tables are stored with the side named first as white. Positions
+        // where black has that material are probed with the colors swapped
+        // and the board turned over, and so are symmetric ones with black
+        // to move, which are only stored for white.
+        let black_to_move = position.side_to_move == Color::Black;
+        let flip = (material.symmetric && black_to_move)
+            || material_name(position, Color::White) != material.name;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip != black_to_move) as usize;
+
+        let mut squares = [0usize; MAX_PIECES];
+        let mut pieces = [0u8; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = 0;
+        let mut lead_pawn_count = 0;
+        let mut file = 0;
+
+        // The pawns of one side lead, in the table of the file the one
+        // nearest the edge is on
+        if material.has_pawns {
+            let lead = table.items[0][0].pieces[0] ^ flip_color;
+            let color = if lead & 8 != 0 {
+                Color::Black
+            } else {
+                Color::White
+            };
+            lead_pawns = position.pieces(PieceKind::Pawn) & position.occupancy(color);
+            for sq in Squares(lead_pawns) {
+                squares[size] = sq ^ flip_squares;
+                size += 1;
+            }
+            lead_pawn_count = size;
+            let leader = (0..lead_pawn_count)
+                .reduce(|best, i| {
+                    if maps.map_pawns[squares[i]] > maps.map_pawns[squares[best]] {
+                        i
+                    } else {
+                        best
+                    }
+                })
+                .unwrap_or(0);
+            squares.swap(0, leader);
+            file = squares[0] % 8;
+            if file > 3 {
+                file = 7 - file;
+            }
+        }
+
+        let side = if dtz { 0 } else { stm };
+        let d = &table.items[side][file];
+        // Symmetric tables without pawns hold both sides to move
+        let one_sided = material.has_pawns || !material.symmetric;
+        if dtz && one_sided && (d.flags & STM) as usize != stm {
+            return Some(Lookup::ChangeStm);
+        }
+
+        for sq in Squares(occupied ^ lead_pawns) {
+            let (color, kind) = position.piece_at(sq)?;
+            squares[size] = sq ^ flip_squares;
+            pieces[size] = piece_code(color, kind) ^ flip_color;
+            size += 1;
+        }
+
+        // In the order the table has them
+        for i in lead_pawn_count..size.saturating_sub(1) {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // The leading piece goes on files a to d
+        if squares[0] % 8 > 3 {
+            for sq in squares.iter_mut().take(size) {
+                *sq ^= 7;
+            }
+        }
+
+        let mut idx: u64;
+        if material.has_pawns {
+            idx = maps.lead_pawn_idx[lead_pawn_count][squares[0]];
+            squares[1..lead_pawn_count].sort_by_key(|sq| maps.map_pawns[*sq]);
+            for (i, sq) in squares.iter().enumerate().take(lead_pawn_count).skip(1) {
+                idx += maps.binomial[i][maps.map_pawns[*sq] as usize];
+            }
+        } else {
+            // And below the fifth rank
+            if squares[0] / 8 > 3 {
+                for sq in squares.iter_mut().take(size) {
+                    *sq ^= 56;
+                }
+            }
+            // And the first of the leading group off the a1-h8 diagonal
+            // below it
+            for i in 0..d.group_len[0] {
+                if off_diagonal(squares[i]) == 0 {
+                    continue;
+                }
+                if off_diagonal(squares[i]) > 0 {
+                    for sq in squares.iter_mut().take(size).skip(i) {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            if material.has_unique_pieces {
+                let s = &squares;
+                let adjust1 = (s[1] > s[0]) as u64;
+                let adjust2 = (s[2] > s[0]) as u64 + (s[2] > s[1]) as u64;
+                let rank = |sq: usize| (sq / 8) as u64;
+                idx = if off_diagonal(s[0]) != 0 {
+                    (maps.map_a1d1d4[s[0]] as u64 * 63 + (s[1] as u64 - adjust1)) * 62 + s[2] as u64
+                        - adjust2
+                } else if off_diagonal(s[1]) != 0 {
+                    (6 * 63 + rank(s[0]) * 28 + maps.map_b1h1h7[s[1]]) * 62 + s[2] as u64 - adjust2
+                } else if off_diagonal(s[2]) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + rank(s[0]) * 7 * 28
+                        + (rank(s[1]) - adjust1) * 28
+                        + maps.map_b1h1h7[s[2]]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + rank(s[0]) * 7 * 6
+                        + (rank(s[1]) - adjust1) * 6
+                        + (rank(s[2]) - adjust2)
+                };
+            } else {
+                idx = maps.map_kk[maps.map_a1d1d4[squares[0]]][squares[1]];
+            }
+        }
+
+        // Then every other group, each square counted without those of
+        // the groups before it
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = material.has_pawns && material.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[start + i];
+                let adjust = squares[..start].iter().filter(|s| sq > **s).count();
+                let pawn_rows = if remaining_pawns { 8 } else { 0 };
+                n += maps.binomial[i + 1][sq.checked_sub(adjust + pawn_rows)?];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let value = decompress(d, &table.bytes, idx)? as i32;
+        if !dtz {
+            return Some(Lookup::Value(value - 2));
+        }
+        Some(Lookup::Value(map_dtz(table, file, value, wdl)?))
+    }
+}
+
+// DTZ values are stored by how often they occur, mapped back here. Some
+// tables count in moves rather than plies.
+fn map_dtz(table: &Table, file: usize, value: i32, wdl: Wdl) -> Option<i32> {
+    let d = &table.items[0][file];
+    let mut value = value;
+    if d.flags & MAPPED != 0 {
+        let index = match wdl {
+            Wdl::Win => 0,
+            Wdl::Loss => 1,
+            Wdl::CursedWin => 2,
+            Wdl::BlessedLoss => 3,
+            Wdl::Draw => return None,
+        };
+        let at = d.map_idx[index] + value as usize;
+        value = if d.flags & WIDE != 0 {
+            u16_le(&table.bytes, table.map + 2 * at)? as i32
+        } else {
+            *table.bytes.get(table.map + at)? as i32
+        };
+    }
+    let plies = match wdl {
+        Wdl::Win => d.flags & WIN_PLIES != 0,
+        Wdl::Loss => d.flags & LOSS_PLIES != 0,
+        _ => false,
+    };
+    if !plies {
+        value *= 2;
+    }
+    Some(value + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The 3 to 5 piece tables aren't kept in the repository, so the probe
+    // tests are ignored by default. Run them with
+    //
+    //     CHESS_SYZYGY_TEST_PATH=/path/to/syzygy cargo test -- --ignored
+    fn tablebases() -> Tablebases {
+        let path = std::env::var("CHESS_SYZYGY_TEST_PATH")
+            .expect("CHESS_SYZYGY_TEST_PATH should point at the Syzygy tables");
+        let tablebases = Tablebases::open(&path).unwrap();
+        assert!(tablebases.max_pieces() >= 3, "no tables at {}", path);
+        tablebases
+    }
+
+    fn probe(tablebases: &Tablebases, fen: &str) -> (Wdl, i32) {
+        let position = Position::from_fen(fen).unwrap();
+        let wdl = tablebases.probe_wdl(&position).expect(fen);
+        let dtz = tablebases.probe_dtz(&position).expect(fen);
+        (wdl, dtz)
+    }
+
+    #[test]
+    #[ignore = "needs CHESS_SYZYGY_TEST_PATH"]
+    fn probes_king_and_queen_against_king() {
+        let tablebases = tablebases();
+        // Qc8 mates
+        assert_eq!(
+            probe(&tablebases, "k7/8/1K6/8/8/8/8/2Q5 w - - 0 1"),
+            (Wdl::Win, 1)
+        );
+        assert_eq!(
+            probe(&tablebases, "k7/8/1K6/8/8/8/8/2Q5 b - - 0 1").0,
+            Wdl::Loss
+        );
+        // The queen is left hanging
+        assert_eq!(
+            probe(&tablebases, "8/8/8/8/8/8/1Qk5/7K b - - 0 1"),
+            (Wdl::Draw, 0)
+        );
+    }
+
+    #[test]
+    #[ignore = "needs CHESS_SYZYGY_TEST_PATH"]
+    fn probes_king_and_rook_against_king() {
+        let tablebases = tablebases();
+        // Rc8 mates
+        assert_eq!(
+            probe(&tablebases, "k7/8/1K6/8/8/8/8/2R5 w - - 0 1"),
+            (Wdl::Win, 1)
+        );
+        // With the colors the other way round
+        assert_eq!(
+            probe(&tablebases, "2r5/8/8/8/8/1k6/8/K7 b - - 0 1"),
+            (Wdl::Win, 1)
+        );
+        let (wdl, dtz) = probe(&tablebases, "k7/8/1K6/8/8/8/8/2R5 b - - 0 1");
+        assert_eq!(wdl, Wdl::Loss);
+        assert!(dtz < 0);
+    }
+
+    #[test]
+    #[ignore = "needs CHESS_SYZYGY_TEST_PATH"]
+    fn probes_king_and_pawn_against_king() {
+        let tablebases = tablebases();
+        // Promoting is a pawn move, so it's 1 either way
+        assert_eq!(
+            probe(&tablebases, "8/4P3/3K4/8/8/8/8/k7 w - - 0 1"),
+            (Wdl::Win, 1)
+        );
+        // The pawn on the sixth draws with white to move, and wins with
+        // black to move
+        assert_eq!(
+            probe(&tablebases, "4k3/8/4P3/4K3/8/8/8/8 w - - 0 1"),
+            (Wdl::Draw, 0)
+        );
+        let (wdl, dtz) = probe(&tablebases, "4k3/8/4P3/4K3/8/8/8/8 b - - 0 1");
+        assert_eq!(wdl, Wdl::Loss);
+        assert!(dtz < 0);
+        // The same, mirrored for black
+        assert_eq!(
+            probe(&tablebases, "8/8/8/8/4k3/4p3/8/4K3 b - - 0 1"),
+            (Wdl::Draw, 0)
+        );
+    }
+
+    #[test]
+    fn maps_a_file_and_reads_past_its_end_as_zeros() {
+        let path = std::env::temp_dir().join(format!("syzygy-{}.bin", std::process::id()));
+        std::fs::write(&path, [1, 2, 3, 4, 5, 6]).unwrap();
+        let mapping = Mapping::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&mapping[..], [1, 2, 3, 4, 5, 6]);
+        assert_eq!(u32_le(&mapping, 0), Some(0x04030201));
+        assert_eq!(u32_le(&mapping, 4), None);
+        assert_eq!(u64_be(&mapping, 2), Some(0x0304050600000000));
+        assert_eq!(u32_be(&mapping, 7), None);
+    }
+}
@@ -0,0 +1,157 @@
+// src/engine/tt.rs
+
+// Transposition table shared by all search threads. Each slot holds the
+// entry packed into one word next to the key xor'ed with that word, so a
+// slot torn by two threads writing at once simply fails to match and no
+// locking is needed. Entries are replaced when the new search is at least
+// as deep or the slot holds another position. Mate scores are stored
+// relative to the node so they stay correct at any ply.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::search::MATE_BOUND;
+use crate::position::{Move, PieceKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    Lower, // failed high, the score is at least this
+    Upper, // failed low, the score is at most this
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub depth: i32,
+    pub score: i32,
+    pub bound: Bound,
+    pub best: Option<Move>,
+}
+
+impl Entry {
+    // Score in bits 0-15, depth in 16-23, bound in 24-25 and the move in
+    // 26-41 (from, to, promotion and a bit saying there is a move)
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        let best = self.best.map_or(0, |mv| {
+            1 << 15
+                | (mv.promotion.map_or(0, |kind| kind.index() as u64) << 12)
+                | ((mv.to as u64) << 6)
+                | mv.from as u64
+        });
+        (self.score as i16 as u16 as u64)
+            | ((self.depth as i8 as u8 as u64) << 16)
+            | (bound << 24)
+            | (best << 26)
+    }
+
+    fn unpack(data: u64) -> Entry {
+        let best = (data >> 26) & 0xFFFF;
+        Entry {
+            score: data as u16 as i16 as i32,
+            depth: (data >> 16) as u8 as i8 as i32,
+            bound: match (data >> 24) & 3 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+            best: (best & (1 << 15) != 0).then(|| Move {
+                from: (best & 63) as u8,
+                to: ((best >> 6) & 63) as u8,
+                promotion: match (best >> 12) & 7 {
+                    0 => None,
+                    i => Some(PieceKind::ALL[i as usize]),
+                },
+            }),
+        }
+    }
+}
+
+pub struct Table {
+    slots: Vec<[AtomicU64; 2]>, // key ^ data, data
+}
+
+impl Table {
+    pub fn new(megabytes: usize) -> Self {
+        let slots = (megabytes * 1024 * 1024 / std::mem::size_of::<[AtomicU64; 2]>()).max(1);
+        Table {
+            slots: (0..1usize << slots.ilog2())
+                .map(|_| [AtomicU64::new(0), AtomicU64::new(0)])
+                .collect(),
+        }
+    }
+
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot[0].store(0, Ordering::Relaxed);
+            slot[1].store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn slot(&self, key: u64) -> &[AtomicU64; 2] {
+        &self.slots[(key as usize) & (self.slots.len() - 1)]
+    }
+
+    fn load(&self, key: u64) -> Option<Entry> {
+        let slot = self.slot(key);
+        let data = slot[1].load(Ordering::Relaxed);
+        (slot[0].load(Ordering::Relaxed) ^ data == key && data != 0).then(|| Entry::unpack(data))
+    }
+
+    pub fn probe(&self, key: u64, ply: i32) -> Option<Entry> {
+        let entry = self.load(key)?;
+        Some(Entry {
+            score: from_table(entry.score, ply),
+            ..entry
+        })
+    }
+
+    pub fn store(
+        &self,
+        key: u64,
+        depth: i32,
+        score: i32,
+        bound: Bound,
+        best: Option<Move>,
+        ply: i32,
+    ) {
+        let old = self.load(key);
+        if old.is_some_and(|old| old.depth > depth && bound != Bound::Exact) {
+            return;
+        }
+        let data = Entry {
+            depth,
+            score: to_table(score, ply),
+            bound,
+            // Keep the old move rather than none at all
+            best: best.or(old.and_then(|old| old.best)),
+        }
+        .pack();
+        let slot = self.slot(key);
+        slot[0].store(key ^ data, Ordering::Relaxed);
+        slot[1].store(data, Ordering::Relaxed);
+    }
+}
+
+fn to_table(score: i32, ply: i32) -> i32 {
+    if score >= MATE_BOUND {
+        score + ply
+    } else if score <= -MATE_BOUND {
+        score - ply
+    } else {
+        score
+    }
+}
+
+fn from_table(score: i32, ply: i32) -> i32 {
+    if score >= MATE_BOUND {
+        score - ply
+    } else if score <= -MATE_BOUND {
+        score + ply
+    } else {
+        score
+    }
+}
@@ -0,0 +1,55 @@
+// src/engine/zobrist.rs
+
+// Zobrist hashing for the transposition table and repetition checks. The
+// keys are generated at compile time from a fixed seed, so a position hashes
+// the same way in every run.
+
+use crate::position::{Color, PieceKind, Position, Squares};
+
+const PIECE_KEYS: usize = 2 * 6 * 64;
+const CASTLING_KEYS: usize = PIECE_KEYS; // one per set of castling rights
+const EN_PASSANT_KEYS: usize = CASTLING_KEYS + 16; // one per file
+const SIDE_KEY: usize = EN_PASSANT_KEYS + 8;
+
+const KEYS: [u64; SIDE_KEY + 1] = generate_keys();
+
+// splitmix64
+const fn generate_keys() -> [u64; SIDE_KEY + 1] {
+    let mut keys = [0; SIDE_KEY + 1];
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    let mut i = 0;
+    while i < keys.len() {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        keys[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
+}
+
+pub fn hash(position: &Position) -> u64 {
+    let mut hash = 0;
+    for color in [Color::White, Color::Black] {
+        let own = position.occupancy(color);
+        for kind in PieceKind::ALL {
+            let base = (color.index() * 6 + kind.index()) * 64;
+            for sq in Squares(position.pieces(kind) & own) {
+                hash ^= KEYS[base + sq];
+            }
+        }
+    }
+    hash ^= KEYS[CASTLING_KEYS + position.castling as usize];
+    // Like repetitions, the en passant square only counts if it can be used
+    if let Some(ep) = position
+        .en_passant
+        .filter(|_| position.en_passant_capturable())
+    {
+        hash ^= KEYS[EN_PASSANT_KEYS + ep as usize % 8];
+    }
+    if position.side_to_move == Color::Black {
+        hash ^= KEYS[SIDE_KEY];
+    }
+    hash
+}
@@ -0,0 +1,461 @@
+// src/engine_match.rs
+
+// Games between engines and the statistics of their results, shared by the
+// self-play harness in src/bin/selfplay.rs and the match runner in
+// src/bin/match.rs. A player is a bot level or a UCI engine, a game is
+// played out from an opening on a fixed time per move or on the server's
+// clocks, and a tally of the results gives the Elo difference with its 95%
+// interval and the log-likelihood ratio of a sequential probability ratio
+// test.
+
+use rand::seq::SliceRandom;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::{Clock, TimeControl};
+use crate::engine::{self, Engine, EngineProfile, ExternalEngine, Level, Limits, Tablebases, Wdl};
+use crate::position::{Color, Move, Position};
+
+pub const RANDOM_OPENING_PLIES: usize = 4;
+pub const MAX_PLIES: usize = 400; // adjudicated a draw after this
+pub const HASH_MB: usize = 16;
+// Moves left to plan for when splitting the remaining time
+const MOVES_TO_GO: u32 = 30;
+// Kept back from the clock for talking to UCI engines
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+// Error rates of the SPRT
+const ALPHA: f64 = 0.05;
+const BETA: f64 = 0.05;
+
+#[derive(Clone)]
+pub enum Player {
+    Level(&'static Level),
+    External(EngineProfile),
+}
+
+impl Player {
+    pub fn name(&self) -> String {
+        match self {
+            Player::Level(level) => format!("level {}", level.level),
+            Player::External(profile) => profile.name.clone(),
+        }
+    }
+}
+
+// A player with the engine it thinks with, kept from game to game
+pub enum Side {
+    Level(&'static Level, Engine),
+    External(ExternalEngine),
+}
+
+impl Side {
+    pub fn new(player: &Player, tablebases: Option<&Arc<Tablebases>>) -> Self {
+        match player {
+            Player::Level(level) => {
+                let mut engine = Engine::new(HASH_MB);
+                engine.set_tablebases(tablebases.cloned());
+                Side::Level(level, engine)
+            }
+            Player::External(profile) => Side::External(ExternalEngine::new(profile.clone())),
+        }
+    }
+
+    fn new_game(&mut self) {
+        if let Side::Level(_, engine) = self {
+            engine.clear();
+        }
+    }
+
+    // The move after `moves` from `start`, which lead to `position`
+    fn choose(
+        &mut self,
+        start: &Position,
+        moves: &[Move],
+        position: &Position,
+        history: &[u64],
+        movetime: Duration,
+    ) -> Result<Option<Move>, String> {
+        match self {
+            Side::Level(level, engine) => {
+                Ok(level.choose_move(engine, position, history, Some(movetime)))
+            }
+            Side::External(external) => {
+                let limits = Limits {
+                    movetime: Some(movetime.saturating_sub(MOVE_OVERHEAD).max(MOVE_OVERHEAD)),
+                    ..Limits::default()
+                };
+                external
+                    .search(start, moves, limits, |_| {})
+                    .map(|result| result.best_move)
+                    .map_err(|err| format!("{}: {}", external.name(), err))
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Opening {
+    pub start: Position,
+    pub moves: Vec<Move>,
+}
+
+impl Opening {
+    pub fn position(&self) -> Position {
+        self.moves
+            .iter()
+            .fold(self.start.clone(), |position, mv| position.make_move(mv))
+    }
+}
+
+// A few random moves from the start, leaving a game to play
+pub fn random_opening() -> Opening {
+    let mut rng = rand::thread_rng();
+    loop {
+        let mut position = Position::startpos();
+        let mut moves = Vec::new();
+        for _ in 0..RANDOM_OPENING_PLIES {
+            let Some(mv) = position.legal_moves().choose(&mut rng).copied() else {
+                break;
+            };
+            position = position.make_move(&mv);
+            moves.push(mv);
+        }
+        if !position.legal_moves().is_empty() {
+            return Opening {
+                start: Position::startpos(),
+                moves,
+            };
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TimeLimit {
+    PerMove(Duration),
+    // Losing on time is possible
+    Clock(TimeControl),
+}
+
+pub struct Outcome {
+    pub score: f64, // for white: 1.0, 0.5 or 0.0
+    pub termination: &'static str,
+    pub moves: Vec<Move>, // from the opening's start, the opening included
+}
+
+fn outcome(score: f64, termination: &'static str, moves: Vec<Move>) -> Outcome {
+    Outcome {
+        score,
+        termination,
+        moves,
+    }
+}
+
+// 1.0 for `color`
+fn win_for(color: Color) -> f64 {
+    match color {
+        Color::White => 1.0,
+        Color::Black => 0.0,
+    }
+}
+
+pub fn result_str(score: f64) -> &'static str {
+    if score == 1.0 {
+        "1-0"
+    } else if score == 0.0 {
+        "0-1"
+    } else {
+        "1/2-1/2"
+    }
+}
+
+// With the tablebases a game that reaches them is decided by what they say
+pub fn play_game(
+    sides: [&mut Side; 2], // white first
+    opening: &Opening,
+    time_limit: TimeLimit,
+    tablebases: Option<&Arc<Tablebases>>,
+) -> Outcome {
+    let mut position = opening.start.clone();
+    let mut moves = Vec::new();
+    let mut history = Vec::new();
+    let mut seen: HashMap<u64, usize> = HashMap::new();
+    for mv in &opening.moves {
+        let key = engine::hash(&position);
+        history.push(key);
+        *seen.entry(key).or_default() += 1;
+        position = position.make_move(mv);
+        moves.push(*mv);
+    }
+    let [white, black] = sides;
+    white.new_game();
+    black.new_game();
+
+    let (mut clock, per_move, increment) = match time_limit {
+        TimeLimit::Clock(time_control) => {
+            let mut clock = Clock::new(time_control);
+            clock.start(position.side_to_move.as_str());
+            let increment = Duration::from_secs(time_control.increment);
+            (Some(clock), Duration::ZERO, increment)
+        }
+        TimeLimit::PerMove(movetime) => (None, movetime, Duration::ZERO),
+    };
+    for _ in 0..MAX_PLIES {
+        let mover = position.side_to_move;
+        let key = engine::hash(&position);
+        let repeated = seen.entry(key).or_default();
+        *repeated += 1;
+        if position.legal_moves().is_empty() {
+            return if position.in_check() {
+                outcome(win_for(mover.opposite()), "checkmate", moves)
+            } else {
+                outcome(0.5, "stalemate", moves)
+            };
+        }
+        if *repeated >= 3 {
+            return outcome(0.5, "threefold_repetition", moves);
+        }
+        if position.halfmove_clock >= 100 {
+            return outcome(0.5, "fifty_move_rule", moves);
+        }
+        if position.is_insufficient_material() {
+            return outcome(0.5, "insufficient_material", moves);
+        }
+        // Right after a capture, so the fifty-move rule is accounted for
+        let wdl = tablebases
+            .filter(|_| position.halfmove_clock == 0)
+            .and_then(|tablebases| tablebases.probe_wdl(&position));
+        if let Some(wdl) = wdl {
+            let score = match wdl {
+                Wdl::Win => win_for(mover),
+                Wdl::Loss => win_for(mover.opposite()),
+                _ => 0.5,
+            };
+            return outcome(score, "tablebase_adjudication", moves);
+        }
+
+        let side = match mover {
+            Color::White => &mut *white,
+            Color::Black => &mut *black,
+        };
+        let movetime = clock.as_ref().map_or(per_move, |clock| {
+            engine::allot_time(clock.remaining(mover.as_str()), increment, MOVES_TO_GO)
+        });
+        let chosen = side.choose(&opening.start, &moves, &position, &history, movetime);
+        let mv = match chosen {
+            Ok(Some(mv)) if position.legal_moves().contains(&mv) => mv,
+            Ok(_) => {
+                eprintln!("No legal move from the engine in {}", position.to_fen());
+                return outcome(win_for(mover.opposite()), "engine_error", moves);
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                return outcome(win_for(mover.opposite()), "engine_error", moves);
+            }
+        };
+        if let Some(clock) = clock.as_mut() {
+            if !clock.press(mover.as_str()) {
+                let opponent = mover.opposite();
+                let score = if position.has_mating_material(opponent) {
+                    win_for(opponent)
+                } else {
+                    0.5
+                };
+                return outcome(score, "timeout", moves);
+            }
+        }
+        history.push(key);
+        position = position.make_move(&mv);
+        moves.push(mv);
+    }
+    outcome(0.5, "max_plies", moves)
+}
+
+#[derive(Debug, Default)]
+pub struct Tally {
+    pub wins: usize, // from the first player's point of view
+    pub draws: usize,
+    pub losses: usize,
+}
+
+impl Tally {
+    // A game's score for the first player
+    pub fn add(&mut self, score: f64) {
+        if score == 1.0 {
+            self.wins += 1;
+        } else if score == 0.0 {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+    }
+
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    // Variance of the score of a single game
+    fn variance(&self) -> f64 {
+        let n = self.games().max(1) as f64;
+        let squares = (self.wins as f64 + self.draws as f64 / 4.0) / n;
+        (squares - self.score() * self.score()).max(0.0)
+    }
+
+    // Elo difference and the half width of its 95% confidence interval
+    pub fn elo(&self) -> (f64, f64) {
+        let deviation = (self.variance() / self.games().max(1) as f64).sqrt();
+        let low = elo_of(self.score() - 1.96 * deviation);
+        let high = elo_of(self.score() + 1.96 * deviation);
+        (elo_of(self.score()), (high - low) / 2.0)
+    }
+
+    // Log-likelihood ratio of elo1 against elo0, by the normal
+    // approximation of the score
+    pub fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        let variance = self.variance() / self.games().max(1) as f64;
+        if variance == 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (score_of(elo0), score_of(elo1));
+        (s1 - s0) * (2.0 * self.score() - s0 - s1) / (2.0 * variance)
+    }
+}
+
+pub fn elo_of(score: f64) -> f64 {
+    let score = score.clamp(0.001, 0.999);
+    400.0 * (score / (1.0 - score)).log10()
+}
+
+pub fn score_of(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+// Lower and upper bound of the LLR
+pub fn sprt_bounds() -> (f64, f64) {
+    ((BETA / (1.0 - ALPHA)).ln(), ((1.0 - BETA) / ALPHA).ln())
+}
+
+pub fn sprt_verdict(llr: f64) -> Option<&'static str> {
+    let (lower, upper) = sprt_bounds();
+    if llr >= upper {
+        Some("H1 accepted")
+    } else if llr <= lower {
+        Some("H0 accepted")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tally(wins: usize, draws: usize, losses: usize) -> Tally {
+        Tally {
+            wins,
+            draws,
+            losses,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} isn't {} within {}",
+            actual,
+            expected,
+            tolerance
+        );
+    }
+
+    #[test]
+    fn converts_between_scores_and_elo() {
+        assert_close(elo_of(0.5), 0.0, 1e-9);
+        assert_close(elo_of(0.75), 190.85, 0.01);
+        assert_close(elo_of(0.25), -190.85, 0.01);
+        assert_close(elo_of(10.0 / 11.0), 400.0, 1e-9);
+        assert_close(score_of(400.0), 10.0 / 11.0, 1e-12);
+        for elo in [-300.0, -35.0, 0.0, 5.0, 120.0] {
+            assert_close(elo_of(score_of(elo)), elo, 1e-9);
+        }
+        // A clean sweep is clamped rather than infinite
+        assert_close(elo_of(1.0), 1199.8, 0.1);
+        assert_close(elo_of(0.0), -1199.8, 0.1);
+    }
+
+    #[test]
+    fn tallies_scores() {
+        let mut counted = Tally::default();
+        for score in [1.0, 0.5, 0.0, 1.0] {
+            counted.add(score);
+        }
+        assert_eq!((counted.wins, counted.draws, counted.losses), (2, 1, 1));
+        assert_close(counted.score(), 0.625, 1e-12);
+        assert_eq!(Tally::default().score(), 0.0);
+    }
+
+    #[test]
+    fn works_out_the_elo_and_its_margin() {
+        // Score 0.6 with a variance of 0.55 - 0.36 per game, so a standard
+        // deviation of sqrt(0.19 / 200) over 200 games
+        let (elo, margin) = tally(100, 40, 60).elo();
+        assert_close(elo, 70.44, 0.01);
+        let deviation = (0.19f64 / 200.0).sqrt();
+        let expected = (elo_of(0.6 + 1.96 * deviation) - elo_of(0.6 - 1.96 * deviation)) / 2.0;
+        assert_close(margin, expected, 1e-9);
+        assert_close(margin, 44.0, 0.1);
+
+        let (elo, margin) = tally(50, 100, 50).elo();
+        assert_close(elo, 0.0, 1e-9);
+        assert_close(margin, 34.2, 0.1);
+        // All draws leave no doubt
+        assert_eq!(tally(0, 30, 0).elo(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn works_out_the_sprt_llr() {
+        // s0 = 0.5 and s1 = score_of(10) at elo0 0 and elo1 10
+        let results = tally(100, 40, 60);
+        let (s0, s1) = (0.5, score_of(10.0));
+        let variance = 0.19 / 200.0;
+        let expected = (s1 - s0) * (1.2 - s0 - s1) / (2.0 * variance);
+        assert_close(results.llr(0.0, 10.0), expected, 1e-9);
+        assert_close(results.llr(0.0, 10.0), 1.41, 0.01);
+        // Scoring exactly between the two hypotheses favours neither
+        let even = tally(50, 100, 50);
+        assert_close(even.llr(-10.0, 10.0), 0.0, 1e-9);
+        assert!(even.llr(0.0, 10.0) < 0.0);
+        assert_eq!(tally(0, 10, 0).llr(0.0, 10.0), 0.0);
+    }
+
+    #[test]
+    fn decides_the_sprt_at_its_bounds() {
+        let (lower, upper) = sprt_bounds();
+        assert_close(lower, -2.944, 0.001);
+        assert_close(upper, 2.944, 0.001);
+        assert_eq!(sprt_verdict(3.0), Some("H1 accepted"));
+        assert_eq!(sprt_verdict(-3.0), Some("H0 accepted"));
+        assert_eq!(sprt_verdict(1.0), None);
+    }
+
+    #[test]
+    fn plays_a_game_to_the_end() {
+        let level = engine::level(1).unwrap();
+        let mut white = Side::new(&Player::Level(level), None);
+        let mut black = Side::new(&Player::Level(level), None);
+        let opening = random_opening();
+        let game = play_game(
+            [&mut white, &mut black],
+            &opening,
+            TimeLimit::PerMove(Duration::from_millis(1)),
+            None,
+        );
+        assert!([0.0, 0.5, 1.0].contains(&game.score));
+        assert!(game.moves.starts_with(&opening.moves));
+        assert!(game.moves.len() <= opening.moves.len() + MAX_PLIES);
+    }
+}
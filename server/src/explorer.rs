@@ -0,0 +1,264 @@
+// src/explorer.rs
+
+// The opening explorer: for any position, every move played from it in our
+// games and in imported PGN, with results, ratings and example games.
+// Positions are keyed by the engine's Zobrist hash, so transpositions meet.
+// Games are added as they finish, and the whole index can be built again
+// from the stored games and PGN files:
+//
+//     chess_server explorer-rebuild [FILE.pgn...]
+//     chess_server explorer-import FILE.pgn...
+
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashSet;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::api::{bad_request, internal_error};
+use crate::eco;
+use crate::engine;
+use crate::notation::{move_to_san, parse_pgn};
+use crate::position::{Move, Position};
+use crate::rating::Speed;
+use crate::storage::{
+    civil_from_days, player_for, replay, ExplorerGame, GameStore, RoomFilter, RoomRecord,
+    StorageResult,
+};
+
+// Only the opening is indexed
+pub const MAX_PLIES: usize = 60;
+const EXAMPLES: usize = 3;
+const REBUILD_PAGE: usize = 500;
+
+#[derive(Deserialize)]
+pub struct ExplorerQuery {
+    pub fen: Option<String>, // the initial position if None
+}
+
+// Key and move for every ply, a position counting once per game
+fn plies(start: &Position, moves: &[Move]) -> Vec<(u64, String)> {
+    let mut seen = HashSet::new();
+    let mut position = start.clone();
+    let mut plies = Vec::new();
+    for mv in moves.iter().take(MAX_PLIES) {
+        let key = engine::hash(&position);
+        if seen.insert(key) {
+            plies.push((key, mv.to_uci()));
+        }
+        position = position.make_move(mv);
+    }
+    plies
+}
+
+fn add_game(
+    store: &dyn GameStore,
+    game: &ExplorerGame,
+    start: &Position,
+    moves: &[Move],
+) -> StorageResult<()> {
+    if moves.is_empty() {
+        return Ok(());
+    }
+    store.add_explorer_game(game, &plies(start, moves))
+}
+
+// One of our finished games. Ratings are the players' after the game, and
+// only for rated games.
+pub fn add_room(store: &dyn GameStore, record: &RoomRecord) -> Result<(), String> {
+    let Some(result) = record.result.clone() else {
+        return Ok(());
+    };
+    let (moves, positions) = replay(record)?;
+    let speed = Speed::from_time_control(record.time_control);
+    let player = |color: &str| -> StorageResult<(String, Option<i64>)> {
+        let Some(id) = player_for(record, color) else {
+            return Ok(("?".to_string(), None));
+        };
+        let name = store
+            .load_user(id)?
+            .map_or_else(|| format!("Player {}", id), |user| user.username);
+        let rating = if record.rated {
+            store
+                .rating_history(id, speed)?
+                .iter()
+                .find(|entry| entry.room_id == record.id)
+                .map(|entry| entry.rating.rating.round() as i64)
+        } else {
+            None
+        };
+        Ok((name, rating))
+    };
+    let (white, white_rating) = player("w").map_err(|err| err.to_string())?;
+    let (black, black_rating) = player("b").map_err(|err| err.to_string())?;
+    let (year, month, day) = civil_from_days((record.created_at / 86400) as i64);
+    let game = ExplorerGame {
+        room_id: Some(record.id),
+        white,
+        black,
+        white_rating,
+        black_rating,
+        result,
+        date: Some(format!("{:04}.{:02}.{:02}", year, month, day)),
+    };
+    add_game(store, &game, &positions[0], &moves).map_err(|err| err.to_string())
+}
+
+// Returns how many games were added, and why each of the others wasn't
+pub fn import_pgn(store: &dyn GameStore, text: &str) -> (usize, Vec<String>) {
+    let mut added = 0;
+    let mut errors = Vec::new();
+    for (i, game) in parse_pgn(text).into_iter().enumerate() {
+        let game = match game {
+            Ok(game) => game,
+            Err(err) => {
+                errors.push(format!("Game {}: {}", i + 1, err));
+                continue;
+            }
+        };
+        if !matches!(game.result.as_str(), "1-0" | "0-1" | "1/2-1/2") {
+            errors.push(format!("Game {}: no result", i + 1));
+            continue;
+        }
+        let known = |name: &str| {
+            game.header(name)
+                .filter(|value| !value.is_empty() && !value.contains('?'))
+                .map(|value| value.to_string())
+        };
+        let record = ExplorerGame {
+            // Our own PGN export names the game
+            room_id: known("GameId").and_then(|id| Uuid::parse_str(&id).ok()),
+            white: known("White").unwrap_or_else(|| "?".to_string()),
+            black: known("Black").unwrap_or_else(|| "?".to_string()),
+            white_rating: known("WhiteElo").and_then(|elo| elo.parse().ok()),
+            black_rating: known("BlackElo").and_then(|elo| elo.parse().ok()),
+            result: game.result.clone(),
+            date: known("Date"),
+        };
+        match add_game(store, &record, &game.start, &game.moves) {
+            Ok(()) => added += 1,
+            Err(err) => errors.push(format!("Game {}: {}", i + 1, err)),
+        }
+    }
+    (added, errors)
+}
+
+pub fn import_files(store: &dyn GameStore, paths: &[String]) -> Result<(), String> {
+    for path in paths {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        let (added, errors) = import_pgn(store, &text);
+        for error in &errors {
+            println!("{}: {}", path, error);
+        }
+        println!(
+            "Added {} games from {} to the explorer, skipped {}",
+            added,
+            path,
+            errors.len()
+        );
+    }
+    Ok(())
+}
+
+// Empties the explorer, adds every finished game in the store, oldest
+// first like they were played, then the games in `pgn_files`
+pub fn rebuild(store: &dyn GameStore, pgn_files: &[String]) -> Result<(), String> {
+    store.clear_explorer().map_err(|err| err.to_string())?;
+    let filter = RoomFilter::default();
+    let (_, total) = store
+        .list_rooms(&filter, 0, 0)
+        .map_err(|err| err.to_string())?;
+    let mut added = 0;
+    // Pages come newest first
+    for offset in (0..total).step_by(REBUILD_PAGE).rev() {
+        let (rooms, _) = store
+            .list_rooms(&filter, offset, REBUILD_PAGE)
+            .map_err(|err| err.to_string())?;
+        for record in rooms.iter().rev() {
+            if record.result.is_none() || record.moves.is_empty() {
+                continue;
+            }
+            match add_room(store, record) {
+                Ok(()) => added += 1,
+                Err(err) => println!("Skipping game {}: {}", record.id, err),
+            }
+        }
+    }
+    println!("Added {} stored games to the explorer", added);
+    import_files(store, pgn_files)
+}
+
+fn percent(count: usize, total: usize) -> f64 {
+    (count as f64 * 1000.0 / total.max(1) as f64).round() / 10.0
+}
+
+pub async fn get_explorer(
+    store: web::Data<Arc<dyn GameStore>>,
+    query: web::Query<ExplorerQuery>,
+) -> HttpResponse {
+    let position = match query.fen.as_deref() {
+        Some(fen) => match Position::from_fen(fen) {
+            Ok(position) => position,
+            Err(err) => return bad_request(&format!("Invalid FEN: {}", err)),
+        },
+        None => Position::startpos(),
+    };
+    let key = engine::hash(&position);
+    let store = store.get_ref().clone();
+    let moves = match web::block(move || store.explorer_moves(key, EXAMPLES)).await {
+        Ok(Ok(moves)) => moves,
+        Ok(Err(err)) => return internal_error(&err.to_string()),
+        Err(err) => return internal_error(&err.to_string()),
+    };
+
+    let mut totals = (0, 0, 0, 0);
+    let mut listed = Vec::new();
+    for mv in &moves {
+        // Skips what only got here through a hash collision
+        let Some(legal) = position.find_uci_move(&mv.uci) else {
+            continue;
+        };
+        totals.0 += mv.games;
+        totals.1 += mv.white_wins;
+        totals.2 += mv.draws;
+        totals.3 += mv.black_wins;
+        let examples: Vec<_> = mv
+            .examples
+            .iter()
+            .map(|game| {
+                json!({
+                    "id": game.room_id.map(|id| id.to_string()),
+                    "white": game.white,
+                    "black": game.black,
+                    "white_rating": game.white_rating,
+                    "black_rating": game.black_rating,
+                    "result": game.result,
+                    "date": game.date,
+                })
+            })
+            .collect();
+        listed.push(json!({
+            "uci": mv.uci,
+            "san": move_to_san(&position, &legal),
+            "games": mv.games,
+            "white": percent(mv.white_wins, mv.games),
+            "draws": percent(mv.draws, mv.games),
+            "black": percent(mv.black_wins, mv.games),
+            "average_rating": mv.average_rating,
+            "examples": examples,
+        }));
+    }
+
+    let opening = eco::lookup(&position);
+    HttpResponse::Ok().json(json!({
+        "fen": position.to_fen(),
+        "eco": opening.map(|opening| opening.eco),
+        "opening": opening.map(|opening| opening.name),
+        "games": totals.0,
+        "white": percent(totals.1, totals.0),
+        "draws": percent(totals.2, totals.0),
+        "black": percent(totals.3, totals.0),
+        "moves": listed,
+    }))
+}
@@ -1,72 +1,579 @@
 // src/game_room.rs
 
 use actix::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
-use crate::bitboard::{apply_move, bitboard_to_pieces, Bitboard, MoveData};
-use crate::messages::{RoomJoined, UpdateClient};
+use crate::adjudication::Rules;
+use crate::bitboard::{bitboard_to_pieces, MoveData};
+use crate::clock::{Clock, TimeControl};
+use crate::eco::{self, Opening};
+use crate::messages::{GameOver, LiveRoomInfo, RoomJoined, UpdateClient};
+use crate::position::Position;
+use crate::rating::RatingChange;
+use crate::storage::{unix_now, PlayerRecord, RoomRecord};
 use crate::websocket::MyWebSocket;
 
+// How long after a game its players can still ask for a rematch. The room
+// is dropped from memory after that.
+pub const REMATCH_WINDOW: Duration = Duration::from_secs(120);
+
+pub fn random_color() -> &'static str {
+    if rand::random::<bool>() {
+        "w"
+    } else {
+        "b"
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl GameResult {
+    pub fn win_for(color: &str) -> Self {
+        if color == "w" {
+            GameResult::WhiteWins
+        } else {
+            GameResult::BlackWins
+        }
+    }
+
+    // 1 for a win, 0.5 for a draw and 0 for a loss
+    pub fn score_for(&self, color: &str) -> f64 {
+        match self {
+            GameResult::Draw => 0.5,
+            GameResult::WhiteWins if color == "w" => 1.0,
+            GameResult::BlackWins if color == "b" => 1.0,
+            _ => 0.0,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        }
+    }
+}
+
+impl FromStr for GameResult {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1-0" => Ok(GameResult::WhiteWins),
+            "0-1" => Ok(GameResult::BlackWins),
+            "1/2-1/2" => Ok(GameResult::Draw),
+            _ => Err(format!("Unknown result '{}'", s)),
+        }
+    }
+}
+
 pub struct GameRoom {
     pub id: Uuid,
-    pub players: Vec<(usize, Addr<MyWebSocket>)>, // (session_id, address)
-    pub bitboard: Bitboard,
-    pub turn: String, // "w" or "b"
-    pub player_colors: HashMap<usize, String>, // session_id -> color
+    pub players: Vec<(usize, usize, Addr<MyWebSocket>)>, // (session_id, user_id, address)
+    pub position: Position,
+    pub seats: HashMap<String, usize>, // color -> user_id
+    pub time_control: Option<TimeControl>,
+    pub rated: bool,
+    pub clock: Option<Clock>,
+    pub result: Option<(GameResult, String)>, // (result, termination)
+    pub rematch_offers: HashSet<usize>,       // user ids that asked for a rematch
+    pub rematch_of: Option<Uuid>,
+    pub rematch_room: Option<Uuid>,
+    pub moves: Vec<MoveData>,
+    pub history: Vec<String>, // repetition keys of every position reached
+    pub opening: Option<Opening>, // of the last position the ECO table knows
+    pub adjudication: Option<Rules>,
+    pub evals: Vec<Option<i32>>, // adjudication searches by ply, from White's point of view
+    pub created_at: u64,         // unix seconds
+    pub finished_at: Option<Instant>,
+    pub persisted: bool, // whether storage has the result
 }
 
 impl GameRoom {
-    pub fn new(id: Uuid, bitboard: Bitboard) -> Self {
+    pub fn new(id: Uuid, position: Position, time_control: Option<TimeControl>) -> Self {
         GameRoom {
             id,
             players: Vec::new(),
-            bitboard,
-            turn: "w".to_string(),
-            player_colors: HashMap::new(),
+            history: vec![position.repetition_key()],
+            opening: eco::lookup(&position),
+            position,
+            adjudication: None,
+            evals: Vec::new(),
+            seats: HashMap::new(),
+            time_control,
+            rated: false,
+            clock: time_control.map(Clock::new),
+            result: None,
+            rematch_offers: HashSet::new(),
+            rematch_of: None,
+            rematch_room: None,
+            moves: Vec::new(),
+            created_at: unix_now(),
+            finished_at: None,
+            persisted: false,
+        }
+    }
+
+    // Rebuilds an unfinished room from storage. Seats are kept, the players
+    // reconnect to them through `join_room`.
+    pub fn restore(record: &RoomRecord) -> Self {
+        let mut room = GameRoom::new(record.id, Position::startpos(), record.time_control);
+        room.rematch_of = record.rematch_of;
+        room.rated = record.rated;
+        room.adjudication = record.adjudication;
+        room.created_at = record.created_at;
+
+        for move_data in &record.moves {
+            room.apply_move(move_data);
+        }
+
+        if let Some(clock) = room.clock.as_mut() {
+            if let Some(ms) = record.white_time_ms {
+                clock.white_remaining = Duration::from_millis(ms);
+            }
+            if let Some(ms) = record.black_time_ms {
+                clock.black_remaining = Duration::from_millis(ms);
+            }
+        }
+        // Seated last so the clock restarts for the side to move
+        for player in &record.players {
+            room.take_seat(player.player_id, &player.color);
+        }
+
+        room
+    }
+
+    pub fn to_record(&self) -> RoomRecord {
+        RoomRecord {
+            id: self.id,
+            time_control: self.time_control,
+            rematch_of: self.rematch_of,
+            rated: self.rated,
+            adjudication: self.adjudication,
+            players: self
+                .seats
+                .iter()
+                .map(|(color, id)| PlayerRecord {
+                    player_id: *id,
+                    color: color.clone(),
+                })
+                .collect(),
+            moves: self.moves.clone(),
+            white_time_ms: self.clock_ms().map(|(white, _)| white),
+            black_time_ms: self.clock_ms().map(|(_, black)| black),
+            result: self.result.as_ref().map(|(r, _)| r.as_str().to_string()),
+            termination: self.result.as_ref().map(|(_, t)| t.clone()),
+            eco: None, // stored with the result
+            opening: None,
+            created_at: self.created_at,
+            finished_at: None,
+        }
+    }
+
+    pub fn live_info(&self) -> LiveRoomInfo {
+        LiveRoomInfo {
+            room_id: self.id,
+            white: self.seat("w"),
+            black: self.seat("b"),
+            time_control: self.time_control.map(|tc| tc.to_string()),
+            rated: self.rated,
+            ply_count: self.moves.len(),
+            turn: self.turn().to_string(),
+            fen: self.position.to_fen(),
+            eco: self.opening.map(|opening| opening.eco),
+            opening: self.opening.map(|opening| opening.name),
+            white_time_ms: self.clock_ms().map(|(white, _)| white),
+            black_time_ms: self.clock_ms().map(|(_, black)| black),
+            created_at: self.created_at,
+        }
+    }
+
+    // Takes whichever seat is still free, a random one if both are
+    pub fn add_player(&mut self, user_id: usize, session_id: usize, addr: Addr<MyWebSocket>) {
+        let color = match (self.seat("w"), self.seat("b")) {
+            (None, Some(_)) => "w",
+            (Some(_), None) => "b",
+            _ => random_color(),
+        };
+        self.add_player_as(user_id, session_id, addr, color);
+    }
+
+    pub fn add_player_as(
+        &mut self,
+        user_id: usize,
+        session_id: usize,
+        addr: Addr<MyWebSocket>,
+        color: &str,
+    ) {
+        self.take_seat(user_id, color);
+        self.attach(user_id, session_id, addr);
+    }
+
+    // Seats a player who doesn't have to be connected, tournament games are
+    // set up this way
+    pub fn take_seat(&mut self, user_id: usize, color: &str) {
+        self.seats.insert(color.to_string(), user_id);
+        println!(
+            "User {} took the {} seat in room {}",
+            user_id, color, self.id
+        );
+
+        // The clock starts once both seats are taken
+        if self.is_full() {
+            let turn = self.turn();
+            if let Some(clock) = self.clock.as_mut() {
+                clock.start(turn);
+            }
         }
     }
 
-    pub fn add_player(&mut self, session_id: usize, addr: Addr<MyWebSocket>) {
-        let color = if self.players.is_empty() { "w" } else { "b" };
-        self.player_colors.insert(session_id, color.to_string());
-        self.players.push((session_id, addr.clone()));
+    // Connects another session (a new tab or a reconnect) of a seated user
+    pub fn attach(&mut self, user_id: usize, session_id: usize, addr: Addr<MyWebSocket>) {
+        let color = match self.get_player_color(user_id) {
+            Some(color) => color,
+            None => return,
+        };
+        if !self.players.iter().any(|(id, _, _)| *id == session_id) {
+            self.players.push((session_id, user_id, addr.clone()));
+        }
 
         // Send RoomJoined message to player
         addr.do_send(RoomJoined {
             room_id: self.id,
-            color: color.to_string(),
+            color,
         });
+        if !self.moves.is_empty() {
+            addr.do_send(self.update_message());
+        }
+
+        println!(
+            "Player with session id {} joined room {}",
+            session_id, self.id
+        );
+    }
+
+    pub fn detach(&mut self, session_id: usize) {
+        self.players.retain(|(id, _, _)| *id != session_id);
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.seats.len() == 2
+    }
+
+    pub fn seat(&self, color: &str) -> Option<usize> {
+        self.seats.get(color).copied()
+    }
+
+    pub fn get_player_color(&self, user_id: usize) -> Option<String> {
+        self.seats
+            .iter()
+            .find(|(_, id)| **id == user_id)
+            .map(|(color, _)| color.clone())
+    }
+
+    pub fn get_opponent(&self, user_id: usize) -> Option<usize> {
+        self.seats.values().find(|id| **id != user_id).copied()
+    }
+
+    // Delivers a message to every connected session of the user
+    pub fn send_to_user<M>(&self, user_id: usize, msg: M)
+    where
+        M: Message + Send + Clone + 'static,
+        M::Result: Send,
+        MyWebSocket: Handler<M>,
+    {
+        for (_session_id, id, addr) in &self.players {
+            if *id == user_id {
+                addr.do_send(msg.clone());
+            }
+        }
+    }
 
-        println!("Player with session id {} joined room {}", session_id, self.id);
+    pub fn is_finished(&self) -> bool {
+        self.result.is_some()
     }
 
-    pub fn get_player_color(&self, session_id: usize) -> Option<String> {
-        self.player_colors.get(&session_id).cloned()
+    // Whether the game is over and stored and a rematch can no longer be
+    // asked for
+    pub fn can_evict(&self, now: Instant) -> bool {
+        self.persisted
+            && self
+                .finished_at
+                .is_some_and(|at| self.rematch_room.is_some() || now - at >= REMATCH_WINDOW)
     }
 
+    pub fn turn(&self) -> &'static str {
+        self.position.side_to_move.as_str()
+    }
+
+    // Plays the move if it is legal for the side to move
     pub fn apply_move(&mut self, move_data: &MoveData) -> bool {
-        apply_move(&mut self.bitboard, move_data)
+        match self.position.find_move(move_data) {
+            Some(mv) => {
+                self.position = self.position.make_move(&mv);
+                self.history.push(self.position.repetition_key());
+                self.moves.push(mv.into());
+                if let Some(opening) = eco::lookup(&self.position) {
+                    self.opening = Some(opening);
+                }
+                true
+            }
+            None => false,
+        }
     }
 
-    pub fn switch_turn(&mut self) {
-        self.turn = if self.turn == "w" {
-            "b".to_string()
-        } else {
-            "w".to_string()
+    // Result and termination if the position on the board ends the game
+    pub fn board_result(&self) -> Option<(GameResult, &'static str)> {
+        let position = &self.position;
+        if position.legal_moves().is_empty() {
+            return if position.in_check() {
+                let winner = position.side_to_move.opposite();
+                Some((GameResult::win_for(winner.as_str()), "checkmate"))
+            } else {
+                Some((GameResult::Draw, "stalemate"))
+            };
+        }
+        if position.is_insufficient_material() {
+            return Some((GameResult::Draw, "insufficient_material"));
+        }
+        if position.halfmove_clock >= 100 {
+            return Some((GameResult::Draw, "fifty_move_rule"));
+        }
+        let current = self.history.last();
+        if self
+            .history
+            .iter()
+            .filter(|key| Some(*key) == current)
+            .count()
+            >= 3
+        {
+            return Some((GameResult::Draw, "threefold_repetition"));
+        }
+        None
+    }
+
+    pub fn record_eval(&mut self, ply: usize, cp: i32) {
+        if self.evals.len() <= ply {
+            self.evals.resize(ply + 1, None);
+        }
+        self.evals[ply] = Some(cp);
+    }
+
+    // Result and termination if an adjudication rule ends the game, given
+    // what the tablebases say about the current position
+    pub fn adjudicate(&self, tablebase: Option<GameResult>) -> Option<(GameResult, &'static str)> {
+        let rules = self.adjudication?;
+        if let Some(result) = tablebase.filter(|_| rules.tablebase) {
+            return Some((result, "tablebase_adjudication"));
+        }
+
+        // The evaluations after each of the last `moves` moves of both sides
+        let ply = self.moves.len();
+        let last = |moves: usize| -> Option<Vec<i32>> {
+            let first = (ply + 1).checked_sub(2 * moves)?;
+            self.evals.get(first..=ply)?.iter().copied().collect()
         };
+        if let Some(win) = rules.win {
+            if let Some(evals) = last(win.moves) {
+                if evals.iter().all(|cp| *cp >= win.margin) {
+                    return Some((GameResult::WhiteWins, "win_adjudication"));
+                }
+                if evals.iter().all(|cp| *cp <= -win.margin) {
+                    return Some((GameResult::BlackWins, "win_adjudication"));
+                }
+            }
+        }
+        if let Some(draw) = rules.draw {
+            let dead = ply >= 2 * (draw.after_move + draw.moves)
+                && last(draw.moves)
+                    .is_some_and(|evals| evals.iter().all(|cp| cp.abs() <= draw.margin));
+            if dead {
+                return Some((GameResult::Draw, "draw_adjudication"));
+            }
+        }
+        None
     }
 
-    pub fn broadcast_update(&self) {
-        let pieces = bitboard_to_pieces(&self.bitboard);
+    // Result when the side to move runs out of time. It's a draw if the
+    // opponent couldn't possibly mate.
+    pub fn timeout_result(&self) -> GameResult {
+        let opponent = self.position.side_to_move.opposite();
+        if self.position.has_mating_material(opponent) {
+            GameResult::win_for(opponent.as_str())
+        } else {
+            GameResult::Draw
+        }
+    }
+
+    // Remaining (white, black) time in milliseconds
+    pub fn clock_ms(&self) -> Option<(u64, u64)> {
+        self.clock.as_ref().map(|c| {
+            (
+                c.remaining("w").as_millis() as u64,
+                c.remaining("b").as_millis() as u64,
+            )
+        })
+    }
+
+    // Presses the clock for the side that just moved. Returns false if that
+    // side had already flagged, in which case the game is over on time.
+    pub fn press_clock(&mut self) -> bool {
+        match self.clock.as_mut() {
+            Some(clock) => clock.press(self.position.side_to_move.opposite().as_str()),
+            None => true,
+        }
+    }
 
-        let update_msg = UpdateClient {
-            pieces,
-            turn: self.turn.clone(),
+    pub fn is_flagged(&self) -> bool {
+        match &self.clock {
+            Some(clock) => self.is_full() && clock.is_flagged(self.turn()),
+            None => false,
+        }
+    }
+
+    pub fn finish(
+        &mut self,
+        result: GameResult,
+        termination: &str,
+        rating_changes: Vec<RatingChange>,
+    ) {
+        if self.is_finished() {
+            return;
+        }
+
+        if let Some(clock) = self.clock.as_mut() {
+            clock.stop();
+        }
+        self.result = Some((result, termination.to_string()));
+        self.finished_at = Some(Instant::now());
+
+        let game_over_msg = GameOver {
+            room_id: self.id,
+            result: result.as_str().to_string(),
+            termination: termination.to_string(),
+            rating_changes,
         };
 
-        for (_session_id, player) in &self.players {
+        for (_session_id, _user_id, player) in &self.players {
+            player.do_send(game_over_msg.clone());
+        }
+
+        println!(
+            "Game in room {} finished {} by {}",
+            self.id,
+            result.as_str(),
+            termination
+        );
+    }
+
+    fn update_message(&self) -> UpdateClient {
+        UpdateClient {
+            pieces: bitboard_to_pieces(&self.position.board),
+            turn: self.turn().to_string(),
+            white_time: self.clock_ms().map(|(white, _)| white),
+            black_time: self.clock_ms().map(|(_, black)| black),
+            opening: self.opening,
+        }
+    }
+
+    pub fn broadcast_update(&self) {
+        let update_msg = self.update_message();
+
+        for (_session_id, _user_id, player) in &self.players {
             player.do_send(update_msg.clone());
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(players: &[(usize, &str)], moves: &[&str]) -> RoomRecord {
+        let mut position = Position::startpos();
+        let mut move_data = Vec::new();
+        for uci in moves {
+            let mv = position
+                .legal_moves()
+                .into_iter()
+                .find(|mv| mv.to_uci() == *uci)
+                .unwrap();
+            position = position.make_move(&mv);
+            move_data.push(mv.into());
+        }
+        RoomRecord {
+            id: Uuid::new_v4(),
+            time_control: Some(TimeControl {
+                initial: 60,
+                increment: 0,
+            }),
+            rematch_of: None,
+            rated: true,
+            adjudication: None,
+            players: players
+                .iter()
+                .map(|(id, color)| PlayerRecord {
+                    player_id: *id,
+                    color: color.to_string(),
+                })
+                .collect(),
+            moves: move_data,
+            white_time_ms: Some(50_000),
+            black_time_ms: Some(40_000),
+            result: None,
+            termination: None,
+            eco: None,
+            opening: None,
+            created_at: 7,
+            finished_at: None,
+        }
+    }
+
+    #[test]
+    fn restored_room_runs_the_clock_of_the_side_to_move() {
+        let room = GameRoom::restore(&record(&[(1, "w"), (2, "b")], &["e2e4"]));
+        assert_eq!(room.turn(), "b");
+        assert_eq!(room.seat("w"), Some(1));
+        assert_eq!(room.seat("b"), Some(2));
+        assert!(room.rated);
+        assert_eq!(room.created_at, 7);
+
+        let clock = room.clock.as_ref().unwrap();
+        assert_eq!(
+            clock.running.as_ref().map(|(color, _)| color.as_str()),
+            Some("b")
+        );
+        assert_eq!(clock.remaining("w"), Duration::from_millis(50_000));
+        assert!(clock.remaining("b") <= Duration::from_millis(40_000));
+        assert!(clock.remaining("b") > Duration::from_millis(39_000));
+        assert!(!room.is_flagged());
+    }
+
+    #[test]
+    fn restored_room_waits_for_its_second_player() {
+        let room = GameRoom::restore(&record(&[(1, "w")], &[]));
+        assert!(!room.is_full());
+        assert!(room.clock.as_ref().unwrap().running.is_none());
+    }
+
+    #[test]
+    fn room_knows_its_opening_as_it_goes() {
+        let room = GameRoom::restore(&record(&[(1, "w"), (2, "b")], &["e2e4", "c7c5"]));
+        assert_eq!(room.opening.map(|opening| opening.eco), Some("B20"));
+        // Leaving the table keeps the last name it gave
+        let room = GameRoom::restore(&record(
+            &[(1, "w"), (2, "b")],
+            &["e2e4", "c7c5", "a2a3", "h7h6"],
+        ));
+        assert_eq!(room.opening.map(|opening| opening.eco), Some("B20"));
+    }
+}
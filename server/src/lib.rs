@@ -0,0 +1,12 @@
+// src/lib.rs
+
+// The chess logic, notation, clocks and the engine, which don't depend on
+// the server, and engine_match for playing engines against each other. The
+// server binary and the tools in src/bin build on this.
+
+pub mod bitboard;
+pub mod clock;
+pub mod engine;
+pub mod engine_match;
+pub mod notation;
+pub mod position;
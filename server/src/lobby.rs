@@ -0,0 +1,39 @@
+// src/lobby.rs
+
+use uuid::Uuid;
+
+use crate::clock::TimeControl;
+use crate::messages::SeekInfo;
+use crate::rating::Speed;
+
+// Open seeks a single user can have at once
+pub const MAX_SEEKS_PER_USER: usize = 3;
+
+pub struct Seek {
+    pub id: Uuid,
+    pub user_id: usize,
+    pub session_id: usize, // seeks are withdrawn when this session goes away
+    pub username: String,
+    pub rating: f64,
+    pub time_control: Option<TimeControl>,
+    pub variant: String,
+    pub rated: bool,
+    pub color: String,   // the seeker's choice: "white", "black" or "random"
+    pub created_at: u64, // unix seconds
+}
+
+impl Seek {
+    pub fn info(&self) -> SeekInfo {
+        SeekInfo {
+            seek_id: self.id,
+            username: self.username.clone(),
+            rating: self.rating.round() as i64,
+            time_control: self.time_control.map(|tc| tc.to_string()),
+            speed: Speed::from_time_control(self.time_control),
+            variant: self.variant.clone(),
+            rated: self.rated,
+            color: self.color.clone(),
+            created_at: self.created_at,
+        }
+    }
+}
@@ -1,39 +1,125 @@
 // src/main.rs
 
 use actix::Actor;
+use actix_cors::Cors;
+use actix_web::http::header;
 use actix_web::{web, App, Error, HttpRequest, HttpResponse, HttpServer};
 use actix_web_actors::ws;
+use serde::Deserialize;
+use serde_json::json;
 use std::sync::Arc;
 
-mod bitboard;
+mod accounts;
+mod adjudication;
+mod analysis;
+mod api;
+mod auth;
+mod bot;
+mod challenges;
+mod eco;
+mod explorer;
 mod game_room;
+mod lobby;
+mod matchmaking;
 mod messages;
+mod rating;
+mod review;
 mod server;
+mod storage;
+mod tournament;
 mod websocket;
 
+use chess_server::{bitboard, clock, engine, notation, position};
+
+use crate::auth::TokenSigner;
 use crate::server::Server;
+use crate::storage::{GameStore, MemoryStore, SqliteStore};
 use crate::websocket::MyWebSocket;
 
+#[derive(Deserialize)]
+struct WsQuery {
+    token: Option<String>,
+}
+
+// The session token from /api/login goes in the query string, browsers
+// can't set headers on a websocket upgrade
 async fn ws_index(
     req: HttpRequest,
     stream: web::Payload,
     srv: web::Data<Arc<actix::Addr<Server>>>,
+    store: web::Data<Arc<dyn GameStore>>,
+    signer: web::Data<Arc<TokenSigner>>,
+    query: web::Query<WsQuery>,
 ) -> Result<HttpResponse, Error> {
-    let ws = MyWebSocket::new((**srv.get_ref()).clone());
+    let unauthorized = || {
+        HttpResponse::Unauthorized().json(json!({ "error": "Missing or invalid session token" }))
+    };
+    let user_id = match query.token.as_deref().and_then(|t| signer.verify(t)) {
+        Some(user_id) => user_id,
+        None => return Ok(unauthorized()),
+    };
+    let store = store.get_ref().clone();
+    let user = match web::block(move || store.load_user(user_id)).await? {
+        Ok(Some(user)) => user,
+        Ok(None) => return Ok(unauthorized()),
+        Err(err) => return Err(actix_web::error::ErrorInternalServerError(err.to_string())),
+    };
+    let ws = MyWebSocket::new((**srv.get_ref()).clone(), user.id, user.is_guest);
     ws::start(ws, &req, stream)
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let server = Server::new().start();
+    // CHESS_STORAGE=memory keeps games only for the lifetime of the process
+    let store: Arc<dyn GameStore> = match std::env::var("CHESS_STORAGE").as_deref() {
+        Ok("memory") => Arc::new(MemoryStore::new()),
+        _ => {
+            let path = std::env::var("CHESS_DB").unwrap_or_else(|_| "chess.db".to_string());
+            let store =
+                SqliteStore::open(&path).map_err(|e| std::io::Error::other(e.to_string()))?;
+            println!("Using game database at {}", path);
+            Arc::new(store)
+        }
+    };
+
+    // Maintenance commands for the opening explorer, which run and exit
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("explorer-import") => {
+            return explorer::import_files(store.as_ref(), &args[1..])
+                .map_err(std::io::Error::other)
+        }
+        Some("explorer-rebuild") => {
+            return explorer::rebuild(store.as_ref(), &args[1..]).map_err(std::io::Error::other)
+        }
+        _ => {}
+    }
+
+    let signer = Arc::new(
+        TokenSigner::from_env(store.as_ref()).map_err(|e| std::io::Error::other(e.to_string()))?,
+    );
+    // Browsers only get answers for the web client's own origin
+    let client_origin = std::env::var("CHESS_CLIENT_ORIGIN")
+        .unwrap_or_else(|_| "http://localhost:3000".to_string());
+    let server = Server::new(store.clone()).start();
     let server_addr = Arc::new(server);
 
     println!("Starting WebSocket server at ws://127.0.0.1:8080/ws/");
 
     HttpServer::new(move || {
+        // The web client is served from another origin
+        let cors = Cors::default()
+            .allowed_origin(&client_origin)
+            .allowed_methods(["GET", "POST"])
+            .allowed_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+            .max_age(3600);
         App::new()
+            .wrap(cors)
             .app_data(web::Data::new(server_addr.clone()))
+            .app_data(web::Data::new(store.clone()))
+            .app_data(web::Data::new(signer.clone()))
             .route("/ws/", web::get().to(ws_index))
+            .configure(api::configure)
     })
     .bind("127.0.0.1:8080")?
     .run()
@@ -0,0 +1,361 @@
+// src/matchmaking.rs
+
+use actix::Addr;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::clock::TimeControl;
+use crate::game_room::random_color;
+use crate::websocket::MyWebSocket;
+
+// The rating window starts narrow and grows while a player waits
+const INITIAL_WINDOW: f64 = 100.0;
+const WINDOW_GROWTH_PER_SEC: f64 = 10.0;
+const MAX_WINDOW: f64 = 800.0;
+// Players who just played each other are only paired again after this wait
+const REPEAT_PAIRING_WAIT: Duration = Duration::from_secs(20);
+// Number of recent waits per pool used for the estimate
+const WAIT_SAMPLES: usize = 20;
+
+pub const VARIANTS: &[&str] = &["standard"];
+// Games looked at when balancing colors
+pub const COLOR_HISTORY_LEN: usize = 10;
+
+// Entries can only be paired within the same pool
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    pub time_control: Option<TimeControl>,
+    pub rated: bool,
+    pub variant: String,
+}
+
+pub struct QueueEntry {
+    pub session_id: usize,
+    pub user_id: usize,
+    pub addr: Addr<MyWebSocket>,
+    pub pool: PoolKey,
+    pub rating: f64,
+    pub rating_range: Option<(f64, f64)>, // accepted opponent ratings, inclusive
+    pub joined_at: Instant,
+}
+
+impl QueueEntry {
+    fn window(&self, now: Instant) -> f64 {
+        let waited = now.duration_since(self.joined_at).as_secs_f64();
+        (INITIAL_WINDOW + waited * WINDOW_GROWTH_PER_SEC).min(MAX_WINDOW)
+    }
+
+    fn accepts(&self, other: &QueueEntry, now: Instant) -> bool {
+        match self.rating_range {
+            Some((min, max)) => (min..=max).contains(&other.rating),
+            None => (self.rating - other.rating).abs() <= self.window(now),
+        }
+    }
+}
+
+pub struct QueueStatus {
+    pub position: usize, // 1-based, within the entry's pool
+    pub pool_size: usize,
+    pub estimated_wait: Option<Duration>,
+}
+
+#[derive(Default)]
+pub struct Matchmaker {
+    entries: Vec<QueueEntry>,                        // oldest first
+    last_opponent: HashMap<usize, (usize, Instant)>, // user_id -> (opponent, paired at)
+    waits: HashMap<PoolKey, VecDeque<Duration>>,
+}
+
+impl Matchmaker {
+    pub fn new() -> Self {
+        Matchmaker::default()
+    }
+
+    pub fn join(&mut self, entry: QueueEntry) -> Result<(), &'static str> {
+        // A user can't be paired against their own other tab
+        if self.entries.iter().any(|e| e.user_id == entry.user_id) {
+            return Err("Already in the queue");
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn leave(&mut self, session_id: usize) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.session_id != session_id);
+        self.entries.len() != before
+    }
+
+    fn is_repeat(&self, a: &QueueEntry, b: &QueueEntry, now: Instant) -> bool {
+        let waited_enough =
+            |entry: &QueueEntry| now.duration_since(entry.joined_at) >= REPEAT_PAIRING_WAIT;
+        match self.last_opponent.get(&a.user_id) {
+            Some((opponent, _)) if *opponent == b.user_id => {
+                !(waited_enough(a) && waited_enough(b))
+            }
+            _ => false,
+        }
+    }
+
+    fn compatible(&self, a: &QueueEntry, b: &QueueEntry, now: Instant) -> bool {
+        a.user_id != b.user_id
+            && a.pool == b.pool
+            && a.accepts(b, now)
+            && b.accepts(a, now)
+            && !self.is_repeat(a, b, now)
+    }
+
+    // Pairs as many entries as possible, longest waiting first. Each entry
+    // gets the compatible opponent closest to its rating.
+    pub fn find_pairs(&mut self) -> Vec<(QueueEntry, QueueEntry)> {
+        let now = Instant::now();
+        let mut pairs = Vec::new();
+        let mut i = 0;
+        while i < self.entries.len() {
+            let entry = &self.entries[i];
+            let best = self
+                .entries
+                .iter()
+                .enumerate()
+                .skip(i + 1)
+                .filter(|(_, other)| self.compatible(entry, other, now))
+                .min_by(|(_, x), (_, y)| {
+                    let dx = (x.rating - entry.rating).abs();
+                    let dy = (y.rating - entry.rating).abs();
+                    dx.total_cmp(&dy)
+                })
+                .map(|(j, _)| j);
+
+            match best {
+                Some(j) => {
+                    let second = self.entries.remove(j);
+                    let first = self.entries.remove(i);
+                    self.record_pairing(&first, &second, now);
+                    pairs.push((first, second));
+                }
+                None => i += 1,
+            }
+        }
+        pairs
+    }
+
+    fn record_pairing(&mut self, a: &QueueEntry, b: &QueueEntry, now: Instant) {
+        self.last_opponent.insert(a.user_id, (b.user_id, now));
+        self.last_opponent.insert(b.user_id, (a.user_id, now));
+        // Old pairings no longer matter once the wait would be over anyway
+        self.last_opponent
+            .retain(|_, (_, at)| now.duration_since(*at) < REPEAT_PAIRING_WAIT * 10);
+
+        let waits = self.waits.entry(a.pool.clone()).or_default();
+        for entry in [a, b] {
+            waits.push_back(now.duration_since(entry.joined_at));
+            if waits.len() > WAIT_SAMPLES {
+                waits.pop_front();
+            }
+        }
+    }
+
+    // Average wait in the pool minus the time already spent waiting
+    fn estimated_wait(&self, entry: &QueueEntry, now: Instant) -> Option<Duration> {
+        let waits = self.waits.get(&entry.pool)?;
+        if waits.is_empty() {
+            return None;
+        }
+        let average = waits.iter().sum::<Duration>() / waits.len() as u32;
+        Some(average.saturating_sub(now.duration_since(entry.joined_at)))
+    }
+
+    pub fn statuses(&self) -> Vec<(Addr<MyWebSocket>, QueueStatus)> {
+        let now = Instant::now();
+        let mut statuses = Vec::with_capacity(self.entries.len());
+        let mut positions: HashMap<&PoolKey, usize> = HashMap::new();
+        for entry in &self.entries {
+            let position = positions.entry(&entry.pool).or_insert(0);
+            *position += 1;
+            statuses.push((entry.addr.clone(), *position, entry));
+        }
+        statuses
+            .into_iter()
+            .map(|(addr, position, entry)| {
+                let status = QueueStatus {
+                    position,
+                    pool_size: positions[&entry.pool],
+                    estimated_wait: self.estimated_wait(entry, now),
+                };
+                (addr, status)
+            })
+            .collect()
+    }
+}
+
+// Colors for a new pairing given each player's recent colors, newest first.
+// Whoever has had white more often gets black. On a tie, whoever had white
+// last gets black, and if that doesn't settle it either it's random.
+pub fn assign_colors(first: &[String], second: &[String]) -> (&'static str, &'static str) {
+    let balance = |history: &[String]| -> i32 {
+        history
+            .iter()
+            .map(|color| if color == "w" { 1 } else { -1 })
+            .sum()
+    };
+    let last_white = |history: &[String]| history.first().map(|color| color == "w");
+
+    let first_gets_white = match balance(first).cmp(&balance(second)) {
+        std::cmp::Ordering::Less => true,
+        std::cmp::Ordering::Greater => false,
+        std::cmp::Ordering::Equal => match (last_white(first), last_white(second)) {
+            (Some(false), Some(true)) | (Some(false), None) | (None, Some(true)) => true,
+            (Some(true), Some(false)) | (Some(true), None) | (None, Some(false)) => false,
+            _ => random_color() == "w",
+        },
+    };
+
+    if first_gets_white {
+        ("w", "b")
+    } else {
+        ("b", "w")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::dev::channel;
+
+    fn pool() -> PoolKey {
+        PoolKey {
+            time_control: Some(TimeControl {
+                initial: 300,
+                increment: 0,
+            }),
+            rated: false,
+            variant: "standard".to_string(),
+        }
+    }
+
+    // `waited` seconds in the queue already. The address goes nowhere.
+    fn entry(user_id: usize, rating: f64, waited: u64) -> QueueEntry {
+        QueueEntry {
+            session_id: user_id,
+            user_id,
+            addr: Addr::new(channel::channel(1).0),
+            pool: pool(),
+            rating,
+            rating_range: None,
+            joined_at: Instant::now() - Duration::from_secs(waited),
+        }
+    }
+
+    fn pair_ids(matchmaker: &mut Matchmaker) -> Vec<(usize, usize)> {
+        matchmaker
+            .find_pairs()
+            .iter()
+            .map(|(first, second)| (first.user_id, second.user_id))
+            .collect()
+    }
+
+    #[test]
+    fn pairs_the_closest_rating_longest_waiting_first() {
+        let mut matchmaker = Matchmaker::new();
+        matchmaker.join(entry(1, 1500.0, 5)).unwrap();
+        matchmaker.join(entry(2, 1580.0, 0)).unwrap();
+        matchmaker.join(entry(3, 1520.0, 0)).unwrap();
+        matchmaker.join(entry(4, 1600.0, 0)).unwrap();
+        assert_eq!(pair_ids(&mut matchmaker), [(1, 3), (2, 4)]);
+        assert!(matchmaker.entries.is_empty());
+    }
+
+    #[test]
+    fn keeps_pools_users_and_rating_ranges_apart() {
+        let mut matchmaker = Matchmaker::new();
+        matchmaker.join(entry(1, 1500.0, 0)).unwrap();
+        assert!(matchmaker.join(entry(1, 1500.0, 0)).is_err());
+        let mut rated = entry(2, 1500.0, 0);
+        rated.pool.rated = true;
+        matchmaker.join(rated).unwrap();
+        let mut picky = entry(3, 1500.0, 0);
+        picky.rating_range = Some((1600.0, 1800.0));
+        matchmaker.join(picky).unwrap();
+        assert!(pair_ids(&mut matchmaker).is_empty());
+
+        let mut open = entry(4, 1650.0, 0);
+        open.rating_range = Some((1400.0, 1600.0));
+        matchmaker.join(open).unwrap();
+        assert_eq!(pair_ids(&mut matchmaker), [(3, 4)]);
+        assert!(matchmaker.leave(1));
+        assert!(!matchmaker.leave(1));
+    }
+
+    #[test]
+    fn widens_the_window_while_players_wait() {
+        let mut matchmaker = Matchmaker::new();
+        matchmaker.join(entry(1, 1500.0, 0)).unwrap();
+        matchmaker.join(entry(2, 1700.0, 0)).unwrap();
+        assert!(pair_ids(&mut matchmaker).is_empty());
+
+        // 100 + 15 * 10 covers the 200 points between them
+        let mut matchmaker = Matchmaker::new();
+        matchmaker.join(entry(1, 1500.0, 15)).unwrap();
+        matchmaker.join(entry(2, 1700.0, 15)).unwrap();
+        assert_eq!(pair_ids(&mut matchmaker), [(1, 2)]);
+
+        // Never wider than the maximum
+        assert_eq!(entry(1, 1500.0, 3600).window(Instant::now()), MAX_WINDOW);
+    }
+
+    #[test]
+    fn waits_before_pairing_the_same_players_again() {
+        let mut matchmaker = Matchmaker::new();
+        matchmaker.join(entry(1, 1500.0, 0)).unwrap();
+        matchmaker.join(entry(2, 1500.0, 0)).unwrap();
+        assert_eq!(pair_ids(&mut matchmaker), [(1, 2)]);
+
+        matchmaker.join(entry(1, 1500.0, 0)).unwrap();
+        matchmaker.join(entry(2, 1500.0, 0)).unwrap();
+        assert!(pair_ids(&mut matchmaker).is_empty());
+        // Anyone else is fine
+        matchmaker.join(entry(3, 1500.0, 0)).unwrap();
+        assert_eq!(pair_ids(&mut matchmaker), [(1, 3)]);
+
+        let mut matchmaker = Matchmaker::new();
+        matchmaker.join(entry(1, 1500.0, 0)).unwrap();
+        matchmaker.join(entry(2, 1500.0, 0)).unwrap();
+        pair_ids(&mut matchmaker);
+        let waited = REPEAT_PAIRING_WAIT.as_secs();
+        matchmaker.join(entry(1, 1500.0, waited)).unwrap();
+        matchmaker.join(entry(2, 1500.0, waited)).unwrap();
+        assert_eq!(pair_ids(&mut matchmaker), [(1, 2)]);
+    }
+
+    #[test]
+    fn estimates_the_wait_from_recent_pairings() {
+        let mut matchmaker = Matchmaker::new();
+        let waiting = entry(5, 1500.0, 4);
+        assert_eq!(matchmaker.estimated_wait(&waiting, Instant::now()), None);
+
+        matchmaker.join(entry(1, 1500.0, 10)).unwrap();
+        matchmaker.join(entry(2, 1500.0, 30)).unwrap();
+        pair_ids(&mut matchmaker);
+        // Waits of 10 and 30 seconds average 20, of which 4 have passed
+        let estimate = matchmaker.estimated_wait(&waiting, Instant::now()).unwrap();
+        assert!(estimate.abs_diff(Duration::from_secs(16)) < Duration::from_millis(500));
+
+        let mut other = entry(6, 1500.0, 0);
+        other.pool.variant = "chess960".to_string();
+        assert_eq!(matchmaker.estimated_wait(&other, Instant::now()), None);
+    }
+
+    fn history(colors: &str) -> Vec<String> {
+        colors.chars().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn gives_black_to_whoever_had_more_whites() {
+        assert_eq!(assign_colors(&history("wwb"), &history("bwb")), ("b", "w"));
+        assert_eq!(assign_colors(&history("bwb"), &history("wwb")), ("w", "b"));
+        assert_eq!(assign_colors(&history("w"), &[]), ("b", "w"));
+        // Level, so whoever had white last gets black
+        assert_eq!(assign_colors(&history("wb"), &history("bw")), ("b", "w"));
+        assert_eq!(assign_colors(&history("bw"), &history("wb")), ("w", "b"));
+    }
+}
@@ -5,10 +5,20 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use uuid::Uuid;
 
+use crate::adjudication::Rules;
 use crate::bitboard::MoveData;
+use crate::clock::TimeControl;
+use crate::eco::Opening;
+use crate::game_room::GameResult;
+use crate::rating::{RatingChange, Speed};
+use crate::tournament::{
+    Format, Standing, Tournament, TournamentError, TournamentPlayer, TournamentStatus,
+};
 
 pub struct Connect {
     pub addr: Addr<crate::websocket::MyWebSocket>,
+    pub user_id: usize,
+    pub guest: bool,
 }
 
 impl Message for Connect {
@@ -25,6 +35,12 @@ impl Message for Disconnect {
 
 pub struct CreateRoom {
     pub id: usize,
+    pub time_control: Option<TimeControl>,
+    pub color: Option<String>, // "white", "black" or "random" (the default)
+    pub opponent: Option<String>, // "bot" to play the computer, otherwise anyone can join
+    pub level: Option<u8>,     // bot strength
+    pub engine: Option<String>, // an engine profile for the bot to play with instead
+    pub adjudication: Option<String>, // rules as for `adjudication::Rules`, bot games only
 }
 
 impl Message for CreateRoom {
@@ -42,12 +58,24 @@ impl Message for JoinRoom {
 
 pub struct JoinQueue {
     pub id: usize,
+    pub time_control: Option<TimeControl>,
+    pub rated: bool,
+    pub variant: String,
+    pub rating_range: Option<(f64, f64)>, // (min, max) opponent rating
 }
 
 impl Message for JoinQueue {
     type Result = ();
 }
 
+pub struct LeaveQueue {
+    pub id: usize,
+}
+
+impl Message for LeaveQueue {
+    type Result = ();
+}
+
 pub struct ClientMove {
     pub id: usize,
     pub room_id: Uuid,
@@ -58,6 +86,263 @@ impl Message for ClientMove {
     type Result = ();
 }
 
+// A search of the bot is over, with the move it found after `ply` moves if
+// there was one
+pub struct BotMoved {
+    pub room_id: Uuid,
+    pub ply: usize,
+    pub move_data: Option<MoveData>,
+}
+
+impl Message for BotMoved {
+    type Result = ();
+}
+
+// The adjudication search of the position after `ply` moves of a room
+pub struct PositionEvaluated {
+    pub room_id: Uuid,
+    pub ply: usize,
+    pub cp: i32,                       // from White's point of view
+    pub tablebase: Option<GameResult>, // None if not in the tablebases
+}
+
+impl Message for PositionEvaluated {
+    type Result = ();
+}
+
+// Either a FEN or a room, whose current position is analyzed
+pub struct Analyze {
+    pub id: usize,
+    pub fen: Option<String>,
+    pub room_id: Option<Uuid>,
+    pub depth: Option<i32>, // until stopped if None
+    pub multipv: Option<usize>,
+    pub engine: Option<String>, // an engine profile instead of the built-in engine
+}
+
+impl Message for Analyze {
+    type Result = ();
+}
+
+pub struct StopAnalysis {
+    pub id: usize,
+}
+
+impl Message for StopAnalysis {
+    type Result = ();
+}
+
+// The opening book's moves for a FEN or a room's current position
+pub struct GetBookMoves {
+    pub id: usize,
+    pub fen: Option<String>,
+    pub room_id: Option<Uuid>,
+}
+
+impl Message for GetBookMoves {
+    type Result = ();
+}
+
+pub struct Resign {
+    pub id: usize,
+    pub room_id: Uuid,
+}
+
+impl Message for Resign {
+    type Result = ();
+}
+
+pub struct RematchOffer {
+    pub id: usize,
+    pub room_id: Uuid,
+}
+
+impl Message for RematchOffer {
+    type Result = ();
+}
+
+pub struct RematchAccept {
+    pub id: usize,
+    pub room_id: Uuid,
+}
+
+impl Message for RematchAccept {
+    type Result = ();
+}
+
+pub struct RematchDecline {
+    pub id: usize,
+    pub room_id: Uuid,
+}
+
+impl Message for RematchDecline {
+    type Result = ();
+}
+
+pub struct SendChallenge {
+    pub id: usize,
+    pub username: String, // who is being challenged
+    pub time_control: Option<TimeControl>,
+    pub color: Option<String>, // "white", "black" or "random" (the default)
+    pub variant: String,
+    pub rated: bool,
+}
+
+impl Message for SendChallenge {
+    type Result = ();
+}
+
+pub struct ChallengeAccept {
+    pub id: usize,
+    pub challenge_id: Uuid,
+}
+
+impl Message for ChallengeAccept {
+    type Result = ();
+}
+
+pub struct ChallengeDecline {
+    pub id: usize,
+    pub challenge_id: Uuid,
+}
+
+impl Message for ChallengeDecline {
+    type Result = ();
+}
+
+pub struct ChallengeCancel {
+    pub id: usize,
+    pub challenge_id: Uuid,
+}
+
+impl Message for ChallengeCancel {
+    type Result = ();
+}
+
+pub struct CreateSeek {
+    pub id: usize,
+    pub time_control: Option<TimeControl>,
+    pub variant: String,
+    pub rated: bool,
+    pub color: Option<String>, // "white", "black" or "random" (the default)
+}
+
+impl Message for CreateSeek {
+    type Result = ();
+}
+
+pub struct CancelSeek {
+    pub id: usize,
+    pub seek_id: Uuid,
+}
+
+impl Message for CancelSeek {
+    type Result = ();
+}
+
+pub struct AcceptSeek {
+    pub id: usize,
+    pub seek_id: Uuid,
+}
+
+impl Message for AcceptSeek {
+    type Result = ();
+}
+
+pub struct ListLiveRooms;
+
+impl Message for ListLiveRooms {
+    type Result = Vec<LiveRoomInfo>;
+}
+
+// Tournament messages come from the HTTP API and answer with the updated
+// tournament
+pub struct CreateTournament {
+    pub created_by: usize,
+    pub name: String,
+    pub format: Format,
+    pub time_control: Option<TimeControl>,
+    pub rated: bool,
+    pub rounds: usize,
+    pub adjudication: Option<Rules>,
+}
+
+impl Message for CreateTournament {
+    type Result = Tournament;
+}
+
+pub struct JoinTournament {
+    pub tournament_id: Uuid,
+    pub player: TournamentPlayer,
+    pub guest: bool,
+}
+
+impl Message for JoinTournament {
+    type Result = Result<Tournament, TournamentError>;
+}
+
+pub struct WithdrawTournament {
+    pub tournament_id: Uuid,
+    pub user_id: usize,
+}
+
+impl Message for WithdrawTournament {
+    type Result = Result<Tournament, TournamentError>;
+}
+
+pub struct StartTournament {
+    pub tournament_id: Uuid,
+    pub user_id: usize,
+}
+
+impl Message for StartTournament {
+    type Result = Result<Tournament, TournamentError>;
+}
+
+pub struct GetTournament {
+    pub tournament_id: Uuid,
+}
+
+impl Message for GetTournament {
+    type Result = Option<Tournament>;
+}
+
+pub struct ListTournaments;
+
+impl Message for ListTournaments {
+    type Result = Vec<Tournament>;
+}
+
+#[derive(Serialize)]
+pub struct LiveRoomInfo {
+    pub room_id: Uuid,
+    pub white: Option<usize>,
+    pub black: Option<usize>,
+    pub time_control: Option<String>,
+    pub rated: bool,
+    pub ply_count: usize,
+    pub turn: String,
+    pub fen: String,
+    pub eco: Option<&'static str>,
+    pub opening: Option<&'static str>,
+    pub white_time_ms: Option<u64>,
+    pub black_time_ms: Option<u64>,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct SeekInfo {
+    pub seek_id: Uuid,
+    pub username: String,
+    pub rating: i64,
+    pub time_control: Option<String>,
+    pub speed: Speed,
+    pub variant: String,
+    pub rated: bool,
+    pub color: String,
+    pub created_at: u64,
+}
+
 pub struct RoomJoined {
     pub room_id: Uuid,
     pub color: String, // "w" or "b"
@@ -71,12 +356,170 @@ impl Message for RoomJoined {
 pub struct UpdateClient {
     pub pieces: Vec<Value>,
     pub turn: String,
+    pub white_time: Option<u64>, // milliseconds left, None for untimed games
+    pub black_time: Option<u64>,
+    pub opening: Option<Opening>,
 }
 
 impl Message for UpdateClient {
     type Result = ();
 }
 
+#[derive(Clone)]
+pub struct GameOver {
+    pub room_id: Uuid,
+    pub result: String,                    // "1-0", "0-1" or "1/2-1/2"
+    pub termination: String,               // "resignation", "timeout", ...
+    pub rating_changes: Vec<RatingChange>, // empty for casual games
+}
+
+impl Message for GameOver {
+    type Result = ();
+}
+
+#[derive(Clone)]
+pub struct RematchOffered {
+    pub room_id: Uuid,
+}
+
+impl Message for RematchOffered {
+    type Result = ();
+}
+
+#[derive(Clone)]
+pub struct RematchDeclined {
+    pub room_id: Uuid,
+}
+
+impl Message for RematchDeclined {
+    type Result = ();
+}
+
+#[derive(Clone)]
+pub struct ChallengeReceived {
+    pub challenge_id: Uuid,
+    pub from: String, // challenger's username
+    pub time_control: Option<TimeControl>,
+    pub color: String, // the challenger's color choice
+    pub variant: String,
+    pub rated: bool,
+    pub expires_in: u64, // seconds
+}
+
+impl Message for ChallengeReceived {
+    type Result = ();
+}
+
+pub struct ChallengeSent {
+    pub challenge_id: Uuid,
+    pub to: String,
+    pub expires_in: u64,
+}
+
+impl Message for ChallengeSent {
+    type Result = ();
+}
+
+#[derive(Clone)]
+pub struct ChallengeClosed {
+    pub challenge_id: Uuid,
+    pub reason: String, // "accepted", "declined", "cancelled" or "expired"
+}
+
+impl Message for ChallengeClosed {
+    type Result = ();
+}
+
+#[derive(Clone)]
+pub enum LobbyUpdate {
+    Snapshot(Vec<SeekInfo>), // sent once on connect
+    Added(SeekInfo),
+    Removed { seek_id: Uuid, reason: String }, // "taken" or "cancelled"
+}
+
+impl Message for LobbyUpdate {
+    type Result = ();
+}
+
+// Sent to every participant when a tournament's standings change
+#[derive(Clone)]
+pub struct TournamentStandings {
+    pub tournament_id: Uuid,
+    pub status: TournamentStatus,
+    pub round: usize,
+    pub standings: Vec<Standing>,
+}
+
+impl Message for TournamentStandings {
+    type Result = ();
+}
+
+pub struct QueueStatusUpdate {
+    pub position: usize,
+    pub pool_size: usize,
+    pub estimated_wait: Option<u64>, // seconds, None until the pool has paired someone
+}
+
+impl Message for QueueStatusUpdate {
+    type Result = ();
+}
+
+pub struct QueueLeft;
+
+impl Message for QueueLeft {
+    type Result = ();
+}
+
+pub struct AnalysisLine {
+    pub fen: String,
+    pub depth: i32,
+    pub multipv: usize,
+    pub cp: i32,
+    pub mate: Option<i32>, // moves to mate, sent instead of `cp`
+    pub pv: Vec<String>,   // SAN
+    pub nodes: u64,
+    pub nps: u64,
+    pub tablebase: Option<TablebaseInfo>, // None unless the position is in the tables
+}
+
+impl Message for AnalysisLine {
+    type Result = ();
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TablebaseInfo {
+    pub wdl: &'static str, // "win", "cursed_win", "draw", "blessed_loss" or "loss"
+    pub dtz: i32,          // plies to a capture or pawn move, negative when losing
+    pub best_move: Option<String>, // SAN
+}
+
+// The analysis ended on its own, not when it was stopped
+pub struct AnalysisDone {
+    pub fen: String,
+    pub best_move: Option<String>, // SAN, None if there's no legal move
+    pub tablebase: Option<TablebaseInfo>,
+}
+
+impl Message for AnalysisDone {
+    type Result = ();
+}
+
+#[derive(Serialize)]
+pub struct BookMoveInfo {
+    pub uci: String,
+    pub san: String,
+    pub weight: u16,
+}
+
+pub struct BookMoves {
+    pub fen: String,
+    pub moves: Vec<BookMoveInfo>, // heaviest first, empty when out of book
+}
+
+impl Message for BookMoves {
+    type Result = ();
+}
+
 pub struct ErrorMessage {
     pub error: String,
 }
@@ -0,0 +1,318 @@
+// src/notation.rs
+
+use crate::position::{square_name, Color, Move, PieceKind, Position};
+
+pub fn move_to_san(position: &Position, mv: &Move) -> String {
+    let mut san = if position.is_castling(mv) {
+        if mv.to > mv.from {
+            "O-O".to_string()
+        } else {
+            "O-O-O".to_string()
+        }
+    } else {
+        let kind = match position.piece_at(mv.from as usize) {
+            Some((_, kind)) => kind,
+            None => return mv.to_uci(),
+        };
+        let capture = position.is_capture(mv);
+        let mut san = String::new();
+
+        if kind == PieceKind::Pawn {
+            if capture {
+                san.push(square_name(mv.from as usize).remove(0));
+            }
+        } else {
+            san.push(kind.to_char().to_ascii_uppercase());
+
+            // Other pieces of the same kind that can reach the same square
+            let rivals: Vec<Move> = position
+                .legal_moves()
+                .into_iter()
+                .filter(|other| {
+                    other.to == mv.to
+                        && other.from != mv.from
+                        && position.piece_at(other.from as usize).map(|(_, k)| k) == Some(kind)
+                })
+                .collect();
+            if !rivals.is_empty() {
+                let from = square_name(mv.from as usize);
+                let same_file = rivals.iter().any(|o| o.from % 8 == mv.from % 8);
+                let same_rank = rivals.iter().any(|o| o.from / 8 == mv.from / 8);
+                if !same_file {
+                    san.push_str(&from[..1]);
+                } else if !same_rank {
+                    san.push_str(&from[1..]);
+                } else {
+                    san.push_str(&from);
+                }
+            }
+        }
+
+        if capture {
+            san.push('x');
+        }
+        san.push_str(&square_name(mv.to as usize));
+        if let Some(promotion) = mv.promotion {
+            san.push('=');
+            san.push(promotion.to_char().to_ascii_uppercase());
+        }
+        san
+    };
+
+    let next = position.make_move(mv);
+    if next.in_check() {
+        san.push(if next.legal_moves().is_empty() {
+            '#'
+        } else {
+            '+'
+        });
+    }
+    san
+}
+
+// SAN for a whole line of moves starting from `position`
+pub fn moves_to_san(position: &Position, moves: &[Move]) -> Vec<String> {
+    let mut position = position.clone();
+    let mut sans = Vec::with_capacity(moves.len());
+    for mv in moves {
+        sans.push(move_to_san(&position, mv));
+        position = position.make_move(mv);
+    }
+    sans
+}
+
+// Numbered movetext such as "1. e4 e5 2. Nf3", honouring a black first move.
+// Annotations (NAGs and comments) follow their move, an empty one or none
+// at all leaves the move bare.
+pub fn format_movetext(position: &Position, sans: &[String], annotations: &[String]) -> String {
+    let mut parts = Vec::new();
+    let mut number = position.fullmove_number;
+    let mut white_to_move = position.side_to_move == Color::White;
+
+    let annotation = |i: usize| annotations.get(i).filter(|a| !a.is_empty());
+    for (i, san) in sans.iter().enumerate() {
+        if white_to_move {
+            parts.push(format!("{}. {}", number, san));
+        } else if i == 0 || annotation(i - 1).is_some() {
+            // Black's move is numbered again after a comment
+            parts.push(format!("{}... {}", number, san));
+        } else {
+            parts.push(san.clone());
+        }
+        if let Some(annotation) = annotation(i) {
+            parts.push(annotation.clone());
+        }
+        if !white_to_move {
+            number += 1;
+        }
+        white_to_move = !white_to_move;
+    }
+    parts.join(" ")
+}
+
+pub fn to_pgn(
+    headers: &[(String, String)],
+    position: &Position,
+    moves: &[Move],
+    annotations: &[String],
+    result: &str,
+) -> String {
+    let mut pgn = String::new();
+    for (name, value) in headers {
+        pgn.push_str(&format!(
+            "[{} \"{}\"]\n",
+            name,
+            value.replace('\\', "\\\\").replace('"', "\\\"")
+        ));
+    }
+    pgn.push('\n');
+
+    let sans = moves_to_san(position, moves);
+    let mut movetext = format_movetext(position, &sans, annotations);
+    if !movetext.is_empty() {
+        movetext.push(' ');
+    }
+    movetext.push_str(result);
+    pgn.push_str(&wrap(&movetext, 80));
+    pgn.push('\n');
+    pgn
+}
+
+fn wrap(text: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut line_len = 0;
+    for word in text.split(' ') {
+        if line_len > 0 && line_len + 1 + word.len() > width {
+            out.push('\n');
+            line_len = 0;
+        } else if line_len > 0 {
+            out.push(' ');
+            line_len += 1;
+        }
+        out.push_str(word);
+        line_len += word.len();
+    }
+    out
+}
+
+// A game read from PGN
+#[derive(Clone)]
+pub struct PgnGame {
+    pub headers: Vec<(String, String)>,
+    pub start: Position, // from the FEN header, if any
+    pub moves: Vec<Move>,
+    pub result: String, // "1-0", "0-1", "1/2-1/2" or "*"
+}
+
+impl PgnGame {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// The move in `position` written as `san`, which may leave out or add the
+// check sign, carry !? annotations, castle with zeros or promote without '='
+pub fn parse_san(position: &Position, san: &str) -> Option<Move> {
+    let normalize = |san: &str| {
+        san.trim_end_matches(['+', '#', '!', '?'])
+            .replace("0-0", "O-O")
+            .replace('=', "")
+    };
+    let wanted = normalize(san);
+    position
+        .legal_moves()
+        .into_iter()
+        .find(|mv| normalize(&move_to_san(position, mv)) == wanted)
+}
+
+// Every game in `text`, one result per game so that a bad game doesn't
+// lose the others. Comments, variations and NAGs are skipped.
+pub fn parse_pgn(text: &str) -> Vec<Result<PgnGame, String>> {
+    let mut games = Vec::new();
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut movetext = String::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('%') {
+            continue; // escaped line
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            // Headers after movetext start the next game
+            if !movetext.trim().is_empty() {
+                games.push(read_game(
+                    std::mem::take(&mut headers),
+                    &std::mem::take(&mut movetext),
+                ));
+            }
+            if let Some((name, value)) = header.split_once(' ') {
+                let value = value.trim().trim_matches('"');
+                headers.push((
+                    name.to_string(),
+                    value.replace("\\\"", "\"").replace("\\\\", "\\"),
+                ));
+            }
+        } else {
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+    }
+    if !headers.is_empty() || !movetext.trim().is_empty() {
+        games.push(read_game(headers, &movetext));
+    }
+    games
+}
+
+fn read_game(headers: Vec<(String, String)>, movetext: &str) -> Result<PgnGame, String> {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.clone())
+    };
+    let start = match header("FEN") {
+        Some(fen) => Position::from_fen(&fen).map_err(|err| format!("Invalid FEN: {}", err))?,
+        None => Position::startpos(),
+    };
+    let mut position = start.clone();
+    let mut moves = Vec::new();
+    let mut result = header("Result").unwrap_or_else(|| "*".to_string());
+
+    // Braces and semicolons comment, parentheses nest variations
+    let mut depth = 0;
+    let mut chars = movetext.chars().peekable();
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                }
+            }
+            ';' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if c.is_whitespace() => {}
+            c => {
+                token.push(c);
+                if chars.peek().is_some_and(|next| {
+                    next.is_whitespace() || matches!(next, '{' | '}' | '(' | ')' | ';')
+                }) || chars.peek().is_none()
+                {
+                    let word = std::mem::take(&mut token);
+                    if depth == 0 {
+                        tokens.push(word);
+                    }
+                }
+            }
+        }
+    }
+
+    for token in tokens {
+        if matches!(token.as_str(), "1-0" | "0-1" | "1/2-1/2" | "*") {
+            result = token;
+            break;
+        }
+        if token.starts_with('$') {
+            continue;
+        }
+        // "12." or "12..." possibly glued to the move, as in "12.e4"
+        let digits = token.trim_start_matches(|c: char| c.is_ascii_digit());
+        let san = if digits.starts_with('.') {
+            digits.trim_start_matches('.')
+        } else {
+            token.as_str()
+        };
+        if san.is_empty() {
+            continue;
+        }
+        let mv = parse_san(&position, san).ok_or_else(|| {
+            format!(
+                "Illegal move {} after {} moves{}",
+                san,
+                moves.len(),
+                header("Event").map_or(String::new(), |event| format!(" in {}", event))
+            )
+        })?;
+        position = position.make_move(&mv);
+        moves.push(mv);
+    }
+
+    Ok(PgnGame {
+        headers,
+        start,
+        moves,
+        result,
+    })
+}
@@ -0,0 +1,946 @@
+// src/position.rs
+
+use std::sync::OnceLock;
+
+use crate::bitboard::{init_bitboard, Bitboard, MoveData};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Color {
+    White,
+    Black,
+}
+
+impl Color {
+    pub fn opposite(self) -> Color {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Color::White => "w",
+            Color::Black => "b",
+        }
+    }
+
+    pub fn index(self) -> usize {
+        match self {
+            Color::White => 0,
+            Color::Black => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PieceKind {
+    Pawn,
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+    King,
+}
+
+impl PieceKind {
+    pub const ALL: [PieceKind; 6] = [
+        PieceKind::Pawn,
+        PieceKind::Knight,
+        PieceKind::Bishop,
+        PieceKind::Rook,
+        PieceKind::Queen,
+        PieceKind::King,
+    ];
+
+    pub fn from_char(c: char) -> Option<PieceKind> {
+        match c.to_ascii_lowercase() {
+            'p' => Some(PieceKind::Pawn),
+            'n' => Some(PieceKind::Knight),
+            'b' => Some(PieceKind::Bishop),
+            'r' => Some(PieceKind::Rook),
+            'q' => Some(PieceKind::Queen),
+            'k' => Some(PieceKind::King),
+            _ => None,
+        }
+    }
+
+    // Position in `ALL`
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    // Lowercase letter, as used in FEN for black and in UCI promotions
+    pub fn to_char(self) -> char {
+        match self {
+            PieceKind::Pawn => 'p',
+            PieceKind::Knight => 'n',
+            PieceKind::Bishop => 'b',
+            PieceKind::Rook => 'r',
+            PieceKind::Queen => 'q',
+            PieceKind::King => 'k',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub from: u8,
+    pub to: u8,
+    pub promotion: Option<PieceKind>,
+}
+
+impl Move {
+    pub fn to_uci(self) -> String {
+        let mut uci = format!(
+            "{}{}",
+            square_name(self.from as usize),
+            square_name(self.to as usize)
+        );
+        if let Some(kind) = self.promotion {
+            uci.push(kind.to_char());
+        }
+        uci
+    }
+}
+
+impl From<Move> for MoveData {
+    fn from(mv: Move) -> Self {
+        MoveData {
+            from: mv.from as usize,
+            to: mv.to as usize,
+            promotion: mv.promotion.map(|kind| kind.to_char().to_string()),
+        }
+    }
+}
+
+pub const CASTLE_WHITE_KING: u8 = 1;
+pub const CASTLE_WHITE_QUEEN: u8 = 2;
+pub const CASTLE_BLACK_KING: u8 = 4;
+pub const CASTLE_BLACK_QUEEN: u8 = 8;
+
+#[derive(Clone)]
+pub struct Position {
+    pub board: Bitboard,
+    pub side_to_move: Color,
+    pub castling: u8,
+    pub en_passant: Option<u8>,
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+}
+
+pub fn square_name(sq: usize) -> String {
+    let file = (b'a' + (sq % 8) as u8) as char;
+    let rank = (b'1' + (sq / 8) as u8) as char;
+    format!("{}{}", file, rank)
+}
+
+// The reverse of `square_name`, "e4" is 28
+pub fn square_index(name: &str) -> Option<usize> {
+    match name.as_bytes() {
+        [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => {
+            Some((rank - b'1') as usize * 8 + (file - b'a') as usize)
+        }
+        _ => None,
+    }
+}
+
+struct AttackTables {
+    knight: [u64; 64],
+    king: [u64; 64],
+    pawn: [[u64; 64]; 2], // squares attacked by a pawn of each color
+    rays: [[u64; 64]; 8],
+}
+
+// Ray directions as (file step, rank step). The first four run towards
+// higher square indices, the last four towards lower ones.
+const DIRECTIONS: [(i32, i32); 8] = [
+    (0, 1),   // north
+    (1, 0),   // east
+    (1, 1),   // north east
+    (-1, 1),  // north west
+    (0, -1),  // south
+    (-1, 0),  // west
+    (1, -1),  // south east
+    (-1, -1), // south west
+];
+const ROOK_DIRECTIONS: [usize; 4] = [0, 1, 4, 5];
+const BISHOP_DIRECTIONS: [usize; 4] = [2, 3, 6, 7];
+
+fn offset_mask(sq: usize, steps: &[(i32, i32)]) -> u64 {
+    let (file, rank) = ((sq % 8) as i32, (sq / 8) as i32);
+    let mut mask = 0;
+    for (df, dr) in steps {
+        let (f, r) = (file + df, rank + dr);
+        if (0..8).contains(&f) && (0..8).contains(&r) {
+            mask |= 1u64 << (r * 8 + f);
+        }
+    }
+    mask
+}
+
+fn tables() -> &'static AttackTables {
+    static TABLES: OnceLock<AttackTables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let mut t = AttackTables {
+            knight: [0; 64],
+            king: [0; 64],
+            pawn: [[0; 64]; 2],
+            rays: [[0; 64]; 8],
+        };
+        for sq in 0..64 {
+            t.knight[sq] = offset_mask(
+                sq,
+                &[
+                    (1, 2),
+                    (2, 1),
+                    (2, -1),
+                    (1, -2),
+                    (-1, -2),
+                    (-2, -1),
+                    (-2, 1),
+                    (-1, 2),
+                ],
+            );
+            t.king[sq] = offset_mask(
+                sq,
+                &[
+                    (1, 0),
+                    (1, 1),
+                    (0, 1),
+                    (-1, 1),
+                    (-1, 0),
+                    (-1, -1),
+                    (0, -1),
+                    (1, -1),
+                ],
+            );
+            t.pawn[0][sq] = offset_mask(sq, &[(-1, 1), (1, 1)]);
+            t.pawn[1][sq] = offset_mask(sq, &[(-1, -1), (1, -1)]);
+
+            for (dir, (df, dr)) in DIRECTIONS.iter().enumerate() {
+                let (mut f, mut r) = ((sq % 8) as i32 + df, (sq / 8) as i32 + dr);
+                while (0..8).contains(&f) && (0..8).contains(&r) {
+                    t.rays[dir][sq] |= 1u64 << (r * 8 + f);
+                    f += df;
+                    r += dr;
+                }
+            }
+        }
+        t
+    })
+}
+
+fn ray_attacks(dir: usize, sq: usize, occupied: u64) -> u64 {
+    let rays = &tables().rays;
+    let ray = rays[dir][sq];
+    let blockers = ray & occupied;
+    if blockers == 0 {
+        return ray;
+    }
+    let blocker = if dir < 4 {
+        blockers.trailing_zeros() as usize
+    } else {
+        63 - blockers.leading_zeros() as usize
+    };
+    ray ^ rays[dir][blocker]
+}
+
+pub fn knight_attacks(sq: usize) -> u64 {
+    tables().knight[sq]
+}
+
+pub fn king_attacks(sq: usize) -> u64 {
+    tables().king[sq]
+}
+
+pub fn pawn_attacks(color: Color, sq: usize) -> u64 {
+    tables().pawn[color.index()][sq]
+}
+
+pub fn bishop_attacks(sq: usize, occupied: u64) -> u64 {
+    BISHOP_DIRECTIONS
+        .iter()
+        .fold(0, |acc, &dir| acc | ray_attacks(dir, sq, occupied))
+}
+
+pub fn rook_attacks(sq: usize, occupied: u64) -> u64 {
+    ROOK_DIRECTIONS
+        .iter()
+        .fold(0, |acc, &dir| acc | ray_attacks(dir, sq, occupied))
+}
+
+// Iterates over the set squares of a bitboard, lowest first
+pub struct Squares(pub u64);
+
+impl Iterator for Squares {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 {
+            return None;
+        }
+        let sq = self.0.trailing_zeros() as usize;
+        self.0 &= self.0 - 1;
+        Some(sq)
+    }
+}
+
+// Castling rights that survive a move touching the given square
+fn castling_mask(sq: usize) -> u8 {
+    match sq {
+        0 => !CASTLE_WHITE_QUEEN,
+        4 => !(CASTLE_WHITE_KING | CASTLE_WHITE_QUEEN),
+        7 => !CASTLE_WHITE_KING,
+        56 => !CASTLE_BLACK_QUEEN,
+        60 => !(CASTLE_BLACK_KING | CASTLE_BLACK_QUEEN),
+        63 => !CASTLE_BLACK_KING,
+        _ => 0xFF,
+    }
+}
+
+impl Position {
+    pub fn startpos() -> Self {
+        Position {
+            board: init_bitboard(),
+            side_to_move: Color::White,
+            castling: CASTLE_WHITE_KING
+                | CASTLE_WHITE_QUEEN
+                | CASTLE_BLACK_KING
+                | CASTLE_BLACK_QUEEN,
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+        }
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.piece_at(rank * 8 + file) {
+                    Some((color, kind)) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        let c = kind.to_char();
+                        fen.push(if color == Color::White {
+                            c.to_ascii_uppercase()
+                        } else {
+                            c
+                        });
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push(' ');
+        fen.push_str(self.side_to_move.as_str());
+
+        fen.push(' ');
+        if self.castling == 0 {
+            fen.push('-');
+        } else {
+            for (flag, c) in [
+                (CASTLE_WHITE_KING, 'K'),
+                (CASTLE_WHITE_QUEEN, 'Q'),
+                (CASTLE_BLACK_KING, 'k'),
+                (CASTLE_BLACK_QUEEN, 'q'),
+            ] {
+                if self.castling & flag != 0 {
+                    fen.push(c);
+                }
+            }
+        }
+
+        fen.push(' ');
+        match self.en_passant {
+            Some(sq) => fen.push_str(&square_name(sq as usize)),
+            None => fen.push('-'),
+        }
+
+        fen.push_str(&format!(
+            " {} {}",
+            self.halfmove_clock, self.fullmove_number
+        ));
+        fen
+    }
+
+    // The move counters may be left out and default to "0 1". Castling
+    // rights without the king and rook on their squares are dropped, and so
+    // is an en passant square no pawn could have just skipped.
+    pub fn from_fen(fen: &str) -> Result<Position, String> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if !(4..=6).contains(&fields.len()) {
+            return Err("A FEN needs 4 to 6 fields".to_string());
+        }
+
+        let mut position = Position {
+            board: Bitboard {
+                pawns: 0,
+                knights: 0,
+                bishops: 0,
+                rooks: 0,
+                queens: 0,
+                kings: 0,
+                white_pieces: 0,
+                black_pieces: 0,
+            },
+            side_to_move: Color::White,
+            castling: 0,
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+        };
+
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err("The board needs 8 ranks".to_string());
+        }
+        for (i, rank) in ranks.iter().enumerate() {
+            let rank_start = (7 - i) * 8;
+            let mut file = 0;
+            for c in rank.chars() {
+                if let Some(empty) = c.to_digit(10).filter(|n| (1..=8).contains(n)) {
+                    file += empty as usize;
+                } else {
+                    let kind =
+                        PieceKind::from_char(c).ok_or_else(|| format!("Unknown piece '{}'", c))?;
+                    if file >= 8 {
+                        return Err(format!("Rank {} has more than 8 squares", 8 - i));
+                    }
+                    let color = if c.is_ascii_uppercase() {
+                        Color::White
+                    } else {
+                        Color::Black
+                    };
+                    position.put_piece(rank_start + file, color, kind);
+                    file += 1;
+                }
+            }
+            if file != 8 {
+                return Err(format!("Rank {} doesn't have 8 squares", 8 - i));
+            }
+        }
+        for color in [Color::White, Color::Black] {
+            if (position.board.kings & position.occupancy(color)).count_ones() != 1 {
+                return Err("Each side needs exactly one king".to_string());
+            }
+        }
+        if position.board.pawns & 0xFF00_0000_0000_00FF != 0 {
+            return Err("Pawns can't be on the first or last rank".to_string());
+        }
+
+        position.side_to_move = match fields[1] {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err("The side to move must be w or b".to_string()),
+        };
+        if position.is_square_attacked(
+            position.king_square(position.side_to_move.opposite()),
+            position.side_to_move,
+        ) {
+            return Err("The side not to move is in check".to_string());
+        }
+
+        if fields[2] != "-" {
+            for c in fields[2].chars() {
+                let (flag, color, king, rook) = match c {
+                    'K' => (CASTLE_WHITE_KING, Color::White, 4, 7),
+                    'Q' => (CASTLE_WHITE_QUEEN, Color::White, 4, 0),
+                    'k' => (CASTLE_BLACK_KING, Color::Black, 60, 63),
+                    'q' => (CASTLE_BLACK_QUEEN, Color::Black, 60, 56),
+                    _ => return Err(format!("Unknown castling right '{}'", c)),
+                };
+                if position.piece_at(king) == Some((color, PieceKind::King))
+                    && position.piece_at(rook) == Some((color, PieceKind::Rook))
+                {
+                    position.castling |= flag;
+                }
+            }
+        }
+
+        if fields[3] != "-" {
+            let sq = square_index(fields[3]).ok_or("Invalid en passant square")?;
+            // The pawn that just moved two squares sits in front of it
+            let (rank, pawn) = match position.side_to_move {
+                Color::White => (5, sq.wrapping_sub(8)),
+                Color::Black => (2, sq + 8),
+            };
+            let them = position.side_to_move.opposite();
+            if sq / 8 == rank && position.piece_at(pawn) == Some((them, PieceKind::Pawn)) {
+                position.en_passant = Some(sq as u8);
+            }
+        }
+
+        if let Some(halfmove) = fields.get(4) {
+            position.halfmove_clock = halfmove
+                .parse()
+                .map_err(|_| "Invalid halfmove clock".to_string())?;
+        }
+        if let Some(fullmove) = fields.get(5) {
+            position.fullmove_number = fullmove
+                .parse::<u32>()
+                .map_err(|_| "Invalid move number".to_string())?
+                .max(1);
+        }
+        Ok(position)
+    }
+
+    pub fn occupancy(&self, color: Color) -> u64 {
+        match color {
+            Color::White => self.board.white_pieces,
+            Color::Black => self.board.black_pieces,
+        }
+    }
+
+    pub fn occupied(&self) -> u64 {
+        self.board.white_pieces | self.board.black_pieces
+    }
+
+    pub fn pieces(&self, kind: PieceKind) -> u64 {
+        match kind {
+            PieceKind::Pawn => self.board.pawns,
+            PieceKind::Knight => self.board.knights,
+            PieceKind::Bishop => self.board.bishops,
+            PieceKind::Rook => self.board.rooks,
+            PieceKind::Queen => self.board.queens,
+            PieceKind::King => self.board.kings,
+        }
+    }
+
+    fn pieces_mut(&mut self, kind: PieceKind) -> &mut u64 {
+        match kind {
+            PieceKind::Pawn => &mut self.board.pawns,
+            PieceKind::Knight => &mut self.board.knights,
+            PieceKind::Bishop => &mut self.board.bishops,
+            PieceKind::Rook => &mut self.board.rooks,
+            PieceKind::Queen => &mut self.board.queens,
+            PieceKind::King => &mut self.board.kings,
+        }
+    }
+
+    fn occupancy_mut(&mut self, color: Color) -> &mut u64 {
+        match color {
+            Color::White => &mut self.board.white_pieces,
+            Color::Black => &mut self.board.black_pieces,
+        }
+    }
+
+    pub fn piece_at(&self, sq: usize) -> Option<(Color, PieceKind)> {
+        let bb = 1u64 << sq;
+        let color = if self.board.white_pieces & bb != 0 {
+            Color::White
+        } else if self.board.black_pieces & bb != 0 {
+            Color::Black
+        } else {
+            return None;
+        };
+        PieceKind::ALL
+            .iter()
+            .find(|kind| self.pieces(**kind) & bb != 0)
+            .map(|kind| (color, *kind))
+    }
+
+    fn put_piece(&mut self, sq: usize, color: Color, kind: PieceKind) {
+        let bb = 1u64 << sq;
+        *self.pieces_mut(kind) |= bb;
+        *self.occupancy_mut(color) |= bb;
+    }
+
+    fn remove_piece(&mut self, sq: usize, color: Color, kind: PieceKind) {
+        let bb = !(1u64 << sq);
+        *self.pieces_mut(kind) &= bb;
+        *self.occupancy_mut(color) &= bb;
+    }
+
+    pub fn king_square(&self, color: Color) -> usize {
+        (self.board.kings & self.occupancy(color)).trailing_zeros() as usize
+    }
+
+    pub fn is_square_attacked(&self, sq: usize, by: Color) -> bool {
+        let them = self.occupancy(by);
+        let occupied = self.occupied();
+        let b = &self.board;
+
+        pawn_attacks(by.opposite(), sq) & b.pawns & them != 0
+            || knight_attacks(sq) & b.knights & them != 0
+            || king_attacks(sq) & b.kings & them != 0
+            || bishop_attacks(sq, occupied) & (b.bishops | b.queens) & them != 0
+            || rook_attacks(sq, occupied) & (b.rooks | b.queens) & them != 0
+    }
+
+    pub fn in_check(&self) -> bool {
+        self.is_square_attacked(
+            self.king_square(self.side_to_move),
+            self.side_to_move.opposite(),
+        )
+    }
+
+    pub fn is_capture(&self, mv: &Move) -> bool {
+        self.occupancy(self.side_to_move.opposite()) & (1u64 << mv.to) != 0
+            || self.is_en_passant(mv)
+    }
+
+    pub fn is_en_passant(&self, mv: &Move) -> bool {
+        Some(mv.to) == self.en_passant && self.board.pawns & (1u64 << mv.from) != 0
+    }
+
+    pub fn is_castling(&self, mv: &Move) -> bool {
+        self.board.kings & (1u64 << mv.from) != 0 && (mv.from as i32 - mv.to as i32).abs() == 2
+    }
+
+    pub fn pseudo_legal_moves(&self, moves: &mut Vec<Move>) {
+        let us = self.side_to_move;
+        let own = self.occupancy(us);
+        let enemy = self.occupancy(us.opposite());
+        let occupied = own | enemy;
+
+        // Pawns
+        let (push, start_rank, last_rank): (i32, usize, usize) = match us {
+            Color::White => (8, 1, 7),
+            Color::Black => (-8, 6, 0),
+        };
+        let add_pawn_move = |from: usize, to: usize, moves: &mut Vec<Move>| {
+            if to / 8 == last_rank {
+                for kind in [
+                    PieceKind::Queen,
+                    PieceKind::Rook,
+                    PieceKind::Bishop,
+                    PieceKind::Knight,
+                ] {
+                    moves.push(Move {
+                        from: from as u8,
+                        to: to as u8,
+                        promotion: Some(kind),
+                    });
+                }
+            } else {
+                moves.push(Move {
+                    from: from as u8,
+                    to: to as u8,
+                    promotion: None,
+                });
+            }
+        };
+        for from in Squares(self.board.pawns & own) {
+            let one = (from as i32 + push) as usize;
+            if occupied & (1u64 << one) == 0 {
+                add_pawn_move(from, one, moves);
+                let two = (one as i32 + push) as usize;
+                if from / 8 == start_rank && occupied & (1u64 << two) == 0 {
+                    add_pawn_move(from, two, moves);
+                }
+            }
+
+            let mut targets = pawn_attacks(us, from) & enemy;
+            if let Some(ep) = self.en_passant {
+                targets |= pawn_attacks(us, from) & (1u64 << ep);
+            }
+            for to in Squares(targets) {
+                add_pawn_move(from, to, moves);
+            }
+        }
+
+        // Pieces
+        for kind in [
+            PieceKind::Knight,
+            PieceKind::Bishop,
+            PieceKind::Rook,
+            PieceKind::Queen,
+            PieceKind::King,
+        ] {
+            for from in Squares(self.pieces(kind) & own) {
+                let attacks = match kind {
+                    PieceKind::Knight => knight_attacks(from),
+                    PieceKind::Bishop => bishop_attacks(from, occupied),
+                    PieceKind::Rook => rook_attacks(from, occupied),
+                    PieceKind::Queen => {
+                        bishop_attacks(from, occupied) | rook_attacks(from, occupied)
+                    }
+                    _ => king_attacks(from),
+                };
+                for to in Squares(attacks & !own) {
+                    moves.push(Move {
+                        from: from as u8,
+                        to: to as u8,
+                        promotion: None,
+                    });
+                }
+            }
+        }
+
+        // Castling
+        let them = us.opposite();
+        let (king_side, queen_side, king_from) = match us {
+            Color::White => (CASTLE_WHITE_KING, CASTLE_WHITE_QUEEN, 4),
+            Color::Black => (CASTLE_BLACK_KING, CASTLE_BLACK_QUEEN, 60),
+        };
+        let own_rooks = self.board.rooks & own;
+        if self.castling & king_side != 0
+            && own_rooks & (1u64 << (king_from + 3)) != 0
+            && occupied & (0b11u64 << (king_from + 1)) == 0
+            && !self.is_square_attacked(king_from, them)
+            && !self.is_square_attacked(king_from + 1, them)
+            && !self.is_square_attacked(king_from + 2, them)
+        {
+            moves.push(Move {
+                from: king_from as u8,
+                to: (king_from + 2) as u8,
+                promotion: None,
+            });
+        }
+        if self.castling & queen_side != 0
+            && own_rooks & (1u64 << (king_from - 4)) != 0
+            && occupied & (0b111u64 << (king_from - 3)) == 0
+            && !self.is_square_attacked(king_from, them)
+            && !self.is_square_attacked(king_from - 1, them)
+            && !self.is_square_attacked(king_from - 2, them)
+        {
+            moves.push(Move {
+                from: king_from as u8,
+                to: (king_from - 2) as u8,
+                promotion: None,
+            });
+        }
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::with_capacity(64);
+        self.pseudo_legal_moves(&mut moves);
+        moves.retain(|mv| self.leaves_king_safe(mv));
+        moves
+    }
+
+    // True if playing the pseudo-legal move doesn't leave our king in check
+    pub fn leaves_king_safe(&self, mv: &Move) -> bool {
+        let us = self.side_to_move;
+        let next = self.make_move(mv);
+        !next.is_square_attacked(next.king_square(us), us.opposite())
+    }
+
+    // Finds the legal move a client asked for. Promotions default to a queen
+    // when the client didn't say what to promote to.
+    pub fn find_move(&self, move_data: &MoveData) -> Option<Move> {
+        let promotion = match move_data.promotion.as_deref() {
+            Some(p) => Some(PieceKind::from_char(p.chars().next()?)?),
+            None => None,
+        };
+        self.legal_moves().into_iter().find(|mv| {
+            mv.from as usize == move_data.from
+                && mv.to as usize == move_data.to
+                && (mv.promotion == promotion
+                    || (promotion.is_none() && mv.promotion == Some(PieceKind::Queen)))
+        })
+    }
+
+    // The legal move in UCI notation, e.g. "e2e4" or "e7e8q"
+    pub fn find_uci_move(&self, uci: &str) -> Option<Move> {
+        let from = square_index(uci.get(0..2)?)?;
+        let to = square_index(uci.get(2..4)?)?;
+        let promotion = match uci.get(4..)? {
+            "" => None,
+            p => Some(PieceKind::from_char(p.chars().next()?)?),
+        };
+        self.legal_moves().into_iter().find(|mv| {
+            mv.from as usize == from && mv.to as usize == to && mv.promotion == promotion
+        })
+    }
+
+    // Plays a move that is known to be at least pseudo-legal
+    pub fn make_move(&self, mv: &Move) -> Position {
+        let mut next = self.clone();
+        let us = self.side_to_move;
+        let them = us.opposite();
+        let (from, to) = (mv.from as usize, mv.to as usize);
+
+        let kind = match self.piece_at(from) {
+            Some((_, kind)) => kind,
+            None => return next,
+        };
+        let captured = self.piece_at(to).map(|(_, kind)| kind);
+
+        next.remove_piece(from, us, kind);
+        if let Some(captured) = captured {
+            next.remove_piece(to, them, captured);
+        }
+        if kind == PieceKind::Pawn && Some(mv.to) == self.en_passant {
+            let victim = if us == Color::White { to - 8 } else { to + 8 };
+            next.remove_piece(victim, them, PieceKind::Pawn);
+        }
+        next.put_piece(to, us, mv.promotion.unwrap_or(kind));
+
+        if kind == PieceKind::King && (from as i32 - to as i32).abs() == 2 {
+            let (rook_from, rook_to) = if to > from {
+                (from + 3, from + 1)
+            } else {
+                (from - 4, from - 1)
+            };
+            next.remove_piece(rook_from, us, PieceKind::Rook);
+            next.put_piece(rook_to, us, PieceKind::Rook);
+        }
+
+        next.castling &= castling_mask(from) & castling_mask(to);
+
+        next.en_passant = if kind == PieceKind::Pawn && (from as i32 - to as i32).abs() == 16 {
+            Some(((from + to) / 2) as u8)
+        } else {
+            None
+        };
+
+        next.halfmove_clock = if kind == PieceKind::Pawn || captured.is_some() {
+            0
+        } else {
+            self.halfmove_clock + 1
+        };
+        if us == Color::Black {
+            next.fullmove_number += 1;
+        }
+        next.side_to_move = them;
+        next
+    }
+
+    pub fn has_mating_material(&self, color: Color) -> bool {
+        let own = self.occupancy(color);
+        let b = &self.board;
+        if (b.pawns | b.rooks | b.queens) & own != 0 {
+            return true;
+        }
+        let minors = (b.knights | b.bishops) & own;
+        if minors.count_ones() >= 2 {
+            // Two knights can't force mate, but mate is still possible
+            return true;
+        }
+        // A lone minor can only mate with the help of other enemy material
+        minors != 0 && (self.occupancy(color.opposite()) & !b.kings) != 0
+    }
+
+    pub fn is_insufficient_material(&self) -> bool {
+        let b = &self.board;
+        if b.pawns | b.rooks | b.queens != 0 {
+            return false;
+        }
+        let minors = b.knights | b.bishops;
+        if minors.count_ones() <= 1 {
+            return true;
+        }
+        // Only bishops left, all on squares of the same color
+        const DARK_SQUARES: u64 = 0xAA55AA55AA55AA55;
+        b.knights == 0 && (b.bishops & DARK_SQUARES == 0 || b.bishops & !DARK_SQUARES == 0)
+    }
+
+    // True if a pawn of the side to move stands next to the en passant square
+    pub fn en_passant_capturable(&self) -> bool {
+        match self.en_passant {
+            Some(ep) => {
+                let us = self.side_to_move;
+                pawn_attacks(us.opposite(), ep as usize) & self.board.pawns & self.occupancy(us)
+                    != 0
+            }
+            None => false,
+        }
+    }
+
+    // Board, side, castling and en passant: the parts of the FEN that decide
+    // whether two positions are the same for repetition purposes
+    pub fn repetition_key(&self) -> String {
+        let fen = self.to_fen();
+        let mut fields: Vec<&str> = fen.split(' ').take(4).collect();
+        if !self.en_passant_capturable() {
+            fields[3] = "-";
+        }
+        fields.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perft(position: &Position, depth: u32) -> u64 {
+        let moves = position.legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves
+            .iter()
+            .map(|mv| perft(&position.make_move(mv), depth - 1))
+            .sum()
+    }
+
+    fn fen(fen: &str) -> Position {
+        Position::from_fen(fen).unwrap()
+    }
+
+    fn can_play(fen_str: &str, uci: &str) -> bool {
+        fen(fen_str).find_uci_move(uci).is_some()
+    }
+
+    // Counts from https://www.chessprogramming.org/Perft_Results
+    #[test]
+    fn perft_of_the_start_position() {
+        assert_eq!(perft(&Position::startpos(), 4), 197_281);
+    }
+
+    #[test]
+    fn perft_of_kiwipete() {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(perft(&fen(kiwipete), 3), 97_862);
+    }
+
+    #[test]
+    fn perft_of_en_passant_pins() {
+        assert_eq!(
+            perft(&fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"), 4),
+            43_238
+        );
+    }
+
+    #[test]
+    fn perft_of_promotions() {
+        let position = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+        assert_eq!(perft(&fen(position), 3), 9_467);
+        let position = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+        assert_eq!(perft(&fen(position), 3), 62_379);
+    }
+
+    #[test]
+    fn takes_en_passant_only_right_away() {
+        let position = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+        assert!(can_play(position, "e5f6"));
+        assert!(!can_play(position, "e5d6"));
+        let after = fen(position).make_move(&fen(position).find_uci_move("e5f6").unwrap());
+        assert_eq!(after.piece_at(37), None);
+    }
+
+    #[test]
+    fn never_castles_through_check() {
+        // The rook on f8 covers f1, the one on b8 only b1
+        let position = "1r3r2/8/2k5/8/8/8/8/R3K2R w KQ - 0 1";
+        assert!(!can_play(position, "e1g1"));
+        assert!(can_play(position, "e1c1"));
+        // Nor out of it
+        assert!(!can_play("4r3/8/2k5/8/8/8/8/R3K2R w KQ - 0 1", "e1c1"));
+    }
+
+    #[test]
+    fn promotes_to_any_piece() {
+        let mut moves: Vec<String> = fen("8/P7/8/8/8/8/8/k6K w - - 0 1")
+            .legal_moves()
+            .into_iter()
+            .filter(|mv| mv.from == 48)
+            .map(Move::to_uci)
+            .collect();
+        moves.sort();
+        assert_eq!(moves, ["a7a8b", "a7a8n", "a7a8q", "a7a8r"]);
+    }
+}
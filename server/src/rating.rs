@@ -0,0 +1,234 @@
+// src/rating.rs
+
+use serde::Serialize;
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::clock::TimeControl;
+
+// Glicko-2 constants, see http://www.glicko.net/glicko/glicko2.pdf
+const SCALE: f64 = 173.7178;
+const TAU: f64 = 0.5; // limits how fast the volatility can change
+const EPSILON: f64 = 0.000001;
+
+pub const DEFAULT_RATING: f64 = 1500.0;
+pub const DEFAULT_DEVIATION: f64 = 350.0;
+pub const DEFAULT_VOLATILITY: f64 = 0.06;
+const MIN_DEVIATION: f64 = 45.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Speed {
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Correspondence,
+}
+
+impl Speed {
+    pub const ALL: [Speed; 5] = [
+        Speed::Bullet,
+        Speed::Blitz,
+        Speed::Rapid,
+        Speed::Classical,
+        Speed::Correspondence,
+    ];
+
+    // Categorised by the expected game length, assuming 40 moves per side.
+    // Untimed games count as correspondence.
+    pub fn from_time_control(time_control: Option<TimeControl>) -> Self {
+        match time_control {
+            None => Speed::Correspondence,
+            Some(tc) => match tc.initial.saturating_add(tc.increment.saturating_mul(40)) {
+                0..=179 => Speed::Bullet,
+                180..=479 => Speed::Blitz,
+                480..=1499 => Speed::Rapid,
+                _ => Speed::Classical,
+            },
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Speed::Bullet => "bullet",
+            Speed::Blitz => "blitz",
+            Speed::Rapid => "rapid",
+            Speed::Classical => "classical",
+            Speed::Correspondence => "correspondence",
+        }
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Speed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Speed::ALL
+            .into_iter()
+            .find(|speed| speed.as_str() == s)
+            .ok_or_else(|| format!("Unknown speed '{}'", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Rating {
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+fn expected_score(mu: f64, opponent_mu: f64, opponent_phi: f64) -> f64 {
+    1.0 / (1.0 + (-g(opponent_phi) * (mu - opponent_mu)).exp())
+}
+
+impl Rating {
+    // New rating after a single game, each game is its own rating period.
+    // `score` is 1 for a win, 0.5 for a draw and 0 for a loss.
+    pub fn update(&self, opponent: &Rating, score: f64) -> Rating {
+        self.update_period(&[(*opponent, score)])
+    }
+
+    // New rating after a rating period with the given (opponent, score) games
+    fn update_period(&self, games: &[(Rating, f64)]) -> Rating {
+        let mu = (self.rating - DEFAULT_RATING) / SCALE;
+        let phi = self.deviation / SCALE;
+
+        let mut v_inverse = 0.0;
+        let mut improvement = 0.0;
+        for (opponent, score) in games {
+            let opponent_mu = (opponent.rating - DEFAULT_RATING) / SCALE;
+            let opponent_phi = opponent.deviation / SCALE;
+            let g_phi = g(opponent_phi);
+            let expected = expected_score(mu, opponent_mu, opponent_phi);
+            v_inverse += g_phi * g_phi * expected * (1.0 - expected);
+            improvement += g_phi * (score - expected);
+        }
+        let v = 1.0 / v_inverse;
+        let delta = v * improvement;
+
+        let volatility = self.new_volatility(phi, v, delta);
+
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let new_mu = mu + new_phi * new_phi * improvement;
+
+        Rating {
+            rating: SCALE * new_mu + DEFAULT_RATING,
+            deviation: (SCALE * new_phi).clamp(MIN_DEVIATION, DEFAULT_DEVIATION),
+            volatility,
+        }
+    }
+
+    // Step 5 of the paper, the Illinois variant of regula falsi
+    fn new_volatility(&self, phi: f64, v: f64, delta: f64) -> f64 {
+        let a = (self.volatility * self.volatility).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            let d = phi * phi + v + ex;
+            ex * (delta * delta - d) / (2.0 * d * d) - (x - a) / (TAU * TAU)
+        };
+
+        let mut lower = a;
+        let mut upper = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+
+        let mut f_lower = f(lower);
+        let mut f_upper = f(upper);
+        while (upper - lower).abs() > EPSILON {
+            let c = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+            let f_c = f(c);
+            if f_c * f_upper <= 0.0 {
+                lower = upper;
+                f_lower = f_upper;
+            } else {
+                f_lower /= 2.0;
+            }
+            upper = c;
+            f_upper = f_c;
+        }
+
+        (lower / 2.0).exp()
+    }
+}
+
+// One player's rating before and after a rated game
+#[derive(Debug, Clone, Serialize)]
+pub struct RatingChange {
+    pub user_id: usize,
+    pub color: String,
+    pub speed: Speed,
+    pub before: Rating,
+    pub after: Rating,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> Rating {
+        Rating {
+            rating,
+            deviation,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+
+    // The example worked through in Glickman's paper
+    #[test]
+    fn matches_the_example_of_the_paper() {
+        let player = rating(1500.0, 200.0);
+        let games = [
+            (rating(1400.0, 30.0), 1.0),
+            (rating(1550.0, 100.0), 0.0),
+            (rating(1700.0, 300.0), 0.0),
+        ];
+        let after = player.update_period(&games);
+        assert!((after.rating - 1464.06).abs() < 0.01, "{:?}", after);
+        assert!((after.deviation - 151.52).abs() < 0.01, "{:?}", after);
+        assert!((after.volatility - 0.05999).abs() < 0.00001, "{:?}", after);
+    }
+
+    #[test]
+    fn sorts_time_controls_by_speed() {
+        let speed =
+            |initial, increment| Speed::from_time_control(Some(TimeControl { initial, increment }));
+        assert_eq!(speed(60, 0), Speed::Bullet);
+        assert_eq!(speed(120, 1), Speed::Bullet);
+        assert_eq!(speed(120, 2), Speed::Blitz);
+        assert_eq!(speed(180, 0), Speed::Blitz);
+        assert_eq!(speed(300, 5), Speed::Rapid);
+        assert_eq!(speed(900, 10), Speed::Rapid);
+        assert_eq!(speed(1800, 0), Speed::Classical);
+        assert_eq!(speed(u64::MAX, u64::MAX), Speed::Classical);
+        assert_eq!(Speed::from_time_control(None), Speed::Correspondence);
+    }
+}
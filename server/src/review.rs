@@ -0,0 +1,337 @@
+// src/review.rs
+
+// Engine review of finished games. Every position of the game is searched
+// once, each move is judged by how much of the mover's winning chances it
+// gave away and each player gets an accuracy score, the way the big sites
+// do it. A review takes a while, so they run one at a time on a thread of
+// their own and are saved with the game once done.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use uuid::Uuid;
+
+use crate::bot;
+use crate::engine::{self, Engine, Limits};
+use crate::notation::move_to_san;
+use crate::position::{Color, Move, Position};
+use crate::storage::{replay, GameStore};
+
+pub const DEPTH: i32 = 12;
+const NODES: u64 = 200_000; // per position, in case the depth takes long
+const HASH_MB: usize = 32;
+// Beyond this the game is decided and the exact score doesn't matter
+const MAX_CP: i32 = 1_000;
+// Winning chances lost, in percentage points
+const INACCURACY: f64 = 5.0;
+const MISTAKE: f64 = 10.0;
+const BLUNDER: f64 = 15.0;
+// Reviews a user can ask for in an hour
+const REQUESTS_PER_HOUR: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Class {
+    Best, // the engine's own choice
+    Good,
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+// From White's point of view, as {"cp": 35} or {"mate": -3}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Eval {
+    Cp(i32),
+    Mate(i32),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MoveReview {
+    pub ply: usize, // from 1
+    pub san: String,
+    pub best: Option<String>, // what the engine would have played
+    pub eval: Option<Eval>,   // after the move, None once the game is over
+    pub cp_loss: i32,
+    pub win_loss: f64,
+    pub class: Class,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PlayerReview {
+    pub accuracy: Option<f64>, // 0 to 100, None without a move to judge
+    pub acpl: Option<i32>,     // average centipawn loss
+    pub inaccuracies: usize,
+    pub mistakes: usize,
+    pub blunders: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Review {
+    pub depth: i32,
+    pub white: PlayerReview,
+    pub black: PlayerReview,
+    pub moves: Vec<MoveReview>,
+}
+
+impl MoveReview {
+    // NAG and comment for the PGN export, e.g. "$2 {[%eval -1.35] Mistake.
+    // Nf3 was best.}"
+    pub fn annotation(&self) -> String {
+        let mut comment = match self.eval {
+            Some(Eval::Cp(cp)) => format!("[%eval {:.2}]", cp as f64 / 100.0),
+            Some(Eval::Mate(moves)) => format!("[%eval #{}]", moves),
+            None => String::new(),
+        };
+        let (nag, verdict) = match self.class {
+            Class::Inaccuracy => ("$6 ", "Inaccuracy."),
+            Class::Mistake => ("$2 ", "Mistake."),
+            Class::Blunder => ("$4 ", "Blunder."),
+            Class::Best | Class::Good => ("", ""),
+        };
+        if !verdict.is_empty() {
+            comment.push_str(&format!(" {}", verdict));
+            if let Some(best) = &self.best {
+                comment.push_str(&format!(" {} was best.", best));
+            }
+        }
+        let comment = comment.trim();
+        if comment.is_empty() {
+            nag.trim().to_string()
+        } else {
+            format!("{}{{{}}}", nag, comment)
+        }
+    }
+}
+
+fn pending() -> &'static Mutex<HashSet<Uuid>> {
+    static PENDING: OnceLock<Mutex<HashSet<Uuid>>> = OnceLock::new();
+    PENDING.get_or_init(|| Mutex::new(HashSet::new()))
+}
+
+fn queue() -> &'static Sender<(Arc<dyn GameStore>, Uuid)> {
+    static QUEUE: OnceLock<Sender<(Arc<dyn GameStore>, Uuid)>> = OnceLock::new();
+    QUEUE.get_or_init(|| {
+        let (sender, jobs) = mpsc::channel::<(Arc<dyn GameStore>, Uuid)>();
+        std::thread::spawn(move || {
+            let mut engine = Engine::new(HASH_MB);
+            engine.set_tablebases(bot::tablebases());
+            for (store, room_id) in jobs {
+                match run(&mut engine, store.as_ref(), room_id) {
+                    Ok(()) => println!("Reviewed game {}", room_id),
+                    Err(err) => println!("Failed to review game {}: {}", room_id, err),
+                }
+                pending().lock().unwrap().remove(&room_id);
+            }
+        });
+        sender
+    })
+}
+
+// CHESS_AUTO_REVIEW=1 reviews every game as soon as it finishes
+pub fn auto_review() -> bool {
+    static AUTO: OnceLock<bool> = OnceLock::new();
+    *AUTO.get_or_init(|| std::env::var("CHESS_AUTO_REVIEW").is_ok_and(|value| value == "1"))
+}
+
+// Queues a review of a finished game, false if one is already waiting or
+// under way
+pub fn request(store: Arc<dyn GameStore>, room_id: Uuid) -> bool {
+    if !pending().lock().unwrap().insert(room_id) {
+        return false;
+    }
+    let _ = queue().send((store, room_id));
+    true
+}
+
+// Counts a review the user asks for, false if they've asked for too many in
+// the last hour
+pub fn allow_request(user_id: usize, now: u64) -> bool {
+    static REQUESTS: OnceLock<Mutex<HashMap<usize, Vec<u64>>>> = OnceLock::new();
+    let mut requests = REQUESTS.get_or_init(Default::default).lock().unwrap();
+    let times = requests.entry(user_id).or_default();
+    times.retain(|at| now.saturating_sub(*at) < 60 * 60);
+    if times.len() >= REQUESTS_PER_HOUR {
+        return false;
+    }
+    times.push(now);
+    true
+}
+
+pub fn is_pending(room_id: Uuid) -> bool {
+    pending().lock().unwrap().contains(&room_id)
+}
+
+fn run(engine: &mut Engine, store: &dyn GameStore, room_id: Uuid) -> Result<(), String> {
+    let record = store
+        .load_room(room_id)
+        .map_err(|err| err.to_string())?
+        .ok_or_else(|| "game not found".to_string())?;
+    let (moves, positions) = replay(&record)?;
+    engine.clear();
+    let review = review_game(engine, &moves, &positions);
+    let json = serde_json::to_string(&review).map_err(|err| err.to_string())?;
+    store
+        .save_review(room_id, &json)
+        .map_err(|err| err.to_string())
+}
+
+// `positions` holds the start and the position after every move
+pub fn review_game(engine: &mut Engine, moves: &[Move], positions: &[Position]) -> Review {
+    // Score for the side to move and the best move in every position
+    let mut history = Vec::with_capacity(positions.len());
+    let mut keys = Vec::with_capacity(positions.len());
+    let searched: Vec<(i32, Option<Move>)> = positions
+        .iter()
+        .map(|position| {
+            keys.push(position.repetition_key());
+            let searched = search(engine, position, &history, &keys);
+            history.push(engine::hash(position));
+            searched
+        })
+        .collect();
+
+    let mut reviews = Vec::with_capacity(moves.len());
+    for (i, mv) in moves.iter().enumerate() {
+        let (before, best) = searched[i];
+        // The engine's own move loses nothing, whatever the next search
+        // makes of it
+        let after = if best == Some(*mv) {
+            before
+        } else {
+            -searched[i + 1].0
+        };
+        let win_loss = (win_chance(before) - win_chance(after)).max(0.0);
+        let class = if best == Some(*mv) {
+            Class::Best
+        } else if win_loss >= BLUNDER {
+            Class::Blunder
+        } else if win_loss >= MISTAKE {
+            Class::Mistake
+        } else if win_loss >= INACCURACY {
+            Class::Inaccuracy
+        } else {
+            Class::Good
+        };
+
+        let next = &positions[i + 1];
+        let score = searched[i + 1].0;
+        let eval = if next.legal_moves().is_empty() && next.in_check() {
+            None
+        } else {
+            let sign = if next.side_to_move == Color::White {
+                1
+            } else {
+                -1
+            };
+            Some(match engine::mate_in(score) {
+                Some(moves) => Eval::Mate(sign * moves),
+                None => Eval::Cp(sign * score),
+            })
+        };
+        reviews.push(MoveReview {
+            ply: i + 1,
+            san: move_to_san(&positions[i], mv),
+            best: best.map(|best| move_to_san(&positions[i], &best)),
+            eval,
+            cp_loss: (before.clamp(-MAX_CP, MAX_CP) - after.clamp(-MAX_CP, MAX_CP)).max(0),
+            win_loss: round(win_loss),
+            class,
+        });
+    }
+
+    Review {
+        depth: DEPTH,
+        white: player_review(&reviews, positions, Color::White),
+        black: player_review(&reviews, positions, Color::Black),
+        moves: reviews,
+    }
+}
+
+fn search(
+    engine: &mut Engine,
+    position: &Position,
+    history: &[u64],
+    keys: &[String],
+) -> (i32, Option<Move>) {
+    if position.legal_moves().is_empty() {
+        let score = if position.in_check() {
+            engine::mate_score(0)
+        } else {
+            0
+        };
+        return (score, None);
+    }
+    let repetitions = keys.iter().filter(|key| Some(*key) == keys.last()).count();
+    if repetitions >= 3 || position.halfmove_clock >= 100 || position.is_insufficient_material() {
+        return (0, None);
+    }
+    let result = engine.search(
+        position,
+        history,
+        Limits {
+            depth: Some(DEPTH),
+            movetime: None,
+            nodes: Some(NODES),
+        },
+    );
+    (result.score, result.best_move)
+}
+
+fn player_review(reviews: &[MoveReview], positions: &[Position], color: Color) -> PlayerReview {
+    let own: Vec<&MoveReview> = reviews
+        .iter()
+        .filter(|review| positions[review.ply - 1].side_to_move == color)
+        .collect();
+    let count = |class: Class| own.iter().filter(|review| review.class == class).count();
+    let moves = own.len().max(1) as f64;
+    PlayerReview {
+        accuracy: (!own.is_empty()).then(|| {
+            round(
+                own.iter()
+                    .map(|review| accuracy(review.win_loss))
+                    .sum::<f64>()
+                    / moves,
+            )
+        }),
+        acpl: (!own.is_empty()).then(|| {
+            (own.iter().map(|review| review.cp_loss as f64).sum::<f64>() / moves).round() as i32
+        }),
+        inaccuracies: count(Class::Inaccuracy),
+        mistakes: count(Class::Mistake),
+        blunders: count(Class::Blunder),
+    }
+}
+
+// Chance of winning, 0 to 100, for a score in centipawns
+fn win_chance(score: i32) -> f64 {
+    let cp = score.clamp(-MAX_CP, MAX_CP) as f64;
+    50.0 + 50.0 * (2.0 / (1.0 + (-0.00368208 * cp).exp()) - 1.0)
+}
+
+// 100 for a move that keeps the winning chances, falling off quickly with
+// what it gives away
+fn accuracy(win_loss: f64) -> f64 {
+    (103.1668 * (-0.04354 * win_loss).exp() - 3.1669).clamp(0.0, 100.0)
+}
+
+fn round(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_requests_per_hour() {
+        let user_id = usize::MAX; // nobody else's
+        for _ in 0..REQUESTS_PER_HOUR {
+            assert!(allow_request(user_id, 1_000));
+        }
+        assert!(!allow_request(user_id, 1_000 + 60 * 60 - 1));
+        assert!(allow_request(user_id, 1_000 + 60 * 60));
+    }
+}
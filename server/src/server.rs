@@ -1,39 +1,672 @@
 // src/server.rs
 
 use actix::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
-use crate::bitboard::init_bitboard;
-use crate::game_room::GameRoom;
+use crate::adjudication;
+use crate::analysis::{self, AnalysisPool, Job};
+use crate::bot::{self, Bot, BOT_USERNAME, DEFAULT_LEVEL, MAX_LEVEL, MIN_LEVEL};
+use crate::challenges::Challenge;
+use crate::explorer;
+use crate::game_room::{random_color, GameResult, GameRoom};
+use crate::lobby::{Seek, MAX_SEEKS_PER_USER};
+use crate::matchmaking::{
+    assign_colors, Matchmaker, PoolKey, QueueEntry, COLOR_HISTORY_LEN, VARIANTS,
+};
 use crate::messages::*;
+use crate::notation::move_to_san;
+use crate::position::{Move, Position};
+use crate::rating::{RatingChange, Speed, DEFAULT_RATING};
+use crate::review;
+use crate::storage::{unix_now, GameStore, PlayerRecord, StorageResult};
+use crate::tournament::{Tournament, TournamentError, TournamentStatus};
 use crate::websocket::MyWebSocket;
 
 pub struct Server {
     pub sessions: HashMap<usize, Addr<MyWebSocket>>,
+    pub session_users: HashMap<usize, usize>, // session_id -> user_id
+    pub guest_sessions: HashSet<usize>,
     pub rooms: HashMap<Uuid, GameRoom>,
-    pub waiting_players: Vec<(usize, Addr<MyWebSocket>)>,
+    pub queue: Matchmaker,
+    pub challenges: HashMap<Uuid, Challenge>,
+    pub seeks: Vec<Seek>, // oldest first
+    pub tournaments: HashMap<Uuid, Tournament>,
+    pub bot: Option<Bot>, // None if its account couldn't be set up
+    pub analysis: AnalysisPool,
+    pub analyses: HashMap<usize, Arc<AtomicBool>>, // session_id -> stop flag of its analysis
     pub session_id_counter: usize,
+    pub store: Arc<dyn GameStore>,
+}
+
+const QUEUE_STATUS_INTERVAL: Duration = Duration::from_secs(3);
+
+fn log_storage_error(result: StorageResult<()>) {
+    if let Err(err) = result {
+        println!("Failed to persist game state: {}", err);
+    }
 }
 
 impl Server {
-    pub fn new() -> Self {
-        Server {
+    pub fn new(store: Arc<dyn GameStore>) -> Self {
+        let mut rooms = HashMap::new();
+
+        match store.load_unfinished_rooms() {
+            Ok(records) => {
+                for record in &records {
+                    rooms.insert(record.id, GameRoom::restore(record));
+                }
+                println!("Restored {} unfinished rooms", records.len());
+            }
+            Err(err) => println!("Failed to restore rooms: {}", err),
+        }
+
+        let mut tournaments = HashMap::new();
+        match store.load_tournaments() {
+            Ok(states) => {
+                for state in &states {
+                    match serde_json::from_str::<Tournament>(state) {
+                        Ok(tournament) => {
+                            tournaments.insert(tournament.id, tournament);
+                        }
+                        Err(err) => println!("Skipping unreadable tournament: {}", err),
+                    }
+                }
+                println!("Restored {} tournaments", tournaments.len());
+                // Rooms stored before they kept their own rules get them
+                // from their tournament
+                for tournament in tournaments.values() {
+                    let room_ids = tournament.pairings.iter().flatten();
+                    for room_id in room_ids.filter_map(|pairing| pairing.room_id) {
+                        if let Some(room) = rooms.get_mut(&room_id) {
+                            room.adjudication = room.adjudication.or(tournament.adjudication);
+                        }
+                    }
+                }
+            }
+            Err(err) => println!("Failed to restore tournaments: {}", err),
+        }
+
+        let mut server = Server {
             sessions: HashMap::new(),
-            rooms: HashMap::new(),
-            waiting_players: Vec::new(),
+            session_users: HashMap::new(),
+            guest_sessions: HashSet::new(),
+            rooms,
+            queue: Matchmaker::new(),
+            challenges: HashMap::new(),
+            seeks: Vec::new(),
+            tournaments,
+            bot: None,
+            analysis: AnalysisPool::new(
+                std::thread::available_parallelism().map_or(1, |n| n.get()),
+            ),
+            analyses: HashMap::new(),
             session_id_counter: 0,
+            store,
+        };
+        server.set_up_bot();
+        server
+    }
+
+    // The bot has an account of its own, without a password so nobody can
+    // log in as it, and a session that only exists on the server
+    fn set_up_bot(&mut self) {
+        let user = match self.store.find_user(BOT_USERNAME) {
+            Ok(Some(user)) => Ok(user),
+            Ok(None) => self.store.create_user(BOT_USERNAME, "", false),
+            Err(err) => Err(err),
+        };
+        let user = match user {
+            Ok(user) => user,
+            Err(err) => return println!("Failed to set up the bot: {}", err),
+        };
+
+        let session_id = self.generate_session_id();
+        self.session_users.insert(session_id, user.id);
+        let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let mut bot = Bot::new(user.id, session_id, workers);
+        // Restored games carry on at the default level
+        for room in self.rooms.values() {
+            if room.get_player_color(user.id).is_some() {
+                bot.add_game(room.id, DEFAULT_LEVEL, None);
+            }
+        }
+        self.bot = Some(bot);
+    }
+
+    fn play_bots(&mut self, ctx: &Context<Self>) {
+        if let Some(bot) = self.bot.as_mut() {
+            bot.play(&self.rooms, &ctx.address());
+        }
+    }
+
+    fn stop_analysis(&mut self, session_id: usize) {
+        if let Some(stop) = self.analyses.remove(&session_id) {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // Whether the user is seated in a game that's being played
+    fn is_playing(&self, user_id: usize) -> bool {
+        self.rooms.values().any(|room| {
+            room.is_full() && !room.is_finished() && room.get_player_color(user_id).is_some()
+        })
+    }
+
+    // The moves of a room, live or from the database
+    fn room_moves(&self, room_id: Uuid) -> Option<Vec<Move>> {
+        let move_data = match self.rooms.get(&room_id) {
+            Some(room) => room.moves.clone(),
+            None => match self.store.load_room(room_id) {
+                Ok(record) => record?.moves,
+                Err(err) => {
+                    println!("Failed to load room {}: {}", room_id, err);
+                    return None;
+                }
+            },
+        };
+        // Rooms always start from the initial position
+        let mut position = Position::startpos();
+        let mut moves = Vec::with_capacity(move_data.len());
+        for move_data in &move_data {
+            let mv = position.find_move(move_data)?;
+            position = position.make_move(&mv);
+            moves.push(mv);
         }
+        Some(moves)
     }
 
     fn generate_session_id(&mut self) -> usize {
         self.session_id_counter += 1;
         self.session_id_counter
     }
+
+    fn user_of(&self, session_id: usize) -> Option<usize> {
+        self.session_users.get(&session_id).copied()
+    }
+
+    // Every connected session of a user, oldest first
+    fn sessions_of(&self, user_id: usize) -> Vec<(usize, Addr<MyWebSocket>)> {
+        let mut sessions: Vec<(usize, Addr<MyWebSocket>)> = self
+            .session_users
+            .iter()
+            .filter(|(_, user)| **user == user_id)
+            .filter_map(|(id, _)| self.sessions.get(id).map(|addr| (*id, addr.clone())))
+            .collect();
+        sessions.sort_by_key(|(id, _)| *id);
+        sessions
+    }
+
+    fn send_error(&self, id: usize, error: &str) {
+        if let Some(addr) = self.sessions.get(&id) {
+            addr.do_send(ErrorMessage {
+                error: error.to_string(),
+            });
+        }
+    }
+
+    fn insert_room(&mut self, room: GameRoom) {
+        log_storage_error(self.store.create_room(&room.to_record()));
+        self.rooms.insert(room.id, room);
+    }
+
+    // Glicko-2 update for both players of a rated game. Nothing is changed
+    // if a rating can't be loaded.
+    fn rate_game(&self, room: &GameRoom, result: GameResult) -> Vec<RatingChange> {
+        let speed = Speed::from_time_control(room.time_control);
+        let mut players = Vec::new();
+        for (color, user_id) in &room.seats {
+            match self.store.load_rating(*user_id, speed) {
+                Ok(rating) => players.push((*user_id, color.clone(), rating.unwrap_or_default())),
+                Err(err) => {
+                    println!("Failed to load rating of user {}: {}", user_id, err);
+                    return Vec::new();
+                }
+            }
+        }
+        if players.len() != 2 {
+            return Vec::new();
+        }
+        // White first
+        players.sort_by_key(|(_, color, _)| color != "w");
+
+        let mut changes = Vec::new();
+        for (i, (user_id, color, before)) in players.iter().enumerate() {
+            let opponent = &players[1 - i].2;
+            let after = before.update(opponent, result.score_for(color));
+            log_storage_error(self.store.record_rating(*user_id, speed, room.id, &after));
+            changes.push(RatingChange {
+                user_id: *user_id,
+                color: color.clone(),
+                speed,
+                before: *before,
+                after,
+            });
+        }
+        changes
+    }
+
+    fn finish_room(&mut self, room_id: Uuid, result: GameResult, termination: &str) {
+        let newly_finished = self
+            .rooms
+            .get(&room_id)
+            .is_some_and(|room| !room.is_finished());
+        let rating_changes = match self.rooms.get(&room_id) {
+            Some(room) if room.rated && newly_finished => self.rate_game(room, result),
+            _ => Vec::new(),
+        };
+        if let Some(room) = self.rooms.get_mut(&room_id) {
+            room.finish(result, termination, rating_changes);
+            let stored = self.store.finish_room(
+                room_id,
+                result.as_str(),
+                termination,
+                room.opening,
+                room.clock_ms(),
+            );
+            room.persisted = stored.is_ok();
+            log_storage_error(stored);
+        }
+        if let Some(bot) = self.bot.as_mut() {
+            bot.finish_game(room_id);
+        }
+        adjudication::forget(room_id);
+        if newly_finished {
+            self.record_tournament_result(room_id, result);
+            self.add_to_explorer(room_id);
+            if review::auto_review() {
+                review::request(self.store.clone(), room_id);
+            }
+        }
+    }
+
+    // Has the position after the last move searched for the adjudication
+    // rules, see `PositionEvaluated` for the rest
+    fn request_adjudication(&self, room_id: Uuid, ctx: &Context<Self>) {
+        let Some(rules) = self.rooms.get(&room_id).and_then(|room| room.adjudication) else {
+            return;
+        };
+        if let Some(moves) = self.room_moves(room_id) {
+            adjudication::request(ctx.address(), room_id, moves, &rules);
+        }
+    }
+
+    fn add_to_explorer(&self, room_id: Uuid) {
+        let added = match self.store.load_room(room_id) {
+            Ok(Some(record)) => explorer::add_room(self.store.as_ref(), &record),
+            Ok(None) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        };
+        if let Err(err) = added {
+            println!("Failed to add game {} to the explorer: {}", room_id, err);
+        }
+    }
+
+    fn save_tournament(&self, tournament_id: Uuid) {
+        if let Some(tournament) = self.tournaments.get(&tournament_id) {
+            match serde_json::to_string(tournament) {
+                Ok(state) => log_storage_error(self.store.save_tournament(tournament_id, &state)),
+                Err(err) => println!("Failed to serialize tournament {}: {}", tournament_id, err),
+            }
+        }
+    }
+
+    // The scheduling core shared by every format: lets the tournament pair
+    // whatever is due and sets up rooms for the new games. Players who are
+    // connected are put into their rooms right away, the others find their
+    // game through the tournament API and join it like any other room.
+    // Returns whether anything changed.
+    fn schedule_tournament(&mut self, tournament_id: Uuid) -> bool {
+        let online: HashSet<usize> = self.session_users.values().copied().collect();
+        let Some(tournament) = self.tournaments.get_mut(&tournament_id) else {
+            return false;
+        };
+        if !tournament.schedule(&online, unix_now()) {
+            return false;
+        }
+        let (time_control, rated, adjudication) = (
+            tournament.time_control,
+            tournament.rated,
+            tournament.adjudication,
+        );
+
+        let mut rooms = Vec::new();
+        for pairing in tournament.unstarted_games() {
+            let Some(black) = pairing.black else {
+                continue;
+            };
+            let room_id = Uuid::new_v4();
+            let time_control = pairing.time_control.or(time_control);
+            let mut room = GameRoom::new(room_id, Position::startpos(), time_control);
+            room.rated = rated;
+            room.adjudication = adjudication;
+            // Armageddon gives black less time
+            if let (Some(seconds), Some(clock)) = (pairing.black_initial, room.clock.as_mut()) {
+                clock.black_remaining = Duration::from_secs(seconds);
+            }
+            room.take_seat(pairing.white, "w");
+            room.take_seat(black, "b");
+            pairing.room_id = Some(room_id);
+            rooms.push(room);
+        }
+        if tournament.status == TournamentStatus::Finished {
+            println!("Tournament {} finished", tournament_id);
+        } else if !rooms.is_empty() {
+            println!("Tournament {} started {} games", tournament_id, rooms.len());
+        }
+
+        for mut room in rooms {
+            for user_id in room.seats.values().copied().collect::<Vec<_>>() {
+                for (session_id, addr) in self.sessions_of(user_id) {
+                    room.attach(user_id, session_id, addr);
+                }
+            }
+            self.insert_room(room);
+        }
+        self.save_tournament(tournament_id);
+        self.send_tournament_standings(tournament_id);
+        true
+    }
+
+    fn schedule_tournaments(&mut self) {
+        let running: Vec<Uuid> = self
+            .tournaments
+            .values()
+            .filter(|tournament| tournament.status == TournamentStatus::Running)
+            .map(|tournament| tournament.id)
+            .collect();
+        for tournament_id in running {
+            self.schedule_tournament(tournament_id);
+        }
+    }
+
+    fn send_tournament_standings(&self, tournament_id: Uuid) {
+        let Some(tournament) = self.tournaments.get(&tournament_id) else {
+            return;
+        };
+        let msg = TournamentStandings {
+            tournament_id,
+            status: tournament.status,
+            round: tournament.current_round(),
+            standings: tournament.standings(),
+        };
+        for user_id in tournament.participants() {
+            for (_, addr) in self.sessions_of(user_id) {
+                addr.do_send(msg.clone());
+            }
+        }
+    }
+
+    fn record_tournament_result(&mut self, room_id: Uuid, result: GameResult) {
+        let tournament_id = self
+            .tournaments
+            .values_mut()
+            .filter(|tournament| tournament.status == TournamentStatus::Running)
+            .find_map(|tournament| {
+                tournament
+                    .record_result(room_id, result)
+                    .then_some(tournament.id)
+            });
+        let Some(tournament_id) = tournament_id else {
+            return;
+        };
+
+        if !self.schedule_tournament(tournament_id) {
+            self.save_tournament(tournament_id);
+            self.send_tournament_standings(tournament_id);
+        }
+    }
+
+    // Applies `change` to a tournament and saves it if that worked
+    fn update_tournament<F>(
+        &mut self,
+        tournament_id: Uuid,
+        change: F,
+    ) -> Result<Tournament, TournamentError>
+    where
+        F: FnOnce(&mut Tournament) -> Result<(), TournamentError>,
+    {
+        let tournament = self
+            .tournaments
+            .get_mut(&tournament_id)
+            .ok_or(TournamentError::NotFound)?;
+        change(tournament)?;
+        self.save_tournament(tournament_id);
+        Ok(self.tournaments[&tournament_id].clone())
+    }
+
+    fn broadcast_lobby(&self, update: LobbyUpdate) {
+        for addr in self.sessions.values() {
+            addr.do_send(update.clone());
+        }
+    }
+
+    // Withdraws the seeks matching `predicate` and tells everyone
+    fn remove_seeks<F>(&mut self, predicate: F, reason: &str)
+    where
+        F: Fn(&Seek) -> bool,
+    {
+        let (removed, kept): (Vec<Seek>, Vec<Seek>) = std::mem::take(&mut self.seeks)
+            .into_iter()
+            .partition(predicate);
+        self.seeks = kept;
+        for seek in removed {
+            self.broadcast_lobby(LobbyUpdate::Removed {
+                seek_id: seek.id,
+                reason: reason.to_string(),
+            });
+        }
+    }
+
+    fn close_challenge(&self, challenge: &Challenge, reason: &str) {
+        let msg = ChallengeClosed {
+            challenge_id: challenge.id,
+            reason: reason.to_string(),
+        };
+        for user_id in [challenge.challenger, challenge.target] {
+            for (_, addr) in self.sessions_of(user_id) {
+                addr.do_send(msg.clone());
+            }
+        }
+    }
+
+    fn expire_challenges(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<Uuid> = self
+            .challenges
+            .values()
+            .filter(|challenge| challenge.is_expired(now))
+            .map(|challenge| challenge.id)
+            .collect();
+        for id in expired {
+            if let Some(challenge) = self.challenges.remove(&id) {
+                self.close_challenge(&challenge, "expired");
+            }
+        }
+    }
+
+    fn check_clocks(&mut self) {
+        let flagged: Vec<(Uuid, GameResult)> = self
+            .rooms
+            .values()
+            .filter(|room| !room.is_finished() && room.is_flagged())
+            .map(|room| (room.id, room.timeout_result()))
+            .collect();
+
+        for (room_id, result) in flagged {
+            self.finish_room(room_id, result, "timeout");
+        }
+    }
+
+    // Drops finished rooms from memory once they're stored and their rematch
+    // window has closed. A result that failed to store is tried again.
+    fn evict_finished_rooms(&mut self) {
+        let now = Instant::now();
+        for room in self.rooms.values_mut() {
+            if let (Some((result, termination)), false) = (&room.result, room.persisted) {
+                let stored = self.store.finish_room(
+                    room.id,
+                    result.as_str(),
+                    termination,
+                    room.opening,
+                    room.clock_ms(),
+                );
+                room.persisted = stored.is_ok();
+                log_storage_error(stored);
+            }
+        }
+        self.rooms.retain(|_, room| !room.can_evict(now));
+        if let Some(bot) = self.bot.as_mut() {
+            bot.forget_evicted(&self.rooms);
+        }
+    }
+
+    fn match_queue(&mut self) {
+        for (first, second) in self.queue.find_pairs() {
+            let room_id = Uuid::new_v4();
+            let position = Position::startpos();
+            let mut room = GameRoom::new(room_id, position, first.pool.time_control);
+            room.rated = first.pool.rated;
+
+            let (first_color, second_color) = assign_colors(
+                &self.color_history(first.user_id),
+                &self.color_history(second.user_id),
+            );
+            room.add_player_as(first.user_id, first.session_id, first.addr, first_color);
+            room.add_player_as(second.user_id, second.session_id, second.addr, second_color);
+
+            self.insert_room(room);
+
+            println!(
+                "Room {} created with users {} and {}",
+                room_id, first.user_id, second.user_id
+            );
+        }
+    }
+
+    fn color_history(&self, user_id: usize) -> Vec<String> {
+        self.store
+            .recent_colors(user_id, COLOR_HISTORY_LEN)
+            .unwrap_or_else(|err| {
+                println!("Failed to load color history of user {}: {}", user_id, err);
+                Vec::new()
+            })
+    }
+
+    fn send_queue_statuses(&self) {
+        for (addr, status) in self.queue.statuses() {
+            addr.do_send(QueueStatusUpdate {
+                position: status.position,
+                pool_size: status.pool_size,
+                estimated_wait: status.estimated_wait.map(|wait| wait.as_secs()),
+            });
+        }
+    }
+
+    // Tournament games are paired by their tournament
+    fn is_tournament_room(&self, room_id: Uuid) -> bool {
+        self.tournaments.values().any(|tournament| {
+            tournament
+                .pairings
+                .iter()
+                .flatten()
+                .any(|pairing| pairing.room_id == Some(room_id))
+        })
+    }
+
+    fn start_rematch(&mut self, room_id: Uuid, ctx: &Context<Self>) {
+        let (time_control, rated, seats, connections) = match self.rooms.get(&room_id) {
+            Some(room) => (
+                room.time_control,
+                room.rated,
+                room.seats
+                    .iter()
+                    .map(|(color, user_id)| (*user_id, color.clone()))
+                    .collect::<Vec<_>>(),
+                room.players.clone(),
+            ),
+            None => return,
+        };
+        let bot_id = self.bot.as_ref().map(|bot| bot.user_id);
+
+        // The bot never connects, it only has to be there
+        for (user_id, _) in &seats {
+            if Some(*user_id) != bot_id && !connections.iter().any(|(_, id, _)| id == user_id) {
+                for (session_id, _, _) in &connections {
+                    self.send_error(*session_id, "Opponent is not connected");
+                }
+                return;
+            }
+        }
+
+        let new_room_id = Uuid::new_v4();
+        let mut new_room = GameRoom::new(new_room_id, Position::startpos(), time_control);
+        new_room.rematch_of = Some(room_id);
+        new_room.rated = rated;
+
+        // Colors are swapped, white takes the first seat
+        let mut seats = seats;
+        seats.sort_by_key(|(_, color)| color == "w");
+        for (user_id, old_color) in &seats {
+            let color = if old_color == "w" { "b" } else { "w" };
+            if Some(*user_id) == bot_id {
+                let added = self
+                    .bot
+                    .as_mut()
+                    .is_some_and(|bot| bot.add_rematch(room_id, new_room_id));
+                if !added {
+                    for (session_id, _, _) in &connections {
+                        self.send_error(*session_id, "The bot is not available");
+                    }
+                    return;
+                }
+                new_room.take_seat(*user_id, color);
+                continue;
+            }
+            let mut sessions = connections.iter().filter(|(_, id, _)| id == user_id);
+            if let Some((session_id, _, addr)) = sessions.next() {
+                new_room.add_player_as(*user_id, *session_id, addr.clone(), color);
+            }
+            for (session_id, _, addr) in sessions {
+                new_room.attach(*user_id, *session_id, addr.clone());
+            }
+        }
+
+        if let Some(room) = self.rooms.get_mut(&room_id) {
+            room.rematch_room = Some(new_room_id);
+            room.rematch_offers.clear();
+        }
+        self.insert_room(new_room);
+        self.play_bots(ctx);
+
+        println!(
+            "Rematch of room {} started in room {}",
+            room_id, new_room_id
+        );
+    }
 }
 
 impl Actor for Server {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(Duration::from_secs(1), |act, ctx| {
+            act.check_clocks();
+            act.evict_finished_rooms();
+            act.play_bots(ctx);
+            act.expire_challenges();
+            // Rating windows widen over time, so pairs can appear without
+            // anyone joining
+            act.match_queue();
+            act.schedule_tournaments();
+        });
+        ctx.run_interval(QUEUE_STATUS_INTERVAL, |act, _| {
+            act.send_queue_statuses();
+        });
+    }
 }
 
 impl Handler<Connect> for Server {
@@ -42,7 +675,19 @@ impl Handler<Connect> for Server {
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) -> Self::Result {
         let id = self.generate_session_id();
         self.sessions.insert(id, msg.addr);
-        println!("Client connected with session id: {}", id);
+        self.session_users.insert(id, msg.user_id);
+        if msg.guest {
+            self.guest_sessions.insert(id);
+        }
+        if let Some(addr) = self.sessions.get(&id) {
+            addr.do_send(LobbyUpdate::Snapshot(
+                self.seeks.iter().map(|seek| seek.info()).collect(),
+            ));
+        }
+        println!(
+            "Client connected with session id: {} as user {}",
+            id, msg.user_id
+        );
         id
     }
 }
@@ -52,23 +697,175 @@ impl Handler<Disconnect> for Server {
 
     fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
         self.sessions.remove(&msg.id);
+        self.session_users.remove(&msg.id);
+        self.guest_sessions.remove(&msg.id);
+        self.queue.leave(msg.id);
+        self.stop_analysis(msg.id);
+        self.remove_seeks(|seek| seek.session_id == msg.id, "cancelled");
+        for room in self.rooms.values_mut() {
+            room.detach(msg.id);
+        }
         println!("Client disconnected with session id: {}", msg.id);
     }
 }
 
+impl Handler<ListLiveRooms> for Server {
+    type Result = MessageResult<ListLiveRooms>;
+
+    fn handle(&mut self, _: ListLiveRooms, _: &mut Context<Self>) -> Self::Result {
+        let mut rooms: Vec<LiveRoomInfo> = self
+            .rooms
+            .values()
+            .filter(|room| !room.is_finished())
+            .map(|room| room.live_info())
+            .collect();
+        rooms.sort_by_key(|room| std::cmp::Reverse(room.created_at));
+        MessageResult(rooms)
+    }
+}
+
+impl Handler<CreateTournament> for Server {
+    type Result = MessageResult<CreateTournament>;
+
+    fn handle(&mut self, msg: CreateTournament, _: &mut Context<Self>) -> Self::Result {
+        let mut tournament = Tournament::new(
+            msg.name,
+            msg.created_by,
+            msg.format,
+            msg.time_control,
+            msg.rated,
+            msg.rounds,
+        );
+        tournament.adjudication = msg.adjudication;
+        let tournament_id = tournament.id;
+        self.tournaments.insert(tournament_id, tournament);
+        self.save_tournament(tournament_id);
+        println!(
+            "Tournament {} created by user {}",
+            tournament_id, msg.created_by
+        );
+        MessageResult(self.tournaments[&tournament_id].clone())
+    }
+}
+
+impl Handler<JoinTournament> for Server {
+    type Result = Result<Tournament, TournamentError>;
+
+    fn handle(&mut self, msg: JoinTournament, _: &mut Context<Self>) -> Self::Result {
+        self.update_tournament(msg.tournament_id, |tournament| {
+            tournament.join(msg.player, msg.guest)
+        })
+    }
+}
+
+impl Handler<WithdrawTournament> for Server {
+    type Result = Result<Tournament, TournamentError>;
+
+    fn handle(&mut self, msg: WithdrawTournament, _: &mut Context<Self>) -> Self::Result {
+        self.update_tournament(msg.tournament_id, |tournament| {
+            tournament.withdraw(msg.user_id)
+        })
+    }
+}
+
+impl Handler<StartTournament> for Server {
+    type Result = Result<Tournament, TournamentError>;
+
+    fn handle(&mut self, msg: StartTournament, _: &mut Context<Self>) -> Self::Result {
+        self.update_tournament(msg.tournament_id, |tournament| {
+            tournament.start(msg.user_id)
+        })?;
+        self.schedule_tournament(msg.tournament_id);
+        Ok(self.tournaments[&msg.tournament_id].clone())
+    }
+}
+
+impl Handler<GetTournament> for Server {
+    type Result = Option<Tournament>;
+
+    fn handle(&mut self, msg: GetTournament, _: &mut Context<Self>) -> Self::Result {
+        self.tournaments.get(&msg.tournament_id).cloned()
+    }
+}
+
+impl Handler<ListTournaments> for Server {
+    type Result = MessageResult<ListTournaments>;
+
+    fn handle(&mut self, _: ListTournaments, _: &mut Context<Self>) -> Self::Result {
+        let mut tournaments: Vec<Tournament> = self.tournaments.values().cloned().collect();
+        tournaments.sort_by_key(|tournament| std::cmp::Reverse(tournament.created_at));
+        MessageResult(tournaments)
+    }
+}
+
 impl Handler<CreateRoom> for Server {
     type Result = ();
 
-    fn handle(&mut self, msg: CreateRoom, _: &mut Context<Self>) {
+    fn handle(&mut self, msg: CreateRoom, ctx: &mut Context<Self>) {
+        let color = match msg.color.as_deref() {
+            Some("white") => "w",
+            Some("black") => "b",
+            Some("random") | None => random_color(),
+            Some(_) => return self.send_error(msg.id, "Invalid color choice"),
+        };
+        if let Some(Err(err)) = msg.time_control.map(|tc| tc.validate()) {
+            return self.send_error(msg.id, &err);
+        }
+        let bot_level = match msg.opponent.as_deref() {
+            Some("bot") => match msg.level {
+                Some(level) if !(MIN_LEVEL..=MAX_LEVEL).contains(&level) => {
+                    return self.send_error(
+                        msg.id,
+                        &format!("Bot level must be between {} and {}", MIN_LEVEL, MAX_LEVEL),
+                    )
+                }
+                level => Some(level.unwrap_or(DEFAULT_LEVEL)),
+            },
+            Some(_) => return self.send_error(msg.id, "Unknown opponent"),
+            None => None,
+        };
+        match msg.engine.as_deref() {
+            Some(_) if bot_level.is_none() => {
+                return self.send_error(msg.id, "Only a bot opponent can use an engine")
+            }
+            Some(name) if bot::engine_profile(name).is_none() => {
+                return self.send_error(msg.id, "Unknown engine")
+            }
+            _ => {}
+        }
+        let adjudication = match msg.adjudication.as_deref() {
+            Some(_) if bot_level.is_none() && msg.time_control.is_some() => {
+                return self.send_error(
+                    msg.id,
+                    "Only games against the bot and untimed games can be adjudicated",
+                )
+            }
+            Some(spec) => match adjudication::parse(spec) {
+                Ok(rules) => rules,
+                Err(err) => return self.send_error(msg.id, &err),
+            },
+            None => None,
+        };
+
         let room_id = Uuid::new_v4();
-        let bitboard = init_bitboard();
-        let mut room = GameRoom::new(room_id, bitboard);
+        let position = Position::startpos();
+        let mut room = GameRoom::new(room_id, position, msg.time_control);
+        room.adjudication = adjudication;
 
-        if let Some(addr) = self.sessions.get(&msg.id) {
-            room.add_player(msg.id, addr.clone());
+        if let Some(level) = bot_level {
+            let Some(bot) = self.bot.as_mut() else {
+                return self.send_error(msg.id, "The bot is not available");
+            };
+            let bot_color = if color == "w" { "b" } else { "w" };
+            room.take_seat(bot.user_id, bot_color);
+            bot.add_game(room_id, level, msg.engine.as_deref());
+        }
+        if let (Some(addr), Some(user_id)) = (self.sessions.get(&msg.id), self.user_of(msg.id)) {
+            room.add_player_as(user_id, msg.id, addr.clone(), color);
         }
 
-        self.rooms.insert(room_id, room);
+        self.insert_room(room);
+        self.play_bots(ctx);
 
         println!("Room created with id: {}", room_id);
     }
@@ -78,27 +875,34 @@ impl Handler<JoinRoom> for Server {
     type Result = ();
 
     fn handle(&mut self, msg: JoinRoom, _: &mut Context<Self>) {
+        let (addr, user_id) = match (self.sessions.get(&msg.id), self.user_of(msg.id)) {
+            (Some(addr), Some(user_id)) => (addr.clone(), user_id),
+            _ => return,
+        };
+
         if let Some(room) = self.rooms.get_mut(&msg.room_id) {
-            if room.players.len() < 2 {
-                if let Some(addr) = self.sessions.get(&msg.id) {
-                    room.add_player(msg.id, addr.clone());
-                    println!("Client {} joined room {}", msg.id, msg.room_id);
+            if room.get_player_color(user_id).is_some() {
+                // Another tab or a reconnect of a seated player
+                room.attach(user_id, msg.id, addr);
+            } else if !room.is_full() {
+                room.add_player(user_id, msg.id, addr);
+                if let Some(color) = room.get_player_color(user_id) {
+                    log_storage_error(self.store.add_player(
+                        msg.room_id,
+                        &PlayerRecord {
+                            player_id: user_id,
+                            color,
+                        },
+                    ));
                 }
+                println!("User {} joined room {}", user_id, msg.room_id);
             } else {
                 // Room is full
-                if let Some(addr) = self.sessions.get(&msg.id) {
-                    addr.do_send(ErrorMessage {
-                        error: "Room is full".to_string(),
-                    });
-                }
+                self.send_error(msg.id, "Room is full");
             }
         } else {
             // Room not found
-            if let Some(addr) = self.sessions.get(&msg.id) {
-                addr.do_send(ErrorMessage {
-                    error: "Room not found".to_string(),
-                });
-            }
+            self.send_error(msg.id, "Room not found");
         }
     }
 }
@@ -107,25 +911,71 @@ impl Handler<JoinQueue> for Server {
     type Result = ();
 
     fn handle(&mut self, msg: JoinQueue, _: &mut Context<Self>) {
-        if let Some(addr) = self.sessions.get(&msg.id) {
-            self.waiting_players.push((msg.id, addr.clone()));
-            println!("Client {} joined the queue", msg.id);
+        let (addr, user_id) = match (self.sessions.get(&msg.id), self.user_of(msg.id)) {
+            (Some(addr), Some(user_id)) => (addr.clone(), user_id),
+            _ => return,
+        };
+
+        if self.is_playing(user_id) {
+            return self.send_error(msg.id, "You're already playing a game");
+        }
+        if msg.rated && self.guest_sessions.contains(&msg.id) {
+            return self.send_error(msg.id, "Rated games are for registered users");
+        }
+        if !VARIANTS.contains(&msg.variant.as_str()) {
+            return self.send_error(msg.id, "Unsupported variant");
+        }
+        if msg.rating_range.is_some_and(|(min, max)| min > max) {
+            return self.send_error(msg.id, "Invalid rating range");
+        }
+        if let Some(Err(err)) = msg.time_control.map(|tc| tc.validate()) {
+            return self.send_error(msg.id, &err);
+        }
 
-            if self.waiting_players.len() >= 2 {
-                let (id1, player1) = self.waiting_players.remove(0);
-                let (id2, player2) = self.waiting_players.remove(0);
+        let speed = Speed::from_time_control(msg.time_control);
+        let rating = match self.store.load_rating(user_id, speed) {
+            Ok(rating) => rating.unwrap_or_default().rating,
+            Err(err) => {
+                println!("Failed to load rating of user {}: {}", user_id, err);
+                DEFAULT_RATING
+            }
+        };
 
-                let room_id = Uuid::new_v4();
-                let bitboard = init_bitboard();
-                let mut room = GameRoom::new(room_id, bitboard);
+        let entry = QueueEntry {
+            session_id: msg.id,
+            user_id,
+            addr,
+            pool: PoolKey {
+                time_control: msg.time_control,
+                rated: msg.rated,
+                variant: msg.variant,
+            },
+            rating,
+            rating_range: msg.rating_range,
+            joined_at: Instant::now(),
+        };
+        if let Err(err) = self.queue.join(entry) {
+            return self.send_error(msg.id, err);
+        }
+        println!("Client {} joined the queue", msg.id);
 
-                room.add_player(id1, player1.clone());
-                room.add_player(id2, player2.clone());
+        self.match_queue();
+        self.send_queue_statuses();
+    }
+}
 
-                self.rooms.insert(room_id, room);
+impl Handler<LeaveQueue> for Server {
+    type Result = ();
 
-                println!("Room {} created with players {} and {}", room_id, id1, id2);
+    fn handle(&mut self, msg: LeaveQueue, _: &mut Context<Self>) {
+        if self.queue.leave(msg.id) {
+            if let Some(addr) = self.sessions.get(&msg.id) {
+                addr.do_send(QueueLeft);
             }
+            println!("Client {} left the queue", msg.id);
+            self.send_queue_statuses();
+        } else {
+            self.send_error(msg.id, "Not in the queue");
         }
     }
 }
@@ -133,17 +983,49 @@ impl Handler<JoinQueue> for Server {
 impl Handler<ClientMove> for Server {
     type Result = ();
 
-    fn handle(&mut self, msg: ClientMove, _: &mut Context<Self>) {
+    fn handle(&mut self, msg: ClientMove, ctx: &mut Context<Self>) {
+        let user_id = self.user_of(msg.id).unwrap_or_default();
         if let Some(room) = self.rooms.get_mut(&msg.room_id) {
-            let player_color = room.get_player_color(msg.id);
+            if room.is_finished() {
+                self.send_error(msg.id, "Game is over");
+                return;
+            }
+
+            let player_color = room.get_player_color(user_id);
 
             if let Some(color) = player_color {
-                if color == room.turn {
+                if color == room.turn() {
+                    if room.is_flagged() {
+                        let result = room.timeout_result();
+                        self.finish_room(msg.room_id, result, "timeout");
+                        return;
+                    }
+
                     let valid_move = room.apply_move(&msg.move_data);
 
                     if valid_move {
-                        room.switch_turn();
+                        room.press_clock();
+                        if let Some(move_data) = room.moves.last() {
+                            log_storage_error(self.store.record_move(
+                                msg.room_id,
+                                room.moves.len(),
+                                move_data,
+                                room.clock_ms(),
+                            ));
+                        }
                         room.broadcast_update();
+
+                        if let Some((result, termination)) = room.board_result() {
+                            self.finish_room(msg.room_id, result, termination);
+                        } else {
+                            self.request_adjudication(msg.room_id, ctx);
+                        }
+                        self.play_bots(ctx);
+                        // No engine help from here on, even if the analysis
+                        // was started before the game
+                        for (session_id, _) in self.sessions_of(user_id) {
+                            self.stop_analysis(session_id);
+                        }
                     } else {
                         // Invalid move
                         if let Some(addr) = self.sessions.get(&msg.id) {
@@ -178,3 +1060,605 @@ impl Handler<ClientMove> for Server {
         }
     }
 }
+
+impl Handler<BotMoved> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: BotMoved, ctx: &mut Context<Self>) {
+        let Some(bot) = self.bot.as_mut() else {
+            return;
+        };
+        bot.search_done(msg.room_id, msg.ply);
+        let session_id = bot.session_id;
+        // A move for a position that has changed since is dropped
+        let current = self
+            .rooms
+            .get(&msg.room_id)
+            .is_some_and(|room| room.moves.len() == msg.ply);
+        match msg.move_data {
+            Some(move_data) if current => self.handle(
+                ClientMove {
+                    id: session_id,
+                    room_id: msg.room_id,
+                    move_data,
+                },
+                ctx,
+            ),
+            _ => {}
+        }
+    }
+}
+
+impl Handler<PositionEvaluated> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: PositionEvaluated, _: &mut Context<Self>) {
+        let Some(room) = self.rooms.get_mut(&msg.room_id) else {
+            return;
+        };
+        if room.is_finished() {
+            return;
+        }
+        room.record_eval(msg.ply, msg.cp);
+        // A move played since gets a search of its own
+        if msg.ply != room.moves.len() {
+            return;
+        }
+        if let Some((result, termination)) = room.adjudicate(msg.tablebase) {
+            self.finish_room(msg.room_id, result, termination);
+        }
+    }
+}
+
+impl Handler<Analyze> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: Analyze, _: &mut Context<Self>) {
+        let (Some(session), Some(user_id)) =
+            (self.sessions.get(&msg.id).cloned(), self.user_of(msg.id))
+        else {
+            return;
+        };
+        // Not even for another position, it could be the one on their board
+        if self.is_playing(user_id) {
+            return self.send_error(msg.id, "Analysis isn't available while you're playing");
+        }
+        if msg
+            .depth
+            .is_some_and(|depth| !(1..=analysis::MAX_DEPTH).contains(&depth))
+        {
+            return self.send_error(
+                msg.id,
+                &format!("Depth must be between 1 and {}", analysis::MAX_DEPTH),
+            );
+        }
+        let multipv = msg.multipv.unwrap_or(1);
+        if !(1..=analysis::MAX_MULTIPV).contains(&multipv) {
+            return self.send_error(
+                msg.id,
+                &format!("multipv must be between 1 and {}", analysis::MAX_MULTIPV),
+            );
+        }
+        let engine = match msg.engine.as_deref() {
+            Some(name) => match bot::engine_profile(name) {
+                Some(profile) => Some(profile),
+                None => return self.send_error(msg.id, "Unknown engine"),
+            },
+            None => None,
+        };
+        let (start, moves) = match (msg.fen, msg.room_id) {
+            (Some(fen), None) => match Position::from_fen(&fen) {
+                Ok(position) => (position, Vec::new()),
+                Err(err) => return self.send_error(msg.id, &format!("Invalid FEN: {}", err)),
+            },
+            (None, Some(room_id)) => match self.room_moves(room_id) {
+                Some(moves) => (Position::startpos(), moves),
+                None => return self.send_error(msg.id, "Room not found"),
+            },
+            _ => return self.send_error(msg.id, "Send either a fen or a room_id"),
+        };
+
+        self.stop_analysis(msg.id);
+        let stop = Arc::new(AtomicBool::new(false));
+        self.analyses.insert(msg.id, stop.clone());
+        self.analysis.submit(Job {
+            start,
+            moves,
+            depth: msg.depth,
+            multipv,
+            engine,
+            session,
+            stop,
+        });
+    }
+}
+
+impl Handler<GetBookMoves> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: GetBookMoves, _: &mut Context<Self>) {
+        let (Some(session), Some(user_id)) =
+            (self.sessions.get(&msg.id).cloned(), self.user_of(msg.id))
+        else {
+            return;
+        };
+        // The same rule as for analysis
+        if self.is_playing(user_id) {
+            return self.send_error(
+                msg.id,
+                "The opening book isn't available while you're playing",
+            );
+        }
+        let Some(book) = bot::opening_book() else {
+            return self.send_error(msg.id, "There's no opening book");
+        };
+        let position = match (msg.fen, msg.room_id) {
+            (Some(fen), None) => match Position::from_fen(&fen) {
+                Ok(position) => position,
+                Err(err) => return self.send_error(msg.id, &format!("Invalid FEN: {}", err)),
+            },
+            (None, Some(room_id)) => match self.room_moves(room_id) {
+                Some(moves) => moves
+                    .iter()
+                    .fold(Position::startpos(), |position, mv| position.make_move(mv)),
+                None => return self.send_error(msg.id, "Room not found"),
+            },
+            _ => return self.send_error(msg.id, "Send either a fen or a room_id"),
+        };
+
+        let moves = book
+            .moves(&position)
+            .into_iter()
+            .map(|book_move| BookMoveInfo {
+                uci: book_move.mv.to_uci(),
+                san: move_to_san(&position, &book_move.mv),
+                weight: book_move.weight,
+            })
+            .collect();
+        session.do_send(BookMoves {
+            fen: position.to_fen(),
+            moves,
+        });
+    }
+}
+
+impl Handler<StopAnalysis> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: StopAnalysis, _: &mut Context<Self>) {
+        self.stop_analysis(msg.id);
+    }
+}
+
+impl Handler<Resign> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: Resign, _: &mut Context<Self>) {
+        let user_id = self.user_of(msg.id).unwrap_or_default();
+        if let Some(room) = self.rooms.get_mut(&msg.room_id) {
+            if room.is_finished() {
+                self.send_error(msg.id, "Game is over");
+                return;
+            }
+
+            if let Some(color) = room.get_player_color(user_id) {
+                let opponent = if color == "w" { "b" } else { "w" };
+                self.finish_room(msg.room_id, GameResult::win_for(opponent), "resignation");
+            } else {
+                self.send_error(msg.id, "Player not found in room");
+            }
+        } else {
+            self.send_error(msg.id, "Room not found");
+        }
+    }
+}
+
+impl Handler<RematchOffer> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: RematchOffer, ctx: &mut Context<Self>) {
+        let user_id = self.user_of(msg.id).unwrap_or_default();
+        if self.is_tournament_room(msg.room_id) {
+            return self.send_error(msg.id, "Tournament games can't be rematched");
+        }
+        let bot_id = self.bot.as_ref().map(|bot| bot.user_id);
+        let room = match self.rooms.get_mut(&msg.room_id) {
+            Some(room) => room,
+            None => return self.send_error(msg.id, "Room not found"),
+        };
+
+        if room.get_player_color(user_id).is_none() {
+            return self.send_error(msg.id, "Player not found in room");
+        }
+        if !room.is_finished() {
+            return self.send_error(msg.id, "Game is still in progress");
+        }
+        if room.rematch_room.is_some() {
+            return self.send_error(msg.id, "Rematch already started");
+        }
+
+        let opponent = room.get_opponent(user_id);
+        room.rematch_offers.insert(user_id);
+
+        match opponent {
+            // Both players asked, treat the second offer as an acceptance.
+            // The bot takes every rematch.
+            Some(opponent_id)
+                if room.rematch_offers.contains(&opponent_id) || Some(opponent_id) == bot_id =>
+            {
+                self.start_rematch(msg.room_id, ctx);
+            }
+            Some(opponent_id) => {
+                room.send_to_user(
+                    opponent_id,
+                    RematchOffered {
+                        room_id: msg.room_id,
+                    },
+                );
+                println!(
+                    "Client {} offered a rematch in room {}",
+                    msg.id, msg.room_id
+                );
+            }
+            None => self.send_error(msg.id, "No opponent to rematch"),
+        }
+    }
+}
+
+impl Handler<RematchAccept> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: RematchAccept, ctx: &mut Context<Self>) {
+        let user_id = self.user_of(msg.id).unwrap_or_default();
+        let room = match self.rooms.get(&msg.room_id) {
+            Some(room) => room,
+            None => return self.send_error(msg.id, "Room not found"),
+        };
+
+        let offered = match room.get_opponent(user_id) {
+            Some(opponent_id) => room.rematch_offers.contains(&opponent_id),
+            None => false,
+        };
+
+        if room.get_player_color(user_id).is_none() {
+            self.send_error(msg.id, "Player not found in room");
+        } else if room.rematch_room.is_some() {
+            self.send_error(msg.id, "Rematch already started");
+        } else if !offered {
+            self.send_error(msg.id, "No rematch offer to accept");
+        } else {
+            self.start_rematch(msg.room_id, ctx);
+        }
+    }
+}
+
+impl Handler<RematchDecline> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: RematchDecline, _: &mut Context<Self>) {
+        let user_id = self.user_of(msg.id).unwrap_or_default();
+        if let Some(room) = self.rooms.get_mut(&msg.room_id) {
+            if room.get_player_color(user_id).is_none() {
+                return self.send_error(msg.id, "Player not found in room");
+            }
+
+            room.rematch_offers.clear();
+            if let Some(opponent_id) = room.get_opponent(user_id) {
+                room.send_to_user(
+                    opponent_id,
+                    RematchDeclined {
+                        room_id: msg.room_id,
+                    },
+                );
+            }
+        } else {
+            self.send_error(msg.id, "Room not found");
+        }
+    }
+}
+
+impl Handler<SendChallenge> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendChallenge, _: &mut Context<Self>) {
+        let user_id = match self.user_of(msg.id) {
+            Some(user_id) => user_id,
+            None => return,
+        };
+
+        let color = msg.color.unwrap_or_else(|| "random".to_string());
+        if !["white", "black", "random"].contains(&color.as_str()) {
+            return self.send_error(msg.id, "Invalid color choice");
+        }
+        if !VARIANTS.contains(&msg.variant.as_str()) {
+            return self.send_error(msg.id, "Unsupported variant");
+        }
+        if msg.rated && self.guest_sessions.contains(&msg.id) {
+            return self.send_error(msg.id, "Rated games are for registered users");
+        }
+        if let Some(Err(err)) = msg.time_control.map(|tc| tc.validate()) {
+            return self.send_error(msg.id, &err);
+        }
+
+        let (target, challenger) = match (
+            self.store.find_user(&msg.username),
+            self.store.load_user(user_id),
+        ) {
+            (Ok(Some(target)), Ok(Some(challenger))) => (target, challenger),
+            (Ok(None), _) => return self.send_error(msg.id, "User not found"),
+            (Err(err), _) | (_, Err(err)) => {
+                println!("Failed to look up challenge users: {}", err);
+                return self.send_error(msg.id, "Internal server error");
+            }
+            (_, Ok(None)) => return,
+        };
+        if target.id == user_id {
+            return self.send_error(msg.id, "You can't challenge yourself");
+        }
+        if msg.rated && target.is_guest {
+            return self.send_error(msg.id, "Rated games are for registered users");
+        }
+        let target_sessions = self.sessions_of(target.id);
+        if target_sessions.is_empty() {
+            return self.send_error(msg.id, "User is not online");
+        }
+
+        let challenge = Challenge {
+            id: Uuid::new_v4(),
+            challenger: user_id,
+            challenger_session: msg.id,
+            target: target.id,
+            time_control: msg.time_control,
+            color,
+            variant: msg.variant,
+            rated: msg.rated,
+            created_at: Instant::now(),
+        };
+
+        let received = ChallengeReceived {
+            challenge_id: challenge.id,
+            from: challenger.username,
+            time_control: challenge.time_control,
+            color: challenge.color.clone(),
+            variant: challenge.variant.clone(),
+            rated: challenge.rated,
+            expires_in: challenge.expires_in().as_secs(),
+        };
+        for (_, addr) in target_sessions {
+            addr.do_send(received.clone());
+        }
+        if let Some(addr) = self.sessions.get(&msg.id) {
+            addr.do_send(ChallengeSent {
+                challenge_id: challenge.id,
+                to: target.username,
+                expires_in: challenge.expires_in().as_secs(),
+            });
+        }
+
+        println!(
+            "User {} challenged user {} ({})",
+            user_id, challenge.target, challenge.id
+        );
+        self.challenges.insert(challenge.id, challenge);
+    }
+}
+
+impl Handler<ChallengeAccept> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChallengeAccept, _: &mut Context<Self>) {
+        let (addr, user_id) = match (self.sessions.get(&msg.id), self.user_of(msg.id)) {
+            (Some(addr), Some(user_id)) => (addr.clone(), user_id),
+            _ => return,
+        };
+        if self
+            .challenges
+            .get(&msg.challenge_id)
+            .is_none_or(|challenge| challenge.target != user_id)
+        {
+            return self.send_error(msg.id, "Challenge not found");
+        }
+        let challenge = match self.challenges.remove(&msg.challenge_id) {
+            Some(challenge) => challenge,
+            None => return,
+        };
+
+        // Prefer the tab the challenge was sent from
+        let challenger_session = if self.sessions.contains_key(&challenge.challenger_session) {
+            self.sessions
+                .get(&challenge.challenger_session)
+                .map(|addr| (challenge.challenger_session, addr.clone()))
+        } else {
+            self.sessions_of(challenge.challenger).into_iter().next()
+        };
+        let (challenger_session, challenger_addr) = match challenger_session {
+            Some(session) => session,
+            None => {
+                self.close_challenge(&challenge, "cancelled");
+                return self.send_error(msg.id, "Challenger is not connected");
+            }
+        };
+
+        let challenger_color = match challenge.color.as_str() {
+            "white" => "w",
+            "black" => "b",
+            _ => random_color(),
+        };
+        let target_color = if challenger_color == "w" { "b" } else { "w" };
+
+        let room_id = Uuid::new_v4();
+        let mut room = GameRoom::new(room_id, Position::startpos(), challenge.time_control);
+        room.rated = challenge.rated;
+        room.add_player_as(
+            challenge.challenger,
+            challenger_session,
+            challenger_addr,
+            challenger_color,
+        );
+        room.add_player_as(user_id, msg.id, addr, target_color);
+        self.insert_room(room);
+
+        self.close_challenge(&challenge, "accepted");
+        println!(
+            "Challenge {} accepted, game in room {}",
+            challenge.id, room_id
+        );
+    }
+}
+
+impl Handler<ChallengeDecline> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChallengeDecline, _: &mut Context<Self>) {
+        let user_id = self.user_of(msg.id).unwrap_or_default();
+        if self
+            .challenges
+            .get(&msg.challenge_id)
+            .is_none_or(|challenge| challenge.target != user_id)
+        {
+            return self.send_error(msg.id, "Challenge not found");
+        }
+        if let Some(challenge) = self.challenges.remove(&msg.challenge_id) {
+            self.close_challenge(&challenge, "declined");
+        }
+    }
+}
+
+impl Handler<ChallengeCancel> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChallengeCancel, _: &mut Context<Self>) {
+        let user_id = self.user_of(msg.id).unwrap_or_default();
+        if self
+            .challenges
+            .get(&msg.challenge_id)
+            .is_none_or(|challenge| challenge.challenger != user_id)
+        {
+            return self.send_error(msg.id, "Challenge not found");
+        }
+        if let Some(challenge) = self.challenges.remove(&msg.challenge_id) {
+            self.close_challenge(&challenge, "cancelled");
+        }
+    }
+}
+
+impl Handler<CreateSeek> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: CreateSeek, _: &mut Context<Self>) {
+        let user_id = match self.user_of(msg.id) {
+            Some(user_id) => user_id,
+            None => return,
+        };
+
+        let color = msg.color.unwrap_or_else(|| "random".to_string());
+        if !["white", "black", "random"].contains(&color.as_str()) {
+            return self.send_error(msg.id, "Invalid color choice");
+        }
+        if !VARIANTS.contains(&msg.variant.as_str()) {
+            return self.send_error(msg.id, "Unsupported variant");
+        }
+        if msg.rated && self.guest_sessions.contains(&msg.id) {
+            return self.send_error(msg.id, "Rated games are for registered users");
+        }
+        if let Some(Err(err)) = msg.time_control.map(|tc| tc.validate()) {
+            return self.send_error(msg.id, &err);
+        }
+        if self.seeks.iter().filter(|s| s.user_id == user_id).count() >= MAX_SEEKS_PER_USER {
+            return self.send_error(msg.id, "Too many open seeks");
+        }
+
+        let speed = Speed::from_time_control(msg.time_control);
+        let (username, rating) = match (
+            self.store.load_user(user_id),
+            self.store.load_rating(user_id, speed),
+        ) {
+            (Ok(Some(user)), Ok(rating)) => (user.username, rating.unwrap_or_default().rating),
+            (Err(err), _) | (_, Err(err)) => {
+                println!("Failed to load seek details of user {}: {}", user_id, err);
+                return self.send_error(msg.id, "Internal server error");
+            }
+            (Ok(None), _) => return,
+        };
+
+        let seek = Seek {
+            id: Uuid::new_v4(),
+            user_id,
+            session_id: msg.id,
+            username,
+            rating,
+            time_control: msg.time_control,
+            variant: msg.variant,
+            rated: msg.rated,
+            color,
+            created_at: unix_now(),
+        };
+        println!("User {} posted seek {}", user_id, seek.id);
+        self.broadcast_lobby(LobbyUpdate::Added(seek.info()));
+        self.seeks.push(seek);
+    }
+}
+
+impl Handler<CancelSeek> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: CancelSeek, _: &mut Context<Self>) {
+        let user_id = self.user_of(msg.id).unwrap_or_default();
+        if !self
+            .seeks
+            .iter()
+            .any(|seek| seek.id == msg.seek_id && seek.user_id == user_id)
+        {
+            return self.send_error(msg.id, "Seek not found");
+        }
+        self.remove_seeks(|seek| seek.id == msg.seek_id, "cancelled");
+    }
+}
+
+impl Handler<AcceptSeek> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: AcceptSeek, _: &mut Context<Self>) {
+        let (addr, user_id) = match (self.sessions.get(&msg.id), self.user_of(msg.id)) {
+            (Some(addr), Some(user_id)) => (addr.clone(), user_id),
+            _ => return,
+        };
+        let seek = match self.seeks.iter().find(|seek| seek.id == msg.seek_id) {
+            Some(seek) => seek,
+            None => return self.send_error(msg.id, "Seek not found"),
+        };
+        if seek.user_id == user_id {
+            return self.send_error(msg.id, "You can't accept your own seek");
+        }
+        if seek.rated && self.guest_sessions.contains(&msg.id) {
+            return self.send_error(msg.id, "Rated games are for registered users");
+        }
+        let seeker_addr = match self.sessions.get(&seek.session_id) {
+            Some(addr) => addr.clone(),
+            None => return self.send_error(msg.id, "Seek not found"),
+        };
+
+        let seeker_color = match seek.color.as_str() {
+            "white" => "w",
+            "black" => "b",
+            _ => random_color(),
+        };
+        let taker_color = if seeker_color == "w" { "b" } else { "w" };
+
+        let room_id = Uuid::new_v4();
+        let mut room = GameRoom::new(room_id, Position::startpos(), seek.time_control);
+        room.rated = seek.rated;
+        room.add_player_as(seek.user_id, seek.session_id, seeker_addr, seeker_color);
+        room.add_player_as(user_id, msg.id, addr, taker_color);
+        println!("Seek {} taken, game in room {}", seek.id, room_id);
+        let seeker = seek.user_id;
+        self.insert_room(room);
+
+        // Both players are busy now, their other seeks go away as well
+        self.remove_seeks(|seek| seek.id == msg.seek_id, "taken");
+        self.remove_seeks(
+            |seek| seek.user_id == seeker || seek.user_id == user_id,
+            "cancelled",
+        );
+    }
+}
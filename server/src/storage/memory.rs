@@ -0,0 +1,384 @@
+// src/storage/memory.rs
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use super::{
+    unix_now, ExplorerGame, ExplorerMove, GameStore, PlayerRecord, RatingHistoryEntry, RoomFilter,
+    RoomRecord, StorageError, StorageResult, UserRecord,
+};
+use crate::bitboard::MoveData;
+use crate::eco::Opening;
+use crate::rating::{Rating, Speed};
+
+// Keeps everything in a map, used for tests and throwaway servers.
+pub struct MemoryStore {
+    rooms: Mutex<HashMap<Uuid, RoomRecord>>,
+    users: Mutex<Vec<UserRecord>>, // user id - 1 is the index
+    ratings: Mutex<HashMap<(usize, Speed), Vec<RatingHistoryEntry>>>, // last entry is current
+    tournaments: Mutex<Vec<(Uuid, String)>>, // oldest first
+    reviews: Mutex<HashMap<Uuid, String>>,
+    explorer: Mutex<ExplorerIndex>,
+    token_secret: Mutex<Option<Vec<u8>>>,
+}
+
+#[derive(Default)]
+struct ExplorerIndex {
+    games: Vec<ExplorerGame>,                  // oldest first
+    plies: HashMap<u64, Vec<(String, usize)>>, // key -> (move, index into `games`)
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            rooms: Mutex::new(HashMap::new()),
+            users: Mutex::new(Vec::new()),
+            ratings: Mutex::new(HashMap::new()),
+            tournaments: Mutex::new(Vec::new()),
+            reviews: Mutex::new(HashMap::new()),
+            explorer: Mutex::new(ExplorerIndex::default()),
+            token_secret: Mutex::new(None),
+        }
+    }
+
+    fn with_room<F>(&self, room_id: Uuid, f: F) -> StorageResult<()>
+    where
+        F: FnOnce(&mut RoomRecord),
+    {
+        let mut rooms = self.rooms.lock().unwrap();
+        match rooms.get_mut(&room_id) {
+            Some(room) => {
+                f(room);
+                Ok(())
+            }
+            None => Err(StorageError(format!("room {} not found", room_id))),
+        }
+    }
+}
+
+impl GameStore for MemoryStore {
+    fn create_room(&self, room: &RoomRecord) -> StorageResult<()> {
+        self.rooms.lock().unwrap().insert(room.id, room.clone());
+        Ok(())
+    }
+
+    fn add_player(&self, room_id: Uuid, player: &PlayerRecord) -> StorageResult<()> {
+        self.with_room(room_id, |room| {
+            room.players.retain(|p| p.color != player.color);
+            room.players.push(player.clone());
+        })
+    }
+
+    fn record_move(
+        &self,
+        room_id: Uuid,
+        ply: usize,
+        move_data: &MoveData,
+        clock: Option<(u64, u64)>,
+    ) -> StorageResult<()> {
+        self.with_room(room_id, |room| {
+            room.moves.truncate(ply - 1);
+            room.moves.push(move_data.clone());
+            if let Some((white, black)) = clock {
+                room.white_time_ms = Some(white);
+                room.black_time_ms = Some(black);
+            }
+        })
+    }
+
+    fn finish_room(
+        &self,
+        room_id: Uuid,
+        result: &str,
+        termination: &str,
+        opening: Option<Opening>,
+        clock: Option<(u64, u64)>,
+    ) -> StorageResult<()> {
+        self.with_room(room_id, |room| {
+            room.result = Some(result.to_string());
+            room.termination = Some(termination.to_string());
+            room.finished_at = Some(unix_now());
+            room.eco = opening.map(|opening| opening.eco.to_string());
+            room.opening = opening.map(|opening| opening.name.to_string());
+            if let Some((white, black)) = clock {
+                room.white_time_ms = Some(white);
+                room.black_time_ms = Some(black);
+            }
+        })
+    }
+
+    fn load_unfinished_rooms(&self) -> StorageResult<Vec<RoomRecord>> {
+        let rooms = self.rooms.lock().unwrap();
+        let mut unfinished: Vec<RoomRecord> = rooms
+            .values()
+            .filter(|room| room.result.is_none())
+            .cloned()
+            .collect();
+        unfinished.sort_by_key(|room| room.created_at);
+        Ok(unfinished)
+    }
+
+    fn load_room(&self, room_id: Uuid) -> StorageResult<Option<RoomRecord>> {
+        Ok(self.rooms.lock().unwrap().get(&room_id).cloned())
+    }
+
+    fn list_rooms(
+        &self,
+        filter: &RoomFilter,
+        offset: usize,
+        limit: usize,
+    ) -> StorageResult<(Vec<RoomRecord>, usize)> {
+        let rooms = self.rooms.lock().unwrap();
+        let mut matching: Vec<&RoomRecord> =
+            rooms.values().filter(|room| filter.matches(room)).collect();
+        matching.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+        let total = matching.len();
+        let page = matching
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect();
+        Ok((page, total))
+    }
+
+    fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        is_guest: bool,
+    ) -> StorageResult<UserRecord> {
+        let mut users = self.users.lock().unwrap();
+        if users
+            .iter()
+            .any(|u| u.username.eq_ignore_ascii_case(username))
+        {
+            return Err(StorageError(format!("username {} is taken", username)));
+        }
+        let user = UserRecord {
+            id: users.len() + 1,
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            is_guest,
+            created_at: unix_now(),
+        };
+        users.push(user.clone());
+        Ok(user)
+    }
+
+    fn claim_guest(
+        &self,
+        user_id: usize,
+        username: &str,
+        password_hash: &str,
+    ) -> StorageResult<Option<UserRecord>> {
+        let mut users = self.users.lock().unwrap();
+        if users
+            .iter()
+            .any(|u| u.id != user_id && u.username.eq_ignore_ascii_case(username))
+        {
+            return Err(StorageError(format!("username {} is taken", username)));
+        }
+        match user_id.checked_sub(1).and_then(|i| users.get_mut(i)) {
+            Some(user) if user.is_guest => {
+                user.username = username.to_string();
+                user.password_hash = password_hash.to_string();
+                user.is_guest = false;
+                Ok(Some(user.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn find_user(&self, username: &str) -> StorageResult<Option<UserRecord>> {
+        let users = self.users.lock().unwrap();
+        Ok(users
+            .iter()
+            .find(|u| u.username.eq_ignore_ascii_case(username))
+            .cloned())
+    }
+
+    fn load_user(&self, user_id: usize) -> StorageResult<Option<UserRecord>> {
+        let users = self.users.lock().unwrap();
+        Ok(user_id.checked_sub(1).and_then(|i| users.get(i)).cloned())
+    }
+
+    fn recent_colors(&self, user_id: usize, limit: usize) -> StorageResult<Vec<String>> {
+        let rooms = self.rooms.lock().unwrap();
+        let mut played: Vec<(u64, Uuid, String)> = rooms
+            .values()
+            .filter_map(|room| {
+                room.players
+                    .iter()
+                    .find(|p| p.player_id == user_id)
+                    .map(|p| (room.created_at, room.id, p.color.clone()))
+            })
+            .collect();
+        played.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        Ok(played
+            .into_iter()
+            .take(limit)
+            .map(|(_, _, color)| color)
+            .collect())
+    }
+
+    fn load_rating(&self, user_id: usize, speed: Speed) -> StorageResult<Option<Rating>> {
+        let ratings = self.ratings.lock().unwrap();
+        Ok(ratings
+            .get(&(user_id, speed))
+            .and_then(|history| history.last())
+            .map(|entry| entry.rating))
+    }
+
+    fn load_ratings(&self, user_id: usize) -> StorageResult<Vec<(Speed, Rating)>> {
+        let ratings = self.ratings.lock().unwrap();
+        Ok(Speed::ALL
+            .into_iter()
+            .filter_map(|speed| {
+                ratings
+                    .get(&(user_id, speed))
+                    .and_then(|history| history.last())
+                    .map(|entry| (speed, entry.rating))
+            })
+            .collect())
+    }
+
+    fn record_rating(
+        &self,
+        user_id: usize,
+        speed: Speed,
+        room_id: Uuid,
+        rating: &Rating,
+    ) -> StorageResult<()> {
+        let mut ratings = self.ratings.lock().unwrap();
+        ratings
+            .entry((user_id, speed))
+            .or_default()
+            .push(RatingHistoryEntry {
+                room_id,
+                rating: *rating,
+                recorded_at: unix_now(),
+            });
+        Ok(())
+    }
+
+    fn rating_history(
+        &self,
+        user_id: usize,
+        speed: Speed,
+    ) -> StorageResult<Vec<RatingHistoryEntry>> {
+        let ratings = self.ratings.lock().unwrap();
+        Ok(ratings.get(&(user_id, speed)).cloned().unwrap_or_default())
+    }
+
+    fn save_tournament(&self, id: Uuid, state: &str) -> StorageResult<()> {
+        let mut tournaments = self.tournaments.lock().unwrap();
+        match tournaments.iter_mut().find(|(existing, _)| *existing == id) {
+            Some((_, saved)) => *saved = state.to_string(),
+            None => tournaments.push((id, state.to_string())),
+        }
+        Ok(())
+    }
+
+    fn load_tournaments(&self) -> StorageResult<Vec<String>> {
+        let tournaments = self.tournaments.lock().unwrap();
+        Ok(tournaments.iter().map(|(_, state)| state.clone()).collect())
+    }
+
+    fn save_review(&self, room_id: Uuid, review: &str) -> StorageResult<()> {
+        if !self.rooms.lock().unwrap().contains_key(&room_id) {
+            return Err(StorageError(format!("room {} not found", room_id)));
+        }
+        self.reviews
+            .lock()
+            .unwrap()
+            .insert(room_id, review.to_string());
+        Ok(())
+    }
+
+    fn load_review(&self, room_id: Uuid) -> StorageResult<Option<String>> {
+        Ok(self.reviews.lock().unwrap().get(&room_id).cloned())
+    }
+
+    fn add_explorer_game(&self, game: &ExplorerGame, plies: &[(u64, String)]) -> StorageResult<()> {
+        let mut explorer = self.explorer.lock().unwrap();
+        if game.room_id.is_some()
+            && explorer
+                .games
+                .iter()
+                .any(|known| known.room_id == game.room_id)
+        {
+            return Ok(());
+        }
+        let index = explorer.games.len();
+        explorer.games.push(game.clone());
+        for (key, uci) in plies {
+            explorer
+                .plies
+                .entry(*key)
+                .or_default()
+                .push((uci.clone(), index));
+        }
+        Ok(())
+    }
+
+    fn explorer_moves(&self, key: u64, examples: usize) -> StorageResult<Vec<ExplorerMove>> {
+        let explorer = self.explorer.lock().unwrap();
+        let mut moves: Vec<ExplorerMove> = Vec::new();
+        // Rating sum and count by move
+        let mut ratings: HashMap<String, (i64, i64)> = HashMap::new();
+        // Latest first, so examples come out in order
+        for (uci, index) in explorer.plies.get(&key).into_iter().flatten().rev() {
+            let game = &explorer.games[*index];
+            let mv = match moves.iter_mut().find(|mv| mv.uci == *uci) {
+                Some(mv) => mv,
+                None => {
+                    moves.push(ExplorerMove {
+                        uci: uci.clone(),
+                        games: 0,
+                        white_wins: 0,
+                        draws: 0,
+                        black_wins: 0,
+                        average_rating: None,
+                        examples: Vec::new(),
+                    });
+                    moves.last_mut().unwrap()
+                }
+            };
+            mv.games += 1;
+            match game.result.as_str() {
+                "1-0" => mv.white_wins += 1,
+                "0-1" => mv.black_wins += 1,
+                _ => mv.draws += 1,
+            }
+            if mv.examples.len() < examples {
+                mv.examples.push(game.clone());
+            }
+            let known: Vec<i64> = [game.white_rating, game.black_rating]
+                .into_iter()
+                .flatten()
+                .collect();
+            if !known.is_empty() {
+                let sum = ratings.entry(uci.clone()).or_default();
+                sum.0 += known.iter().sum::<i64>() / known.len() as i64;
+                sum.1 += 1;
+            }
+        }
+        for mv in &mut moves {
+            mv.average_rating = ratings.get(&mv.uci).map(|(sum, count)| sum / count);
+        }
+        moves.sort_by(|a, b| b.games.cmp(&a.games).then(a.uci.cmp(&b.uci)));
+        Ok(moves)
+    }
+
+    fn clear_explorer(&self) -> StorageResult<()> {
+        *self.explorer.lock().unwrap() = ExplorerIndex::default();
+        Ok(())
+    }
+
+    fn token_secret(&self, generated: &[u8]) -> StorageResult<Vec<u8>> {
+        let mut secret = self.token_secret.lock().unwrap();
+        Ok(secret.get_or_insert_with(|| generated.to_vec()).clone())
+    }
+}
@@ -0,0 +1,512 @@
+// src/storage/mod.rs
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::adjudication::Rules;
+use crate::bitboard::MoveData;
+use crate::clock::TimeControl;
+use crate::eco::Opening;
+use crate::position::{Move, Position};
+use crate::rating::{Rating, Speed};
+
+mod memory;
+mod sqlite;
+
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+#[derive(Debug)]
+pub struct StorageError(pub String);
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "storage error: {}", self.0)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        StorageError(err.to_string())
+    }
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+#[derive(Debug, Clone)]
+pub struct PlayerRecord {
+    pub player_id: usize,
+    pub color: String, // "w" or "b"
+}
+
+#[derive(Debug, Clone)]
+pub struct RoomRecord {
+    pub id: Uuid,
+    pub time_control: Option<TimeControl>,
+    pub rematch_of: Option<Uuid>,
+    pub rated: bool,
+    pub adjudication: Option<Rules>,
+    pub players: Vec<PlayerRecord>,
+    pub moves: Vec<MoveData>,
+    pub white_time_ms: Option<u64>,
+    pub black_time_ms: Option<u64>,
+    pub result: Option<String>,
+    pub termination: Option<String>,
+    pub eco: Option<String>, // named when the game finished
+    pub opening: Option<String>,
+    pub created_at: u64, // unix seconds
+    pub finished_at: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct UserRecord {
+    pub id: usize,
+    pub username: String,
+    pub password_hash: String, // empty for guests, who can't log in
+    pub is_guest: bool,
+    pub created_at: u64, // unix seconds
+}
+
+#[derive(Debug, Clone)]
+pub struct RatingHistoryEntry {
+    pub room_id: Uuid,
+    pub rating: Rating, // after the game
+    pub recorded_at: u64,
+}
+
+// A finished game as the opening explorer knows it, one of ours or one
+// imported from PGN
+#[derive(Debug, Clone)]
+pub struct ExplorerGame {
+    pub room_id: Option<Uuid>, // None if imported
+    pub white: String,
+    pub black: String,
+    pub white_rating: Option<i64>,
+    pub black_rating: Option<i64>,
+    pub result: String,       // "1-0", "0-1" or "1/2-1/2"
+    pub date: Option<String>, // "YYYY.MM.DD" as in PGN
+}
+
+// Everything played from one position
+#[derive(Debug, Clone)]
+pub struct ExplorerMove {
+    pub uci: String,
+    pub games: usize,
+    pub white_wins: usize,
+    pub draws: usize,
+    pub black_wins: usize,
+    pub average_rating: Option<i64>, // of the players, over games with ratings
+    pub examples: Vec<ExplorerGame>, // latest first
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RoomFilter {
+    pub player_id: Option<usize>,
+    pub result: Option<String>,
+    pub since: Option<u64>, // unix seconds, inclusive
+    pub until: Option<u64>, // unix seconds, exclusive
+    pub time_control: Option<TimeControl>,
+}
+
+impl RoomFilter {
+    pub fn matches(&self, room: &RoomRecord) -> bool {
+        self.player_id
+            .is_none_or(|id| room.players.iter().any(|p| p.player_id == id))
+            && self
+                .result
+                .as_ref()
+                .is_none_or(|result| room.result.as_ref() == Some(result))
+            && self.since.is_none_or(|since| room.created_at >= since)
+            && self.until.is_none_or(|until| room.created_at < until)
+            && self
+                .time_control
+                .is_none_or(|tc| room.time_control == Some(tc))
+    }
+}
+
+// Everything the server needs to survive a restart. Calls are made from the
+// `Server` actor as events happen, so implementations should be quick.
+pub trait GameStore: Send + Sync {
+    fn create_room(&self, room: &RoomRecord) -> StorageResult<()>;
+
+    fn add_player(&self, room_id: Uuid, player: &PlayerRecord) -> StorageResult<()>;
+
+    fn record_move(
+        &self,
+        room_id: Uuid,
+        ply: usize,
+        move_data: &MoveData,
+        clock: Option<(u64, u64)>, // (white ms, black ms) after the move
+    ) -> StorageResult<()>;
+
+    fn finish_room(
+        &self,
+        room_id: Uuid,
+        result: &str,
+        termination: &str,
+        opening: Option<Opening>,
+        clock: Option<(u64, u64)>,
+    ) -> StorageResult<()>;
+
+    fn load_unfinished_rooms(&self) -> StorageResult<Vec<RoomRecord>>;
+
+    fn load_room(&self, room_id: Uuid) -> StorageResult<Option<RoomRecord>>;
+
+    // Newest first. Returns the requested page and the total number of matches.
+    fn list_rooms(
+        &self,
+        filter: &RoomFilter,
+        offset: usize,
+        limit: usize,
+    ) -> StorageResult<(Vec<RoomRecord>, usize)>;
+
+    // Fails if the username is already taken
+    fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        is_guest: bool,
+    ) -> StorageResult<UserRecord>;
+
+    // Turns a guest into a registered user with the same id, so their games
+    // stay with them. Returns None if the user isn't a guest.
+    fn claim_guest(
+        &self,
+        user_id: usize,
+        username: &str,
+        password_hash: &str,
+    ) -> StorageResult<Option<UserRecord>>;
+
+    // Usernames are matched case-insensitively
+    fn find_user(&self, username: &str) -> StorageResult<Option<UserRecord>>;
+
+    fn load_user(&self, user_id: usize) -> StorageResult<Option<UserRecord>>;
+
+    // Colors the user played in their latest games, newest first
+    fn recent_colors(&self, user_id: usize, limit: usize) -> StorageResult<Vec<String>>;
+
+    // None until the user has played a rated game at this speed
+    fn load_rating(&self, user_id: usize, speed: Speed) -> StorageResult<Option<Rating>>;
+
+    fn load_ratings(&self, user_id: usize) -> StorageResult<Vec<(Speed, Rating)>>;
+
+    // Stores the rating after a game and appends it to the user's history
+    fn record_rating(
+        &self,
+        user_id: usize,
+        speed: Speed,
+        room_id: Uuid,
+        rating: &Rating,
+    ) -> StorageResult<()>;
+
+    // Oldest first
+    fn rating_history(
+        &self,
+        user_id: usize,
+        speed: Speed,
+    ) -> StorageResult<Vec<RatingHistoryEntry>>;
+
+    // Tournaments are small and always loaded together, so they're kept as
+    // one JSON document each. Saving replaces the previous state.
+    fn save_tournament(&self, id: Uuid, state: &str) -> StorageResult<()>;
+
+    // Oldest first
+    fn load_tournaments(&self) -> StorageResult<Vec<String>>;
+
+    // The engine review of a finished game, as a JSON document. Saving
+    // replaces an earlier review.
+    fn save_review(&self, room_id: Uuid, review: &str) -> StorageResult<()>;
+
+    fn load_review(&self, room_id: Uuid) -> StorageResult<Option<String>>;
+
+    // Adds a game to the opening explorer, `plies` holding the key of each
+    // position it went through and the move played there in UCI. A game of
+    // ours that is already in goes in only once.
+    fn add_explorer_game(&self, game: &ExplorerGame, plies: &[(u64, String)]) -> StorageResult<()>;
+
+    // Most played first, with up to `examples` games each
+    fn explorer_moves(&self, key: u64, examples: usize) -> StorageResult<Vec<ExplorerMove>>;
+
+    // Empties the explorer, before it's built again
+    fn clear_explorer(&self) -> StorageResult<()>;
+
+    // The key session tokens are signed with. The first call keeps
+    // `generated`, later ones return that same key.
+    fn token_secret(&self, generated: &[u8]) -> StorageResult<Vec<u8>>;
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Days since 1970-01-01 for a proleptic Gregorian date
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// The date of a day counted from 1970-01-01, the inverse of `days_from_civil`
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Replays the stored moves, returning every position from the start onwards
+pub fn replay(record: &RoomRecord) -> Result<(Vec<Move>, Vec<Position>), String> {
+    let mut positions = vec![Position::startpos()];
+    let mut moves = Vec::with_capacity(record.moves.len());
+    for (i, move_data) in record.moves.iter().enumerate() {
+        let position = positions.last().unwrap();
+        let mv = position
+            .find_move(move_data)
+            .ok_or_else(|| format!("Stored move {} of game {} is illegal", i + 1, record.id))?;
+        positions.push(position.make_move(&mv));
+        moves.push(mv);
+    }
+    Ok((moves, positions))
+}
+
+pub fn player_for(record: &RoomRecord, color: &str) -> Option<usize> {
+    record
+        .players
+        .iter()
+        .find(|p| p.color == color)
+        .map(|p| p.player_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every implementation goes through the same tests
+    fn stores() -> Vec<(&'static str, Box<dyn GameStore>)> {
+        vec![
+            ("memory", Box::new(MemoryStore::new())),
+            ("sqlite", Box::new(SqliteStore::open(":memory:").unwrap())),
+        ]
+    }
+
+    fn room(created_at: u64, players: &[(usize, &str)], tc: Option<(u64, u64)>) -> RoomRecord {
+        RoomRecord {
+            id: Uuid::new_v4(),
+            time_control: tc.map(|(initial, increment)| TimeControl { initial, increment }),
+            rematch_of: None,
+            rated: false,
+            adjudication: None,
+            players: players
+                .iter()
+                .map(|(id, color)| PlayerRecord {
+                    player_id: *id,
+                    color: color.to_string(),
+                })
+                .collect(),
+            moves: Vec::new(),
+            white_time_ms: None,
+            black_time_ms: None,
+            result: None,
+            termination: None,
+            eco: None,
+            opening: None,
+            created_at,
+            finished_at: None,
+        }
+    }
+
+    fn move_data(from: usize, to: usize) -> MoveData {
+        MoveData {
+            from,
+            to,
+            promotion: None,
+        }
+    }
+
+    fn squares(room: &RoomRecord) -> Vec<(usize, usize, Option<String>)> {
+        room.moves
+            .iter()
+            .map(|m| (m.from, m.to, m.promotion.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn saves_and_loads_rooms() {
+        for (name, store) in stores() {
+            let mut record = room(100, &[(1, "w")], Some((300, 2)));
+            record.rated = true;
+            record.adjudication = Some("draw=40:8:10,win=5:1000".parse().unwrap());
+            store.create_room(&record).unwrap();
+            let player = PlayerRecord {
+                player_id: 2,
+                color: "b".to_string(),
+            };
+            store.add_player(record.id, &player).unwrap();
+            store
+                .record_move(record.id, 1, &move_data(12, 28), Some((299_000, 300_000)))
+                .unwrap();
+            let promotion = MoveData {
+                promotion: Some("q".to_string()),
+                ..move_data(52, 60)
+            };
+            store
+                .record_move(record.id, 2, &promotion, Some((299_000, 298_500)))
+                .unwrap();
+
+            let loaded = store.load_room(record.id).unwrap().expect(name);
+            assert_eq!(loaded.time_control, record.time_control, "{}", name);
+            assert!(loaded.rated, "{}", name);
+            assert_eq!(loaded.adjudication, record.adjudication, "{}", name);
+            assert_eq!(loaded.created_at, 100, "{}", name);
+            let mut players: Vec<(usize, String)> = loaded
+                .players
+                .iter()
+                .map(|p| (p.player_id, p.color.clone()))
+                .collect();
+            players.sort();
+            assert_eq!(
+                players,
+                vec![(1, "w".to_string()), (2, "b".to_string())],
+                "{}",
+                name
+            );
+            assert_eq!(
+                squares(&loaded),
+                vec![(12, 28, None), (52, 60, Some("q".to_string()))],
+                "{}",
+                name
+            );
+            assert_eq!(loaded.white_time_ms, Some(299_000), "{}", name);
+            assert_eq!(loaded.black_time_ms, Some(298_500), "{}", name);
+            assert_eq!(loaded.result, None, "{}", name);
+            assert_eq!(store.load_unfinished_rooms().unwrap().len(), 1, "{}", name);
+
+            store
+                .finish_room(
+                    record.id,
+                    "0-1",
+                    "resignation",
+                    Some(Opening {
+                        eco: "B00",
+                        name: "King's Pawn Game",
+                    }),
+                    Some((1_000, 2_000)),
+                )
+                .unwrap();
+            let finished = store.load_room(record.id).unwrap().expect(name);
+            assert_eq!(finished.result.as_deref(), Some("0-1"), "{}", name);
+            assert_eq!(
+                finished.termination.as_deref(),
+                Some("resignation"),
+                "{}",
+                name
+            );
+            assert!(finished.finished_at.is_some(), "{}", name);
+            assert_eq!(finished.eco.as_deref(), Some("B00"), "{}", name);
+            assert_eq!(
+                finished.opening.as_deref(),
+                Some("King's Pawn Game"),
+                "{}",
+                name
+            );
+            assert_eq!(finished.white_time_ms, Some(1_000), "{}", name);
+            assert!(
+                store.load_unfinished_rooms().unwrap().is_empty(),
+                "{}",
+                name
+            );
+
+            assert!(
+                store.load_room(Uuid::new_v4()).unwrap().is_none(),
+                "{}",
+                name
+            );
+            assert!(
+                store
+                    .finish_room(Uuid::new_v4(), "1-0", "checkmate", None, None)
+                    .is_err(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn lists_and_filters_rooms() {
+        for (name, store) in stores() {
+            let rooms = [
+                room(10, &[(1, "w"), (2, "b")], Some((300, 0))),
+                room(20, &[(2, "w"), (3, "b")], Some((60, 1))),
+                room(30, &[(1, "w"), (3, "b")], None),
+                room(40, &[(3, "w"), (1, "b")], Some((300, 0))),
+            ];
+            for room in &rooms {
+                store.create_room(room).unwrap();
+            }
+            store
+                .finish_room(rooms[0].id, "1-0", "checkmate", None, None)
+                .unwrap();
+            store
+                .finish_room(rooms[3].id, "1-0", "timeout", None, None)
+                .unwrap();
+
+            let ids = |filter: &RoomFilter, offset: usize, limit: usize| {
+                let (page, total) = store.list_rooms(filter, offset, limit).unwrap();
+                let times: Vec<u64> = page.iter().map(|room| room.created_at).collect();
+                (times, total)
+            };
+            let all = RoomFilter::default();
+            assert_eq!(ids(&all, 0, 10), (vec![40, 30, 20, 10], 4), "{}", name);
+            assert_eq!(ids(&all, 1, 2), (vec![30, 20], 4), "{}", name);
+            assert_eq!(ids(&all, 8, 2), (vec![], 4), "{}", name);
+
+            let player = RoomFilter {
+                player_id: Some(1),
+                ..RoomFilter::default()
+            };
+            assert_eq!(ids(&player, 0, 10), (vec![40, 30, 10], 3), "{}", name);
+            let result = RoomFilter {
+                result: Some("1-0".to_string()),
+                ..RoomFilter::default()
+            };
+            assert_eq!(ids(&result, 0, 10), (vec![40, 10], 2), "{}", name);
+            let period = RoomFilter {
+                since: Some(20),
+                until: Some(40),
+                ..RoomFilter::default()
+            };
+            assert_eq!(ids(&period, 0, 10), (vec![30, 20], 2), "{}", name);
+            let tc = RoomFilter {
+                player_id: Some(3),
+                time_control: Some(TimeControl {
+                    initial: 300,
+                    increment: 0,
+                }),
+                ..RoomFilter::default()
+            };
+            assert_eq!(ids(&tc, 0, 10), (vec![40], 1), "{}", name);
+        }
+    }
+
+    #[test]
+    fn keeps_the_first_token_secret() {
+        for (name, store) in stores() {
+            assert_eq!(store.token_secret(b"first").unwrap(), b"first", "{}", name);
+            assert_eq!(store.token_secret(b"second").unwrap(), b"first", "{}", name);
+        }
+    }
+}
@@ -0,0 +1,809 @@
+// src/storage/sqlite.rs
+
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use super::{
+    unix_now, ExplorerGame, ExplorerMove, GameStore, PlayerRecord, RatingHistoryEntry, RoomFilter,
+    RoomRecord, StorageError, StorageResult, UserRecord,
+};
+use crate::adjudication::Rules;
+use crate::bitboard::MoveData;
+use crate::clock::TimeControl;
+use crate::eco::Opening;
+use crate::rating::{Rating, Speed};
+
+// Applied in order, `PRAGMA user_version` records how many have run
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS rooms (
+        id TEXT PRIMARY KEY,
+        time_initial INTEGER,
+        time_increment INTEGER,
+        rematch_of TEXT,
+        white_time_ms INTEGER,
+        black_time_ms INTEGER,
+        result TEXT,
+        termination TEXT,
+        created_at INTEGER NOT NULL,
+        finished_at INTEGER
+    );
+
+    CREATE TABLE IF NOT EXISTS room_players (
+        room_id TEXT NOT NULL REFERENCES rooms(id),
+        color TEXT NOT NULL,
+        player_id INTEGER NOT NULL,
+        PRIMARY KEY (room_id, color)
+    );
+
+    CREATE TABLE IF NOT EXISTS moves (
+        room_id TEXT NOT NULL REFERENCES rooms(id),
+        ply INTEGER NOT NULL,
+        from_square INTEGER NOT NULL,
+        to_square INTEGER NOT NULL,
+        white_time_ms INTEGER,
+        black_time_ms INTEGER,
+        PRIMARY KEY (room_id, ply)
+    );",
+    "ALTER TABLE moves ADD COLUMN promotion TEXT;
+    CREATE INDEX IF NOT EXISTS rooms_created_at ON rooms(created_at);
+    CREATE INDEX IF NOT EXISTS room_players_player ON room_players(player_id);",
+    "CREATE TABLE IF NOT EXISTS users (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        username TEXT NOT NULL UNIQUE COLLATE NOCASE,
+        password_hash TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );",
+    "ALTER TABLE users ADD COLUMN is_guest INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE rooms ADD COLUMN rated INTEGER NOT NULL DEFAULT 0;",
+    "CREATE TABLE IF NOT EXISTS ratings (
+        user_id INTEGER NOT NULL REFERENCES users(id),
+        speed TEXT NOT NULL,
+        rating REAL NOT NULL,
+        deviation REAL NOT NULL,
+        volatility REAL NOT NULL,
+        PRIMARY KEY (user_id, speed)
+    );
+
+    CREATE TABLE IF NOT EXISTS rating_history (
+        user_id INTEGER NOT NULL REFERENCES users(id),
+        speed TEXT NOT NULL,
+        room_id TEXT NOT NULL REFERENCES rooms(id),
+        rating REAL NOT NULL,
+        deviation REAL NOT NULL,
+        volatility REAL NOT NULL,
+        recorded_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS rating_history_user ON rating_history(user_id, speed);",
+    "CREATE TABLE IF NOT EXISTS tournaments (
+        id TEXT PRIMARY KEY,
+        state TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );",
+    "ALTER TABLE rooms ADD COLUMN review TEXT;",
+    "CREATE TABLE IF NOT EXISTS explorer_games (
+        id INTEGER PRIMARY KEY,
+        room_id TEXT,
+        white TEXT NOT NULL,
+        black TEXT NOT NULL,
+        white_rating INTEGER,
+        black_rating INTEGER,
+        result TEXT NOT NULL,
+        date TEXT
+    );
+    CREATE TABLE IF NOT EXISTS explorer_plies (
+        key INTEGER NOT NULL,
+        uci TEXT NOT NULL,
+        game_id INTEGER NOT NULL REFERENCES explorer_games(id)
+    );
+    CREATE INDEX IF NOT EXISTS explorer_plies_key ON explorer_plies(key, uci);
+    CREATE INDEX IF NOT EXISTS explorer_games_room ON explorer_games(room_id);",
+    "ALTER TABLE rooms ADD COLUMN eco TEXT;
+    ALTER TABLE rooms ADD COLUMN opening TEXT;",
+    "CREATE TABLE IF NOT EXISTS secrets (
+        name TEXT PRIMARY KEY,
+        value BLOB NOT NULL
+    );",
+    "ALTER TABLE rooms ADD COLUMN adjudication TEXT;",
+];
+
+const ROOM_COLUMNS: &str = "id, time_initial, time_increment, rematch_of, white_time_ms,
+    black_time_ms, result, termination, created_at, finished_at, rated, eco, opening,
+    adjudication";
+
+const USER_COLUMNS: &str = "id, username, password_hash, created_at, is_guest";
+
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> StorageResult<Self> {
+        let conn = Connection::open(path)?;
+        migrate(&conn)?;
+        Ok(SqliteStore {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+fn migrate(conn: &Connection) -> StorageResult<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        conn.execute_batch(migration)?;
+        conn.pragma_update(None, "user_version", i + 1)?;
+    }
+    Ok(())
+}
+
+fn room_from_row(row: &rusqlite::Row) -> rusqlite::Result<RoomRecord> {
+    let initial: Option<i64> = row.get(1)?;
+    let increment: Option<i64> = row.get(2)?;
+    let rematch_of: Option<String> = row.get(3)?;
+    Ok(RoomRecord {
+        id: parse_uuid(row.get(0)?)?,
+        time_control: initial
+            .zip(increment)
+            .map(|(initial, increment)| TimeControl {
+                initial: initial as u64,
+                increment: increment as u64,
+            }),
+        rematch_of: rematch_of.map(parse_uuid).transpose()?,
+        rated: row.get(10)?,
+        adjudication: row
+            .get::<_, Option<String>>(13)?
+            .map(|rules| parse_rules(&rules))
+            .transpose()?,
+        players: Vec::new(),
+        moves: Vec::new(),
+        white_time_ms: row.get::<_, Option<i64>>(4)?.map(|ms| ms as u64),
+        black_time_ms: row.get::<_, Option<i64>>(5)?.map(|ms| ms as u64),
+        result: row.get(6)?,
+        termination: row.get(7)?,
+        eco: row.get(11)?,
+        opening: row.get(12)?,
+        created_at: row.get::<_, i64>(8)? as u64,
+        finished_at: row.get::<_, Option<i64>>(9)?.map(|t| t as u64),
+    })
+}
+
+fn user_from_row(row: &rusqlite::Row) -> rusqlite::Result<UserRecord> {
+    Ok(UserRecord {
+        id: row.get::<_, i64>(0)? as usize,
+        username: row.get(1)?,
+        password_hash: row.get(2)?,
+        created_at: row.get::<_, i64>(3)? as u64,
+        is_guest: row.get(4)?,
+    })
+}
+
+fn rating_from_row(row: &rusqlite::Row, start: usize) -> rusqlite::Result<Rating> {
+    Ok(Rating {
+        rating: row.get(start)?,
+        deviation: row.get(start + 1)?,
+        volatility: row.get(start + 2)?,
+    })
+}
+
+// Fills in the players and moves of rooms loaded from the rooms table
+fn load_details(conn: &Connection, rooms: &mut [RoomRecord]) -> StorageResult<()> {
+    let mut players_stmt =
+        conn.prepare_cached("SELECT color, player_id FROM room_players WHERE room_id = ?1")?;
+    let mut moves_stmt = conn.prepare_cached(
+        "SELECT from_square, to_square, promotion FROM moves WHERE room_id = ?1 ORDER BY ply",
+    )?;
+
+    for room in rooms.iter_mut() {
+        let id = room.id.to_string();
+        room.players = players_stmt
+            .query_map(params![id], |row| {
+                Ok(PlayerRecord {
+                    color: row.get(0)?,
+                    player_id: row.get::<_, i64>(1)? as usize,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        room.moves = moves_stmt
+            .query_map(params![id], |row| {
+                Ok(MoveData {
+                    from: row.get::<_, i64>(0)? as usize,
+                    to: row.get::<_, i64>(1)? as usize,
+                    promotion: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+    }
+    Ok(())
+}
+
+fn parse_uuid(value: String) -> rusqlite::Result<Uuid> {
+    Uuid::parse_str(&value).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+fn parse_rules(value: &str) -> rusqlite::Result<Rules> {
+    value.parse().map_err(|e: String| {
+        rusqlite::Error::FromSqlConversionFailure(13, rusqlite::types::Type::Text, e.into())
+    })
+}
+
+impl GameStore for SqliteStore {
+    fn create_room(&self, room: &RoomRecord) -> StorageResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO rooms (id, time_initial, time_increment, rematch_of, white_time_ms,
+                                black_time_ms, created_at, rated, adjudication)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                room.id.to_string(),
+                room.time_control.map(|tc| tc.initial as i64),
+                room.time_control.map(|tc| tc.increment as i64),
+                room.rematch_of.map(|id| id.to_string()),
+                room.white_time_ms.map(|ms| ms as i64),
+                room.black_time_ms.map(|ms| ms as i64),
+                room.created_at as i64,
+                room.rated,
+                room.adjudication.map(|rules| rules.to_string()),
+            ],
+        )?;
+        for player in &room.players {
+            tx.execute(
+                "INSERT INTO room_players (room_id, color, player_id) VALUES (?1, ?2, ?3)",
+                params![room.id.to_string(), player.color, player.player_id as i64],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn add_player(&self, room_id: Uuid, player: &PlayerRecord) -> StorageResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO room_players (room_id, color, player_id) VALUES (?1, ?2, ?3)",
+            params![room_id.to_string(), player.color, player.player_id as i64],
+        )?;
+        Ok(())
+    }
+
+    fn record_move(
+        &self,
+        room_id: Uuid,
+        ply: usize,
+        move_data: &MoveData,
+        clock: Option<(u64, u64)>,
+    ) -> StorageResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO moves (room_id, ply, from_square, to_square, promotion,
+                                           white_time_ms, black_time_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                room_id.to_string(),
+                ply as i64,
+                move_data.from as i64,
+                move_data.to as i64,
+                move_data.promotion,
+                clock.map(|(white, _)| white as i64),
+                clock.map(|(_, black)| black as i64),
+            ],
+        )?;
+        if let Some((white, black)) = clock {
+            tx.execute(
+                "UPDATE rooms SET white_time_ms = ?2, black_time_ms = ?3 WHERE id = ?1",
+                params![room_id.to_string(), white as i64, black as i64],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn finish_room(
+        &self,
+        room_id: Uuid,
+        result: &str,
+        termination: &str,
+        opening: Option<Opening>,
+        clock: Option<(u64, u64)>,
+    ) -> StorageResult<()> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE rooms SET result = ?2, termination = ?3, finished_at = ?4,
+                              white_time_ms = COALESCE(?5, white_time_ms),
+                              black_time_ms = COALESCE(?6, black_time_ms),
+                              eco = ?7, opening = ?8
+             WHERE id = ?1",
+            params![
+                room_id.to_string(),
+                result,
+                termination,
+                unix_now() as i64,
+                clock.map(|(white, _)| white as i64),
+                clock.map(|(_, black)| black as i64),
+                opening.map(|opening| opening.eco),
+                opening.map(|opening| opening.name),
+            ],
+        )?;
+        if updated == 0 {
+            return Err(StorageError(format!("room {} not found", room_id)));
+        }
+        Ok(())
+    }
+
+    fn load_unfinished_rooms(&self) -> StorageResult<Vec<RoomRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM rooms WHERE result IS NULL ORDER BY created_at",
+            ROOM_COLUMNS
+        ))?;
+        let mut rooms = stmt
+            .query_map([], room_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        load_details(&conn, &mut rooms)?;
+        Ok(rooms)
+    }
+
+    fn load_room(&self, room_id: Uuid) -> StorageResult<Option<RoomRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare(&format!("SELECT {} FROM rooms WHERE id = ?1", ROOM_COLUMNS))?;
+        let mut rooms = stmt
+            .query_map(params![room_id.to_string()], room_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        load_details(&conn, &mut rooms)?;
+        Ok(rooms.pop())
+    }
+
+    fn list_rooms(
+        &self,
+        filter: &RoomFilter,
+        offset: usize,
+        limit: usize,
+    ) -> StorageResult<(Vec<RoomRecord>, usize)> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+
+        if let Some(player_id) = filter.player_id {
+            values.push(Value::Integer(player_id as i64));
+            conditions.push(format!(
+                "id IN (SELECT room_id FROM room_players WHERE player_id = ?{})",
+                values.len()
+            ));
+        }
+        if let Some(result) = &filter.result {
+            values.push(Value::Text(result.clone()));
+            conditions.push(format!("result = ?{}", values.len()));
+        }
+        if let Some(since) = filter.since {
+            values.push(Value::Integer(since as i64));
+            conditions.push(format!("created_at >= ?{}", values.len()));
+        }
+        if let Some(until) = filter.until {
+            values.push(Value::Integer(until as i64));
+            conditions.push(format!("created_at < ?{}", values.len()));
+        }
+        if let Some(tc) = filter.time_control {
+            values.push(Value::Integer(tc.initial as i64));
+            values.push(Value::Integer(tc.increment as i64));
+            conditions.push(format!(
+                "time_initial = ?{} AND time_increment = ?{}",
+                values.len() - 1,
+                values.len()
+            ));
+        }
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        let conn = self.conn.lock().unwrap();
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM rooms {}", where_clause),
+            params_from_iter(values.iter()),
+            |row| row.get(0),
+        )?;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM rooms {} ORDER BY created_at DESC, id LIMIT {} OFFSET {}",
+            ROOM_COLUMNS, where_clause, limit, offset
+        ))?;
+        let mut rooms = stmt
+            .query_map(params_from_iter(values.iter()), room_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        load_details(&conn, &mut rooms)?;
+
+        Ok((rooms, total as usize))
+    }
+
+    fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        is_guest: bool,
+    ) -> StorageResult<UserRecord> {
+        let conn = self.conn.lock().unwrap();
+        let created_at = unix_now();
+        conn.execute(
+            "INSERT INTO users (username, password_hash, created_at, is_guest)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![username, password_hash, created_at as i64, is_guest],
+        )?;
+        Ok(UserRecord {
+            id: conn.last_insert_rowid() as usize,
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            is_guest,
+            created_at,
+        })
+    }
+
+    fn claim_guest(
+        &self,
+        user_id: usize,
+        username: &str,
+        password_hash: &str,
+    ) -> StorageResult<Option<UserRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE users SET username = ?2, password_hash = ?3, is_guest = 0
+             WHERE id = ?1 AND is_guest = 1",
+            params![user_id as i64, username, password_hash],
+        )?;
+        if updated == 0 {
+            return Ok(None);
+        }
+        Ok(conn
+            .query_row(
+                &format!("SELECT {} FROM users WHERE id = ?1", USER_COLUMNS),
+                params![user_id as i64],
+                user_from_row,
+            )
+            .optional()?)
+    }
+
+    fn find_user(&self, username: &str) -> StorageResult<Option<UserRecord>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row(
+                &format!("SELECT {} FROM users WHERE username = ?1", USER_COLUMNS),
+                params![username],
+                user_from_row,
+            )
+            .optional()?)
+    }
+
+    fn load_user(&self, user_id: usize) -> StorageResult<Option<UserRecord>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row(
+                &format!("SELECT {} FROM users WHERE id = ?1", USER_COLUMNS),
+                params![user_id as i64],
+                user_from_row,
+            )
+            .optional()?)
+    }
+
+    fn recent_colors(&self, user_id: usize, limit: usize) -> StorageResult<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT room_players.color FROM room_players
+             JOIN rooms ON rooms.id = room_players.room_id
+             WHERE room_players.player_id = ?1
+             ORDER BY rooms.created_at DESC, rooms.id LIMIT ?2",
+        )?;
+        let colors = stmt
+            .query_map(params![user_id as i64, limit as i64], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(colors)
+    }
+
+    fn load_rating(&self, user_id: usize, speed: Speed) -> StorageResult<Option<Rating>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row(
+                "SELECT rating, deviation, volatility FROM ratings
+                 WHERE user_id = ?1 AND speed = ?2",
+                params![user_id as i64, speed.as_str()],
+                |row| rating_from_row(row, 0),
+            )
+            .optional()?)
+    }
+
+    fn load_ratings(&self, user_id: usize) -> StorageResult<Vec<(Speed, Rating)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT speed, rating, deviation, volatility FROM ratings WHERE user_id = ?1",
+        )?;
+        let rows = stmt
+            .query_map(params![user_id as i64], |row| {
+                Ok((row.get::<_, String>(0)?, rating_from_row(row, 1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        // Rows with a speed this version doesn't know about are skipped
+        let mut ratings: Vec<(Speed, Rating)> = rows
+            .into_iter()
+            .filter_map(|(speed, rating)| speed.parse().ok().map(|speed| (speed, rating)))
+            .collect();
+        ratings.sort_by_key(|(speed, _)| Speed::ALL.iter().position(|s| s == speed));
+        Ok(ratings)
+    }
+
+    fn record_rating(
+        &self,
+        user_id: usize,
+        speed: Speed,
+        room_id: Uuid,
+        rating: &Rating,
+    ) -> StorageResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO ratings (user_id, speed, rating, deviation, volatility)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                user_id as i64,
+                speed.as_str(),
+                rating.rating,
+                rating.deviation,
+                rating.volatility,
+            ],
+        )?;
+        tx.execute(
+            "INSERT INTO rating_history (user_id, speed, room_id, rating, deviation, volatility,
+                                         recorded_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                user_id as i64,
+                speed.as_str(),
+                room_id.to_string(),
+                rating.rating,
+                rating.deviation,
+                rating.volatility,
+                unix_now() as i64,
+            ],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn rating_history(
+        &self,
+        user_id: usize,
+        speed: Speed,
+    ) -> StorageResult<Vec<RatingHistoryEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT room_id, rating, deviation, volatility, recorded_at FROM rating_history
+             WHERE user_id = ?1 AND speed = ?2 ORDER BY rowid",
+        )?;
+        let history = stmt
+            .query_map(params![user_id as i64, speed.as_str()], |row| {
+                Ok(RatingHistoryEntry {
+                    room_id: parse_uuid(row.get(0)?)?,
+                    rating: rating_from_row(row, 1)?,
+                    recorded_at: row.get::<_, i64>(4)? as u64,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(history)
+    }
+
+    fn save_tournament(&self, id: Uuid, state: &str) -> StorageResult<()> {
+        let conn = self.conn.lock().unwrap();
+        let now = unix_now() as i64;
+        conn.execute(
+            "INSERT INTO tournaments (id, state, created_at, updated_at) VALUES (?1, ?2, ?3, ?3)
+             ON CONFLICT(id) DO UPDATE SET state = excluded.state, updated_at = excluded.updated_at",
+            params![id.to_string(), state, now],
+        )?;
+        Ok(())
+    }
+
+    fn load_tournaments(&self) -> StorageResult<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT state FROM tournaments ORDER BY created_at, rowid")?;
+        let states = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(states)
+    }
+
+    fn save_review(&self, room_id: Uuid, review: &str) -> StorageResult<()> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE rooms SET review = ?2 WHERE id = ?1",
+            params![room_id.to_string(), review],
+        )?;
+        if updated == 0 {
+            return Err(StorageError(format!("room {} not found", room_id)));
+        }
+        Ok(())
+    }
+
+    fn load_review(&self, room_id: Uuid) -> StorageResult<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        let review = conn
+            .query_row(
+                "SELECT review FROM rooms WHERE id = ?1",
+                params![room_id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(review.flatten())
+    }
+
+    fn add_explorer_game(&self, game: &ExplorerGame, plies: &[(u64, String)]) -> StorageResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        if let Some(room_id) = game.room_id {
+            let known: i64 = tx.query_row(
+                "SELECT COUNT(*) FROM explorer_games WHERE room_id = ?1",
+                params![room_id.to_string()],
+                |row| row.get(0),
+            )?;
+            if known > 0 {
+                return Ok(());
+            }
+        }
+        tx.execute(
+            "INSERT INTO explorer_games (room_id, white, black, white_rating, black_rating,
+                                         result, date)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                game.room_id.map(|id| id.to_string()),
+                game.white,
+                game.black,
+                game.white_rating,
+                game.black_rating,
+                game.result,
+                game.date,
+            ],
+        )?;
+        let game_id = tx.last_insert_rowid();
+        {
+            let mut stmt =
+                tx.prepare("INSERT INTO explorer_plies (key, uci, game_id) VALUES (?1, ?2, ?3)")?;
+            for (key, uci) in plies {
+                // SQLite integers are signed, the bits are what matters
+                stmt.execute(params![*key as i64, uci, game_id])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn explorer_moves(&self, key: u64, examples: usize) -> StorageResult<Vec<ExplorerMove>> {
+        let conn = self.conn.lock().unwrap();
+        // A game's rating is the average of the players', or the one known
+        let mut stmt = conn.prepare(
+            "SELECT p.uci, COUNT(*), SUM(g.result = '1-0'), SUM(g.result = '1/2-1/2'),
+                    SUM(g.result = '0-1'),
+                    AVG((COALESCE(g.white_rating, g.black_rating)
+                         + COALESCE(g.black_rating, g.white_rating)) / 2.0)
+             FROM explorer_plies p JOIN explorer_games g ON g.id = p.game_id
+             WHERE p.key = ?1
+             GROUP BY p.uci
+             ORDER BY COUNT(*) DESC, p.uci",
+        )?;
+        let mut moves = stmt
+            .query_map(params![key as i64], |row| {
+                Ok(ExplorerMove {
+                    uci: row.get(0)?,
+                    games: row.get::<_, i64>(1)? as usize,
+                    white_wins: row.get::<_, i64>(2)? as usize,
+                    draws: row.get::<_, i64>(3)? as usize,
+                    black_wins: row.get::<_, i64>(4)? as usize,
+                    average_rating: row.get::<_, Option<f64>>(5)?.map(|r| r.round() as i64),
+                    examples: Vec::new(),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT g.room_id, g.white, g.black, g.white_rating, g.black_rating, g.result, g.date
+             FROM explorer_plies p JOIN explorer_games g ON g.id = p.game_id
+             WHERE p.key = ?1 AND p.uci = ?2
+             ORDER BY g.id DESC
+             LIMIT ?3",
+        )?;
+        for mv in &mut moves {
+            mv.examples = stmt
+                .query_map(params![key as i64, mv.uci, examples as i64], |row| {
+                    Ok(ExplorerGame {
+                        room_id: row
+                            .get::<_, Option<String>>(0)?
+                            .and_then(|id| Uuid::parse_str(&id).ok()),
+                        white: row.get(1)?,
+                        black: row.get(2)?,
+                        white_rating: row.get(3)?,
+                        black_rating: row.get(4)?,
+                        result: row.get(5)?,
+                        date: row.get(6)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+        }
+        Ok(moves)
+    }
+
+    fn clear_explorer(&self) -> StorageResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute_batch("DELETE FROM explorer_plies; DELETE FROM explorer_games;")?;
+        Ok(())
+    }
+
+    fn token_secret(&self, generated: &[u8]) -> StorageResult<Vec<u8>> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR IGNORE INTO secrets (name, value) VALUES ('token', ?1)",
+            params![generated],
+        )?;
+        let secret = conn.query_row(
+            "SELECT value FROM secrets WHERE name = 'token'",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_version(conn: &Connection) -> usize {
+        conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn migrates_a_new_database() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        // Running them again changes nothing
+        migrate(&conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn migrates_an_old_database_keeping_its_rooms() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.pragma_update(None, "user_version", 1).unwrap();
+        conn.execute(
+            "INSERT INTO rooms (id, time_initial, time_increment, created_at)
+             VALUES (?1, 300, 5, 42)",
+            params![Uuid::nil().to_string()],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO moves (room_id, ply, from_square, to_square) VALUES (?1, 1, 12, 28)",
+            params![Uuid::nil().to_string()],
+        )
+        .unwrap();
+
+        migrate(&conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        let store = SqliteStore {
+            conn: Mutex::new(conn),
+        };
+        let room = store.load_room(Uuid::nil()).unwrap().unwrap();
+        assert_eq!(
+            room.time_control,
+            Some(TimeControl {
+                initial: 300,
+                increment: 5
+            })
+        );
+        assert!(!room.rated);
+        assert_eq!(room.moves.len(), 1);
+        assert_eq!(room.moves[0].promotion, None);
+    }
+}
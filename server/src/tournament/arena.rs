@@ -0,0 +1,147 @@
+// src/tournament/arena.rs
+
+// Arena scoring and pairing. A win is worth 2 points and a draw 1. After two
+// wins in a row a player is on a streak and gets double points until they
+// fail to win a game.
+
+use std::collections::HashSet;
+
+use super::{Pairing, Tournament};
+use crate::matchmaking::assign_colors;
+
+const WIN_POINTS: f64 = 2.0;
+const DRAW_POINTS: f64 = 1.0;
+const STREAK_LENGTH: usize = 2;
+
+pub fn points(tournament: &Tournament, user_id: usize) -> f64 {
+    let mut points = 0.0;
+    let mut wins_in_a_row = 0;
+    for pairing in tournament.games_of(user_id) {
+        let Some(scored) = pairing.points_for(user_id) else {
+            continue;
+        };
+        let multiplier = if wins_in_a_row >= STREAK_LENGTH {
+            2.0
+        } else {
+            1.0
+        };
+        if scored == 1.0 {
+            points += WIN_POINTS * multiplier;
+            wins_in_a_row += 1;
+        } else {
+            if scored > 0.0 {
+                points += DRAW_POINTS * multiplier;
+            }
+            wins_in_a_row = 0;
+        }
+    }
+    points
+}
+
+// Newest first, in the form `assign_colors` expects
+fn color_history(tournament: &Tournament, user_id: usize) -> Vec<String> {
+    let mut colors: Vec<String> = tournament
+        .games_of(user_id)
+        .filter(|pairing| !pairing.is_bye())
+        .map(|pairing| if pairing.white == user_id { "w" } else { "b" }.to_string())
+        .collect();
+    colors.reverse();
+    colors
+}
+
+fn last_opponent(tournament: &Tournament, user_id: usize) -> Option<usize> {
+    tournament
+        .games_of(user_id)
+        .last()
+        .and_then(|pairing| pairing.opponent_of(user_id))
+}
+
+// Pairs the players who are online and not in a game, neighbours in the
+// standings first. The last opponent is skipped while anyone else is free.
+pub fn pair_waiting(tournament: &Tournament, online: &HashSet<usize>) -> Vec<Pairing> {
+    let busy: HashSet<usize> = tournament
+        .pairings
+        .iter()
+        .flatten()
+        .filter(|pairing| !pairing.is_finished())
+        .flat_map(|pairing| [Some(pairing.white), pairing.black])
+        .flatten()
+        .collect();
+
+    let mut waiting: Vec<(usize, f64, f64)> = tournament
+        .active_players()
+        .filter(|p| online.contains(&p.user_id) && !busy.contains(&p.user_id))
+        .map(|p| (p.user_id, tournament.points(p.user_id), p.rating))
+        .collect();
+    waiting.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.2.total_cmp(&a.2)));
+    let mut waiting: Vec<usize> = waiting.into_iter().map(|(user_id, _, _)| user_id).collect();
+
+    let mut batch = Vec::new();
+    while waiting.len() >= 2 {
+        let first = waiting.remove(0);
+        let last = last_opponent(tournament, first);
+        let j = waiting
+            .iter()
+            .position(|user_id| Some(*user_id) != last)
+            .unwrap_or(0);
+        let second = waiting.remove(j);
+
+        let (first_color, _) = assign_colors(
+            &color_history(tournament, first),
+            &color_history(tournament, second),
+        );
+        let (white, black) = if first_color == "w" {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        batch.push(Pairing::new(white, Some(black)));
+    }
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tournament::tests::tournament;
+    use crate::tournament::Format;
+
+    fn game(white: usize, black: usize, result: &str) -> Pairing {
+        let mut pairing = Pairing::new(white, Some(black));
+        pairing.result = Some(result.to_string());
+        pairing
+    }
+
+    #[test]
+    fn doubles_points_on_a_streak() {
+        let mut tournament = tournament(Format::Arena { minutes: 60 }, 0, 2);
+        // Player 1 wins, wins, wins on a streak, draws on a streak and wins
+        for i in 0..5 {
+            let pairing = match i % 2 {
+                0 => game(1, 2, "1-0"),
+                _ => game(2, 1, "0-1"),
+            };
+            tournament.pairings.push(vec![pairing]);
+        }
+        tournament.pairings[3][0].result = Some("1/2-1/2".to_string());
+        assert_eq!(points(&tournament, 1), 2.0 + 2.0 + 4.0 + 2.0 + 2.0);
+        assert_eq!(points(&tournament, 2), 1.0);
+    }
+
+    #[test]
+    fn avoids_the_last_opponent_while_others_wait() {
+        let mut tournament = tournament(Format::Arena { minutes: 60 }, 0, 3);
+        tournament.pairings.push(vec![game(1, 2, "1/2-1/2")]);
+        let online: HashSet<usize> = [1, 2, 3].into();
+        let batch = pair_waiting(&tournament, &online);
+        assert_eq!(batch.len(), 1);
+        assert!(!(batch[0].involves(1) && batch[0].involves(2)));
+
+        // Nobody playing is paired, and nobody offline
+        tournament.pairings.push(vec![Pairing::new(1, Some(3))]);
+        assert!(pair_waiting(&tournament, &online).is_empty());
+        let online: HashSet<usize> = [1, 3].into();
+        tournament.pairings[1][0].result = Some("1-0".to_string());
+        assert_eq!(pair_waiting(&tournament, &online).len(), 1);
+    }
+}
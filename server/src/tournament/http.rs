@@ -0,0 +1,427 @@
+// src/tournament/http.rs
+
+use actix::Addr;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::knockout::Heat;
+use super::{
+    Format, MatchRules, Tournament, TournamentError, TournamentPlayer, MAX_ARENA_MINUTES,
+    MAX_MATCH_GAMES, MAX_NAME_LEN, MAX_ROUNDS,
+};
+use crate::adjudication;
+use crate::api::{
+    authenticate, bad_request, error_response, internal_error, not_found, unauthorized,
+};
+use crate::auth::TokenSigner;
+use crate::clock::TimeControl;
+use crate::messages::{
+    CreateTournament, GetTournament, JoinTournament, ListTournaments, StartTournament,
+    WithdrawTournament,
+};
+use crate::rating::{Speed, DEFAULT_RATING};
+use crate::server::Server;
+use crate::storage::{GameStore, StorageError};
+
+#[derive(Deserialize)]
+pub struct NewTournament {
+    pub name: String,
+    // "swiss" (the default), "round_robin", "double_round_robin", "arena" or
+    // "knockout"
+    pub format: Option<String>,
+    pub rounds: Option<usize>, // Swiss only
+    pub minutes: Option<u64>,  // arena only
+    // Knockouts only, regular games per match and the tiebreak time controls
+    pub games: Option<usize>,
+    pub rapid: Option<TimeControl>,
+    pub blitz: Option<TimeControl>,
+    pub armageddon: Option<TimeControl>,
+    pub armageddon_black: Option<u64>,
+    pub time_control: Option<TimeControl>,
+    #[serde(default)]
+    pub rated: bool,
+    pub adjudication: Option<String>, // rules as for `adjudication::Rules`
+}
+
+#[derive(Deserialize)]
+pub struct StandingsQuery {
+    pub format: Option<String>, // "json" (the default) or "csv"
+}
+
+fn error_for(err: TournamentError) -> HttpResponse {
+    match err {
+        TournamentError::NotFound => not_found("Tournament not found"),
+        TournamentError::Forbidden(error) => {
+            error_response(actix_web::http::StatusCode::FORBIDDEN, error)
+        }
+        TournamentError::Invalid(error) => bad_request(&error),
+    }
+}
+
+fn summary(tournament: &Tournament) -> serde_json::Value {
+    json!({
+        "id": tournament.id.to_string(),
+        "name": tournament.name,
+        "format": tournament.format.as_str(),
+        "status": tournament.status,
+        "time_control": tournament.time_control.map(|tc| tc.to_string()),
+        "rated": tournament.rated,
+        "rounds": tournament.rounds,
+        "current_round": tournament.current_round(),
+        "players": tournament.players.len(),
+        "created_at": tournament.created_at,
+        "ends_at": tournament.ends_at,
+    })
+}
+
+fn details(tournament: &Tournament) -> serde_json::Value {
+    let name_of = |user_id: usize| tournament.player(user_id).map(|p| p.username.clone());
+    let mut value = summary(tournament);
+    value["created_by"] = json!(tournament.created_by);
+    value["adjudication"] = json!(tournament.adjudication);
+    value["players"] = tournament
+        .players
+        .iter()
+        .map(|player| {
+            json!({
+                "user_id": player.user_id,
+                "username": player.username,
+                "rating": player.rating.round(),
+                "withdrawn": player.withdrawn,
+            })
+        })
+        .collect();
+    value["pairings"] = tournament
+        .pairings
+        .iter()
+        .enumerate()
+        .map(|(i, round)| {
+            json!({
+                "round": i + 1,
+                "games": round
+                    .iter()
+                    .enumerate()
+                    .map(|(board, pairing)| json!({
+                        "board": board + 1,
+                        "white": name_of(pairing.white),
+                        "black": pairing.black.and_then(name_of),
+                        "game_id": pairing.room_id.map(|id| id.to_string()),
+                        "result": if pairing.is_bye() {
+                            Some("bye".to_string())
+                        } else {
+                            pairing.result.clone()
+                        },
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    if !tournament.bracket.is_empty() {
+        value["bracket"] = bracket(tournament);
+    }
+    value["standings"] = json!(tournament.standings());
+    value
+}
+
+fn bracket(tournament: &Tournament) -> serde_json::Value {
+    let name_of = |user_id: usize| tournament.player(user_id).map(|p| p.username.clone());
+    tournament
+        .bracket
+        .iter()
+        .enumerate()
+        .map(|(i, round)| {
+            let heats: Vec<_> = round
+                .iter()
+                .map(|heat| match heat {
+                    Heat::Bye { player } => json!({
+                        "players": [name_of(*player)],
+                        "winner": name_of(*player),
+                        "bye": true,
+                    }),
+                    Heat::Match(m) => json!({
+                        "players": m.players.map(name_of),
+                        "score": m.score(None),
+                        "stage": m.stage(),
+                        "winner": m.winner.and_then(name_of),
+                        "games": m
+                            .games
+                            .iter()
+                            .map(|game| json!({
+                                "stage": game.stage,
+                                "white": name_of(game.pairing.white),
+                                "black": game.pairing.black.and_then(name_of),
+                                "game_id": game.pairing.room_id.map(|id| id.to_string()),
+                                "result": game.pairing.result,
+                            }))
+                            .collect::<Vec<_>>(),
+                    }),
+                })
+                .collect();
+            json!({ "round": i + 1, "heats": heats })
+        })
+        .collect()
+}
+
+fn respond(
+    result: Result<Result<Tournament, TournamentError>, actix::MailboxError>,
+) -> HttpResponse {
+    match result {
+        Ok(Ok(tournament)) => HttpResponse::Ok().json(details(&tournament)),
+        Ok(Err(err)) => error_for(err),
+        Err(err) => internal_error(&err.to_string()),
+    }
+}
+
+pub async fn list_tournaments(srv: web::Data<Arc<Addr<Server>>>) -> HttpResponse {
+    match srv.send(ListTournaments).await {
+        Ok(tournaments) => HttpResponse::Ok().json(json!({
+            "tournaments": tournaments.iter().map(summary).collect::<Vec<_>>(),
+        })),
+        Err(err) => internal_error(&err.to_string()),
+    }
+}
+
+pub async fn create_tournament(
+    req: HttpRequest,
+    srv: web::Data<Arc<Addr<Server>>>,
+    signer: web::Data<Arc<TokenSigner>>,
+    body: web::Json<NewTournament>,
+) -> HttpResponse {
+    let Some(user_id) = authenticate(&req, &signer) else {
+        return unauthorized();
+    };
+    let NewTournament {
+        name,
+        format,
+        rounds,
+        minutes,
+        games,
+        rapid,
+        blitz,
+        armageddon,
+        armageddon_black,
+        time_control,
+        rated,
+        adjudication,
+    } = body.into_inner();
+    let name = name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return bad_request(&format!(
+            "Name must be between 1 and {} characters",
+            MAX_NAME_LEN
+        ));
+    }
+    for tc in [time_control, rapid, blitz, armageddon]
+        .into_iter()
+        .flatten()
+    {
+        if let Err(err) = tc.validate() {
+            return bad_request(&err);
+        }
+    }
+    let format = match format.as_deref().unwrap_or("swiss") {
+        "swiss" => Format::Swiss,
+        "round_robin" => Format::RoundRobin { double: false },
+        "double_round_robin" => Format::RoundRobin { double: true },
+        "arena" => match minutes {
+            Some(minutes) if (1..=MAX_ARENA_MINUTES).contains(&minutes) => {
+                Format::Arena { minutes }
+            }
+            _ => {
+                return bad_request(&format!(
+                    "Arenas need a length of 1 to {} minutes",
+                    MAX_ARENA_MINUTES
+                ))
+            }
+        },
+        // Two players make a single match
+        "knockout" => {
+            let defaults = MatchRules::default();
+            let rules = MatchRules {
+                games: games.unwrap_or(defaults.games),
+                rapid: rapid.unwrap_or(defaults.rapid),
+                blitz: blitz.unwrap_or(defaults.blitz),
+                armageddon: armageddon.unwrap_or(defaults.armageddon),
+                armageddon_black: armageddon_black.unwrap_or(defaults.armageddon_black),
+            };
+            if !(1..=MAX_MATCH_GAMES).contains(&rules.games) {
+                return bad_request(&format!(
+                    "Matches must have between 1 and {} games",
+                    MAX_MATCH_GAMES
+                ));
+            }
+            if rules.armageddon_black == 0 || rules.armageddon_black >= rules.armageddon.initial {
+                return bad_request("Black must have less time than white in the armageddon");
+            }
+            Format::Knockout { rules }
+        }
+        other => return bad_request(&format!("Unknown format '{}'", other)),
+    };
+    // Round robins work out their rounds on start
+    let rounds = match (format, rounds) {
+        (Format::Swiss, Some(rounds)) if (1..=MAX_ROUNDS).contains(&rounds) => rounds,
+        (Format::Swiss, _) => {
+            return bad_request(&format!("Rounds must be between 1 and {}", MAX_ROUNDS))
+        }
+        _ => 0,
+    };
+    let adjudication = match adjudication.as_deref().map(adjudication::parse) {
+        Some(Ok(rules)) => rules,
+        Some(Err(err)) => return bad_request(&err),
+        None => None,
+    };
+
+    let msg = CreateTournament {
+        created_by: user_id,
+        name,
+        format,
+        time_control,
+        rated,
+        rounds,
+        adjudication,
+    };
+    match srv.send(msg).await {
+        Ok(tournament) => HttpResponse::Created().json(details(&tournament)),
+        Err(err) => internal_error(&err.to_string()),
+    }
+}
+
+pub async fn get_tournament(
+    srv: web::Data<Arc<Addr<Server>>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let Ok(tournament_id) = Uuid::parse_str(&path) else {
+        return bad_request("Invalid tournament ID format");
+    };
+    match srv.send(GetTournament { tournament_id }).await {
+        Ok(Some(tournament)) => HttpResponse::Ok().json(details(&tournament)),
+        Ok(None) => not_found("Tournament not found"),
+        Err(err) => internal_error(&err.to_string()),
+    }
+}
+
+// Players are seeded by their rating at the tournament's speed
+pub async fn join_tournament(
+    req: HttpRequest,
+    srv: web::Data<Arc<Addr<Server>>>,
+    store: web::Data<Arc<dyn GameStore>>,
+    signer: web::Data<Arc<TokenSigner>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let Some(user_id) = authenticate(&req, &signer) else {
+        return unauthorized();
+    };
+    let Ok(tournament_id) = Uuid::parse_str(&path) else {
+        return bad_request("Invalid tournament ID format");
+    };
+    let speed = match srv.send(GetTournament { tournament_id }).await {
+        Ok(Some(tournament)) => Speed::from_time_control(tournament.time_control),
+        Ok(None) => return not_found("Tournament not found"),
+        Err(err) => return internal_error(&err.to_string()),
+    };
+
+    let store = store.get_ref().clone();
+    let found = web::block(move || match store.load_user(user_id)? {
+        Some(user) => {
+            let rating = store.load_rating(user_id, speed)?;
+            Ok(Some((user, rating)))
+        }
+        None => Ok::<_, StorageError>(None),
+    })
+    .await;
+    let (user, rating) = match found {
+        Ok(Ok(Some(found))) => found,
+        Ok(Ok(None)) => return not_found("User not found"),
+        Ok(Err(err)) => return internal_error(&err.to_string()),
+        Err(err) => return internal_error(&err.to_string()),
+    };
+
+    let msg = JoinTournament {
+        tournament_id,
+        player: TournamentPlayer {
+            user_id,
+            username: user.username,
+            rating: rating.map_or(DEFAULT_RATING, |r| r.rating),
+            withdrawn: false,
+        },
+        guest: user.is_guest,
+    };
+    respond(srv.send(msg).await)
+}
+
+pub async fn withdraw_tournament(
+    req: HttpRequest,
+    srv: web::Data<Arc<Addr<Server>>>,
+    signer: web::Data<Arc<TokenSigner>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let Some(user_id) = authenticate(&req, &signer) else {
+        return unauthorized();
+    };
+    let Ok(tournament_id) = Uuid::parse_str(&path) else {
+        return bad_request("Invalid tournament ID format");
+    };
+    respond(
+        srv.send(WithdrawTournament {
+            tournament_id,
+            user_id,
+        })
+        .await,
+    )
+}
+
+pub async fn start_tournament(
+    req: HttpRequest,
+    srv: web::Data<Arc<Addr<Server>>>,
+    signer: web::Data<Arc<TokenSigner>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let Some(user_id) = authenticate(&req, &signer) else {
+        return unauthorized();
+    };
+    let Ok(tournament_id) = Uuid::parse_str(&path) else {
+        return bad_request("Invalid tournament ID format");
+    };
+    respond(
+        srv.send(StartTournament {
+            tournament_id,
+            user_id,
+        })
+        .await,
+    )
+}
+
+pub async fn get_standings(
+    srv: web::Data<Arc<Addr<Server>>>,
+    path: web::Path<String>,
+    query: web::Query<StandingsQuery>,
+) -> HttpResponse {
+    let Ok(tournament_id) = Uuid::parse_str(&path) else {
+        return bad_request("Invalid tournament ID format");
+    };
+    let tournament = match srv.send(GetTournament { tournament_id }).await {
+        Ok(Some(tournament)) => tournament,
+        Ok(None) => return not_found("Tournament not found"),
+        Err(err) => return internal_error(&err.to_string()),
+    };
+
+    match query.format.as_deref() {
+        Some("csv") => HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"{}.csv\"", tournament.id),
+            ))
+            .body(tournament.standings_csv()),
+        Some("json") | None => HttpResponse::Ok().json(json!({
+            "id": tournament.id.to_string(),
+            "round": tournament.current_round(),
+            "status": tournament.status,
+            "standings": tournament.standings(),
+        })),
+        Some(other) => bad_request(&format!("Unknown format '{}'", other)),
+    }
+}
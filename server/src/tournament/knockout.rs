@@ -0,0 +1,148 @@
+// src/tournament/knockout.rs
+
+// Knockout brackets made of matches. Players are seeded by rating into a
+// bracket the size of the next power of two, so the top seeds can only meet
+// in the late rounds and get the byes when the field isn't full. Winners
+// move on in bracket order until one is left.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::matches::Match;
+use super::Tournament;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Heat {
+    Bye { player: usize },
+    Match(Match),
+}
+
+impl Heat {
+    pub fn winner(&self) -> Option<usize> {
+        match self {
+            Heat::Bye { player } => Some(*player),
+            Heat::Match(m) => m.winner,
+        }
+    }
+
+    fn from_pair(first: Option<usize>, second: Option<usize>) -> Option<Heat> {
+        match (first, second) {
+            (Some(a), Some(b)) => Some(Heat::Match(Match::new([a, b]))),
+            (Some(player), None) | (None, Some(player)) => Some(Heat::Bye { player }),
+            (None, None) => None,
+        }
+    }
+}
+
+pub fn rounds(players: usize) -> usize {
+    players.next_power_of_two().trailing_zeros() as usize
+}
+
+// Seed numbers (from 1) in bracket order, e.g. 1 8 4 5 2 7 3 6 for eight
+fn seed_order(size: usize) -> Vec<usize> {
+    let mut order = vec![1];
+    while order.len() < size {
+        let mirror = order.len() * 2 + 1;
+        order = order
+            .iter()
+            .flat_map(|seed| [*seed, mirror - seed])
+            .collect();
+    }
+    order
+}
+
+pub fn first_round(tournament: &Tournament) -> Vec<Heat> {
+    let mut seeds: Vec<_> = tournament.players.iter().collect();
+    seeds.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+    let order = seed_order(seeds.len().next_power_of_two());
+    order
+        .chunks(2)
+        .filter_map(|pair| {
+            let player = |seed: usize| seeds.get(seed - 1).map(|p| p.user_id);
+            Heat::from_pair(player(pair[0]), player(pair[1]))
+        })
+        .collect()
+}
+
+// Pairs the winners of `previous` in bracket order
+pub fn next_round(previous: &[Heat]) -> Vec<Heat> {
+    previous
+        .chunks(2)
+        .filter_map(|pair| {
+            Heat::from_pair(pair[0].winner(), pair.get(1).and_then(|heat| heat.winner()))
+        })
+        .collect()
+}
+
+// How many rounds each player reached, the winner of the final counts as
+// reaching one more
+pub fn rounds_reached(bracket: &[Vec<Heat>]) -> HashMap<usize, usize> {
+    let mut reached = HashMap::new();
+    for (i, round) in bracket.iter().enumerate() {
+        for heat in round {
+            let players = match heat {
+                Heat::Bye { player } => vec![*player],
+                Heat::Match(m) => m.players.to_vec(),
+            };
+            for player in players {
+                reached.insert(player, i + 1);
+            }
+        }
+    }
+    if let Some([final_heat]) = bracket.last().map(Vec::as_slice) {
+        if let Some(winner) = final_heat.winner() {
+            reached.insert(winner, bracket.len() + 1);
+        }
+    }
+    reached
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tournament::tests::tournament;
+    use crate::tournament::{Format, MatchRules};
+
+    #[test]
+    fn seeds_the_bracket() {
+        assert_eq!(seed_order(8), [1, 8, 4, 5, 2, 7, 3, 6]);
+        assert_eq!(rounds(5), 3);
+        assert_eq!(rounds(8), 3);
+    }
+
+    #[test]
+    fn top_seeds_get_the_byes() {
+        let tournament = tournament(
+            Format::Knockout {
+                rules: MatchRules::default(),
+            },
+            0,
+            5,
+        );
+        let round = first_round(&tournament);
+        let heats: Vec<Vec<usize>> = round
+            .iter()
+            .map(|heat| match heat {
+                Heat::Bye { player } => vec![*player],
+                Heat::Match(m) => m.players.to_vec(),
+            })
+            .collect();
+        assert_eq!(heats, [vec![1], vec![4, 5], vec![2], vec![3]]);
+
+        // Once the match is won the winner meets the top seed
+        let mut round = round;
+        if let Heat::Match(m) = &mut round[1] {
+            m.winner = Some(5);
+        }
+        let next = next_round(&round);
+        let players: Vec<[usize; 2]> = next
+            .iter()
+            .filter_map(|heat| match heat {
+                Heat::Match(m) => Some(m.players),
+                Heat::Bye { .. } => None,
+            })
+            .collect();
+        assert_eq!(players, [[1, 5], [2, 3]]);
+    }
+}
@@ -0,0 +1,250 @@
+// src/tournament/matches.rs
+
+// A match between two players over several games. The regular games are
+// followed, while the score is level, by two rapid games, two blitz games
+// and finally an armageddon game in which black has less time but wins the
+// match with a draw. Colors alternate from game to game, the armageddon
+// colors are drawn by lot. A match is decided as soon as the trailing
+// player can no longer catch up in the current stage.
+
+use serde::{Deserialize, Serialize};
+
+use super::Pairing;
+use crate::clock::TimeControl;
+use crate::game_room::{random_color, GameResult};
+
+const TIEBREAK_GAMES: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Regular,
+    Rapid,
+    Blitz,
+    Armageddon,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchRules {
+    pub games: usize, // regular games, played at the tournament's time control
+    pub rapid: TimeControl,
+    pub blitz: TimeControl,
+    pub armageddon: TimeControl, // white's clock
+    pub armageddon_black: u64,   // black's starting time in seconds
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        MatchRules {
+            games: 2,
+            rapid: TimeControl {
+                initial: 900,
+                increment: 10,
+            },
+            blitz: TimeControl {
+                initial: 300,
+                increment: 3,
+            },
+            armageddon: TimeControl {
+                initial: 300,
+                increment: 0,
+            },
+            armageddon_black: 240,
+        }
+    }
+}
+
+impl MatchRules {
+    fn stages(&self) -> [(Stage, usize); 4] {
+        [
+            (Stage::Regular, self.games),
+            (Stage::Rapid, TIEBREAK_GAMES),
+            (Stage::Blitz, TIEBREAK_GAMES),
+            (Stage::Armageddon, 1),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchGame {
+    pub stage: Stage,
+    #[serde(flatten)]
+    pub pairing: Pairing,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Match {
+    pub players: [usize; 2], // user ids, the first has white in game one
+    pub games: Vec<MatchGame>,
+    pub winner: Option<usize>,
+}
+
+impl Match {
+    pub fn new(players: [usize; 2]) -> Self {
+        Match {
+            players,
+            games: Vec::new(),
+            winner: None,
+        }
+    }
+
+    // Points of both players in `players` order, over one stage or the
+    // whole match
+    pub fn score(&self, stage: Option<Stage>) -> [f64; 2] {
+        let mut score = [0.0, 0.0];
+        for game in &self.games {
+            if stage.is_some_and(|stage| stage != game.stage) {
+                continue;
+            }
+            for (i, player) in self.players.iter().enumerate() {
+                score[i] += game.pairing.points_for(*player).unwrap_or(0.0);
+            }
+        }
+        score
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.games.iter().any(|game| !game.pairing.is_finished())
+    }
+
+    pub fn stage(&self) -> Option<Stage> {
+        self.games.last().map(|game| game.stage)
+    }
+
+    // Gives the match to `winner`, used when the opponent withdraws
+    pub fn forfeit_to(&mut self, winner: usize) {
+        if self.winner.is_none() {
+            self.winner = Some(winner);
+        }
+    }
+
+    // Decides the match if the score allows it, otherwise adds the next game.
+    // Does nothing while a game is being played or once there's a winner.
+    pub fn advance(&mut self, rules: &MatchRules) {
+        if self.winner.is_some() || self.is_playing() {
+            return;
+        }
+
+        for (stage, length) in rules.stages() {
+            let played = self.games.iter().filter(|g| g.stage == stage).count();
+            if stage == Stage::Armageddon && played > 0 {
+                // Black has draw odds
+                let game = &self.games[self.games.len() - 1].pairing;
+                let result = game.result.as_deref().and_then(|r| r.parse().ok());
+                self.winner = match result {
+                    Some(GameResult::WhiteWins) => Some(game.white),
+                    _ => game.black,
+                };
+                return;
+            }
+
+            let [first, second] = self.score(Some(stage));
+            let remaining = length.saturating_sub(played) as f64;
+            if (first - second).abs() > remaining {
+                let leader = if first > second { 0 } else { 1 };
+                self.winner = Some(self.players[leader]);
+                return;
+            }
+            if played < length {
+                self.games.push(self.next_game(stage, rules));
+                return;
+            }
+            // Level after this stage, on to the next one
+        }
+    }
+
+    fn next_game(&self, stage: Stage, rules: &MatchRules) -> MatchGame {
+        let white = match stage {
+            Stage::Armageddon if random_color() == "w" => 0,
+            Stage::Armageddon => 1,
+            _ => self.games.len() % 2,
+        };
+        let mut pairing = Pairing::new(self.players[white], Some(self.players[1 - white]));
+        // Regular games use the tournament's time control
+        pairing.time_control = match stage {
+            Stage::Regular => None,
+            Stage::Rapid => Some(rules.rapid),
+            Stage::Blitz => Some(rules.blitz),
+            Stage::Armageddon => Some(rules.armageddon),
+        };
+        if stage == Stage::Armageddon {
+            pairing.black_initial = Some(rules.armageddon_black);
+        }
+        MatchGame { stage, pairing }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Finishes the game in progress, `white_score` being 1, 0.5 or 0
+    fn finish(m: &mut Match, white_score: f64) {
+        let game = &mut m.games.last_mut().unwrap().pairing;
+        let result = match white_score {
+            1.0 => "1-0",
+            0.0 => "0-1",
+            _ => "1/2-1/2",
+        };
+        game.result = Some(result.to_string());
+    }
+
+    #[test]
+    fn decides_as_soon_as_the_score_allows() {
+        let rules = MatchRules::default();
+        let mut m = Match::new([1, 2]);
+        m.advance(&rules);
+        finish(&mut m, 1.0);
+        m.advance(&rules);
+        // One game left and one point behind, it goes on
+        assert_eq!(m.winner, None);
+        assert_eq!(m.games.len(), 2);
+        assert_eq!(m.games[1].pairing.white, 2);
+        finish(&mut m, 0.0);
+        m.advance(&rules);
+        assert_eq!(m.winner, Some(1));
+        assert_eq!(m.score(None), [2.0, 0.0]);
+    }
+
+    #[test]
+    fn goes_through_the_tiebreaks_to_armageddon() {
+        let rules = MatchRules::default();
+        let mut m = Match::new([1, 2]);
+        m.advance(&rules);
+        let mut stages = Vec::new();
+        while m.stage() != Some(Stage::Armageddon) {
+            let game = &m.games.last().unwrap();
+            stages.push(game.stage);
+            let expected = match game.stage {
+                Stage::Regular => None,
+                Stage::Rapid => Some(rules.rapid),
+                Stage::Blitz => Some(rules.blitz),
+                Stage::Armageddon => unreachable!(),
+            };
+            assert_eq!(game.pairing.time_control, expected);
+            finish(&mut m, 0.5);
+            m.advance(&rules);
+            assert_eq!(m.winner, None);
+        }
+        assert_eq!(
+            stages,
+            [
+                Stage::Regular,
+                Stage::Regular,
+                Stage::Rapid,
+                Stage::Rapid,
+                Stage::Blitz,
+                Stage::Blitz,
+            ]
+        );
+
+        let armageddon = m.games.last().unwrap().pairing.clone();
+        assert_eq!(armageddon.time_control, Some(rules.armageddon));
+        assert_eq!(armageddon.black_initial, Some(rules.armageddon_black));
+        // A draw is a win for black
+        finish(&mut m, 0.5);
+        m.advance(&rules);
+        assert_eq!(m.winner, armageddon.black);
+        assert_eq!(m.games.len(), 7);
+    }
+}
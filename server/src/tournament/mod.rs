@@ -0,0 +1,604 @@
+// src/tournament/mod.rs
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::adjudication::Rules;
+use crate::clock::TimeControl;
+use crate::game_room::GameResult;
+use crate::storage::unix_now;
+
+mod arena;
+pub mod http;
+mod knockout;
+mod matches;
+mod round_robin;
+mod swiss;
+
+use knockout::Heat;
+pub use matches::MatchRules;
+
+// A bye is worth as much as a win
+const BYE_POINTS: f64 = 1.0;
+pub const MAX_ROUNDS: usize = 20;
+pub const MAX_ARENA_MINUTES: u64 = 24 * 60;
+pub const MAX_NAME_LEN: usize = 60;
+pub const MAX_MATCH_GAMES: usize = 24;
+
+#[derive(Debug)]
+pub enum TournamentError {
+    NotFound,
+    Forbidden(&'static str),
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TournamentStatus {
+    Registering,
+    Running,
+    Finished,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Format {
+    // A fixed number of rounds, see swiss.rs
+    #[default]
+    Swiss,
+    // Everyone plays everyone, twice with swapped colors when `double`
+    RoundRobin {
+        double: bool,
+    },
+    // Players are paired again as soon as their game ends until time is up
+    Arena {
+        minutes: u64,
+    },
+    // A bracket of matches, see knockout.rs and matches.rs. With two players
+    // this is a single match.
+    Knockout {
+        rules: MatchRules,
+    },
+}
+
+impl Format {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Format::Swiss => "swiss",
+            Format::RoundRobin { double: false } => "round_robin",
+            Format::RoundRobin { double: true } => "double_round_robin",
+            Format::Arena { .. } => "arena",
+            Format::Knockout { .. } => "knockout",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TournamentPlayer {
+    pub user_id: usize,
+    pub username: String,
+    pub rating: f64, // at registration, used for seeding
+    pub withdrawn: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pairing {
+    pub white: usize,         // user id, or the player with the bye
+    pub black: Option<usize>, // None for a bye
+    pub room_id: Option<Uuid>,
+    pub result: Option<String>, // "1-0", "0-1" or "1/2-1/2" once the game is over
+    // Replaces the tournament's time control, for match tiebreaks
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
+    // Black's starting time in seconds when it differs from white's
+    #[serde(default)]
+    pub black_initial: Option<u64>,
+}
+
+impl Pairing {
+    pub fn new(white: usize, black: Option<usize>) -> Self {
+        Pairing {
+            white,
+            black,
+            room_id: None,
+            result: None,
+            time_control: None,
+            black_initial: None,
+        }
+    }
+
+    pub fn is_bye(&self) -> bool {
+        self.black.is_none()
+    }
+
+    pub fn involves(&self, user_id: usize) -> bool {
+        self.white == user_id || self.black == Some(user_id)
+    }
+
+    pub fn opponent_of(&self, user_id: usize) -> Option<usize> {
+        if self.white == user_id {
+            self.black
+        } else if self.black == Some(user_id) {
+            Some(self.white)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.is_bye() || self.result.is_some()
+    }
+
+    // Points scored by `user_id`, None while the game is still running
+    pub fn points_for(&self, user_id: usize) -> Option<f64> {
+        if self.is_bye() {
+            return (self.white == user_id).then_some(BYE_POINTS);
+        }
+        let color = if self.white == user_id { "w" } else { "b" };
+        let result: GameResult = self.result.as_deref()?.parse().ok()?;
+        Some(result.score_for(color))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Standing {
+    pub rank: usize,
+    pub user_id: usize,
+    pub username: String,
+    pub rating: f64,
+    pub points: f64,
+    pub buchholz: f64,
+    pub sonneborn_berger: f64,
+    pub games: usize, // finished games and byes
+    pub withdrawn: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tournament {
+    pub id: Uuid,
+    pub name: String,
+    pub created_by: usize, // user id, the only one who can start it
+    #[serde(default)]
+    pub format: Format,
+    pub time_control: Option<TimeControl>,
+    pub rated: bool,
+    pub rounds: usize, // round robins set this on start, arenas have none
+    pub status: TournamentStatus,
+    pub players: Vec<TournamentPlayer>, // registration order
+    // One list per round paired so far. Arenas have no rounds, every batch
+    // of games paired together gets a list.
+    pub pairings: Vec<Vec<Pairing>>,
+    pub created_at: u64, // unix seconds
+    #[serde(default)]
+    pub ends_at: Option<u64>, // arenas only, set on start
+    #[serde(default)]
+    pub bracket: Vec<Vec<Heat>>, // knockouts only, one list per round
+    #[serde(default)]
+    pub adjudication: Option<Rules>, // for every game
+}
+
+impl Tournament {
+    pub fn new(
+        name: String,
+        created_by: usize,
+        format: Format,
+        time_control: Option<TimeControl>,
+        rated: bool,
+        rounds: usize,
+    ) -> Self {
+        Tournament {
+            id: Uuid::new_v4(),
+            name,
+            created_by,
+            format,
+            time_control,
+            rated,
+            rounds,
+            status: TournamentStatus::Registering,
+            players: Vec::new(),
+            pairings: Vec::new(),
+            created_at: unix_now(),
+            ends_at: None,
+            bracket: Vec::new(),
+            adjudication: None,
+        }
+    }
+
+    pub fn current_round(&self) -> usize {
+        match self.format {
+            Format::Knockout { .. } => self.bracket.len(),
+            _ => self.pairings.len(),
+        }
+    }
+
+    pub fn player(&self, user_id: usize) -> Option<&TournamentPlayer> {
+        self.players.iter().find(|p| p.user_id == user_id)
+    }
+
+    fn active_players(&self) -> impl Iterator<Item = &TournamentPlayer> {
+        self.players.iter().filter(|p| !p.withdrawn)
+    }
+
+    // Arenas can be joined until they end, other formats only before the start
+    pub fn join(&mut self, player: TournamentPlayer, guest: bool) -> Result<(), TournamentError> {
+        let open = match self.format {
+            Format::Arena { .. } => self.status != TournamentStatus::Finished,
+            _ => self.status == TournamentStatus::Registering,
+        };
+        if !open {
+            return Err(TournamentError::Invalid(
+                "Registration is closed".to_string(),
+            ));
+        }
+        if guest && self.rated {
+            return Err(TournamentError::Forbidden(
+                "Guests can't play in rated tournaments",
+            ));
+        }
+        match self
+            .players
+            .iter_mut()
+            .find(|p| p.user_id == player.user_id)
+        {
+            // Coming back to an arena after withdrawing
+            Some(existing) if existing.withdrawn => existing.withdrawn = false,
+            Some(_) => return Err(TournamentError::Invalid("Already registered".to_string())),
+            None => self.players.push(player),
+        }
+        Ok(())
+    }
+
+    // Before the start the player is removed, afterwards they're just left
+    // out of the remaining rounds and keep their place in the standings
+    pub fn withdraw(&mut self, user_id: usize) -> Result<(), TournamentError> {
+        match self.status {
+            TournamentStatus::Registering => {
+                let before = self.players.len();
+                self.players.retain(|p| p.user_id != user_id);
+                if self.players.len() == before {
+                    return Err(TournamentError::Invalid("Not registered".to_string()));
+                }
+            }
+            TournamentStatus::Running => {
+                match self.players.iter_mut().find(|p| p.user_id == user_id) {
+                    Some(player) if !player.withdrawn => player.withdrawn = true,
+                    _ => return Err(TournamentError::Invalid("Not registered".to_string())),
+                }
+            }
+            TournamentStatus::Finished => {
+                return Err(TournamentError::Invalid(
+                    "The tournament is over".to_string(),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn start(&mut self, user_id: usize) -> Result<(), TournamentError> {
+        if user_id != self.created_by {
+            return Err(TournamentError::Forbidden(
+                "Only the organiser can start the tournament",
+            ));
+        }
+        if self.status != TournamentStatus::Registering {
+            return Err(TournamentError::Invalid(
+                "The tournament has already started".to_string(),
+            ));
+        }
+        if self.players.len() < 2 {
+            return Err(TournamentError::Invalid(
+                "At least two players are needed".to_string(),
+            ));
+        }
+        match self.format {
+            Format::Swiss => {}
+            Format::RoundRobin { double } => {
+                self.rounds = round_robin::rounds(self.players.len(), double)
+            }
+            Format::Arena { minutes } => self.ends_at = Some(unix_now() + minutes * 60),
+            Format::Knockout { .. } => self.rounds = knockout::rounds(self.players.len()),
+        }
+        self.status = TournamentStatus::Running;
+        Ok(())
+    }
+
+    // Moves the tournament along, this is called whenever a game ends and
+    // once a second. Round based formats pair the next round once the current
+    // one is over, arenas pair everyone who is waiting and online and
+    // knockouts start the next game of every match. New games are added
+    // without a room. Returns whether anything changed.
+    pub fn schedule(&mut self, online: &HashSet<usize>, now: u64) -> bool {
+        if self.status != TournamentStatus::Running {
+            return false;
+        }
+        match self.format {
+            Format::Swiss | Format::RoundRobin { .. } => {
+                if !self.is_round_finished() {
+                    return false;
+                }
+                if self.current_round() >= self.rounds || self.active_players().count() < 2 {
+                    self.status = TournamentStatus::Finished;
+                    return true;
+                }
+                let round = match self.format {
+                    Format::Swiss => swiss::pair_round(self),
+                    _ => Ok(round_robin::pair_round(self)),
+                };
+                match round {
+                    Ok(round) => self.pairings.push(round),
+                    Err(err) => {
+                        println!("Tournament {} ends early: {}", self.id, err);
+                        self.status = TournamentStatus::Finished;
+                    }
+                }
+                true
+            }
+            Format::Arena { .. } => {
+                // Games still running when time is up count, no new ones start
+                if self.ends_at.is_some_and(|ends_at| now >= ends_at) {
+                    let done = self.is_round_finished();
+                    if done {
+                        self.status = TournamentStatus::Finished;
+                    }
+                    return done;
+                }
+                let batch = arena::pair_waiting(self, online);
+                if batch.is_empty() {
+                    return false;
+                }
+                self.pairings.push(batch);
+                true
+            }
+            Format::Knockout { rules } => self.schedule_knockout(&rules),
+        }
+    }
+
+    fn schedule_knockout(&mut self, rules: &MatchRules) -> bool {
+        let mut changed = false;
+        if self.bracket.is_empty() {
+            self.bracket.push(knockout::first_round(self));
+            changed = true;
+        }
+        let withdrawn: HashSet<usize> = self
+            .players
+            .iter()
+            .filter(|p| p.withdrawn)
+            .map(|p| p.user_id)
+            .collect();
+
+        loop {
+            let Some(round) = self.bracket.last_mut() else {
+                return changed;
+            };
+            for heat in round.iter_mut() {
+                let Heat::Match(m) = heat else {
+                    continue;
+                };
+                let before = (m.games.len(), m.winner);
+                // Withdrawing loses the match once the current game is over
+                if !m.is_playing() {
+                    if let Some(i) = m.players.iter().position(|p| withdrawn.contains(p)) {
+                        m.forfeit_to(m.players[1 - i]);
+                    }
+                }
+                m.advance(rules);
+                changed |= (m.games.len(), m.winner) != before;
+            }
+
+            if round.iter().any(|heat| heat.winner().is_none()) {
+                return changed;
+            }
+            if round.len() == 1 {
+                self.status = TournamentStatus::Finished;
+                return true;
+            }
+            let next = knockout::next_round(round);
+            self.bracket.push(next);
+            changed = true;
+        }
+    }
+
+    // Games of the latest round, batch or bracket round that still need a room
+    pub fn unstarted_games(&mut self) -> Vec<&mut Pairing> {
+        let latest: Vec<&mut Pairing> = match self.format {
+            Format::Knockout { .. } => self
+                .bracket
+                .last_mut()
+                .into_iter()
+                .flatten()
+                .filter_map(|heat| match heat {
+                    Heat::Match(m) => m.games.last_mut().map(|game| &mut game.pairing),
+                    Heat::Bye { .. } => None,
+                })
+                .collect(),
+            _ => self.pairings.last_mut().into_iter().flatten().collect(),
+        };
+        latest
+            .into_iter()
+            .filter(|pairing| !pairing.is_bye() && pairing.room_id.is_none())
+            .collect()
+    }
+
+    // User ids of everyone taking part, for live updates
+    pub fn participants(&self) -> impl Iterator<Item = usize> + '_ {
+        self.players.iter().map(|p| p.user_id)
+    }
+
+    // For arenas, whether every game so far has finished
+    pub fn is_round_finished(&self) -> bool {
+        match self.format {
+            Format::Arena { .. } => self.pairings.iter().flatten().all(|p| p.is_finished()),
+            _ => self
+                .pairings
+                .last()
+                .is_none_or(|round| round.iter().all(|pairing| pairing.is_finished())),
+        }
+    }
+
+    // Every game of the tournament, knockout games by bracket round
+    fn all_pairings(&self) -> impl Iterator<Item = &Pairing> {
+        let matches = self.bracket.iter().flatten().filter_map(|heat| match heat {
+            Heat::Match(m) => Some(m),
+            Heat::Bye { .. } => None,
+        });
+        self.pairings
+            .iter()
+            .flatten()
+            .chain(matches.flat_map(|m| m.games.iter().map(|game| &game.pairing)))
+    }
+
+    // Returns false if the room isn't one of this tournament's games
+    pub fn record_result(&mut self, room_id: Uuid, result: GameResult) -> bool {
+        let matches = self
+            .bracket
+            .iter_mut()
+            .flatten()
+            .filter_map(|heat| match heat {
+                Heat::Match(m) => Some(m),
+                Heat::Bye { .. } => None,
+            });
+        let pairing = self
+            .pairings
+            .iter_mut()
+            .flatten()
+            .chain(matches.flat_map(|m| m.games.iter_mut().map(|game| &mut game.pairing)))
+            .find(|pairing| pairing.room_id == Some(room_id));
+        match pairing {
+            Some(pairing) => {
+                pairing.result = Some(result.as_str().to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn games_of(&self, user_id: usize) -> impl Iterator<Item = &Pairing> {
+        self.all_pairings()
+            .filter(move |pairing| pairing.involves(user_id))
+    }
+
+    // Arena points come with streak bonuses, see arena.rs
+    pub fn points(&self, user_id: usize) -> f64 {
+        match self.format {
+            Format::Arena { .. } => arena::points(self, user_id),
+            _ => self
+                .games_of(user_id)
+                .filter_map(|pairing| pairing.points_for(user_id))
+                .sum(),
+        }
+    }
+
+    // Ordered by points, then the format's tiebreaks, then rating. Buchholz
+    // is the sum of the opponents' points and Sonneborn-Berger the points of
+    // beaten opponents plus half the points of drawn ones. Byes add nothing
+    // to either. Swiss breaks ties by Buchholz first, round robins (where
+    // everyone has the same opponents) by Sonneborn-Berger, arenas by neither.
+    // Knockouts rank by how far players got before anything else.
+    pub fn standings(&self) -> Vec<Standing> {
+        let points: HashMap<usize, f64> = self
+            .players
+            .iter()
+            .map(|p| (p.user_id, self.points(p.user_id)))
+            .collect();
+
+        let mut standings: Vec<Standing> = self
+            .players
+            .iter()
+            .map(|player| {
+                let mut buchholz = 0.0;
+                let mut sonneborn_berger = 0.0;
+                let mut games = 0;
+                for pairing in self.games_of(player.user_id) {
+                    let Some(scored) = pairing.points_for(player.user_id) else {
+                        continue;
+                    };
+                    games += 1;
+                    if let Some(opponent) = pairing.opponent_of(player.user_id) {
+                        let opponent_points = points.get(&opponent).copied().unwrap_or(0.0);
+                        buchholz += opponent_points;
+                        sonneborn_berger += scored * opponent_points;
+                    }
+                }
+                Standing {
+                    rank: 0,
+                    user_id: player.user_id,
+                    username: player.username.clone(),
+                    rating: player.rating,
+                    points: points[&player.user_id],
+                    buchholz,
+                    sonneborn_berger,
+                    games,
+                    withdrawn: player.withdrawn,
+                }
+            })
+            .collect();
+
+        let reached = knockout::rounds_reached(&self.bracket);
+        let reached = |user_id: usize| reached.get(&user_id).copied().unwrap_or(0);
+        let format = self.format;
+        standings.sort_by(|a, b| {
+            let tiebreaks = match format {
+                Format::Swiss => b
+                    .buchholz
+                    .total_cmp(&a.buchholz)
+                    .then(b.sonneborn_berger.total_cmp(&a.sonneborn_berger)),
+                Format::RoundRobin { .. } => b.sonneborn_berger.total_cmp(&a.sonneborn_berger),
+                Format::Arena { .. } | Format::Knockout { .. } => std::cmp::Ordering::Equal,
+            };
+            reached(b.user_id)
+                .cmp(&reached(a.user_id))
+                .then(b.points.total_cmp(&a.points))
+                .then(tiebreaks)
+                .then(b.rating.total_cmp(&a.rating))
+        });
+        for (i, standing) in standings.iter_mut().enumerate() {
+            standing.rank = i + 1;
+        }
+        standings
+    }
+
+    pub fn standings_csv(&self) -> String {
+        let mut csv =
+            String::from("rank,username,rating,points,buchholz,sonneborn_berger,games,withdrawn\n");
+        for s in self.standings() {
+            // Usernames are limited to letters, digits, '-' and '_', no quoting needed
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{}",
+                s.rank,
+                s.username,
+                s.rating.round(),
+                s.points,
+                s.buchholz,
+                s.sonneborn_berger,
+                s.games,
+                s.withdrawn
+            );
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A started tournament of `players` players with user ids from 1, best
+    // rated first
+    pub(super) fn tournament(format: Format, rounds: usize, players: usize) -> Tournament {
+        let mut tournament = Tournament::new("Test".to_string(), 1, format, None, false, rounds);
+        for user_id in 1..=players {
+            let player = TournamentPlayer {
+                user_id,
+                username: format!("player{}", user_id),
+                rating: 2000.0 - 10.0 * user_id as f64,
+                withdrawn: false,
+            };
+            tournament.join(player, false).unwrap();
+        }
+        tournament.start(1).unwrap();
+        tournament
+    }
+}
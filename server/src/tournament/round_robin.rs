@@ -0,0 +1,130 @@
+// src/tournament/round_robin.rs
+
+// Round robin pairings from the Berger tables (FIDE C.05 annex 1). Players
+// are numbered by rating, with an odd field the highest number is a dummy
+// and whoever is drawn against it sits the round out. A double round robin
+// plays the table twice, the second time with colors reversed.
+
+use super::{Pairing, Tournament};
+
+pub fn rounds(players: usize, double: bool) -> usize {
+    let per_cycle = (players + players % 2).saturating_sub(1);
+    if double {
+        per_cycle * 2
+    } else {
+        per_cycle
+    }
+}
+
+// Pairs for round `round` (0-based) of a Berger table for `n` players,
+// numbered from 1, white first. `n` must be even.
+fn berger_round(n: usize, round: usize) -> Vec<(usize, usize)> {
+    let rotating = n - 1;
+    let wrap = |x: isize| (x - 1).rem_euclid(rotating as isize) as usize + 1;
+    let first = wrap((round * n / 2) as isize + 1);
+
+    // Player n stays put and alternates colors with the first board
+    let mut pairs = vec![if round.is_multiple_of(2) {
+        (first, n)
+    } else {
+        (n, first)
+    }];
+    for board in 1..n / 2 {
+        let white = wrap((first + board) as isize);
+        let black = wrap(first as isize - board as isize);
+        pairs.push((white, black));
+    }
+    pairs
+}
+
+pub fn pair_round(tournament: &Tournament) -> Vec<Pairing> {
+    // Seeded by rating, ties in registration order. Ratings are fixed at
+    // registration so the numbering stays the same every round.
+    let mut seeds: Vec<_> = tournament.players.iter().collect();
+    seeds.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+    let n = seeds.len() + seeds.len() % 2;
+
+    let round = tournament.current_round();
+    let per_cycle = n - 1;
+    let reversed = (round / per_cycle) % 2 == 1;
+
+    berger_round(n, round % per_cycle)
+        .into_iter()
+        .filter_map(|(white, black)| {
+            let white = seeds.get(white - 1)?;
+            let black = seeds.get(black - 1)?;
+            // Withdrawn players' games aren't played
+            if white.withdrawn || black.withdrawn {
+                return None;
+            }
+            let (white, black) = if reversed {
+                (black, white)
+            } else {
+                (white, black)
+            };
+            Some(Pairing::new(white.user_id, Some(black.user_id)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tournament::tests::tournament;
+    use crate::tournament::{Format, TournamentStatus};
+    use std::collections::HashSet;
+
+    #[test]
+    fn follows_the_published_berger_table() {
+        // FIDE C.05 annex 1, six players
+        let table = [
+            [(1, 6), (2, 5), (3, 4)],
+            [(6, 4), (5, 3), (1, 2)],
+            [(2, 6), (3, 1), (4, 5)],
+            [(6, 5), (1, 4), (2, 3)],
+            [(3, 6), (4, 2), (5, 1)],
+        ];
+        assert_eq!(rounds(6, false), table.len());
+        for (round, expected) in table.iter().enumerate() {
+            let mut pairs = berger_round(6, round);
+            let mut expected = expected.to_vec();
+            pairs.sort();
+            expected.sort();
+            assert_eq!(pairs, expected, "round {}", round + 1);
+        }
+    }
+
+    #[test]
+    fn everyone_meets_everyone_once_per_cycle() {
+        let players = 5;
+        let mut tournament = tournament(Format::RoundRobin { double: true }, 0, players);
+        assert_eq!(tournament.rounds, 10);
+        let online = HashSet::new();
+        while tournament.schedule(&online, 0) {
+            let Some(round) = tournament.pairings.last_mut() else {
+                break;
+            };
+            // With an odd field one player sits out every round
+            assert_eq!(round.len(), 2);
+            for pairing in round.iter_mut() {
+                pairing.result = Some("1/2-1/2".to_string());
+            }
+        }
+        assert_eq!(tournament.status, TournamentStatus::Finished);
+
+        let games: Vec<(usize, usize)> = tournament
+            .pairings
+            .iter()
+            .flatten()
+            .map(|pairing| (pairing.white, pairing.black.unwrap()))
+            .collect();
+        for white in 1..=players {
+            for black in 1..=players {
+                let count = games.iter().filter(|game| **game == (white, black)).count();
+                // Each color once over the two cycles
+                let expected = if white == black { 0 } else { 1 };
+                assert_eq!(count, expected, "{} against {}", white, black);
+            }
+        }
+    }
+}
@@ -0,0 +1,403 @@
+// src/tournament/swiss.rs
+
+// Swiss pairings after the Dutch system (FIDE C.04.3), somewhat simplified.
+// Players are ranked by points and rating and split into score groups. Each
+// group is paired top half against bottom half, and players that can't be
+// paired in their group float down to the next one. Nobody meets the same
+// opponent twice and the bye goes to the lowest ranked player who hasn't had
+// one yet.
+
+use std::collections::HashSet;
+
+use super::{Pairing, Tournament};
+
+// Upper bound on search steps per attempt, a group without any legal
+// pairing would otherwise take exponential time to rule out
+const SEARCH_LIMIT: usize = 20_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Strength {
+    Mild,
+    Strong,
+    Absolute,
+}
+
+struct Entrant {
+    user_id: usize,
+    points: f64,
+    rating: f64,
+    colors: Vec<Option<char>>, // 'w' or 'b' by round, None without a game
+    opponents: HashSet<usize>,
+    had_bye: bool,
+}
+
+impl Entrant {
+    // The color this player should get next and how much it matters
+    fn preference(&self) -> Option<(char, Strength)> {
+        let played: Vec<char> = self.colors.iter().flatten().copied().collect();
+        let last = *played.last()?;
+        let whites = played.iter().filter(|c| **c == 'w').count() as i32;
+        let difference = whites * 2 - played.len() as i32;
+        let last_two_same = played.len() >= 2 && played[played.len() - 2] == last;
+
+        if difference <= -2 || (last_two_same && last == 'b') {
+            return Some(('w', Strength::Absolute));
+        }
+        if difference >= 2 || (last_two_same && last == 'w') {
+            return Some(('b', Strength::Absolute));
+        }
+        Some(match difference {
+            -1 => ('w', Strength::Strong),
+            1 => ('b', Strength::Strong),
+            _ => (if last == 'w' { 'b' } else { 'w' }, Strength::Mild),
+        })
+    }
+}
+
+fn entrants(tournament: &Tournament) -> Vec<Entrant> {
+    let mut entrants: Vec<Entrant> = tournament
+        .players
+        .iter()
+        .filter(|player| !player.withdrawn)
+        .map(|player| {
+            let mut entrant = Entrant {
+                user_id: player.user_id,
+                points: tournament.points(player.user_id),
+                rating: player.rating,
+                colors: Vec::new(),
+                opponents: HashSet::new(),
+                had_bye: false,
+            };
+            for round in &tournament.pairings {
+                let mut color = None;
+                if let Some(pairing) = round.iter().find(|p| p.involves(player.user_id)) {
+                    match pairing.opponent_of(player.user_id) {
+                        Some(opponent) => {
+                            entrant.opponents.insert(opponent);
+                            color = Some(if pairing.white == player.user_id {
+                                'w'
+                            } else {
+                                'b'
+                            });
+                        }
+                        None => entrant.had_bye = true,
+                    }
+                }
+                entrant.colors.push(color);
+            }
+            entrant
+        })
+        .collect();
+
+    entrants.sort_by(|a, b| {
+        b.points
+            .total_cmp(&a.points)
+            .then(b.rating.total_cmp(&a.rating))
+            .then(a.user_id.cmp(&b.user_id))
+    });
+    entrants
+}
+
+struct Search<'a> {
+    entrants: &'a [Entrant],
+    strict_colors: bool, // two players who must both have the same color can't meet
+    steps: usize,
+}
+
+impl Search<'_> {
+    fn compatible(&self, a: usize, b: usize) -> bool {
+        let (a, b) = (&self.entrants[a], &self.entrants[b]);
+        if a.opponents.contains(&b.user_id) {
+            return false;
+        }
+        match (a.preference(), b.preference()) {
+            (Some((x, Strength::Absolute)), Some((y, Strength::Absolute)))
+                if self.strict_colors =>
+            {
+                x != y
+            }
+            _ => true,
+        }
+    }
+
+    // Pairs everyone in `bracket`, best ranked first. The top player is tried
+    // against the bottom half before the top half, which gives the usual
+    // S1 against S2 pairing when nothing gets in the way.
+    fn pair_all(&mut self, bracket: &[usize]) -> Option<Vec<(usize, usize)>> {
+        let Some((&first, rest)) = bracket.split_first() else {
+            return Some(Vec::new());
+        };
+        self.steps += 1;
+        if self.steps > SEARCH_LIMIT {
+            return None;
+        }
+
+        let half = bracket.len() / 2;
+        let order = (half.saturating_sub(1)..rest.len()).chain(0..half.saturating_sub(1));
+        for j in order {
+            let opponent = rest[j];
+            if !self.compatible(first, opponent) {
+                continue;
+            }
+            let remaining: Vec<usize> = rest
+                .iter()
+                .enumerate()
+                .filter(|(k, _)| *k != j)
+                .map(|(_, i)| *i)
+                .collect();
+            if let Some(mut pairs) = self.pair_all(&remaining) {
+                pairs.insert(0, (first, opponent));
+                return Some(pairs);
+            }
+        }
+        None
+    }
+
+    // Pairs the score groups from the top down. `carry` holds the players
+    // floating down from the group above, they rank first in the next one.
+    fn pair_groups(
+        &mut self,
+        groups: &[Vec<usize>],
+        carry: Vec<usize>,
+    ) -> Option<Vec<(usize, usize)>> {
+        let Some((group, lower)) = groups.split_first() else {
+            return carry.is_empty().then(Vec::new);
+        };
+        let mut bracket = carry;
+        bracket.extend(group);
+
+        // Float as few players as possible, lowest ranked first
+        let mut floaters = bracket.len() % 2;
+        while floaters <= bracket.len() && (floaters == 0 || !lower.is_empty()) {
+            self.steps += 1;
+            if self.steps > SEARCH_LIMIT {
+                return None;
+            }
+            let (paired, floating) = bracket.split_at(bracket.len() - floaters);
+            if let Some(mut pairs) = self.pair_all(paired) {
+                if let Some(rest) = self.pair_groups(lower, floating.to_vec()) {
+                    pairs.extend(rest);
+                    return Some(pairs);
+                }
+            }
+            floaters += 2;
+        }
+        None
+    }
+}
+
+// Indices into `entrants` split by points, leaving out the bye
+fn score_groups(entrants: &[Entrant], bye: Option<usize>) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut last_points = None;
+    for (i, entrant) in entrants.iter().enumerate() {
+        if Some(i) == bye {
+            continue;
+        }
+        match groups.last_mut() {
+            Some(group) if last_points == Some(entrant.points) => group.push(i),
+            _ => groups.push(vec![i]),
+        }
+        last_points = Some(entrant.points);
+    }
+    groups
+}
+
+// Whether `a`, the higher ranked player, gets white. Both preferences are
+// met when they differ, otherwise the stronger one wins. Between equally
+// strong preferences the colors are swapped from the last round in which
+// the two had different colors, and failing that the higher ranked player
+// gets their way. `board` is 0-based and alternates colors in round one.
+fn a_gets_white(a: &Entrant, b: &Entrant, board: usize) -> bool {
+    match (a.preference(), b.preference()) {
+        (Some((x, _)), Some((y, _))) if x != y => x == 'w',
+        (Some((x, a_strength)), Some((_, b_strength))) => {
+            if a_strength != b_strength {
+                return (a_strength > b_strength) == (x == 'w');
+            }
+            // Both have a color for every round, byes are gaps
+            let last_different =
+                a.colors
+                    .iter()
+                    .zip(&b.colors)
+                    .rev()
+                    .find_map(|colors| match colors {
+                        (Some(a_color), Some(b_color)) if a_color != b_color => Some(*a_color),
+                        _ => None,
+                    });
+            match last_different {
+                Some(a_color) => a_color == 'b',
+                None => x == 'w',
+            }
+        }
+        (Some((x, _)), None) => x == 'w',
+        (None, Some((y, _))) => y == 'b',
+        (None, None) => board.is_multiple_of(2),
+    }
+}
+
+pub fn pair_round(tournament: &Tournament) -> Result<Vec<Pairing>, String> {
+    let entrants = entrants(tournament);
+    if entrants.len() < 2 {
+        return Err("Not enough players to pair".to_string());
+    }
+
+    // Lowest ranked first, players who already had a bye only as a last resort
+    let bye_candidates: Vec<Option<usize>> = if entrants.len() % 2 == 1 {
+        let mut candidates: Vec<usize> = (0..entrants.len()).rev().collect();
+        candidates.sort_by_key(|i| entrants[*i].had_bye);
+        candidates.into_iter().map(Some).collect()
+    } else {
+        vec![None]
+    };
+
+    // Color rules are relaxed before the score groups are given up on
+    for (strict_colors, by_score) in [(true, true), (false, true), (false, false)] {
+        for bye in &bye_candidates {
+            let groups = if by_score {
+                score_groups(&entrants, *bye)
+            } else {
+                vec![(0..entrants.len()).filter(|i| Some(*i) != *bye).collect()]
+            };
+            let mut search = Search {
+                entrants: &entrants,
+                strict_colors,
+                steps: 0,
+            };
+            if let Some(pairs) = search.pair_groups(&groups, Vec::new()) {
+                return Ok(build_round(&entrants, pairs, *bye));
+            }
+        }
+    }
+    Err("Every remaining pairing would be a repeat".to_string())
+}
+
+fn build_round(
+    entrants: &[Entrant],
+    mut pairs: Vec<(usize, usize)>,
+    bye: Option<usize>,
+) -> Vec<Pairing> {
+    // Boards are ordered by the best ranked player on them
+    pairs
+        .iter_mut()
+        .for_each(|pair| *pair = (pair.0.min(pair.1), pair.0.max(pair.1)));
+    pairs.sort();
+
+    let mut round: Vec<Pairing> = pairs
+        .into_iter()
+        .enumerate()
+        .map(|(board, (a, b))| {
+            let (a, b) = (&entrants[a], &entrants[b]);
+            let (white, black) = if a_gets_white(a, b, board) {
+                (a, b)
+            } else {
+                (b, a)
+            };
+            Pairing::new(white.user_id, Some(black.user_id))
+        })
+        .collect();
+
+    if let Some(i) = bye {
+        round.push(Pairing::new(entrants[i].user_id, None));
+    }
+    round
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tournament::tests::tournament;
+    use crate::tournament::{Format, TournamentStatus};
+
+    // `colors` has a 'w' or 'b' for every round and '-' for a bye
+    fn entrant(user_id: usize, colors: &str) -> Entrant {
+        Entrant {
+            user_id,
+            points: 0.0,
+            rating: 1500.0,
+            colors: colors.chars().map(|c| (c != '-').then_some(c)).collect(),
+            opponents: HashSet::new(),
+            had_bye: colors.contains('-'),
+        }
+    }
+
+    #[test]
+    fn swaps_colors_of_the_last_round_that_differed() {
+        // Both want white, A had it in round two when B had black
+        let a = entrant(1, "-wbb");
+        let b = entrant(2, "wb-b");
+        assert_eq!(a.preference(), Some(('w', Strength::Absolute)));
+        assert_eq!(b.preference(), Some(('w', Strength::Absolute)));
+        assert!(!a_gets_white(&a, &b, 0));
+        assert!(a_gets_white(&b, &a, 0));
+    }
+
+    #[test]
+    fn pairs_a_small_field_fairly() {
+        for players in [7, 8] {
+            pairs_fairly(players);
+        }
+    }
+
+    fn pairs_fairly(players: usize) {
+        let mut tournament = tournament(Format::Swiss, 5, players);
+        let online = HashSet::new();
+        while tournament.schedule(&online, 0) {
+            let Some(round) = tournament.pairings.last_mut() else {
+                break;
+            };
+            // The better rated player wins, every third board is drawn
+            for (board, pairing) in round.iter_mut().enumerate() {
+                if let Some(black) = pairing.black {
+                    let result = if board % 3 == 2 {
+                        "1/2-1/2"
+                    } else if pairing.white < black {
+                        "1-0"
+                    } else {
+                        "0-1"
+                    };
+                    pairing.result = Some(result.to_string());
+                }
+            }
+        }
+        assert_eq!(tournament.status, TournamentStatus::Finished);
+        assert_eq!(tournament.pairings.len(), 5);
+
+        for user_id in 1..=players {
+            let games: Vec<&Pairing> = tournament
+                .pairings
+                .iter()
+                .flatten()
+                .filter(|pairing| pairing.involves(user_id))
+                .collect();
+            assert_eq!(games.len(), 5, "player {} missed a round", user_id);
+
+            let byes = games.iter().filter(|pairing| pairing.is_bye()).count();
+            assert!(byes <= 1, "player {} had {} byes", user_id, byes);
+
+            let opponents: Vec<usize> = games
+                .iter()
+                .filter_map(|pairing| pairing.opponent_of(user_id))
+                .collect();
+            let unique: HashSet<&usize> = opponents.iter().collect();
+            assert_eq!(
+                unique.len(),
+                opponents.len(),
+                "player {} met someone twice",
+                user_id
+            );
+
+            let whites = games
+                .iter()
+                .filter(|pairing| !pairing.is_bye() && pairing.white == user_id)
+                .count() as i32;
+            let difference = 2 * whites - opponents.len() as i32;
+            assert!(
+                difference.abs() <= 2,
+                "player {} has colors off by {}",
+                user_id,
+                difference
+            );
+        }
+    }
+}
@@ -6,25 +6,34 @@ use std::time::{Duration, Instant};
 use uuid::Uuid;
 
 use crate::bitboard::MoveData;
+use crate::clock::TimeControl;
 use crate::messages::{
-    ClientMessage, ClientMove, CreateRoom, ErrorMessage, JoinQueue, JoinRoom, RoomJoined,
-    UpdateClient,
+    AcceptSeek, AnalysisDone, AnalysisLine, Analyze, BookMoves, CancelSeek, ChallengeAccept,
+    ChallengeCancel, ChallengeClosed, ChallengeDecline, ChallengeReceived, ChallengeSent,
+    ClientMessage, ClientMove, CreateRoom, CreateSeek, ErrorMessage, GameOver, GetBookMoves,
+    JoinQueue, JoinRoom, LeaveQueue, LobbyUpdate, QueueLeft, QueueStatusUpdate, RematchAccept,
+    RematchDecline, RematchDeclined, RematchOffer, RematchOffered, Resign, RoomJoined,
+    SendChallenge, StopAnalysis, TournamentStandings, UpdateClient,
 };
 use crate::server::Server;
 
 pub struct MyWebSocket {
     pub hb: Instant,
     pub id: usize,
+    pub user_id: usize,
+    pub guest: bool,
     pub server_addr: Addr<Server>,
     pub room_id: Option<Uuid>,
     pub color: Option<String>,
 }
 
 impl MyWebSocket {
-    pub fn new(server_addr: Addr<Server>) -> Self {
+    pub fn new(server_addr: Addr<Server>, user_id: usize, guest: bool) -> Self {
         Self {
             hb: Instant::now(),
             id: 0,
+            user_id,
+            guest,
             server_addr,
             room_id: None,
             color: None,
@@ -35,6 +44,35 @@ impl MyWebSocket {
         ctx.text(msg.to_string());
     }
 
+    fn send_not_in_room(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        let response = serde_json::json!({
+            "type": "error",
+            "data": "You are not in a room",
+        });
+        self.send_message(ctx, response);
+    }
+
+    // Reads `challenge_id` from the message data, answering with an error if
+    // it's missing or malformed
+    fn challenge_id(
+        &self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        data: &serde_json::Value,
+    ) -> Option<Uuid> {
+        let challenge_id = data
+            .get("challenge_id")
+            .and_then(|v| v.as_str())
+            .and_then(|s| Uuid::parse_str(s).ok());
+        if challenge_id.is_none() {
+            let response = serde_json::json!({
+                "type": "error",
+                "data": "Invalid challenge ID format",
+            });
+            self.send_message(ctx, response);
+        }
+        challenge_id
+    }
+
     fn start_heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
         ctx.run_interval(Duration::new(5, 0), |act, ctx| {
             if Instant::now().duration_since(act.hb) > Duration::new(10, 0) {
@@ -55,7 +93,11 @@ impl Actor for MyWebSocket {
         // Register with the server
         let addr = ctx.address();
         self.server_addr
-            .send(crate::messages::Connect { addr: addr.clone() })
+            .send(crate::messages::Connect {
+                addr: addr.clone(),
+                user_id: self.user_id,
+                guest: self.guest,
+            })
             .into_actor(self)
             .then(|res, act, ctx| {
                 match res {
@@ -95,7 +137,46 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWebSocket {
                     Ok(client_msg) => {
                         match client_msg.r#type.as_str() {
                             "create_room" => {
-                                self.server_addr.do_send(CreateRoom { id: self.id });
+                                let time_control = client_msg
+                                    .data
+                                    .get("time_control")
+                                    .and_then(|v| {
+                                        serde_json::from_value::<TimeControl>(v.clone()).ok()
+                                    });
+                                let color = client_msg
+                                    .data
+                                    .get("color")
+                                    .and_then(|v| v.as_str())
+                                    .map(|s| s.to_string());
+                                let opponent = client_msg
+                                    .data
+                                    .get("opponent")
+                                    .and_then(|v| v.as_str())
+                                    .map(|s| s.to_string());
+                                let level = client_msg
+                                    .data
+                                    .get("level")
+                                    .and_then(|v| v.as_u64())
+                                    .map(|level| level.min(u8::MAX as u64) as u8);
+                                let engine = client_msg
+                                    .data
+                                    .get("engine")
+                                    .and_then(|v| v.as_str())
+                                    .map(|s| s.to_string());
+                                let adjudication = client_msg
+                                    .data
+                                    .get("adjudication")
+                                    .and_then(|v| v.as_str())
+                                    .map(|s| s.to_string());
+                                self.server_addr.do_send(CreateRoom {
+                                    id: self.id,
+                                    time_control,
+                                    color,
+                                    opponent,
+                                    level,
+                                    engine,
+                                    adjudication,
+                                });
                             }
                             "join_room" => {
                                 if let Some(room_id_str) = client_msg
@@ -118,7 +199,141 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWebSocket {
                                 }
                             }
                             "join_queue" => {
-                                self.server_addr.do_send(JoinQueue { id: self.id });
+                                let data = &client_msg.data;
+                                let time_control = data.get("time_control").and_then(|v| {
+                                    serde_json::from_value::<TimeControl>(v.clone()).ok()
+                                });
+                                let rated =
+                                    data.get("rated").and_then(|v| v.as_bool()).unwrap_or(false);
+                                let variant = data
+                                    .get("variant")
+                                    .and_then(|v| v.as_str())
+                                    .unwrap_or("standard")
+                                    .to_string();
+                                let rating_range = data.get("rating_range").and_then(|v| {
+                                    serde_json::from_value::<(f64, f64)>(v.clone()).ok()
+                                });
+                                self.server_addr.do_send(JoinQueue {
+                                    id: self.id,
+                                    time_control,
+                                    rated,
+                                    variant,
+                                    rating_range,
+                                });
+                            }
+                            "leave_queue" => {
+                                self.server_addr.do_send(LeaveQueue { id: self.id });
+                            }
+                            "seek_create" => {
+                                let data = &client_msg.data;
+                                let time_control = data.get("time_control").and_then(|v| {
+                                    serde_json::from_value::<TimeControl>(v.clone()).ok()
+                                });
+                                self.server_addr.do_send(CreateSeek {
+                                    id: self.id,
+                                    time_control,
+                                    variant: data
+                                        .get("variant")
+                                        .and_then(|v| v.as_str())
+                                        .unwrap_or("standard")
+                                        .to_string(),
+                                    rated: data
+                                        .get("rated")
+                                        .and_then(|v| v.as_bool())
+                                        .unwrap_or(false),
+                                    color: data
+                                        .get("color")
+                                        .and_then(|v| v.as_str())
+                                        .map(|s| s.to_string()),
+                                });
+                            }
+                            "seek_cancel" | "seek_accept" => {
+                                let seek_id = client_msg
+                                    .data
+                                    .get("seek_id")
+                                    .and_then(|v| v.as_str())
+                                    .and_then(|s| Uuid::parse_str(s).ok());
+                                match seek_id {
+                                    Some(seek_id) if client_msg.r#type == "seek_cancel" => {
+                                        self.server_addr.do_send(CancelSeek {
+                                            id: self.id,
+                                            seek_id,
+                                        });
+                                    }
+                                    Some(seek_id) => {
+                                        self.server_addr.do_send(AcceptSeek {
+                                            id: self.id,
+                                            seek_id,
+                                        });
+                                    }
+                                    None => {
+                                        let response = serde_json::json!({
+                                            "type": "error",
+                                            "data": "Invalid seek ID format",
+                                        });
+                                        self.send_message(ctx, response);
+                                    }
+                                }
+                            }
+                            "challenge" => {
+                                let data = &client_msg.data;
+                                let username = data.get("username").and_then(|v| v.as_str());
+                                if let Some(username) = username {
+                                    let time_control = data.get("time_control").and_then(|v| {
+                                        serde_json::from_value::<TimeControl>(v.clone()).ok()
+                                    });
+                                    self.server_addr.do_send(SendChallenge {
+                                        id: self.id,
+                                        username: username.to_string(),
+                                        time_control,
+                                        color: data
+                                            .get("color")
+                                            .and_then(|v| v.as_str())
+                                            .map(|s| s.to_string()),
+                                        variant: data
+                                            .get("variant")
+                                            .and_then(|v| v.as_str())
+                                            .unwrap_or("standard")
+                                            .to_string(),
+                                        rated: data
+                                            .get("rated")
+                                            .and_then(|v| v.as_bool())
+                                            .unwrap_or(false),
+                                    });
+                                } else {
+                                    let response = serde_json::json!({
+                                        "type": "error",
+                                        "data": "Missing username",
+                                    });
+                                    self.send_message(ctx, response);
+                                }
+                            }
+                            "challenge_accept" => {
+                                if let Some(challenge_id) = self.challenge_id(ctx, &client_msg.data)
+                                {
+                                    self.server_addr.do_send(ChallengeAccept {
+                                        id: self.id,
+                                        challenge_id,
+                                    });
+                                }
+                            }
+                            "challenge_decline" => {
+                                if let Some(challenge_id) = self.challenge_id(ctx, &client_msg.data)
+                                {
+                                    self.server_addr.do_send(ChallengeDecline {
+                                        id: self.id,
+                                        challenge_id,
+                                    });
+                                }
+                            }
+                            "challenge_cancel" => {
+                                if let Some(challenge_id) = self.challenge_id(ctx, &client_msg.data)
+                                {
+                                    self.server_addr.do_send(ChallengeCancel {
+                                        id: self.id,
+                                        challenge_id,
+                                    });
+                                }
                             }
                             "move" => {
                                 if let Some(room_id) = self.room_id {
@@ -142,13 +357,116 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWebSocket {
                                         }
                                     }
                                 } else {
-                                    let response = serde_json::json!({
-                                        "type": "error",
-                                        "data": "You are not in a room",
+                                    self.send_not_in_room(ctx);
+                                }
+                            }
+                            "resign" => {
+                                if let Some(room_id) = self.room_id {
+                                    self.server_addr.do_send(Resign {
+                                        id: self.id,
+                                        room_id,
                                     });
-                                    self.send_message(ctx, response);
+                                } else {
+                                    self.send_not_in_room(ctx);
+                                }
+                            }
+                            "rematch_offer" => {
+                                if let Some(room_id) = self.room_id {
+                                    self.server_addr.do_send(RematchOffer {
+                                        id: self.id,
+                                        room_id,
+                                    });
+                                } else {
+                                    self.send_not_in_room(ctx);
                                 }
                             }
+                            "rematch_accept" => {
+                                if let Some(room_id) = self.room_id {
+                                    self.server_addr.do_send(RematchAccept {
+                                        id: self.id,
+                                        room_id,
+                                    });
+                                } else {
+                                    self.send_not_in_room(ctx);
+                                }
+                            }
+                            "rematch_decline" => {
+                                if let Some(room_id) = self.room_id {
+                                    self.server_addr.do_send(RematchDecline {
+                                        id: self.id,
+                                        room_id,
+                                    });
+                                } else {
+                                    self.send_not_in_room(ctx);
+                                }
+                            }
+                            "analyze" => {
+                                let data = &client_msg.data;
+                                let room_id = match data.get("room_id").and_then(|v| v.as_str())
+                                {
+                                    Some(s) => match Uuid::parse_str(s) {
+                                        Ok(room_id) => Some(room_id),
+                                        Err(_) => {
+                                            let response = serde_json::json!({
+                                                "type": "error",
+                                                "data": "Invalid room ID format",
+                                            });
+                                            self.send_message(ctx, response);
+                                            return;
+                                        }
+                                    },
+                                    None => None,
+                                };
+                                self.server_addr.do_send(Analyze {
+                                    id: self.id,
+                                    fen: data
+                                        .get("fen")
+                                        .and_then(|v| v.as_str())
+                                        .map(|s| s.to_string()),
+                                    room_id,
+                                    depth: data
+                                        .get("depth")
+                                        .and_then(|v| v.as_u64())
+                                        .map(|depth| depth.min(i32::MAX as u64) as i32),
+                                    multipv: data
+                                        .get("multipv")
+                                        .and_then(|v| v.as_u64())
+                                        .map(|multipv| multipv as usize),
+                                    engine: data
+                                        .get("engine")
+                                        .and_then(|v| v.as_str())
+                                        .map(|s| s.to_string()),
+                                });
+                            }
+                            "stop_analysis" => {
+                                self.server_addr.do_send(StopAnalysis { id: self.id });
+                            }
+                            "book_moves" => {
+                                let data = &client_msg.data;
+                                let room_id = match data.get("room_id").and_then(|v| v.as_str())
+                                {
+                                    Some(s) => match Uuid::parse_str(s) {
+                                        Ok(room_id) => Some(room_id),
+                                        Err(_) => {
+                                            let response = serde_json::json!({
+                                                "type": "error",
+                                                "data": "Invalid room ID format",
+                                            });
+                                            self.send_message(ctx, response);
+                                            return;
+                                        }
+                                    },
+                                    None => None,
+                                };
+                                self.server_addr.do_send(GetBookMoves {
+                                    id: self.id,
+                                    fen: data
+                                        .get("fen")
+                                        .and_then(|v| v.as_str())
+                                        .map(|s| s.to_string()),
+                                    room_id,
+                                });
+                            }
                             _ => {}
                         }
                     }
@@ -205,6 +523,249 @@ impl Handler<UpdateClient> for MyWebSocket {
             "data": {
                 "pieces": msg.pieces,
                 "turn": msg.turn,
+                "white_time": msg.white_time,
+                "black_time": msg.black_time,
+                "eco": msg.opening.map(|opening| opening.eco),
+                "opening": msg.opening.map(|opening| opening.name),
+            }
+        });
+        self.send_message(ctx, response);
+    }
+}
+
+impl Handler<GameOver> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: GameOver, ctx: &mut Self::Context) {
+        let response = serde_json::json!({
+            "type": "game_over",
+            "data": {
+                "room_id": msg.room_id.to_string(),
+                "result": msg.result,
+                "termination": msg.termination,
+                "rating_changes": msg
+                    .rating_changes
+                    .iter()
+                    .map(|change| {
+                        let before = change.before.rating.round();
+                        let after = change.after.rating.round();
+                        serde_json::json!({
+                            "user_id": change.user_id,
+                            "color": change.color,
+                            "speed": change.speed,
+                            "rating": after,
+                            "deviation": change.after.deviation.round(),
+                            "change": after - before,
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            }
+        });
+        self.send_message(ctx, response);
+    }
+}
+
+impl Handler<RematchOffered> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: RematchOffered, ctx: &mut Self::Context) {
+        let response = serde_json::json!({
+            "type": "rematch_offer",
+            "data": {
+                "room_id": msg.room_id.to_string(),
+            }
+        });
+        self.send_message(ctx, response);
+    }
+}
+
+impl Handler<RematchDeclined> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: RematchDeclined, ctx: &mut Self::Context) {
+        let response = serde_json::json!({
+            "type": "rematch_declined",
+            "data": {
+                "room_id": msg.room_id.to_string(),
+            }
+        });
+        self.send_message(ctx, response);
+    }
+}
+
+impl Handler<ChallengeReceived> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChallengeReceived, ctx: &mut Self::Context) {
+        let response = serde_json::json!({
+            "type": "challenge",
+            "data": {
+                "challenge_id": msg.challenge_id.to_string(),
+                "from": msg.from,
+                "time_control": msg.time_control,
+                "color": msg.color,
+                "variant": msg.variant,
+                "rated": msg.rated,
+                "expires_in": msg.expires_in,
+            }
+        });
+        self.send_message(ctx, response);
+    }
+}
+
+impl Handler<ChallengeSent> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChallengeSent, ctx: &mut Self::Context) {
+        let response = serde_json::json!({
+            "type": "challenge_sent",
+            "data": {
+                "challenge_id": msg.challenge_id.to_string(),
+                "to": msg.to,
+                "expires_in": msg.expires_in,
+            }
+        });
+        self.send_message(ctx, response);
+    }
+}
+
+impl Handler<ChallengeClosed> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChallengeClosed, ctx: &mut Self::Context) {
+        let response = serde_json::json!({
+            "type": "challenge_closed",
+            "data": {
+                "challenge_id": msg.challenge_id.to_string(),
+                "reason": msg.reason,
+            }
+        });
+        self.send_message(ctx, response);
+    }
+}
+
+impl Handler<LobbyUpdate> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: LobbyUpdate, ctx: &mut Self::Context) {
+        let data = match msg {
+            LobbyUpdate::Snapshot(seeks) => serde_json::json!({
+                "action": "snapshot",
+                "seeks": seeks,
+            }),
+            LobbyUpdate::Added(seek) => serde_json::json!({
+                "action": "added",
+                "seek": seek,
+            }),
+            LobbyUpdate::Removed { seek_id, reason } => serde_json::json!({
+                "action": "removed",
+                "seek_id": seek_id.to_string(),
+                "reason": reason,
+            }),
+        };
+        let response = serde_json::json!({
+            "type": "lobby_update",
+            "data": data,
+        });
+        self.send_message(ctx, response);
+    }
+}
+
+impl Handler<TournamentStandings> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: TournamentStandings, ctx: &mut Self::Context) {
+        let response = serde_json::json!({
+            "type": "tournament_standings",
+            "data": {
+                "tournament_id": msg.tournament_id.to_string(),
+                "status": msg.status,
+                "round": msg.round,
+                "standings": msg.standings,
+            }
+        });
+        self.send_message(ctx, response);
+    }
+}
+
+impl Handler<QueueStatusUpdate> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: QueueStatusUpdate, ctx: &mut Self::Context) {
+        let response = serde_json::json!({
+            "type": "queue_status",
+            "data": {
+                "position": msg.position,
+                "pool_size": msg.pool_size,
+                "estimated_wait": msg.estimated_wait,
+            }
+        });
+        self.send_message(ctx, response);
+    }
+}
+
+impl Handler<QueueLeft> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, _: QueueLeft, ctx: &mut Self::Context) {
+        let response = serde_json::json!({
+            "type": "queue_left",
+            "data": {},
+        });
+        self.send_message(ctx, response);
+    }
+}
+
+impl Handler<AnalysisLine> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: AnalysisLine, ctx: &mut Self::Context) {
+        let score = match msg.mate {
+            Some(mate) => serde_json::json!({ "mate": mate }),
+            None => serde_json::json!({ "cp": msg.cp }),
+        };
+        let response = serde_json::json!({
+            "type": "analysis",
+            "data": {
+                "fen": msg.fen,
+                "depth": msg.depth,
+                "multipv": msg.multipv,
+                "score": score,
+                "pv": msg.pv,
+                "nodes": msg.nodes,
+                "nps": msg.nps,
+                "tablebase": msg.tablebase,
+            }
+        });
+        self.send_message(ctx, response);
+    }
+}
+
+impl Handler<AnalysisDone> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: AnalysisDone, ctx: &mut Self::Context) {
+        let response = serde_json::json!({
+            "type": "analysis_done",
+            "data": {
+                "fen": msg.fen,
+                "best_move": msg.best_move,
+                "tablebase": msg.tablebase,
+            }
+        });
+        self.send_message(ctx, response);
+    }
+}
+
+impl Handler<BookMoves> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: BookMoves, ctx: &mut Self::Context) {
+        let response = serde_json::json!({
+            "type": "book_moves",
+            "data": {
+                "fen": msg.fen,
+                "moves": msg.moves,
             }
         });
         self.send_message(ctx, response);
@@ -0,0 +1,145 @@
+// tests/external_engine.rs
+
+// The UCI adapter against examples/stub_engine.rs, which can be told to
+// hang or crash. It's an example so it isn't built or installed with the
+// server, `cargo test` builds it before running the tests.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use chess_server::engine::{EngineError, EngineProfile, ExternalEngine, Limits};
+use chess_server::position::Position;
+
+// Next to the test binaries, in target/<profile>/examples
+fn stub_engine() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    let dir = exe.parent().and_then(|deps| deps.parent()).unwrap();
+    let path = dir
+        .join("examples")
+        .join(format!("stub_engine{}", std::env::consts::EXE_SUFFIX));
+    assert!(
+        path.exists(),
+        "{} is missing, build it with `cargo build --examples`",
+        path.display()
+    );
+    path
+}
+
+fn profile(mode: &str, file: Option<&PathBuf>) -> EngineProfile {
+    let mut args = vec![mode.to_string()];
+    args.extend(file.map(|file| file.display().to_string()));
+    EngineProfile {
+        name: format!("stub {}", mode),
+        path: stub_engine(),
+        args,
+        options: BTreeMap::from([("Hash".to_string(), "16".to_string())]),
+    }
+}
+
+// A file of its own for each test, gone before the test starts
+fn scratch_file(test: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("stub_engine_{}_{}", test, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn movetime(ms: u64) -> Limits {
+    Limits {
+        movetime: Some(Duration::from_millis(ms)),
+        ..Limits::default()
+    }
+}
+
+#[test]
+fn handshake_and_search_round_trip() {
+    let log = scratch_file("round_trip");
+    let mut engine = ExternalEngine::new(profile("normal", Some(&log)));
+    let start = Position::startpos();
+    let e4 = start.find_uci_move("e2e4").unwrap();
+
+    let mut infos = Vec::new();
+    let result = engine
+        .search(&start, &[e4], movetime(500), |info| {
+            infos.push((info.depth, info.score))
+        })
+        .unwrap();
+    let after = start.make_move(&e4);
+    assert_eq!(result.best_move, after.legal_moves().first().copied());
+    assert_eq!((result.depth, result.score, result.nodes), (1, 12, 20));
+    assert_eq!(infos, vec![(1, 12)]);
+
+    // The same process carries on with the next search
+    let result = engine.search(&after, &[], movetime(500), |_| {}).unwrap();
+    assert!(result.best_move.is_some());
+
+    let commands = std::fs::read_to_string(&log).unwrap();
+    let commands: Vec<&str> = commands.lines().collect();
+    assert_eq!(
+        commands[..5],
+        [
+            "uci",
+            "setoption name Hash value 16",
+            "isready",
+            &format!("position fen {} moves e2e4", start.to_fen()),
+            "go movetime 500",
+        ]
+    );
+    assert_eq!(commands.iter().filter(|c| **c == "uci").count(), 1);
+    let _ = std::fs::remove_file(&log);
+}
+
+#[test]
+fn engine_that_stops_answering_times_out() {
+    let log = scratch_file("hang");
+    let mut engine = ExternalEngine::new(profile("hang", Some(&log)));
+    let started = Instant::now();
+    let result = engine.search(&Position::startpos(), &[], movetime(100), |_| {});
+    assert!(
+        matches!(result, Err(EngineError::Timeout)),
+        "{:?}",
+        result.err()
+    );
+    // The movetime and two grace periods, one before and one after `stop`
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_secs(4), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(10), "{:?}", elapsed);
+    let commands = std::fs::read_to_string(&log).unwrap();
+    assert!(commands.lines().any(|command| command == "stop"));
+    let _ = std::fs::remove_file(&log);
+}
+
+#[test]
+fn crashed_engine_is_restarted_once() {
+    let marker = scratch_file("crash_once");
+    let mut engine = ExternalEngine::new(profile("crash-once", Some(&marker)));
+    let result = engine.search(&Position::startpos(), &[], movetime(100), |_| {});
+    assert!(result.unwrap().best_move.is_some());
+    assert!(marker.exists());
+    let _ = std::fs::remove_file(&marker);
+}
+
+#[test]
+fn engine_that_keeps_crashing_fails() {
+    let mut engine = ExternalEngine::new(profile("crash", None));
+    let result = engine.search(&Position::startpos(), &[], movetime(100), |_| {});
+    assert!(
+        matches!(result, Err(EngineError::Crashed)),
+        "{:?}",
+        result.err()
+    );
+}
+
+#[test]
+fn missing_engine_fails_to_start() {
+    let mut engine = ExternalEngine::new(EngineProfile {
+        path: PathBuf::from("/nonexistent/engine"),
+        ..profile("normal", None)
+    });
+    let result = engine.search(&Position::startpos(), &[], movetime(100), |_| {});
+    assert!(
+        matches!(result, Err(EngineError::Start(_))),
+        "{:?}",
+        result.err()
+    );
+}